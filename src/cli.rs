@@ -0,0 +1,467 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+use chrono::{ DateTime, NaiveDate };
+use clap::{ Parser, Subcommand };
+use log::LevelFilter;
+use crate::config::{ self, MonitorConfig };
+use zeroize::Zeroizing;
+use crate::engine::exchange::{ Exchange, types::OrderSide };
+use crate::engine::exchange::config::{ credential_fields, load_legacy };
+use crate::keystore;
+use crate::recorder;
+use crate::engine::guard::{ self, ActionGuard };
+use crate::engine::replay;
+use crate::monitor::{ target, backtest };
+use crate::notify::Channels;
+
+#[derive(Parser, Debug)]
+#[command(name = "monitor", version, about = "crypto loan monitor")]
+pub struct Cli {
+  /// 配置文件路径, 默认使用 confy 的 crypto-loan-monitor
+  #[arg(long, global = true)]
+  pub config: Option<PathBuf>,
+  /// 强制 dry run, 覆盖配置里的 guard.dry_run
+  #[arg(long, global = true)]
+  pub dry_run: bool,
+  #[arg(long, global = true, default_value = "info")]
+  pub log_level: LevelFilter,
+  #[command(subcommand)]
+  pub command: Option<Command>
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+  /// 启动监控, 不带子命令时默认执行
+  Run,
+  /// 查询所有仓位一次并输出表格
+  Status,
+  /// 交易所各账户的余额
+  Balances {
+    venue: String,
+    /// 用母账户的 key 查询配置的子账户
+    #[arg(long)]
+    sub: bool
+  },
+  /// 交易所的借币信息
+  Loans { venue: String },
+  /// 交易对深度, pair 例如 crv/usdt
+  Depth { venue: String, pair: String },
+  /// 按配置里的交易对下单, 撤单, 查询订单
+  #[command(subcommand)]
+  Order(OrderCommand),
+  /// 提币, 执行前需要确认
+  Withdraw {
+    venue: String,
+    asset: String,
+    address: String,
+    amount: f64,
+    /// 交易所的网络名称, 不指定时使用默认网络
+    #[arg(long)]
+    network: Option<String>,
+    /// 跳过确认
+    #[arg(long, short)]
+    yes: bool
+  },
+  /// 配置相关
+  #[command(subcommand)]
+  Config(ConfigCommand),
+  /// 管理加密保存的交易所 API key
+  #[command(subcommand)]
+  Keystore(KeystoreCommand),
+  /// 用历史行情回放配置里 backtest 的策略, 文件格式见 readme
+  Backtest {
+    /// 行情文件, 也可以是 recorder 的目录
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+    /// 读取目录时只用这个交易对的文件, 例如 crv/usdt
+    #[arg(long)]
+    pair: Option<String>,
+    /// 从这一天开始, 例如 2021-05-17
+    #[arg(long)]
+    from: Option<NaiveDate>,
+    /// 到这一天结束(包含)
+    #[arg(long)]
+    to: Option<NaiveDate>
+  }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum OrderCommand {
+  /// 按配置里的交易对下限价单
+  Create {
+    venue: String,
+    #[arg(value_parser = parse_side)]
+    side: OrderSide,
+    price: f64,
+    volume: f64
+  },
+  Cancel { venue: String, id: String },
+  /// 撤销交易对的全部挂单
+  CancelAll { venue: String },
+  Info { venue: String, id: String }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+  /// 检查配置能否解析, 仓位引用的链, 钱包私钥和接口 token
+  Check
+}
+
+#[derive(Subcommand, Debug)]
+pub enum KeystoreCommand {
+  /// 列出保存的凭证名称和字段, 不显示值
+  List,
+  /// 新增凭证, name 是 exchanges 里的 config 名称, 字段值在终端输入
+  Add { name: String },
+  /// 替换已有凭证的所有字段
+  Rotate { name: String },
+  Remove { name: String },
+  /// 导入旧的 confy 明文配置, 导入后请删除明文文件
+  Import { name: String }
+}
+
+fn parse_side(side: &str) -> Result<OrderSide, String> {
+  match side.to_lowercase().as_str() {
+    "buy" => Ok(OrderSide::BUY),
+    "sell" => Ok(OrderSide::SELL),
+    _ => Err(format!("invalid side {}, expect buy or sell", side))
+  }
+}
+
+// venue 可以是配置名称(例如 binance.18520833073), 也可以是交易所名称(只配置了一个时)
+fn find_exchange(cfg: &MonitorConfig, venue: &str) -> Result<Exchange, String> {
+  if let Some(ex) = cfg.exchanges.iter().find(|ex| ex.config == venue) {
+    return Ok(ex.clone());
+  }
+  let matched: Vec<&Exchange> = cfg.exchanges.iter().filter(|ex| ex.name.to_string().eq_ignore_ascii_case(venue)).collect();
+  match matched.len() {
+    0 => Err(format!("venue {} not found in config", venue)),
+    1 => Ok(matched[0].clone()),
+    _ => Err(format!("{} accounts of {} configured, use the config name instead", matched.len(), venue))
+  }
+}
+
+fn confirm(prompt: &str) -> bool {
+  print!("{} [y/N] ", prompt);
+  let _ = std::io::stdout().flush();
+  let mut answer = String::new();
+  if std::io::stdin().read_line(&mut answer).is_err() {
+    return false;
+  }
+  return matches!(answer.trim().to_lowercase().as_str(), "y" | "yes");
+}
+
+async fn status(cfg: &MonitorConfig) -> Result<String, String> {
+  let mut lines = vec![format!("{:<32} {:<20} {:>10} {:>10} {:>14}", "ID", "VENUE", "LTV", "HEALTH", "LIQ PRICE")];
+  for target in target::from_config(cfg).iter() {
+    match target.position().await {
+      Ok(pos) => {
+        let price = pos.liquidation_price.map(|p| format!("{:.4}", p)).unwrap_or(String::from("-"));
+        lines.push(format!("{:<32} {:<20} {:>10.4} {:>10.4} {:>14}", target.id(), target.venue(), pos.ltv, pos.health_factor, price));
+      }
+      Err(err) => lines.push(format!("{:<32} {:<20} error: {}", target.id(), target.venue(), err))
+    }
+  }
+  lines.push(format!("dry run: {}", cfg.guard.dry_run));
+  return Ok(lines.join("\n"));
+}
+
+async fn balances(ex: &Exchange, sub: bool) -> Result<String, String> {
+  let balances = if sub { ex.subaccount_balances().await? } else { ex.balances().await? };
+  let mut lines = vec![format!("{:<24} {:<8} {:>16} {:>16} {:>16} {:>12}", "ACCOUNT", "ASSET", "FREE", "LOCKED", "BORROWED", "INTEREST")];
+  for b in balances.items.iter() {
+    lines.push(format!("{:<24} {:<8} {:>16} {:>16} {:>16} {:>12}", b.account.to_string(), b.asset, b.free, b.locked, b.borrowed, b.interest));
+  }
+  return Ok(lines.join("\n"));
+}
+
+async fn depth(ex: &Exchange, pair: &str) -> Result<String, String> {
+  let parts: Vec<&str> = pair.split(['/', '-', '_']).collect();
+  if parts.len() != 2 {
+    return Err(format!("invalid pair {}, expect symbol/currency", pair));
+  }
+  let mut ex = ex.clone();
+  ex.symbol = parts[0].to_lowercase();
+  ex.currency = parts[1].to_lowercase();
+  let depth = ex.depth().await?;
+  let mut lines = vec![format!("{:>16} {:>16} | {:>16} {:>16}", "BID", "VOLUME", "ASK", "VOLUME")];
+  for i in 0..depth.tick.bids.len().max(depth.tick.asks.len()).min(10) {
+    let bid = depth.tick.bids.get(i).map(|t| format!("{:>16} {:>16}", t[0], t[1])).unwrap_or(format!("{:>33}", ""));
+    let ask = depth.tick.asks.get(i).map(|t| format!("{:>16} {:>16}", t[0], t[1])).unwrap_or_default();
+    lines.push(format!("{} | {}", bid, ask));
+  }
+  return Ok(lines.join("\n"));
+}
+
+// guard 的 max_amount 按下单数量(symbol)检查, 和划转/提币的数量一致
+async fn order(cfg: &MonitorConfig, cmd: OrderCommand, guard: &ActionGuard) -> Result<String, String> {
+  match cmd {
+    OrderCommand::Create { venue, side, price, volume } => {
+      let ex = find_exchange(cfg, &venue)?;
+      let action = format!("{} {} {}{} {} at {}", ex.name, side, volume, ex.symbol, ex.currency, price);
+      if !guard.check(&action, volume)? {
+        return Ok(format!("dry run: {}", action));
+      }
+      return ex.create_order(side, price, volume).await;
+    }
+    OrderCommand::Cancel { venue, id } => {
+      let ex = find_exchange(cfg, &venue)?;
+      return Ok(format!("canceled: {}", ex.cancel_order(id).await?));
+    }
+    OrderCommand::CancelAll { venue } => {
+      let ex = find_exchange(cfg, &venue)?;
+      return Ok(format!("all canceled: {}", ex.cancel_all_order().await?));
+    }
+    OrderCommand::Info { venue, id } => {
+      let ex = find_exchange(cfg, &venue)?;
+      return Ok(format!("{:#?}", ex.order_info(id).await?));
+    }
+  }
+}
+
+async fn withdraw(ex: &Exchange, asset: String, address: String, amount: f64, network: Option<String>, yes: bool, guard: &ActionGuard) -> Result<String, String> {
+  let action = format!("withdraw {} {} from {} to {} ({})", amount, asset, ex.config, address, network.clone().unwrap_or(String::from("default network")));
+  if !guard.check(&action, amount)? {
+    return Ok(format!("dry run: {}", action));
+  }
+  if !yes && !confirm(&format!("{}?", action)) {
+    return Ok(String::from("canceled"));
+  }
+  match network {
+    Some(network) => ex.withdraw_on_chain(asset, network, address, amount, String::new()).await,
+    None => ex.withdraw(asset, address, amount).await
+  }
+}
+
+fn check_config(cfg: &MonitorConfig) -> Result<String, String> {
+  let mut problems: Vec<String> = Vec::new();
+  let positions = cfg.exchanges.len() + cfg.aave.len() + cfg.compound.len() + cfg.maker.len();
+  let targets = target::from_config(cfg);
+  if targets.len() < positions {
+    problems.push(format!("{} positions reference unknown chains", positions - targets.len()));
+  }
+  for chain in cfg.chains.iter().filter(|c| c.rpc_urls.is_empty()) {
+    problems.push(format!("chain {} has no rpc url", chain.name));
+  }
+  for wallet in cfg.wallets.iter() {
+    if cfg.chain(&wallet.chain).is_err() {
+      problems.push(format!("wallet references unknown chain {}", wallet.chain));
+    }
+    if std::env::var(&wallet.key_env).is_err() {
+      problems.push(format!("wallet of {}: env {} is not set", wallet.chain, wallet.key_env));
+    }
+  }
+  // telegram bot token 和 smtp 密码从环境变量读取
+  for notifier in cfg.notifiers.iter().filter(|n| matches!(n.kind, Channels::TELEGRAM | Channels::EMAIL)) {
+    if let Err(err) = notifier.secret() {
+      problems.push(err);
+    }
+  }
+  if let Some(api) = cfg.api.as_ref() {
+    if std::env::var(&api.token_env).is_err() {
+      problems.push(format!("api token env {} is not set, control endpoints are disabled", api.token_env));
+    }
+  }
+  if !problems.is_empty() {
+    return Err(problems.join("\n"));
+  }
+  return Ok(format!("ok: {} positions, dry run {}", targets.len(), cfg.guard.dry_run));
+}
+
+fn format_ts(ts: i64) -> String {
+  DateTime::from_timestamp_millis(ts).map(|t| t.format("%Y-%m-%d %H:%M").to_string()).unwrap_or_default()
+}
+
+fn run_backtest(cfg: &MonitorConfig, paths: &[PathBuf], pair: Option<String>, from: Option<NaiveDate>, to: Option<NaiveDate>) -> Result<String, String> {
+  let mut files: Vec<PathBuf> = Vec::new();
+  for path in paths.iter() {
+    if path.is_dir() {
+      let pair = pair.as_ref().ok_or(format!("{} is a directory, --pair is required", path.display()))?;
+      files.extend(recorder::files(path, pair)?);
+    } else {
+      files.push(path.clone());
+    }
+  }
+  if files.is_empty() {
+    return Err(String::from("no market data files"));
+  }
+  let start = from.and_then(|d| d.and_hms_opt(0, 0, 0)).map(|t| t.and_utc().timestamp_millis()).unwrap_or(i64::MIN);
+  let end = to.and_then(|d| d.succ_opt()).and_then(|d| d.and_hms_opt(0, 0, 0)).map(|t| t.and_utc().timestamp_millis()).unwrap_or(i64::MAX);
+  let events: Vec<replay::MarketEvent> = replay::load_all(&files)?.into_iter().filter(|e| e.ts() >= start && e.ts() < end).collect();
+  let reports = backtest::run(&cfg.backtest, &cfg.alert, &events)?;
+  let mut lines = vec![
+    format!("{} events from {} to {} UTC", events.len(), format_ts(events[0].ts()), format_ts(events[events.len() - 1].ts())),
+    format!("{:<24} {:>8} {:>12} {:>12} {:>10} {:>10} {:>10} {:>7} {:>10}  {}", "STRATEGY", "ACTIONS", "CAPITAL", "SOLD", "FEES", "SLIPPAGE", "INTEREST", "ALERTS", "MIN HEALTH", "RESULT")
+  ];
+  for r in reports.iter() {
+    let result = match (r.liquidated_at, r.liquidation_price) {
+      (Some(ts), Some(price)) => format!("LIQUIDATED at {} price {}", format_ts(ts), price),
+      _ => format!("equity {:.2}", r.equity)
+    };
+    lines.push(format!("{:<24} {:>8} {:>12.2} {:>12.4} {:>10.2} {:>10.2} {:>10.2} {:>7} {:>10.4}  {}",
+      r.strategy, r.actions, r.capital, r.sold, r.fees, r.slippage, r.interest, r.alerts, r.min_health_factor, result));
+  }
+  return Ok(lines.join("\n"));
+}
+
+fn input_fields(cfg: &MonitorConfig, name: &str) -> Result<BTreeMap<String, Zeroizing<String>>, String> {
+  let ex = find_exchange(cfg, name)?;
+  let mut fields = BTreeMap::new();
+  for field in credential_fields(&ex.name).iter() {
+    let value = keystore::prompt(name, field)?;
+    if !value.is_empty() {
+      fields.insert(String::from(*field), value);
+    }
+  }
+  return Ok(fields);
+}
+
+fn manage_keystore(cfg: &MonitorConfig, cmd: KeystoreCommand) -> Result<String, String> {
+  let exists = |name: &str| -> Result<bool, String> {
+    Ok(keystore::list(&cfg.keystore).map(|l| l.iter().any(|(n, _)| n == name)).unwrap_or(false))
+  };
+  match cmd {
+    KeystoreCommand::List => {
+      let lines: Vec<String> = keystore::list(&cfg.keystore)?.iter().map(|(name, fields)| format!("{}: {}", name, fields.join(", "))).collect();
+      return Ok(lines.join("\n"));
+    }
+    KeystoreCommand::Add { name } => {
+      if exists(&name)? {
+        return Err(format!("credential {} already exists, use rotate", name));
+      }
+      keystore::put(&cfg.keystore, &name, input_fields(cfg, &name)?)?;
+      return Ok(format!("added {}", name));
+    }
+    KeystoreCommand::Rotate { name } => {
+      if !exists(&name)? {
+        return Err(format!("credential {} not found, use add", name));
+      }
+      keystore::put(&cfg.keystore, &name, input_fields(cfg, &name)?)?;
+      return Ok(format!("rotated {}, restart the monitor to use it", name));
+    }
+    KeystoreCommand::Remove { name } => {
+      keystore::remove(&cfg.keystore, &name)?;
+      return Ok(format!("removed {}", name));
+    }
+    KeystoreCommand::Import { name } => {
+      let ex = find_exchange(cfg, &name)?;
+      keystore::put(&cfg.keystore, &name, load_legacy(&ex.name, &name)?)?;
+      return Ok(format!("imported {}, delete the plaintext confy file {}", name, name));
+    }
+  }
+}
+
+// 执行一次性的子命令, 输出结果, 出错时返回 Err
+pub async fn execute(command: Command) -> Result<String, String> {
+  let cfg = config::try_load()?;
+  guard::init(&cfg.guard);
+  if !matches!(command, Command::Keystore(_) | Command::Config(_) | Command::Backtest { .. }) {
+    keystore::init(&cfg.keystore)?;
+  }
+  match command {
+    Command::Run => Err(String::from("run is handled by main")),
+    Command::Status => status(&cfg).await,
+    Command::Balances { venue, sub } => balances(&find_exchange(&cfg, &venue)?, sub).await,
+    Command::Loans { venue } => Ok(format!("{:#?}", find_exchange(&cfg, &venue)?.loan_info().await?)),
+    Command::Depth { venue, pair } => depth(&find_exchange(&cfg, &venue)?, &pair).await,
+    Command::Order(cmd) => order(&cfg, cmd, &guard::current()).await,
+    Command::Withdraw { venue, asset, address, amount, network, yes } => {
+      withdraw(&find_exchange(&cfg, &venue)?, asset, address, amount, network, yes, &guard::current()).await
+    }
+    Command::Config(ConfigCommand::Check) => check_config(&cfg),
+    Command::Keystore(cmd) => manage_keystore(&cfg, cmd),
+    Command::Backtest { files, pair, from, to } => run_backtest(&cfg, &files, pair, from, to)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::engine::exchange::mock::MockExchange;
+  use crate::engine::exchange::types::Exchanges;
+
+  fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+    Cli::try_parse_from([&["monitor"], args].concat())
+  }
+
+  fn limited(max_amount: f64) -> ActionGuard {
+    ActionGuard { max_amount, ..ActionGuard::default() }
+  }
+
+  #[test]
+  fn parse_arguments() {
+    let cli = parse(&[]).unwrap();
+    assert!(cli.command.is_none() && !cli.dry_run);
+    assert_eq!(cli.log_level, LevelFilter::Info);
+
+    // 全局参数可以放在子命令后面
+    let cli = parse(&["order", "create", "binance.test", "Buy", "0.5", "10", "--dry-run", "--log-level", "debug"]).unwrap();
+    assert!(cli.dry_run);
+    assert_eq!(cli.log_level, LevelFilter::Debug);
+    match cli.command {
+      Some(Command::Order(OrderCommand::Create { venue, side, price, volume })) => {
+        assert!(matches!(side, OrderSide::BUY));
+        assert_eq!((venue.as_str(), price, volume), ("binance.test", 0.5, 10_f64));
+      }
+      other => panic!("unexpected {:?}", other)
+    }
+    assert!(parse(&["order", "create", "binance", "long", "0.5", "10"]).unwrap_err().to_string().contains("invalid side long"));
+    assert!(parse(&["order", "create", "binance", "buy", "cheap", "10"]).is_err());
+
+    match parse(&["withdraw", "okex", "usdt", "TXaddress", "100", "--network", "USDT-TRC20", "-y"]).unwrap().command {
+      Some(Command::Withdraw { amount, network, yes, .. }) => assert_eq!((amount, network.as_deref(), yes), (100_f64, Some("USDT-TRC20"), true)),
+      other => panic!("unexpected {:?}", other)
+    }
+    match parse(&["backtest", "data/", "--pair", "crv/usdt", "--from", "2021-05-17"]).unwrap().command {
+      Some(Command::Backtest { files, pair, from, to }) => {
+        assert_eq!(files, vec![PathBuf::from("data/")]);
+        assert_eq!(pair.as_deref(), Some("crv/usdt"));
+        assert_eq!(from, NaiveDate::from_ymd_opt(2021, 5, 17));
+        assert!(to.is_none());
+      }
+      other => panic!("unexpected {:?}", other)
+    }
+    // 没有行情文件, 日期格式错误
+    assert!(parse(&["backtest"]).is_err());
+    assert!(parse(&["backtest", "data/", "--from", "17/05/2021"]).is_err());
+    assert!(matches!(parse(&["keystore", "rotate", "binance.test"]).unwrap().command, Some(Command::Keystore(KeystoreCommand::Rotate { name })) if name == "binance.test"));
+    assert!(matches!(parse(&["balances", "okex", "--sub"]).unwrap().command, Some(Command::Balances { sub: true, .. })));
+  }
+
+  #[tokio::test]
+  async fn dry_run_orders_and_withdrawals_are_not_sent() {
+    let mock = MockExchange::start(Exchanges::BINANCE).await;
+    let cfg = MonitorConfig { exchanges: vec![mock.exchange("crv", "usdt")], ..MonitorConfig::default() };
+    let create = |volume: f64| OrderCommand::Create { venue: String::from("binance"), side: OrderSide::SELL, price: 100_f64, volume };
+
+    // 限额按数量检查, 不是按金额
+    let res = order(&cfg, create(40_f64), &limited(50_f64)).await.unwrap();
+    assert_eq!(res, "dry run: BINANCE SELL 40crv usdt at 100");
+    let err = order(&cfg, create(60_f64), &limited(50_f64)).await.unwrap_err();
+    assert!(err.contains("amount 60 exceeds limit 50"), "{}", err);
+    assert!(mock.requests("/api/v3/order").is_empty());
+
+    let ex = find_exchange(&cfg, "binance").unwrap();
+    let res = withdraw(&ex, String::from("usdt"), String::from("TXaddress"), 100_f64, None, false, &limited(0_f64)).await.unwrap();
+    assert_eq!(res, format!("dry run: withdraw 100 usdt from {} to TXaddress (default network)", ex.config));
+    assert!(mock.requests("/sapi/v1/capital/withdraw/apply").is_empty());
+  }
+
+  #[tokio::test]
+  async fn status_and_depth_output() {
+    let mock = MockExchange::start(Exchanges::BINANCE).await;
+    let cfg = MonitorConfig { exchanges: vec![mock.exchange("crv", "usdt")], ..MonitorConfig::default() };
+    let out = status(&cfg).await.unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert!(lines[0].starts_with("ID"));
+    assert!(lines[1].starts_with("BINANCE:crvusdt") && lines[1].contains("0.2500"), "{}", lines[1]);
+    assert_eq!(lines[2], "dry run: true");
+
+    let out = depth(&cfg.exchanges[0], "CRV-USDT").await.unwrap();
+    assert_eq!(out.lines().count(), 6);
+    assert!(out.lines().nth(1).unwrap().contains("0.5 ") && out.lines().nth(1).unwrap().contains("0.501"));
+    assert!(depth(&cfg.exchanges[0], "crvusdt").await.unwrap_err().contains("invalid pair"));
+    assert!(find_exchange(&cfg, "okex").unwrap_err().contains("not found"));
+  }
+}
@@ -0,0 +1,127 @@
+use std::path::PathBuf;
+use std::sync::RwLock;
+use serde::{Deserialize, Serialize};
+use crate::engine::exchange::{ Exchange, types::Exchanges };
+use crate::engine::defi::aave::AaveConfig;
+use crate::engine::defi::compound::CompoundConfig;
+use crate::engine::defi::maker::MakerConfig;
+use crate::engine::defi::tx::WalletConfig;
+use crate::engine::defi::chain::ChainConfig;
+use crate::engine::guard::ActionGuard;
+use crate::notify::NotifierConfig;
+use crate::monitor::alert::AlertConfig;
+use crate::monitor::api::ApiConfig;
+use crate::store::StoreConfig;
+use crate::keystore::KeystoreConfig;
+use crate::monitor::selfcheck::SelfCheckConfig;
+use crate::monitor::backtest::BacktestConfig;
+use crate::recorder::RecorderConfig;
+use crate::monitor::protect::ProtectionConfig;
+
+// confy 配置名称, 保存监控的仓位列表
+pub static MONITOR_CONFIG: &str = "crypto-loan-monitor";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MonitorConfig {
+  pub interval: u64, // 轮询间隔, 秒
+  pub exchanges: Vec<Exchange>,
+  #[serde(default)]
+  pub chains: Vec<ChainConfig>, // DeFi 仓位和发送账户通过名字引用
+  #[serde(default)]
+  pub aave: Vec<AaveConfig>,
+  #[serde(default)]
+  pub compound: Vec<CompoundConfig>,
+  #[serde(default)]
+  pub maker: Vec<MakerConfig>,
+  #[serde(default)]
+  pub guard: ActionGuard, // 交易所和链上操作共用
+  #[serde(default)]
+  pub wallets: Vec<WalletConfig>, // 链上操作的发送账户, 每条链一个
+  #[serde(default)]
+  pub notifiers: Vec<NotifierConfig>,
+  #[serde(default)]
+  pub alert: AlertConfig,
+  #[serde(default)]
+  pub api: Option<ApiConfig>, // 不配置时不启动 http 接口
+  #[serde(default)]
+  pub store: StoreConfig,
+  #[serde(default)]
+  pub keystore: KeystoreConfig, // 交易所 API key
+  #[serde(default)]
+  pub selfcheck: SelfCheckConfig, // 启动时检查 API key 权限
+  #[serde(default)]
+  pub backtest: BacktestConfig, // 回放历史行情时的初始仓位和策略
+  #[serde(default)]
+  pub recorder: RecorderConfig, // 记录收到的深度和成交
+  #[serde(default)]
+  pub protection: ProtectionConfig // protect = true 的交易所仓位的自动保护策略
+}
+
+impl ::std::default::Default for MonitorConfig {
+  fn default() -> Self {
+    Self {
+      interval: 10_u64,
+      exchanges: vec![Exchange {
+        config: String::from("binance.18520833073"),
+        name: Exchanges::BINANCE,
+        symbol: String::from("crv"),
+        currency: String::from("usdt"),
+        host: String::from("api.binance.com"),
+        protocol: String::from("https"),
+        protect: false,
+        subaccount: None
+      }],
+      chains: vec![],
+      aave: vec![],
+      compound: vec![],
+      maker: vec![],
+      guard: ActionGuard::default(),
+      wallets: vec![],
+      notifiers: vec![],
+      alert: AlertConfig::default(),
+      api: None,
+      store: StoreConfig::default(),
+      keystore: KeystoreConfig::default(),
+      selfcheck: SelfCheckConfig::default(),
+      backtest: BacktestConfig::default(),
+      recorder: RecorderConfig::default(),
+      protection: ProtectionConfig::default()
+    }
+  }
+}
+
+impl MonitorConfig {
+  pub fn chain(&self, name: &str) -> Result<&ChainConfig, String> {
+    crate::engine::defi::chain::find(&self.chains, name)
+  }
+}
+
+// 命令行参数指定的配置文件和 dry run, 重新加载配置时同样生效
+static PATH: RwLock<Option<PathBuf>> = RwLock::new(None);
+static FORCE_DRY_RUN: RwLock<bool> = RwLock::new(false);
+
+pub fn set_path(path: Option<PathBuf>) {
+  *PATH.write().unwrap() = path;
+}
+
+pub fn force_dry_run(dry_run: bool) {
+  *FORCE_DRY_RUN.write().unwrap() = dry_run;
+}
+
+pub fn try_load() -> Result<MonitorConfig, String> {
+  let mut cfg: MonitorConfig = match PATH.read().unwrap().as_ref() {
+    Some(path) => {
+      let text = std::fs::read_to_string(path).map_err(|e| format!("read monitor config {} error: {}", path.display(), e))?;
+      toml::from_str(&text).map_err(|e| format!("parse monitor config {} error: {}", path.display(), e))?
+    }
+    None => confy::load(MONITOR_CONFIG).map_err(|e| format!("read monitor config error: {}", e))?
+  };
+  if *FORCE_DRY_RUN.read().unwrap() {
+    cfg.guard.dry_run = true;
+  }
+  return Ok(cfg);
+}
+
+pub fn load() -> MonitorConfig {
+  try_load().unwrap()
+}
@@ -1 +1,8 @@
-pub mod exchange;
\ No newline at end of file
+pub mod exchange;
+pub mod rebalance;
+pub mod position;
+pub mod defi;
+pub mod guard;
+pub mod journal;
+pub mod replay;
+pub mod strategy;
\ No newline at end of file
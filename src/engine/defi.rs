@@ -0,0 +1,11 @@
+pub mod abi;
+pub mod rpc;
+pub mod chain;
+pub mod aave;
+pub mod compound;
+pub mod maker;
+pub mod rlp;
+pub mod signer;
+pub mod tx;
+pub mod actions;#[cfg(test)]
+pub mod mock;
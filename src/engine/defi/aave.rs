@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+use super::{ abi, rpc };
+use super::chain::ChainConfig;
+use crate::engine::position::LoanPosition;
+
+// v2 的 LendingPool 以 ETH 计价(18 位小数), v3 的 Pool 以 USD 计价(8 位小数)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AaveConfig {
+  pub id: String,
+  pub version: u8, // 2 或 3
+  pub chain: String,
+  #[serde(default)]
+  pub pool: String, // LendingPool(v2) 或 Pool(v3) 合约地址, 为空时使用链配置里的 aave-v{version}-pool
+  pub user: String, // 借款地址
+  #[serde(default)]
+  pub collateral_asset: String, // 补充抵押物时 supply 的 token
+  #[serde(default)]
+  pub debt_asset: String // 还款的 token
+}
+
+impl AaveConfig {
+  pub fn pool(&self, chain: &ChainConfig) -> Result<String, String> {
+    if !self.pool.is_empty() {
+      return Ok(self.pool.clone());
+    }
+    return chain.contract(&format!("aave-v{}-pool", self.version));
+  }
+}
+
+pub async fn position(cfg: &AaveConfig, chain: &ChainConfig) -> Result<LoanPosition, String> {
+  let pool = cfg.pool(chain)?;
+  let words = rpc::call_n(&chain.rpc_urls, &pool, "getUserAccountData(address)", &[abi::encode_address(&cfg.user)?], 6).await?;
+  let (decimals, base) = match cfg.version {
+    2 => (18, "eth"),
+    3 => (8, "usd"),
+    v => return Err(format!("aave {}: unsupported version {}", cfg.id, v))
+  };
+  // totalCollateral, totalDebt, availableBorrows, currentLiquidationThreshold, ltv, healthFactor
+  let collateral_value = abi::word_to_f64(&words[0], decimals);
+  let debt_value = abi::word_to_f64(&words[1], decimals);
+  let liquidation_threshold = abi::word_to_f64(&words[3], 4);
+  let mut pos = LoanPosition::new(cfg.id.clone(), format!("aave-v{}:{}", cfg.version, chain.name), String::from(base), collateral_value, debt_value, liquidation_threshold);
+  // 合约按每个抵押物各自的清算线加权计算, 以合约返回的为准
+  pos.health_factor = abi::word_to_f64(&words[5], 18);
+  return Ok(pos);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+  use super::super::mock::MockRpc;
+
+  static POOL: &str = "0x87870bca3f3fd6335c3f4ce8392d69350b4fa4e2";
+  static USER: &str = "0x1111111111111111111111111111111111111111";
+
+  fn config() -> AaveConfig {
+    AaveConfig { id: String::from("aave"), version: 3, chain: String::from("ethereum"), pool: String::from(POOL), user: String::from(USER), collateral_asset: String::new(), debt_asset: String::new() }
+  }
+
+  #[tokio::test]
+  async fn position_from_account_data() {
+    let rpc = MockRpc::start().await;
+    let user = abi::encode_address(USER).unwrap();
+    let data = [10000e8 as u128, 5000e8 as u128, 2500e8 as u128, 8250, 8000, 1.65e18 as u128].map(abi::encode_u128);
+    rpc.call(POOL, "getUserAccountData(address)", &[user], &data);
+    let pos = position(&config(), &rpc.chain("ethereum")).await.unwrap();
+    assert_eq!(pos.venue, "aave-v3:ethereum");
+    assert_eq!(pos.collateral_value, 10000_f64);
+    assert_eq!(pos.debt_value, 5000_f64);
+    assert_eq!(pos.liquidation_ltv, 0.825);
+    assert_eq!(pos.health_factor, 1.65);
+
+    // 返回值不完整时报错, 不 panic
+    rpc.call(POOL, "getUserAccountData(address)", &[user], &data[..3]);
+    let err = position(&config(), &rpc.chain("ethereum")).await.unwrap_err();
+    assert!(err.contains("returns 3 words"), "{}", err);
+
+    rpc.error("eth_call", json!({ "code": -32000, "message": "execution reverted" }));
+    let err = position(&config(), &rpc.chain("ethereum")).await.unwrap_err();
+    assert!(err.contains("execution reverted"), "{}", err);
+  }
+}
@@ -0,0 +1,169 @@
+use tiny_keccak::{Hasher, Keccak};
+
+// 只实现借贷合约用到的静态类型: address, uint256, bool, 每个参数和返回值都是 32 字节
+
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+  let mut hasher = Keccak::v256();
+  let mut out = [0_u8; 32];
+  hasher.update(data);
+  hasher.finalize(&mut out);
+  return out;
+}
+
+// 函数选择器, e.g. "getUserAccountData(address)"
+pub fn selector(signature: &str) -> [u8; 4] {
+  let hash = keccak256(signature.as_bytes());
+  return [hash[0], hash[1], hash[2], hash[3]];
+}
+
+pub fn strip_0x(s: &str) -> &str {
+  s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s)
+}
+
+pub fn encode_address(address: &str) -> Result<[u8; 32], String> {
+  let bytes = hex::decode(strip_0x(address)).map_err(|e| format!("invalid address {}: {}", address, e))?;
+  if bytes.len() != 20 {
+    return Err(format!("invalid address {}: expect 20 bytes", address));
+  }
+  let mut word = [0_u8; 32];
+  word[12..].copy_from_slice(&bytes);
+  return Ok(word);
+}
+
+pub fn encode_u128(value: u128) -> [u8; 32] {
+  let mut word = [0_u8; 32];
+  word[16..].copy_from_slice(&value.to_be_bytes());
+  return word;
+}
+
+// 只有测试需要构造 bytes32 的返回值
+#[cfg(test)]
+pub fn encode_bytes32(hex_str: &str) -> Result<[u8; 32], String> {
+  let bytes = hex::decode(strip_0x(hex_str)).map_err(|e| format!("invalid bytes32 {}: {}", hex_str, e))?;
+  if bytes.len() > 32 {
+    return Err(format!("invalid bytes32 {}: longer than 32 bytes", hex_str));
+  }
+  let mut word = [0_u8; 32];
+  word[..bytes.len()].copy_from_slice(&bytes);
+  return Ok(word);
+}
+
+// 拼出 calldata 的 0x 十六进制字符串
+pub fn encode_call(signature: &str, args: &[[u8; 32]]) -> String {
+  let mut data: Vec<u8> = selector(signature).to_vec();
+  for arg in args.iter() {
+    data.extend_from_slice(arg);
+  }
+  return format!("0x{}", hex::encode(data));
+}
+
+// 把返回值切成 32 字节一个的 word
+pub fn decode_words(result: &str) -> Result<Vec<[u8; 32]>, String> {
+  let bytes = hex::decode(strip_0x(result)).map_err(|e| format!("invalid eth_call result {}: {}", result, e))?;
+  if !bytes.len().is_multiple_of(32) {
+    return Err(format!("invalid eth_call result length {}", bytes.len()));
+  }
+  return Ok(bytes.chunks(32).map(|c| {
+    let mut word = [0_u8; 32];
+    word.copy_from_slice(c);
+    word
+  }).collect());
+}
+
+// uint256 超出 u128 时返回错误, 借贷金额不会这么大
+pub fn word_to_u128(word: &[u8; 32]) -> Result<u128, String> {
+  if word[..16].iter().any(|b| *b != 0) {
+    return Err(format!("uint256 overflow: 0x{}", hex::encode(word)));
+  }
+  let mut buf = [0_u8; 16];
+  buf.copy_from_slice(&word[16..]);
+  return Ok(u128::from_be_bytes(buf));
+}
+
+// 按 decimals 换算成浮点数, 用于展示和比较, uint256 的最大值(例如无借款时的健康因子)返回无穷大
+pub fn word_to_f64(word: &[u8; 32], decimals: u32) -> f64 {
+  if word.iter().all(|b| *b == 0xff) {
+    return f64::INFINITY;
+  }
+  let mut value = 0_f64;
+  for b in word.iter() {
+    value = value * 256_f64 + *b as f64;
+  }
+  return value / 10_f64.powi(decimals as i32);
+}
+
+pub fn word_to_bool(word: &[u8; 32]) -> bool {
+  word[31] != 0
+}
+
+pub fn word_to_address(word: &[u8; 32]) -> String {
+  format!("0x{}", hex::encode(&word[12..]))
+}
+
+fn word_to_offset(word: &[u8; 32]) -> Result<usize, String> {
+  let offset = word_to_u128(word)? as usize;
+  if !offset.is_multiple_of(32) {
+    return Err(format!("invalid abi offset {}", offset));
+  }
+  return Ok(offset / 32);
+}
+
+// 动态类型的返回值, index 位置存的是相对于返回值开头的偏移
+pub fn decode_address_array(words: &[[u8; 32]], index: usize) -> Result<Vec<String>, String> {
+  let start = word_to_offset(words.get(index).ok_or("abi index out of range")?)?;
+  let len = word_to_u128(words.get(start).ok_or("abi offset out of range")?)? as usize;
+  let items = words.get(start + 1..start + 1 + len).ok_or("abi array out of range")?;
+  return Ok(items.iter().map(word_to_address).collect());
+}
+
+pub fn decode_string(words: &[[u8; 32]], index: usize) -> Result<String, String> {
+  let start = word_to_offset(words.get(index).ok_or("abi index out of range")?)?;
+  let len = word_to_u128(words.get(start).ok_or("abi offset out of range")?)? as usize;
+  let bytes: Vec<u8> = words.get(start + 1..).ok_or("abi string out of range")?.iter().flatten().take(len).cloned().collect();
+  return String::from_utf8(bytes).map_err(|e| format!("invalid abi string: {}", e));
+}
+
+// bytes32 里的短字符串, 例如 Maker 的 ilk "ETH-A"
+pub fn word_to_short_string(word: &[u8; 32]) -> String {
+  String::from_utf8_lossy(word).trim_end_matches('\0').to_string()
+}
+
+// int256 补码
+pub fn encode_i128(value: i128) -> [u8; 32] {
+  let fill = if value < 0 { 0xff_u8 } else { 0_u8 };
+  let mut word = [fill; 32];
+  word[16..].copy_from_slice(&value.to_be_bytes());
+  return word;
+}
+
+// 按 decimals 把数量转成链上的整数. f64 的 Display 是能还原出同一个值的最短十进制表示(不会用科学计数法),
+// 在这个十进制字符串上移动小数点, 0.3 得到 3 * 10^17 而不是它的二进制近似值 0.29999..., 多出的小数位舍去
+pub fn to_raw(amount: f64, decimals: u32) -> Result<u128, String> {
+  if amount < 0_f64 || !amount.is_finite() {
+    return Err(format!("invalid amount {}", amount));
+  }
+  let s = amount.to_string();
+  let (int_part, frac_part) = s.split_once('.').unwrap_or((s.as_str(), ""));
+  let mut digits = String::from(int_part);
+  digits.extend(frac_part.chars().chain(std::iter::repeat('0')).take(decimals as usize));
+  return digits.parse::<u128>().map_err(|e| format!("amount {} overflow: {}", amount, e));
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn to_raw_is_exact_for_decimal_amounts() {
+    assert_eq!(to_raw(0.3, 18), Ok(300_000_000_000_000_000));
+    assert_eq!(to_raw(0.1, 18), Ok(100_000_000_000_000_000));
+    assert_eq!(to_raw(123.456789, 6), Ok(123_456_789));
+    assert_eq!(to_raw(1000_f64, 6), Ok(1_000_000_000));
+    // 超出 decimals 的部分舍去
+    assert_eq!(to_raw(1.0000019, 6), Ok(1_000_001));
+    assert_eq!(to_raw(0.0000001, 6), Ok(0));
+    assert!(to_raw(-1_f64, 6).is_err());
+    assert!(to_raw(f64::NAN, 6).is_err());
+    assert!(to_raw(1e30, 18).is_err());
+  }
+}
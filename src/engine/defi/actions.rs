@@ -0,0 +1,110 @@
+use super::{ abi, rpc };
+use super::tx::Wallet;
+use super::maker::MakerConfig;
+use crate::engine::guard;
+use crate::engine::journal::ActionJournal;
+
+// 链上保护操作: 还款或者补充抵押物, 每个操作都经过 guard 检查, dry run 时返回 None;
+// approve 和之后的交易是 journal 里的两步
+
+pub async fn token_decimals(wallet: &Wallet, token: &str) -> Result<u32, String> {
+  let words = rpc::call(&wallet.chain.rpc_urls, token, "decimals()", &[]).await?;
+  return Ok(abi::word_to_u128(&words[0])? as u32);
+}
+
+// allowance 不够时先 approve, approve 的数量就是这次要用的数量, amount 是 raw 换算前的数量, 给 guard 检查
+pub async fn approve_if_needed(wallet: &Wallet, token: &str, spender: &str, amount: f64, raw: u128) -> Result<(), String> {
+  let owner = abi::encode_address(wallet.address())?;
+  let spender_word = abi::encode_address(spender)?;
+  let allowance = abi::word_to_u128(&rpc::call(&wallet.chain.rpc_urls, token, "allowance(address,address)", &[owner, spender_word]).await?[0]).unwrap_or(u128::MAX);
+  if allowance >= raw {
+    return Ok(());
+  }
+  let data = abi::encode_call("approve(address,uint256)", &[spender_word, abi::encode_u128(raw)]);
+  wallet.send_and_confirm(&format!("approve {} {} for {}", amount, token, spender), amount, token, &data).await?;
+  return Ok(());
+}
+
+async fn approve_and_send(wallet: &Wallet, action: &str, token: &str, spender: &str, amount: f64, journal: &mut ActionJournal, data_fn: impl Fn(u128) -> String) -> Result<Option<String>, String> {
+  let raw = abi::to_raw(amount, token_decimals(wallet, token).await?)?;
+  // dry run 时不 approve, 只检查 guard
+  if !guard::current().check(action, amount)? {
+    return Ok(None);
+  }
+  journal.step("approve", approve_if_needed(wallet, token, spender, amount, raw)).await?;
+  return journal.step(action, wallet.send_and_confirm(action, amount, spender, &data_fn(raw))).await;
+}
+
+// Aave 浮动利率借款还款, interestRateMode 2
+pub async fn aave_repay(wallet: &Wallet, pool: &str, asset: &str, amount: f64, journal: &mut ActionJournal) -> Result<Option<String>, String> {
+  let asset_word = abi::encode_address(asset)?;
+  let user = abi::encode_address(wallet.address())?;
+  return approve_and_send(wallet, &format!("aave repay {} {}", amount, asset), asset, pool, amount, journal, |raw| {
+    abi::encode_call("repay(address,uint256,uint256,address)", &[asset_word, abi::encode_u128(raw), abi::encode_u128(2), user])
+  }).await;
+}
+
+// v2 叫 deposit, v3 叫 supply, 参数相同
+pub async fn aave_supply(wallet: &Wallet, version: u8, pool: &str, asset: &str, amount: f64, journal: &mut ActionJournal) -> Result<Option<String>, String> {
+  let asset_word = abi::encode_address(asset)?;
+  let user = abi::encode_address(wallet.address())?;
+  let signature = if version == 2 { "deposit(address,uint256,address,uint16)" } else { "supply(address,uint256,address,uint16)" };
+  return approve_and_send(wallet, &format!("aave supply {} {}", amount, asset), asset, pool, amount, journal, |raw| {
+    abi::encode_call(signature, &[asset_word, abi::encode_u128(raw), user, abi::encode_u128(0)])
+  }).await;
+}
+
+pub async fn compound_v2_repay(wallet: &Wallet, ctoken: &str, underlying: &str, amount: f64, journal: &mut ActionJournal) -> Result<Option<String>, String> {
+  return approve_and_send(wallet, &format!("compound repay {} {}", amount, underlying), underlying, ctoken, amount, journal, |raw| {
+    abi::encode_call("repayBorrow(uint256)", &[abi::encode_u128(raw)])
+  }).await;
+}
+
+pub async fn compound_v2_supply(wallet: &Wallet, ctoken: &str, underlying: &str, amount: f64, journal: &mut ActionJournal) -> Result<Option<String>, String> {
+  return approve_and_send(wallet, &format!("compound supply {} {}", amount, underlying), underlying, ctoken, amount, journal, |raw| {
+    abi::encode_call("mint(uint256)", &[abi::encode_u128(raw)])
+  }).await;
+}
+
+// Comet 里 supply base token 就是还款, supply 其他资产是补充抵押物
+pub async fn compound_v3_supply(wallet: &Wallet, comet: &str, asset: &str, amount: f64, journal: &mut ActionJournal) -> Result<Option<String>, String> {
+  let asset_word = abi::encode_address(asset)?;
+  return approve_and_send(wallet, &format!("comet supply {} {}", amount, asset), asset, comet, amount, journal, |raw| {
+    abi::encode_call("supply(address,uint256)", &[asset_word, abi::encode_u128(raw)])
+  }).await;
+}
+
+// 通过 DssCdpManager 调整 vault, dink 是抵押物(wad), dart 是标准化债务(wad), 负数表示减少,
+// vault 的 owner 必须是签名地址(或者已经 cdpAllow 给它), amount 是这次 lock 或者 wipe 的数量, 给 guard 检查
+pub async fn maker_frob(wallet: &Wallet, cdp_manager: &str, cdp_id: u64, dink: i128, dart: i128, amount: f64) -> Result<Option<String>, String> {
+  let data = abi::encode_call("frob(uint256,int256,int256)", &[abi::encode_u128(cdp_id as u128), abi::encode_i128(dink), abi::encode_i128(dart)]);
+  return wallet.send_and_confirm(&format!("maker frob cdp {} dink {} dart {}", cdp_id, dink, dart), amount, cdp_manager, &data).await;
+}
+
+// 补充抵押物: GemJoin.join 到 urn, 再 frob 增加 ink
+pub async fn maker_lock(wallet: &Wallet, cfg: &MakerConfig, gem_join: &str, gem: &str, urn: &str, amount: f64, journal: &mut ActionJournal) -> Result<Option<String>, String> {
+  let urn_word = abi::encode_address(urn)?;
+  let action = format!("maker lock {} into cdp {}", amount, cfg.cdp_id);
+  if approve_and_send(wallet, &action, gem, gem_join, amount, journal, |raw| {
+    abi::encode_call("join(address,uint256)", &[urn_word, abi::encode_u128(raw)])
+  }).await?.is_none() {
+    return Ok(None);
+  }
+  // Vat 里的 gem 统一是 18 位
+  let dink = abi::to_raw(amount, 18)? as i128;
+  return journal.step("frob", maker_frob(wallet, &cfg.cdp_manager, cfg.cdp_id, dink, 0, amount)).await;
+}
+
+// 还 DAI: DaiJoin.join 到 urn, 再 frob 减少 art, art = dai / rate
+pub async fn maker_wipe(wallet: &Wallet, cfg: &MakerConfig, dai: &str, urn: &str, rate: f64, amount: f64, journal: &mut ActionJournal) -> Result<Option<String>, String> {
+  let urn_word = abi::encode_address(urn)?;
+  let action = format!("maker wipe {} dai of cdp {}", amount, cfg.cdp_id);
+  if approve_and_send(wallet, &action, dai, &cfg.dai_join, amount, journal, |raw| {
+    abi::encode_call("join(address,uint256)", &[urn_word, abi::encode_u128(raw)])
+  }).await?.is_none() {
+    return Ok(None);
+  }
+  // 向下取整, 避免 urn 里的 dai 不够
+  let dart = abi::to_raw((amount / rate * 1e9_f64).floor() / 1e9_f64, 18)? as i128;
+  return journal.step("frob", maker_frob(wallet, &cfg.cdp_manager, cfg.cdp_id, 0, -dart, amount)).await;
+}
@@ -0,0 +1,23 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+
+// 一条 EVM 链, 仓位和发送账户通过 name 引用
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChainConfig {
+  pub name: String, // ethereum, arbitrum, optimism, polygon, bsc ...
+  pub chain_id: u64,
+  pub rpc_urls: Vec<String>, // 按顺序故障切换
+  pub native_token: String, // 支付 gas 的代币, eth, matic, bnb
+  #[serde(default)]
+  pub contracts: HashMap<String, String> // 协议合约地址, e.g. aave-v3-pool, maker-vat
+}
+
+impl ChainConfig {
+  pub fn contract(&self, key: &str) -> Result<String, String> {
+    self.contracts.get(key).cloned().ok_or(format!("chain {} has no contract {}", self.name, key))
+  }
+}
+
+pub fn find<'a>(chains: &'a [ChainConfig], name: &str) -> Result<&'a ChainConfig, String> {
+  chains.iter().find(|c| c.name == name).ok_or(format!("chain {} is not configured", name))
+}
@@ -0,0 +1,241 @@
+use serde::{Deserialize, Serialize};
+use super::{ abi, rpc };
+use super::chain::ChainConfig;
+use crate::engine::position::{ LoanPosition, CollateralAsset, collateral_liquidation_prices };
+
+// v2 的 market 是 Comptroller 地址, v3 的 market 是 Comet 地址, 价值都以 USD 计价
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CompoundConfig {
+  pub id: String,
+  pub version: u8, // 2 或 3
+  pub chain: String,
+  pub market: String, // 合约地址, 或者链配置里 contracts 的 key
+  pub user: String,
+  #[serde(default)]
+  pub collateral_asset: String, // 补充抵押物, v2 是 cToken 地址, v3 是抵押物 token 地址
+  #[serde(default)]
+  pub debt_asset: String // 还款的 cToken 地址, 只有 v2 需要, v3 还 base token
+}
+
+impl CompoundConfig {
+  pub fn market(&self, chain: &ChainConfig) -> Result<String, String> {
+    if self.market.starts_with("0x") {
+      return Ok(self.market.clone());
+    }
+    return chain.contract(&self.market);
+  }
+}
+
+pub async fn position(cfg: &CompoundConfig, chain: &ChainConfig) -> Result<LoanPosition, String> {
+  let market = cfg.market(chain)?;
+  match cfg.version {
+    2 => position_v2(cfg, chain, &market).await,
+    3 => position_v3(cfg, chain, &market).await,
+    v => Err(format!("compound {}: unsupported version {}", cfg.id, v))
+  }
+}
+
+async fn symbol(chain: &ChainConfig, token: &str) -> String {
+  match rpc::call(&chain.rpc_urls, token, "symbol()", &[]).await.and_then(|w| abi::decode_string(&w, 0)) {
+    Ok(s) => s.to_lowercase(),
+    Err(_) => String::from(token)
+  }
+}
+
+async fn position_v2(cfg: &CompoundConfig, chain: &ChainConfig, market: &str) -> Result<LoanPosition, String> {
+  let user = abi::encode_address(&cfg.user)?;
+  let liquidity = rpc::call_n(&chain.rpc_urls, market, "getAccountLiquidity(address)", &[user], 3).await?;
+  if abi::word_to_u128(&liquidity[0])? != 0 {
+    return Err(format!("compound-v2 {}: getAccountLiquidity error", cfg.id));
+  }
+  let shortfall = abi::word_to_f64(&liquidity[2], 18);
+  let oracle_words = rpc::call(&chain.rpc_urls, market, "oracle()", &[]).await?;
+  let oracle = abi::word_to_address(&oracle_words[0]);
+  let assets_words = rpc::call(&chain.rpc_urls, market, "getAssetsIn(address)", &[user]).await?;
+  let ctokens = abi::decode_address_array(&assets_words, 0)?;
+
+  let mut collaterals: Vec<CollateralAsset> = Vec::new();
+  let mut debt_value = 0_f64;
+  for ctoken in ctokens.iter() {
+    let ctoken_word = abi::encode_address(ctoken)?;
+    // 价格按 1e(36 - underlying decimals) 缩放, 原始数量乘价格再除以 1e36 就是 USD
+    let price_words = rpc::call(&chain.rpc_urls, &oracle, "getUnderlyingPrice(address)", &[ctoken_word]).await?;
+    let price = abi::word_to_f64(&price_words[0], 0);
+    let market_words = rpc::call_n(&chain.rpc_urls, market, "markets(address)", &[ctoken_word], 2).await?;
+    let collateral_factor = abi::word_to_f64(&market_words[1], 18);
+    // error, cTokenBalance, borrowBalance, exchangeRateMantissa
+    let snapshot = rpc::call_n(&chain.rpc_urls, ctoken, "getAccountSnapshot(address)", &[user], 4).await?;
+    if abi::word_to_u128(&snapshot[0])? != 0 {
+      return Err(format!("compound-v2 {}: getAccountSnapshot {} error", cfg.id, ctoken));
+    }
+    let underlying_raw = abi::word_to_f64(&snapshot[1], 0) * abi::word_to_f64(&snapshot[3], 18);
+    let borrow_words = rpc::call(&chain.rpc_urls, ctoken, "borrowBalanceCurrent(address)", &[user]).await?;
+    debt_value += abi::word_to_f64(&borrow_words[0], 0) * price / 1e36_f64;
+    if underlying_raw > 0_f64 {
+      let decimals = underlying_decimals(&chain.rpc_urls, ctoken).await?;
+      let amount = underlying_raw / 10_f64.powi(decimals as i32);
+      collaterals.push(CollateralAsset {
+        asset: symbol(chain, ctoken).await,
+        amount,
+        value: underlying_raw * price / 1e36_f64,
+        liquidation_threshold: collateral_factor,
+        liquidation_price: None
+      });
+    }
+  }
+  let collateral_value: f64 = collaterals.iter().map(|c| c.value).sum();
+  let weighted: f64 = collaterals.iter().map(|c| c.value * c.liquidation_threshold).sum();
+  let liquidation_ltv = if collateral_value > 0_f64 { weighted / collateral_value } else { 0_f64 };
+  collateral_liquidation_prices(&mut collaterals, debt_value);
+  let mut pos = LoanPosition::new(cfg.id.clone(), format!("compound-v2:{}", chain.name), String::from("usd"), collateral_value, debt_value, liquidation_ltv);
+  if shortfall > 0_f64 && pos.health_factor >= 1_f64 {
+    // 以合约的结果为准
+    pos.health_factor = if debt_value > 0_f64 { (debt_value - shortfall) / debt_value } else { 0_f64 };
+  }
+  pos.collaterals = collaterals;
+  return Ok(pos);
+}
+
+pub async fn underlying(chain: &ChainConfig, ctoken: &str) -> Result<String, String> {
+  let words = rpc::call(&chain.rpc_urls, ctoken, "underlying()", &[]).await?;
+  return Ok(abi::word_to_address(&words[0]));
+}
+
+pub async fn base_token(chain: &ChainConfig, comet: &str) -> Result<String, String> {
+  let words = rpc::call(&chain.rpc_urls, comet, "baseToken()", &[]).await?;
+  return Ok(abi::word_to_address(&words[0]));
+}
+
+// cETH 没有 underlying(), 按 18 位处理
+async fn underlying_decimals(urls: &[String], ctoken: &str) -> Result<u32, String> {
+  let underlying = match rpc::call(urls, ctoken, "underlying()", &[]).await {
+    Ok(words) => abi::word_to_address(&words[0]),
+    Err(_) => return Ok(18)
+  };
+  let words = rpc::call(urls, &underlying, "decimals()", &[]).await?;
+  return Ok(abi::word_to_u128(&words[0])? as u32);
+}
+
+async fn position_v3(cfg: &CompoundConfig, chain: &ChainConfig, market: &str) -> Result<LoanPosition, String> {
+  let user = abi::encode_address(&cfg.user)?;
+  let liquidatable_words = rpc::call(&chain.rpc_urls, market, "isLiquidatable(address)", &[user]).await?;
+  let liquidatable = abi::word_to_bool(&liquidatable_words[0]);
+
+  let base_feed = abi::word_to_address(&rpc::call(&chain.rpc_urls, market, "baseTokenPriceFeed()", &[]).await?[0]);
+  let base_price = abi::word_to_f64(&rpc::call(&chain.rpc_urls, market, "getPrice(address)", &[abi::encode_address(&base_feed)?]).await?[0], 8);
+  let base_scale = abi::word_to_f64(&rpc::call(&chain.rpc_urls, market, "baseScale()", &[]).await?[0], 0);
+  let borrow = abi::word_to_f64(&rpc::call(&chain.rpc_urls, market, "borrowBalanceOf(address)", &[user]).await?[0], 0);
+  let debt_value = borrow / base_scale * base_price;
+
+  let num_assets = abi::word_to_u128(&rpc::call(&chain.rpc_urls, market, "numAssets()", &[]).await?[0])?;
+  let mut collaterals: Vec<CollateralAsset> = Vec::new();
+  for i in 0..num_assets {
+    // offset, asset, priceFeed, scale, borrowCollateralFactor, liquidateCollateralFactor, liquidationFactor, supplyCap
+    let info = rpc::call_n(&chain.rpc_urls, market, "getAssetInfo(uint8)", &[abi::encode_u128(i)], 8).await?;
+    let asset = abi::word_to_address(&info[1]);
+    let balance_words = rpc::call(&chain.rpc_urls, market, "collateralBalanceOf(address,address)", &[user, info[1]]).await?;
+    let balance = abi::word_to_f64(&balance_words[0], 0);
+    if balance <= 0_f64 {
+      continue;
+    }
+    let amount = balance / abi::word_to_f64(&info[3], 0);
+    let price = abi::word_to_f64(&rpc::call(&chain.rpc_urls, market, "getPrice(address)", &[info[2]]).await?[0], 8);
+    collaterals.push(CollateralAsset {
+      asset: symbol(chain, &asset).await,
+      amount,
+      value: amount * price,
+      liquidation_threshold: abi::word_to_f64(&info[5], 18),
+      liquidation_price: None
+    });
+  }
+  let collateral_value: f64 = collaterals.iter().map(|c| c.value).sum();
+  let weighted: f64 = collaterals.iter().map(|c| c.value * c.liquidation_threshold).sum();
+  let liquidation_ltv = if collateral_value > 0_f64 { weighted / collateral_value } else { 0_f64 };
+  collateral_liquidation_prices(&mut collaterals, debt_value);
+  let mut pos = LoanPosition::new(cfg.id.clone(), format!("compound-v3:{}", chain.name), String::from("usd"), collateral_value, debt_value, liquidation_ltv);
+  if liquidatable && pos.health_factor >= 1_f64 {
+    // 以合约的结果为准
+    pos.health_factor = 0.999_f64;
+  }
+  pos.collaterals = collaterals;
+  return Ok(pos);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::mock::MockRpc;
+
+  static MARKET: &str = "0xc3d688b66703497daa19211eedff47f25384cdc3";
+  static USER: &str = "0x1111111111111111111111111111111111111111";
+  static FEED: &str = "0x2222222222222222222222222222222222222222";
+  static ASSET: &str = "0x3333333333333333333333333333333333333333";
+  static ASSET_FEED: &str = "0x4444444444444444444444444444444444444444";
+
+  fn config(version: u8) -> CompoundConfig {
+    CompoundConfig { id: String::from("compound"), version, chain: String::from("ethereum"), market: String::from(MARKET), user: String::from(USER), collateral_asset: String::new(), debt_asset: String::new() }
+  }
+
+  fn address(s: &str) -> [u8; 32] {
+    abi::encode_address(s).unwrap()
+  }
+
+  // 动态 string 的返回值: offset, length, 内容
+  fn string_words(s: &str) -> Vec<[u8; 32]> {
+    vec![abi::encode_u128(32), abi::encode_u128(s.len() as u128), abi::encode_bytes32(&hex::encode(s)).unwrap()]
+  }
+
+  #[tokio::test]
+  async fn position_v3_from_comet() {
+    let rpc = MockRpc::start().await;
+    let user = address(USER);
+    rpc.call(MARKET, "isLiquidatable(address)", &[user], &[abi::encode_u128(0)]);
+    rpc.call(MARKET, "baseTokenPriceFeed()", &[], &[address(FEED)]);
+    rpc.call(MARKET, "getPrice(address)", &[address(FEED)], &[abi::encode_u128(1e8 as u128)]);
+    rpc.call(MARKET, "baseScale()", &[], &[abi::encode_u128(1e6 as u128)]);
+    rpc.call(MARKET, "borrowBalanceOf(address)", &[user], &[abi::encode_u128(5000e6 as u128)]);
+    rpc.call(MARKET, "numAssets()", &[], &[abi::encode_u128(1)]);
+    let info = [abi::encode_u128(0), address(ASSET), address(ASSET_FEED), abi::encode_u128(1e18 as u128), abi::encode_u128(0.8e18 as u128), abi::encode_u128(0.85e18 as u128), abi::encode_u128(0.9e18 as u128), abi::encode_u128(0)];
+    rpc.call(MARKET, "getAssetInfo(uint8)", &[abi::encode_u128(0)], &info);
+    rpc.call(MARKET, "collateralBalanceOf(address,address)", &[user, address(ASSET)], &[abi::encode_u128(5e18 as u128)]);
+    rpc.call(MARKET, "getPrice(address)", &[address(ASSET_FEED)], &[abi::encode_u128(2000e8 as u128)]);
+    rpc.call(ASSET, "symbol()", &[], &string_words("WETH"));
+
+    let pos = position(&config(3), &rpc.chain("ethereum")).await.unwrap();
+    assert_eq!(pos.collateral_value, 10000_f64);
+    assert_eq!(pos.debt_value, 5000_f64);
+    assert_eq!(pos.liquidation_ltv, 0.85);
+    assert!((pos.health_factor - 1.7).abs() < 1e-9);
+    assert_eq!(pos.collaterals[0].asset, "weth");
+    assert_eq!(pos.collaterals[0].amount, 5_f64);
+
+    // 合约认为可以清算时以合约为准
+    rpc.call(MARKET, "isLiquidatable(address)", &[user], &[abi::encode_u128(1)]);
+    assert!(position(&config(3), &rpc.chain("ethereum")).await.unwrap().health_factor < 1_f64);
+
+    // getAssetInfo 不完整
+    rpc.call(MARKET, "getAssetInfo(uint8)", &[abi::encode_u128(0)], &info[..2]);
+    let err = position(&config(3), &rpc.chain("ethereum")).await.unwrap_err();
+    assert!(err.contains("getAssetInfo(uint8) returns 2 words"), "{}", err);
+  }
+
+  #[tokio::test]
+  async fn position_v2_short_market_result_is_error() {
+    let rpc = MockRpc::start().await;
+    let user = address(USER);
+    let oracle = "0x5555555555555555555555555555555555555555";
+    let ctoken = "0x6666666666666666666666666666666666666666";
+    rpc.call(MARKET, "getAccountLiquidity(address)", &[user], &[abi::encode_u128(0); 3]);
+    rpc.call(MARKET, "oracle()", &[], &[address(oracle)]);
+    rpc.call(MARKET, "getAssetsIn(address)", &[user], &[abi::encode_u128(32), abi::encode_u128(1), address(ctoken)]);
+    rpc.call(oracle, "getUnderlyingPrice(address)", &[address(ctoken)], &[abi::encode_u128(1e18 as u128)]);
+    // markets 应该返回 isListed, collateralFactorMantissa, isComped
+    rpc.call(MARKET, "markets(address)", &[address(ctoken)], &[abi::encode_u128(1)]);
+    let err = position(&config(2), &rpc.chain("ethereum")).await.unwrap_err();
+    assert!(err.contains("markets(address) returns 1 words"), "{}", err);
+
+    // 节点返回 0x(例如地址不是合约)
+    let err = position(&CompoundConfig { market: String::from(ctoken), ..config(2) }, &rpc.chain("ethereum")).await.unwrap_err();
+    assert!(err.contains("empty result"), "{}", err);
+  }
+}
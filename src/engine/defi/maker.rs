@@ -0,0 +1,177 @@
+use serde::{Deserialize, Serialize};
+use chrono::Utc;
+use super::{ abi, rpc };
+use super::chain::ChainConfig;
+use crate::engine::position::{ LoanPosition, CollateralAsset };
+
+// Maker 只在以太坊主网, 默认使用主网合约地址
+fn default_chain() -> String { String::from("ethereum") }
+fn default_vat() -> String { String::from("0x35D1b3F3D7966A1DFe207aa4514C12a259A0492B") }
+fn default_spotter() -> String { String::from("0x65C79fcB50Ca1594B025960e539eD7A9a6D434A3") }
+fn default_jug() -> String { String::from("0x19c0976f590D67707E62397C87829d896Dc0f1F1") }
+fn default_cdp_manager() -> String { String::from("0x5ef30b9986345249bc32d8928B7ee64DE9435E39") }
+fn default_dai_join() -> String { String::from("0x9759A6Ac90977b93B58547b4A71c78317f391A28") }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MakerConfig {
+  pub id: String,
+  #[serde(default = "default_chain")]
+  pub chain: String,
+  pub cdp_id: u64,
+  #[serde(default = "default_vat")]
+  pub vat: String,
+  #[serde(default = "default_spotter")]
+  pub spotter: String,
+  #[serde(default = "default_jug")]
+  pub jug: String,
+  #[serde(default = "default_cdp_manager")]
+  pub cdp_manager: String,
+  #[serde(default)]
+  pub gem_join: String, // 抵押物对应的 GemJoin, 补充抵押物时需要
+  #[serde(default = "default_dai_join")]
+  pub dai_join: String
+}
+
+static WAD: f64 = 1e18_f64;
+static RAY: f64 = 1e27_f64;
+
+// vault 的 urn 地址和 Vat 里当前的累计利率 rate, wipe 时用来把 DAI 换算成 art
+pub async fn urn_and_rate(cfg: &MakerConfig, chain: &ChainConfig) -> Result<(String, f64), String> {
+  let cdp = abi::encode_u128(cfg.cdp_id as u128);
+  let urn = rpc::call(&chain.rpc_urls, &cfg.cdp_manager, "urns(uint256)", &[cdp]).await?[0];
+  let ilk = rpc::call(&chain.rpc_urls, &cfg.cdp_manager, "ilks(uint256)", &[cdp]).await?[0];
+  let vat_ilk = rpc::call_n(&chain.rpc_urls, &cfg.vat, "ilks(bytes32)", &[ilk], 2).await?;
+  return Ok((abi::word_to_address(&urn), abi::word_to_f64(&vat_ilk[1], 0) / RAY));
+}
+
+// GemJoin.gem() 或 DaiJoin.dai()
+pub async fn join_token(chain: &ChainConfig, join: &str, getter: &str) -> Result<String, String> {
+  let words = rpc::call(&chain.rpc_urls, join, getter, &[]).await?;
+  return Ok(abi::word_to_address(&words[0]));
+}
+
+// Vault 的抵押物 ink, 标准化债务 art, 加上 Jug 累计到现在的利率得到实际 DAI 债务,
+// 当前价和下一个价格都从 OSM 读取, OSM 延迟一小时生效, 所以能提前一小时知道下次更新后会不会低于清算线
+pub async fn position(cfg: &MakerConfig, chain: &ChainConfig) -> Result<LoanPosition, String> {
+  let cdp = abi::encode_u128(cfg.cdp_id as u128);
+  let urn = rpc::call(&chain.rpc_urls, &cfg.cdp_manager, "urns(uint256)", &[cdp]).await?[0];
+  let ilk = rpc::call(&chain.rpc_urls, &cfg.cdp_manager, "ilks(uint256)", &[cdp]).await?[0];
+  let ilk_name = abi::word_to_short_string(&ilk);
+
+  let urn_words = rpc::call_n(&chain.rpc_urls, &cfg.vat, "urns(bytes32,address)", &[ilk, urn], 2).await?;
+  let ink = abi::word_to_f64(&urn_words[0], 0) / WAD;
+  let art = abi::word_to_f64(&urn_words[1], 0) / WAD;
+  // Art, rate, spot, line, dust
+  let vat_ilk = rpc::call_n(&chain.rpc_urls, &cfg.vat, "ilks(bytes32)", &[ilk], 2).await?;
+  let rate = abi::word_to_f64(&vat_ilk[1], 0) / RAY;
+  // duty 是每秒的利率, rho 是上次 drip 的时间, Vat 的 rate 只在 drip 时更新
+  let jug_ilk = rpc::call_n(&chain.rpc_urls, &cfg.jug, "ilks(bytes32)", &[ilk], 2).await?;
+  let duty = abi::word_to_f64(&jug_ilk[0], 0) / RAY;
+  let rho = abi::word_to_u128(&jug_ilk[1])? as i64;
+  let elapsed = (Utc::now().timestamp() - rho).max(0);
+  let debt = art * rate * duty.powf(elapsed as f64);
+
+  // pip, mat
+  let spot_ilk = rpc::call_n(&chain.rpc_urls, &cfg.spotter, "ilks(bytes32)", &[ilk], 2).await?;
+  let pip = abi::word_to_address(&spot_ilk[0]);
+  let mat = abi::word_to_f64(&spot_ilk[1], 0) / RAY;
+  // OSM 只允许白名单读取, Spotter 在白名单里
+  let cur = rpc::call_from_n(&chain.rpc_urls, Some(&cfg.spotter), &pip, "peek()", &[], 2).await?;
+  let nxt = rpc::call_from_n(&chain.rpc_urls, Some(&cfg.spotter), &pip, "peep()", &[], 2).await?;
+  if !abi::word_to_bool(&cur[1]) {
+    return Err(format!("maker {}: {} osm has no current price", cfg.id, ilk_name));
+  }
+  let price = abi::word_to_f64(&cur[0], 18);
+  let next_price = if abi::word_to_bool(&nxt[1]) { Some(abi::word_to_f64(&nxt[0], 18)) } else { None };
+
+  let mut pos = LoanPosition::new(cfg.id.clone(), String::from("maker"), String::from("dai"), ink * price, debt, 1_f64 / mat);
+  if ink > 0_f64 && debt > 0_f64 {
+    pos.liquidation_price = Some(debt * mat / ink);
+  }
+  pos.next_health_factor = next_price.map(|p| if debt > 0_f64 { ink * p / (debt * mat) } else { f64::INFINITY });
+  pos.next_update_at = next_price_at(chain, &pip).await.ok();
+  pos.collaterals = vec![CollateralAsset {
+    asset: ilk_name,
+    amount: ink,
+    value: ink * price,
+    liquidation_threshold: 1_f64 / mat,
+    liquidation_price: pos.liquidation_price
+  }];
+  return Ok(pos);
+}
+
+// OSM 下一次更新的时间, zzz 是上次更新, hop 是间隔
+async fn next_price_at(chain: &ChainConfig, pip: &str) -> Result<i64, String> {
+  let zzz = abi::word_to_u128(&rpc::call(&chain.rpc_urls, pip, "zzz()", &[]).await?[0])? as i64;
+  let hop = abi::word_to_u128(&rpc::call(&chain.rpc_urls, pip, "hop()", &[]).await?[0])? as i64;
+  return Ok(zzz + hop);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+  use super::super::mock::MockRpc;
+
+  static URN: &str = "0x1111111111111111111111111111111111111111";
+  static PIP: &str = "0x2222222222222222222222222222222222222222";
+
+  fn config() -> MakerConfig {
+    MakerConfig {
+      id: String::from("maker"),
+      chain: default_chain(),
+      cdp_id: 1,
+      vat: default_vat(),
+      spotter: default_spotter(),
+      jug: default_jug(),
+      cdp_manager: default_cdp_manager(),
+      gem_join: String::new(),
+      dai_join: default_dai_join()
+    }
+  }
+
+  // cdp 1 是 ETH-A, 10 ETH 抵押, 10000 DAI 债务, 清算线 150%, 当前价 2000, 下一个价格 1800
+  fn mock_vault(rpc: &MockRpc, cfg: &MakerConfig) -> [u8; 32] {
+    let cdp = abi::encode_u128(1);
+    let ilk = abi::encode_bytes32(&hex::encode("ETH-A")).unwrap();
+    let urn = abi::encode_address(URN).unwrap();
+    rpc.call(&cfg.cdp_manager, "urns(uint256)", &[cdp], &[urn]);
+    rpc.call(&cfg.cdp_manager, "ilks(uint256)", &[cdp], &[ilk]);
+    rpc.call(&cfg.vat, "urns(bytes32,address)", &[ilk, urn], &[abi::encode_u128(10e18 as u128), abi::encode_u128(10000e18 as u128)]);
+    rpc.call(&cfg.vat, "ilks(bytes32)", &[ilk], &[abi::encode_u128(0), abi::encode_u128(1e27 as u128), abi::encode_u128(0), abi::encode_u128(0), abi::encode_u128(0)]);
+    // duty 为 1 时没有未 drip 的利息
+    rpc.call(&cfg.jug, "ilks(bytes32)", &[ilk], &[abi::encode_u128(1e27 as u128), abi::encode_u128(0)]);
+    rpc.call(&cfg.spotter, "ilks(bytes32)", &[ilk], &[abi::encode_address(PIP).unwrap(), abi::encode_u128(1.5e27 as u128)]);
+    rpc.call(PIP, "peek()", &[], &[abi::encode_u128(2000e18 as u128), abi::encode_u128(1)]);
+    rpc.call(PIP, "peep()", &[], &[abi::encode_u128(1800e18 as u128), abi::encode_u128(1)]);
+    rpc.call(PIP, "zzz()", &[], &[abi::encode_u128(1700000000)]);
+    rpc.call(PIP, "hop()", &[], &[abi::encode_u128(3600)]);
+    return ilk;
+  }
+
+  #[tokio::test]
+  async fn position_with_next_osm_price() {
+    let rpc = MockRpc::start().await;
+    let cfg = config();
+    let ilk = mock_vault(&rpc, &cfg);
+    let pos = position(&cfg, &rpc.chain("ethereum")).await.unwrap();
+    assert_eq!(pos.collateral_value, 20000_f64);
+    assert_eq!(pos.debt_value, 10000_f64);
+    assert!((pos.health_factor - 20000_f64 / 15000_f64).abs() < 1e-9);
+    assert!((pos.next_health_factor.unwrap() - 1.2).abs() < 1e-9);
+    assert!((pos.liquidation_price.unwrap() - 1500_f64).abs() < 1e-9);
+    assert_eq!(pos.next_update_at, Some(1700003600));
+    assert_eq!(pos.collaterals[0].asset, "ETH-A");
+    // OSM 要以 Spotter 的身份读取
+    assert_eq!(rpc.requests("eth_call").iter().find(|p| p[0]["to"] == json!(PIP) && p[0]["from"].is_string()).unwrap()[0]["from"], json!(cfg.spotter));
+
+    // 节点返回的 word 不够时报错, 不 panic
+    rpc.call(&cfg.vat, "urns(bytes32,address)", &[ilk, abi::encode_address(URN).unwrap()], &[abi::encode_u128(10e18 as u128)]);
+    let err = position(&cfg, &rpc.chain("ethereum")).await.unwrap_err();
+    assert!(err.contains("urns(bytes32,address) returns 1 words, expected 2"), "{}", err);
+    mock_vault(&rpc, &cfg);
+    rpc.call(PIP, "peek()", &[], &[abi::encode_u128(2000e18 as u128)]);
+    let err = position(&cfg, &rpc.chain("ethereum")).await.unwrap_err();
+    assert!(err.contains("peek() returns 1 words"), "{}", err);
+  }
+}
@@ -0,0 +1,110 @@
+// 测试用的本地以太坊节点, 只实现 JSON-RPC: eth_call 按 (to, calldata) 返回设置好的 word,
+// 其他方法返回 set 的结果, 也可以让某个方法返回 JSON-RPC 错误. 没有设置的 eth_call 返回 0x, 和调用普通地址一样
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{ Arc, Mutex };
+use hyper::{ Body, Request, Response, Server };
+use hyper::service::{ make_service_fn, service_fn };
+use serde_json::{ json, Value };
+use super::abi;
+use super::chain::ChainConfig;
+
+#[derive(Default)]
+struct State {
+  calls: HashMap<String, String>,
+  results: HashMap<String, Value>,
+  errors: HashMap<String, Value>,
+  requests: Vec<(String, Value)>
+}
+
+pub struct MockRpc {
+  pub addr: SocketAddr,
+  state: Arc<Mutex<State>>
+}
+
+fn call_key(to: &str, data: &str) -> String {
+  format!("{} {}", to.to_lowercase(), data.to_lowercase())
+}
+
+impl MockRpc {
+  // 监听 127.0.0.1 的随机端口, 服务跟随测试的 runtime 退出
+  pub async fn start() -> MockRpc {
+    let state = Arc::new(Mutex::new(State::default()));
+    // rpc::request 会定期读取块高检查节点是否卡住
+    state.lock().unwrap().results.insert(String::from("eth_blockNumber"), json!("0x1"));
+    let svc_state = state.clone();
+    let make_svc = make_service_fn(move |_| {
+      let state = svc_state.clone();
+      async move {
+        Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req)))
+      }
+    });
+    let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    MockRpc { addr, state }
+  }
+
+  pub fn url(&self) -> String {
+    format!("http://{}", self.addr)
+  }
+
+  // 只有这一个节点的链配置
+  pub fn chain(&self, name: &str) -> ChainConfig {
+    ChainConfig {
+      name: String::from(name),
+      chain_id: 1,
+      rpc_urls: vec![self.url()],
+      native_token: String::from("eth"),
+      contracts: HashMap::new()
+    }
+  }
+
+  // 合约 to 的 signature(args) 返回 result
+  pub fn call(&self, to: &str, signature: &str, args: &[[u8; 32]], result: &[[u8; 32]]) {
+    let data = abi::encode_call(signature, args);
+    let hex: String = result.iter().map(hex::encode).collect();
+    self.state.lock().unwrap().calls.insert(call_key(to, &data), format!("0x{}", hex));
+  }
+
+  // eth_call 以外的方法的 result
+  pub fn set(&self, method: &str, result: Value) {
+    self.state.lock().unwrap().results.insert(String::from(method), result);
+  }
+
+  // 之后对这个方法的请求都返回 JSON-RPC 错误, 传 Value::Null 取消
+  pub fn error(&self, method: &str, error: Value) {
+    let mut s = self.state.lock().unwrap();
+    if error.is_null() {
+      s.errors.remove(method);
+    } else {
+      s.errors.insert(String::from(method), error);
+    }
+  }
+
+  // 收到的某个方法的请求参数
+  pub fn requests(&self, method: &str) -> Vec<Value> {
+    self.state.lock().unwrap().requests.iter().filter(|(m, _)| m == method).map(|(_, p)| p.clone()).collect()
+  }
+}
+
+async fn handle(state: Arc<Mutex<State>>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+  let bytes = hyper::body::to_bytes(req.into_body()).await.unwrap_or_default();
+  let payload: Value = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+  let method = payload["method"].as_str().unwrap_or("").to_string();
+  let params = payload["params"].clone();
+  let mut s = state.lock().unwrap();
+  s.requests.push((method.clone(), params.clone()));
+  let reply = if let Some(error) = s.errors.get(&method) {
+    json!({ "jsonrpc": "2.0", "id": payload["id"], "error": error })
+  } else if method == "eth_call" {
+    let key = call_key(params[0]["to"].as_str().unwrap_or(""), params[0]["data"].as_str().unwrap_or(""));
+    json!({ "jsonrpc": "2.0", "id": payload["id"], "result": s.calls.get(&key).cloned().unwrap_or(String::from("0x")) })
+  } else if let Some(result) = s.results.get(&method) {
+    json!({ "jsonrpc": "2.0", "id": payload["id"], "result": result })
+  } else {
+    json!({ "jsonrpc": "2.0", "id": payload["id"], "error": { "code": -32601, "message": format!("the method {} does not exist/is not available", method) } })
+  };
+  Ok(Response::builder().header("Content-Type", "application/json").body(Body::from(reply.to_string())).unwrap())
+}
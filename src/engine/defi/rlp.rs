@@ -0,0 +1,77 @@
+// 交易签名用到的 RLP 编码, 只需要字节串和列表两种
+
+pub enum Item {
+  Bytes(Vec<u8>),
+  List(Vec<Item>)
+}
+
+fn encode_length(len: usize, offset: u8) -> Vec<u8> {
+  if len < 56 {
+    return vec![offset + len as u8];
+  }
+  let len_bytes: Vec<u8> = len.to_be_bytes().iter().skip_while(|b| **b == 0).cloned().collect();
+  let mut out = vec![offset + 55 + len_bytes.len() as u8];
+  out.extend(len_bytes);
+  return out;
+}
+
+pub fn encode(item: &Item) -> Vec<u8> {
+  match item {
+    Item::Bytes(bytes) => {
+      if bytes.len() == 1 && bytes[0] < 0x80 {
+        return bytes.clone();
+      }
+      let mut out = encode_length(bytes.len(), 0x80);
+      out.extend(bytes);
+      out
+    }
+    Item::List(items) => {
+      let payload: Vec<u8> = items.iter().flat_map(encode).collect();
+      let mut out = encode_length(payload.len(), 0xc0);
+      out.extend(payload);
+      out
+    }
+  }
+}
+
+// 整数去掉前导 0, 0 编码成空字节串
+pub fn uint(value: u128) -> Item {
+  Item::Bytes(value.to_be_bytes().iter().skip_while(|b| **b == 0).cloned().collect())
+}
+
+pub fn bytes(value: &[u8]) -> Item {
+  Item::Bytes(value.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn string(s: &str) -> Item {
+    bytes(s.as_bytes())
+  }
+
+  // 以太坊 RLP 文档里的例子
+  #[test]
+  fn spec_examples() {
+    assert_eq!(encode(&string("dog")), vec![0x83, b'd', b'o', b'g']);
+    assert_eq!(encode(&Item::List(vec![string("cat"), string("dog")])), vec![0xc8, 0x83, b'c', b'a', b't', 0x83, b'd', b'o', b'g']);
+    assert_eq!(encode(&string("")), vec![0x80]);
+    assert_eq!(encode(&Item::List(vec![])), vec![0xc0]);
+    assert_eq!(encode(&uint(0)), vec![0x80]);
+    assert_eq!(encode(&bytes(&[0x00])), vec![0x00]);
+    assert_eq!(encode(&uint(15)), vec![0x0f]);
+    assert_eq!(encode(&uint(1024)), vec![0x82, 0x04, 0x00]);
+    // [ [], [[]], [ [], [[]] ] ]
+    let nested = Item::List(vec![
+      Item::List(vec![]),
+      Item::List(vec![Item::List(vec![])]),
+      Item::List(vec![Item::List(vec![]), Item::List(vec![Item::List(vec![])])])
+    ]);
+    assert_eq!(encode(&nested), vec![0xc7, 0xc0, 0xc1, 0xc0, 0xc3, 0xc0, 0xc1, 0xc0]);
+    let lorem = "Lorem ipsum dolor sit amet, consectetur adipisicing elit";
+    let mut expected = vec![0xb8, 0x38];
+    expected.extend(lorem.as_bytes());
+    assert_eq!(encode(&string(lorem)), expected);
+  }
+}
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use serde_json::{ json, Value };
+use crate::util::handle_body;
+
+// 每组节点上次成功的那个, 下次优先使用, key 是第一个节点的 url
+static PREFERRED: Mutex<Vec<(String, usize)>> = Mutex::new(Vec::new());
+
+// 节点的块高超过 HEAD_STALL_SECS 没有增长就先用其他节点(同步卡住的节点会返回过期的仓位), 每个节点最多每 HEAD_CHECK_SECS 查一次块高.
+// 所有节点都停止增长时(空闲时不出块的本地开发链, 交易很少的链, 只配置了一个节点)使用最近增长过的那个
+static HEAD_CHECK_SECS: i64 = 15;
+static HEAD_STALL_SECS: i64 = 120;
+
+struct Head {
+  block: u128,
+  changed_at: i64,
+  checked_at: i64
+}
+
+static HEADS: Mutex<Option<HashMap<String, Head>>> = Mutex::new(None);
+
+fn preferred(urls: &[String]) -> usize {
+  let preferred = PREFERRED.lock().expect("rpc preferred lock error");
+  preferred.iter().find(|(k, _)| *k == urls[0]).map(|(_, i)| *i % urls.len()).unwrap_or(0)
+}
+
+fn set_preferred(urls: &[String], index: usize) {
+  let mut preferred = PREFERRED.lock().expect("rpc preferred lock error");
+  preferred.retain(|(k, _)| *k != urls[0]);
+  preferred.push((urls[0].clone(), index));
+}
+
+// 合约 revert 在每个节点上结果都一样, 不切换节点
+fn is_revert(error: &Value) -> bool {
+  error["code"] == json!(3) || error["message"].as_str().map(|m| m.contains("revert")).unwrap_or(false)
+}
+
+// 返回的错误里 bool 表示是否切换到下一个节点: 网络错误和节点返回的错误(限流, 内部错误等)切换, 合约 revert 不切换
+async fn request_one(rpc_url: &str, method: &str, params: &Value) -> Result<Value, (bool, String)> {
+  let client = reqwest::Client::new();
+  let payload = json!({
+    "jsonrpc": "2.0",
+    "id": 1,
+    "method": method,
+    "params": params
+  });
+  let body_resp = client.post(rpc_url).json(&payload).send().await;
+  let body_text = handle_body(body_resp).await.map_err(|e| (true, e))?;
+  let json_resp: Value = serde_json::from_str(body_text.as_str()).map_err(|e| (true, format!("[RPC ERROR] {}: {}", e, body_text)))?;
+  if !json_resp["error"].is_null() {
+    return Err((!is_revert(&json_resp["error"]), format!("[RPC ERROR] {} {}: {}", host(rpc_url), method, json_resp["error"])));
+  }
+  return Ok(json_resp["result"].clone());
+}
+
+// url 里可能带 api key, 日志, 错误和统计只用 host
+fn host(rpc_url: &str) -> String {
+  return url::Url::parse(rpc_url).ok().and_then(|u| u.host_str().map(String::from)).unwrap_or_default();
+}
+
+// 距离上次检查超过 HEAD_CHECK_SECS 时读取块高, 返回块高停止增长的秒数, 没有停止时为 None
+async fn check_head(rpc_url: &str) -> Result<Option<i64>, (bool, String)> {
+  let now = chrono::Utc::now().timestamp();
+  let due = HEADS.lock().unwrap().as_ref().and_then(|h| h.get(rpc_url)).map(|h| now - h.checked_at >= HEAD_CHECK_SECS).unwrap_or(true);
+  if due {
+    let result = request_one(rpc_url, "eth_blockNumber", &json!([])).await?;
+    let block = quantity(&result).map_err(|e| (true, e))?;
+    let mut guard = HEADS.lock().unwrap();
+    let head = guard.get_or_insert_with(HashMap::new).entry(String::from(rpc_url)).or_insert(Head { block, changed_at: now, checked_at: now });
+    if block != head.block {
+      head.block = block;
+      head.changed_at = now;
+    }
+    head.checked_at = now;
+  }
+  let guard = HEADS.lock().unwrap();
+  match guard.as_ref().and_then(|h| h.get(rpc_url)) {
+    Some(head) if now - head.changed_at > HEAD_STALL_SECS => Ok(Some(now - head.changed_at)),
+    _ => Ok(None)
+  }
+}
+
+// 请求一个节点并按 host 统计耗时
+async fn timed_request(rpc_url: &str, method: &str, params: &Value) -> Result<Value, (bool, String)> {
+  let started = std::time::Instant::now();
+  let res = request_one(rpc_url, method, params).await;
+  crate::metrics::request(&host(rpc_url), method, started.elapsed().as_secs_f64(), res.as_ref().map(|_| ()).map_err(|(_, e)| e.as_str()));
+  return res;
+}
+
+// 以太坊 JSON-RPC, 返回 result 字段, 节点返回 error 时转成 Err, 节点不可用, 返回节点错误或者块高停止增长时依次尝试下一个
+pub async fn request(urls: &[String], method: &str, params: Value) -> Result<Value, String> {
+  if urls.is_empty() {
+    return Err(format!("[RPC ERROR] no rpc url for {}", method));
+  }
+  let start = preferred(urls);
+  let mut last_err = String::new();
+  // (停止增长的秒数, 节点下标)
+  let mut stalled: Vec<(i64, usize)> = Vec::new();
+  for i in 0..urls.len() {
+    let index = (start + i) % urls.len();
+    let res = match check_head(&urls[index]).await {
+      Ok(None) => timed_request(&urls[index], method, &params).await,
+      Ok(Some(secs)) => {
+        log::warn!("rpc {} head not advanced for {}s, try next", host(&urls[index]), secs);
+        stalled.push((secs, index));
+        continue;
+      }
+      Err(err) => Err(err)
+    };
+    match res {
+      Ok(result) => {
+        if index != start {
+          set_preferred(urls, index);
+        }
+        return Ok(result);
+      }
+      Err((true, err)) => {
+        log::warn!("rpc {} unavailable, try next: {}", host(&urls[index]), err);
+        last_err = err;
+      }
+      Err((false, err)) => return Err(err)
+    }
+  }
+  // 其他节点都不可用, 使用停止时间最短的节点, 不让监控读不到数据
+  if let Some((_, index)) = stalled.into_iter().min() {
+    return timed_request(&urls[index], method, &params).await.map_err(|(_, err)| err);
+  }
+  return Err(last_err);
+}
+
+// 只读调用合约, 返回 0x 开头的十六进制结果. 有些合约(例如 Maker 的 OSM)只允许白名单地址读取, 需要指定 from
+pub async fn eth_call_from(urls: &[String], from: Option<&str>, to: &str, data: &str) -> Result<String, String> {
+  let mut tx = json!({ "to": to, "data": data });
+  if let Some(from) = from {
+    tx["from"] = json!(from);
+  }
+  let result = request(urls, "eth_call", json!([tx, "latest"])).await?;
+  return result.as_str().map(String::from).ok_or(format!("[RPC ERROR] eth_call result is not a string: {}", result));
+}
+
+// 编码 calldata, eth_call, 再把结果切成 word
+pub async fn call(urls: &[String], to: &str, signature: &str, args: &[[u8; 32]]) -> Result<Vec<[u8; 32]>, String> {
+  return call_from_n(urls, None, to, signature, args, 1).await;
+}
+
+// 返回值少于 min 个 word 时返回 Err, 调用方可以直接按下标读取前 min 个
+pub async fn call_n(urls: &[String], to: &str, signature: &str, args: &[[u8; 32]], min: usize) -> Result<Vec<[u8; 32]>, String> {
+  return call_from_n(urls, None, to, signature, args, min).await;
+}
+
+pub async fn call_from_n(urls: &[String], from: Option<&str>, to: &str, signature: &str, args: &[[u8; 32]], min: usize) -> Result<Vec<[u8; 32]>, String> {
+  let data = super::abi::encode_call(signature, args);
+  let result = eth_call_from(urls, from, to, &data).await?;
+  let words = super::abi::decode_words(&result)?;
+  if words.is_empty() {
+    return Err(format!("[RPC ERROR] {} {} returns empty result", to, signature));
+  }
+  if words.len() < min {
+    return Err(format!("[RPC ERROR] {} {} returns {} words, expected {}", to, signature, words.len(), min));
+  }
+  return Ok(words);
+}
+
+// JSON-RPC 的 quantity 是不带前导 0 的十六进制
+pub fn quantity(v: &Value) -> Result<u128, String> {
+  let s = v.as_str().ok_or(format!("[RPC ERROR] quantity is not a string: {}", v))?;
+  return u128::from_str_radix(super::abi::strip_0x(s), 16).map_err(|e| format!("[RPC ERROR] invalid quantity {}: {}", s, e));
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::mock::MockRpc;
+
+  async fn nodes() -> (MockRpc, MockRpc, Vec<String>) {
+    let first = MockRpc::start().await;
+    let second = MockRpc::start().await;
+    let urls = vec![first.url(), second.url()];
+    first.set("eth_chainId", json!("0x1"));
+    second.set("eth_chainId", json!("0x1"));
+    return (first, second, urls);
+  }
+
+  #[tokio::test]
+  async fn node_errors_fail_over_but_reverts_do_not() {
+    let (first, second, urls) = nodes().await;
+    // revert 在每个节点上都一样, 直接返回
+    first.error("eth_call", json!({ "code": 3, "message": "execution reverted" }));
+    let err = request(&urls, "eth_call", json!([{ "to": "0x0000000000000000000000000000000000000001", "data": "0x" }, "latest"])).await.unwrap_err();
+    assert!(err.contains("execution reverted"), "{}", err);
+    assert!(second.requests("eth_call").is_empty());
+
+    first.error("eth_chainId", json!({ "code": -32005, "message": "daily request count exceeded, request rate limited" }));
+    assert_eq!(request(&urls, "eth_chainId", json!([])).await, Ok(json!("0x1")));
+    assert_eq!(second.requests("eth_chainId").len(), 1);
+    // 之后优先使用成功的节点
+    assert_eq!(request(&urls, "eth_chainId", json!([])).await, Ok(json!("0x1")));
+    assert_eq!(first.requests("eth_chainId").len(), 1);
+  }
+
+  #[tokio::test]
+  async fn stalled_head_fails_over() {
+    let (first, second, urls) = nodes().await;
+    first.set("eth_blockNumber", json!("0x10"));
+    assert_eq!(request(&urls, "eth_chainId", json!([])).await, Ok(json!("0x1")));
+    assert_eq!(first.requests("eth_chainId").len(), 1);
+
+    // 块高一直是 0x10, 上次变化在 HEAD_STALL_SECS 之前
+    let now = chrono::Utc::now().timestamp();
+    let stall = |url: &str, secs: i64| if let Some(head) = HEADS.lock().unwrap().as_mut().and_then(|h| h.get_mut(url)) {
+      head.changed_at = now - secs;
+      head.checked_at = now - HEAD_CHECK_SECS;
+    };
+    stall(&first.url(), HEAD_STALL_SECS + 1);
+    assert_eq!(request(&urls, "eth_chainId", json!([])).await, Ok(json!("0x1")));
+    assert_eq!(first.requests("eth_chainId").len(), 1);
+    assert_eq!(second.requests("eth_chainId").len(), 1);
+
+    // 块高恢复增长后可以重新使用
+    first.set("eth_blockNumber", json!("0x11"));
+    stall(&first.url(), 0);
+    assert_eq!(check_head(&first.url()).await, Ok(None));
+  }
+
+  // 空闲时不出块的本地开发链只有一个节点, 块高不变也要返回数据
+  #[tokio::test]
+  async fn all_stalled_uses_least_stale() {
+    let (first, second, urls) = nodes().await;
+    assert_eq!(request(&urls, "eth_chainId", json!([])).await, Ok(json!("0x1")));
+    let now = chrono::Utc::now().timestamp();
+    for (url, secs) in [(first.url(), HEAD_STALL_SECS + 600), (second.url(), HEAD_STALL_SECS + 10)] {
+      if let Some(head) = HEADS.lock().unwrap().as_mut().and_then(|h| h.get_mut(&url)) {
+        head.changed_at = now - secs;
+        head.checked_at = now;
+      }
+    }
+    assert_eq!(request(&urls, "eth_chainId", json!([])).await, Ok(json!("0x1")));
+    assert_eq!(second.requests("eth_chainId").len(), 1);
+    assert_eq!(request(&urls[..1], "eth_chainId", json!([])).await, Ok(json!("0x1")));
+    assert_eq!(first.requests("eth_chainId").len(), 2);
+  }
+}
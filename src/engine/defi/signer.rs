@@ -0,0 +1,79 @@
+use k256::ecdsa::SigningKey;
+use super::abi::{ keccak256, strip_0x };
+
+// 本地私钥签名, 私钥从环境变量读取, 不写入配置文件
+pub struct LocalSigner {
+  key: SigningKey,
+  pub address: String
+}
+
+impl LocalSigner {
+  pub fn from_hex(hex_key: &str) -> Result<LocalSigner, String> {
+    let bytes = hex::decode(strip_0x(hex_key.trim())).map_err(|e| format!("invalid private key: {}", e))?;
+    let key = SigningKey::from_slice(&bytes).map_err(|e| format!("invalid private key: {}", e))?;
+    let point = key.verifying_key().to_encoded_point(false);
+    // 地址是非压缩公钥(去掉 0x04 前缀)的 keccak256 的后 20 字节
+    let hash = keccak256(&point.as_bytes()[1..]);
+    let address = format!("0x{}", hex::encode(&hash[12..]));
+    return Ok(LocalSigner { key, address });
+  }
+
+  pub fn from_env(var: &str) -> Result<LocalSigner, String> {
+    let hex_key = std::env::var(var).map_err(|_| format!("private key env {} is not set", var))?;
+    return LocalSigner::from_hex(&hex_key);
+  }
+
+  // 返回 (y parity, r, s)
+  pub fn sign_hash(&self, hash: &[u8; 32]) -> Result<(u8, [u8; 32], [u8; 32]), String> {
+    let (signature, recovery_id) = self.key.sign_prehash_recoverable(hash).map_err(|e| format!("sign error: {}", e))?;
+    let (r, s) = signature.split_bytes();
+    let mut r_bytes = [0_u8; 32];
+    let mut s_bytes = [0_u8; 32];
+    r_bytes.copy_from_slice(&r);
+    s_bytes.copy_from_slice(&s);
+    return Ok((recovery_id.is_y_odd() as u8, r_bytes, s_bytes));
+  }
+}
+
+impl std::fmt::Debug for LocalSigner {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "LocalSigner({})", self.address)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::rlp;
+
+  #[test]
+  fn address_from_private_key() {
+    let signer = LocalSigner::from_hex("0x0000000000000000000000000000000000000000000000000000000000000001").unwrap();
+    assert_eq!(signer.address, "0x7e5f4552091a69125d5dfcb7b8c2659029395bdf");
+    // web3.js 文档里的例子
+    let signer = LocalSigner::from_hex("4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318").unwrap();
+    assert_eq!(signer.address, "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23");
+    assert!(LocalSigner::from_hex("0x1234").is_err());
+  }
+
+  // EIP-155 规范里的例子: 签名的 RLP, 签名哈希, v/r/s 和签名后的交易
+  #[test]
+  fn eip155_example() {
+    let signer = LocalSigner::from_hex("0x4646464646464646464646464646464646464646464646464646464646464646").unwrap();
+    let to = [0x35_u8; 20];
+    let fields = |v: rlp::Item, r: rlp::Item, s: rlp::Item| rlp::Item::List(vec![
+      rlp::uint(9), rlp::uint(20_000_000_000), rlp::uint(21000), rlp::bytes(&to), rlp::uint(1_000_000_000_000_000_000), rlp::bytes(&[]), v, r, s
+    ]);
+    let signing_data = rlp::encode(&fields(rlp::uint(1), rlp::uint(0), rlp::uint(0)));
+    assert_eq!(hex::encode(&signing_data), "ec098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a764000080018080");
+    let hash = keccak256(&signing_data);
+    assert_eq!(hex::encode(hash), "daf5a779ae972f972197303d7b574746c7ef83eadac0f2791ad23db92e4c8e53");
+    let (parity, r, s) = signer.sign_hash(&hash).unwrap();
+    // v = chain_id * 2 + 35 + parity = 37
+    assert_eq!(parity, 0);
+    assert_eq!(hex::encode(r), "28ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276");
+    assert_eq!(hex::encode(s), "67cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83");
+    let raw = rlp::encode(&fields(rlp::uint(37), rlp::bytes(&r), rlp::bytes(&s)));
+    assert_eq!(hex::encode(raw), "f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83");
+  }
+}
@@ -0,0 +1,423 @@
+use std::time;
+use serde::{Deserialize, Serialize};
+use serde_json::{ json, Value };
+use tokio::sync::Mutex;
+use super::{ abi, rlp, rpc };
+use super::signer::LocalSigner;
+use super::chain::ChainConfig;
+use crate::engine::guard;
+use crate::notify::{ self, Severity };
+
+static GWEI: f64 = 1e9_f64;
+// 等待回执时的轮询间隔
+static RECEIPT_POLL_INTERVAL: u64 = 3_u64;
+
+fn default_key_env() -> String { String::from("MONITOR_ETH_KEY") }
+fn default_receipt_timeout() -> u64 { 300_u64 }
+fn default_stuck_timeout() -> u64 { 60_u64 }
+fn default_fee_bump_percent() -> u128 { 25_u128 }
+fn default_max_gas_budget() -> f64 { 0.05_f64 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WalletConfig {
+  pub chain: String,
+  #[serde(default = "default_key_env")]
+  pub key_env: String, // 私钥所在的环境变量
+  pub max_fee_gwei: f64, // maxFeePerGas 上限
+  pub priority_fee_gwei: f64, // 节点不支持 eth_maxPriorityFeePerGas 时使用
+  #[serde(default = "default_receipt_timeout")]
+  pub receipt_timeout: u64,
+  #[serde(default = "default_stuck_timeout")]
+  pub stuck_timeout: u64, // 超过这个时间没有打包就提高手续费重发
+  #[serde(default = "default_fee_bump_percent")]
+  pub fee_bump_percent: u128, // 每次重发提高的比例, 节点一般要求至少 10%
+  #[serde(default = "default_max_gas_budget")]
+  pub max_gas_budget: f64 // 一次操作(包括所有重发)愿意付出的最大手续费, 以原生代币计
+}
+
+// EIP-1559 交易
+#[derive(Debug, Clone)]
+pub struct Eip1559Tx {
+  pub chain_id: u64,
+  pub nonce: u64,
+  pub max_priority_fee: u128,
+  pub max_fee: u128,
+  pub gas_limit: u128,
+  pub to: [u8; 20],
+  pub value: u128,
+  pub data: Vec<u8>
+}
+
+impl Eip1559Tx {
+  fn fields(&self) -> Vec<rlp::Item> {
+    vec![
+      rlp::uint(self.chain_id as u128),
+      rlp::uint(self.nonce as u128),
+      rlp::uint(self.max_priority_fee),
+      rlp::uint(self.max_fee),
+      rlp::uint(self.gas_limit),
+      rlp::bytes(&self.to),
+      rlp::uint(self.value),
+      rlp::bytes(&self.data),
+      rlp::Item::List(vec![]) // access list
+    ]
+  }
+
+  pub fn signing_hash(&self) -> [u8; 32] {
+    let mut payload = vec![0x02_u8];
+    payload.extend(rlp::encode(&rlp::Item::List(self.fields())));
+    return abi::keccak256(&payload);
+  }
+
+  pub fn encode_signed(&self, signer: &LocalSigner) -> Result<Vec<u8>, String> {
+    let (y_parity, r, s) = signer.sign_hash(&self.signing_hash())?;
+    let mut fields = self.fields();
+    fields.push(rlp::uint(y_parity as u128));
+    fields.push(rlp::bytes(strip_zeros(&r)));
+    fields.push(rlp::bytes(strip_zeros(&s)));
+    let mut raw = vec![0x02_u8];
+    raw.extend(rlp::encode(&rlp::Item::List(fields)));
+    return Ok(raw);
+  }
+}
+
+fn strip_zeros(bytes: &[u8]) -> &[u8] {
+  let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+  &bytes[start..]
+}
+
+pub fn parse_address(address: &str) -> Result<[u8; 20], String> {
+  let word = abi::encode_address(address)?;
+  let mut out = [0_u8; 20];
+  out.copy_from_slice(&word[12..]);
+  return Ok(out);
+}
+
+// 已经发出还没确认的交易, 同一个 nonce 的所有重发都记在 hashes 里, 任何一个被打包都算成功
+#[derive(Debug, Clone)]
+pub struct PendingTx {
+  pub action: String,
+  pub tx: Eip1559Tx,
+  pub hashes: Vec<String>,
+  pub sent_at: time::Instant,
+  pub gas_budget_used: f64 // 每次广播的最大手续费 maxFee * gasLimit 累加, 不超过 max_gas_budget
+}
+
+// 这笔交易最多付出的手续费, 以原生代币计
+fn max_gas_cost(tx: &Eip1559Tx) -> f64 {
+  (tx.max_fee * tx.gas_limit) as f64 / 1e18_f64
+}
+
+// 一个链上的发送账户, 本地维护 nonce, 连续发送时不用每次查询
+pub struct Wallet {
+  pub cfg: WalletConfig,
+  pub chain: ChainConfig,
+  pub signer: LocalSigner,
+  nonce: Mutex<Option<u64>>,
+  pending: Mutex<Vec<PendingTx>>
+}
+
+impl Wallet {
+  pub fn new(cfg: WalletConfig, chain: ChainConfig) -> Result<Wallet, String> {
+    let signer = LocalSigner::from_env(&cfg.key_env)?;
+    return Ok(Wallet { cfg, chain, signer, nonce: Mutex::new(None), pending: Mutex::new(Vec::new()) });
+  }
+
+  pub fn address(&self) -> &str {
+    &self.signer.address
+  }
+
+  // 重新加载配置后替换旧的 wallet, 同一个地址时沿用 nonce 和还没有打包的交易
+  pub async fn inherit(&self, old: &Wallet) {
+    if old.address() != self.address() || old.chain.name != self.chain.name {
+      return;
+    }
+    *self.nonce.lock().await = *old.nonce.lock().await;
+    *self.pending.lock().await = old.pending.lock().await.clone();
+  }
+
+  async fn next_nonce(&self) -> Result<u64, String> {
+    let mut nonce = self.nonce.lock().await;
+    let next = match *nonce {
+      Some(n) => n,
+      None => rpc::quantity(&rpc::request(&self.chain.rpc_urls, "eth_getTransactionCount", json!([self.address(), "pending"])).await?)? as u64
+    };
+    *nonce = Some(next + 1);
+    return Ok(next);
+  }
+
+  // 发送失败后 nonce 可能没有被使用, 下次重新从节点读取
+  async fn reset_nonce(&self) {
+    *self.nonce.lock().await = None;
+  }
+
+  // 返回 (maxFeePerGas, maxPriorityFeePerGas), maxFee 取 2 倍 baseFee 加小费, 不超过上限
+  pub async fn fees(&self) -> Result<(u128, u128), String> {
+    let block = rpc::request(&self.chain.rpc_urls, "eth_getBlockByNumber", json!(["latest", false])).await?;
+    let base_fee = rpc::quantity(&block["baseFeePerGas"])?;
+    let tip = match rpc::request(&self.chain.rpc_urls, "eth_maxPriorityFeePerGas", json!([])).await {
+      Ok(v) => rpc::quantity(&v)?,
+      Err(_) => (self.cfg.priority_fee_gwei * GWEI) as u128
+    };
+    let ceiling = (self.cfg.max_fee_gwei * GWEI) as u128;
+    if base_fee >= ceiling {
+      return Err(format!("base fee {} gwei is over ceiling {} gwei", base_fee as f64 / GWEI, self.cfg.max_fee_gwei));
+    }
+    let max_fee = std::cmp::min(base_fee * 2 + tip, ceiling);
+    return Ok((max_fee, std::cmp::min(tip, max_fee - base_fee)));
+  }
+
+  pub async fn estimate_gas(&self, to: &str, data: &str) -> Result<u128, String> {
+    let gas = rpc::quantity(&rpc::request(&self.chain.rpc_urls, "eth_estimateGas", json!([{ "from": self.address(), "to": to, "data": data }])).await?)?;
+    // 多留 20% 余量
+    return Ok(gas * 12 / 10);
+  }
+
+  // 先检查手续费预算再过 guard, dry run 时只估算 gas 和手续费, 返回 None
+  pub async fn send(&self, action: &str, amount: f64, to: &str, data: &str) -> Result<Option<String>, String> {
+    let gas_limit = self.estimate_gas(to, data).await?;
+    let (max_fee, max_priority_fee) = self.fees().await?;
+    let mut tx = Eip1559Tx {
+      chain_id: self.chain.chain_id,
+      nonce: 0,
+      max_priority_fee,
+      max_fee,
+      gas_limit,
+      to: parse_address(to)?,
+      value: 0,
+      data: hex::decode(abi::strip_0x(data)).map_err(|e| format!("invalid calldata: {}", e))?
+    };
+    let cost = max_gas_cost(&tx);
+    if cost > self.cfg.max_gas_budget {
+      return Err(format!("{} may cost up to {} {} gas, over budget {}", action, cost, self.chain.native_token, self.cfg.max_gas_budget));
+    }
+    if !guard::current().check(action, amount)? {
+      log::info!("[DRY RUN] {} -> {} gas {} max fee {} gwei data {}", self.address(), to, gas_limit, max_fee as f64 / GWEI, data);
+      return Ok(None);
+    }
+    tx.nonce = self.next_nonce().await?;
+    match self.broadcast(&tx).await {
+      Ok(hash) => {
+        log::info!("{} sent tx {} nonce {} max fee {} gwei", action, hash, tx.nonce, max_fee as f64 / GWEI);
+        self.pending.lock().await.push(PendingTx {
+          action: String::from(action),
+          tx,
+          hashes: vec![hash.clone()],
+          sent_at: time::Instant::now(),
+          gas_budget_used: cost
+        });
+        return Ok(Some(hash));
+      }
+      Err(err) => {
+        self.reset_nonce().await;
+        return Err(err);
+      }
+    }
+  }
+
+  async fn broadcast(&self, tx: &Eip1559Tx) -> Result<String, String> {
+    let raw = tx.encode_signed(&self.signer)?;
+    let hash = rpc::request(&self.chain.rpc_urls, "eth_sendRawTransaction", json!([format!("0x{}", hex::encode(raw))])).await?;
+    return Ok(String::from(hash.as_str().unwrap_or("")));
+  }
+
+  // 用同一个 nonce 提高手续费重发, 新的 maxFee 取 旧值提高 fee_bump_percent 和当前估算 两者中的较大者,
+  // 之前所有广播加上这次的最大手续费超出 max_gas_budget 时不再重发, 返回 None
+  async fn replace(&self, pending: &PendingTx) -> Result<Option<PendingTx>, String> {
+    let mut tx = pending.tx.clone();
+    let bump = |v: u128| v * (100 + self.cfg.fee_bump_percent) / 100;
+    let (max_fee, tip) = match self.fees().await {
+      Ok((max_fee, tip)) => (std::cmp::max(bump(tx.max_fee), max_fee), std::cmp::max(bump(tx.max_priority_fee), tip)),
+      Err(_) => (bump(tx.max_fee), bump(tx.max_priority_fee))
+    };
+    tx.max_fee = max_fee;
+    let used = pending.gas_budget_used + max_gas_cost(&tx);
+    if used > self.cfg.max_gas_budget {
+      notify::notify(Severity::WARNING, &self.chain.name, "stuck tx over gas budget",
+        &format!("{} tx nonce {} stuck, replacement would bring gas budget used to {} {} over budget {}", pending.action, tx.nonce, used, self.chain.native_token, self.cfg.max_gas_budget)).await;
+      return Ok(None);
+    }
+    tx.max_priority_fee = std::cmp::min(tip, max_fee);
+    let hash = self.broadcast(&tx).await?;
+    notify::notify(Severity::ACTION, &self.chain.name, "replaced stuck tx",
+      &format!("{} tx nonce {} not mined after {}s, replaced {} with {} at max fee {} gwei",
+        pending.action, tx.nonce, pending.sent_at.elapsed().as_secs(), pending.hashes.last().map(|h| h.as_str()).unwrap_or(""), hash, max_fee as f64 / GWEI)).await;
+    let mut hashes = pending.hashes.clone();
+    hashes.push(hash);
+    return Ok(Some(PendingTx { action: pending.action.clone(), tx, hashes, sent_at: time::Instant::now(), gas_budget_used: used }));
+  }
+
+  async fn find_receipt(&self, hashes: &[String]) -> Result<Option<(String, Value)>, String> {
+    for hash in hashes.iter() {
+      let receipt = rpc::request(&self.chain.rpc_urls, "eth_getTransactionReceipt", json!([hash])).await?;
+      if !receipt.is_null() {
+        return Ok(Some((hash.clone(), receipt)));
+      }
+    }
+    return Ok(None);
+  }
+
+  async fn update_pending(&self, nonce: u64, updated: Option<PendingTx>) {
+    let mut pending = self.pending.lock().await;
+    pending.retain(|p| p.tx.nonce != nonce);
+    if let Some(p) = updated {
+      pending.push(p);
+    }
+  }
+
+  // 等待交易被打包并达到 guard 要求的确认数, 超过 stuck_timeout 没有打包就加价重发,
+  // 返回实际被打包的 hash, 交易失败(status 0)返回错误
+  pub async fn wait_receipt(&self, hash: &str) -> Result<String, String> {
+    let started = time::Instant::now();
+    let mut current = self.pending.lock().await.iter().find(|p| p.hashes.iter().any(|h| h == hash)).cloned();
+    let mut hashes = vec![String::from(hash)];
+    let mut can_replace = current.is_some();
+    loop {
+      if let Some(p) = &current {
+        hashes = p.hashes.clone();
+      }
+      if let Some((mined_hash, receipt)) = self.find_receipt(&hashes).await? {
+        if receipt["status"] != "0x1" {
+          if let Some(p) = &current {
+            self.update_pending(p.tx.nonce, None).await;
+          }
+          return Err(format!("tx {} reverted", mined_hash));
+        }
+        let mined = rpc::quantity(&receipt["blockNumber"])?;
+        let latest = rpc::quantity(&rpc::request(&self.chain.rpc_urls, "eth_blockNumber", json!([])).await?)?;
+        if latest + 1 >= mined + guard::current().confirmations as u128 {
+          if let Some(p) = &current {
+            self.update_pending(p.tx.nonce, None).await;
+          }
+          return Ok(mined_hash);
+        }
+      } else if let Some(p) = current.clone() {
+        if can_replace && p.sent_at.elapsed().as_secs() > self.cfg.stuck_timeout {
+          match self.replace(&p).await {
+            Ok(Some(replaced)) => {
+              self.update_pending(p.tx.nonce, Some(replaced.clone())).await;
+              current = Some(replaced);
+            }
+            Ok(None) => can_replace = false,
+            // nonce too low 说明之前的某一笔已经被打包, 继续等回执
+            Err(err) => log::warn!("{} replace tx nonce {} error: {}", p.action, p.tx.nonce, err)
+          }
+        }
+      }
+      if started.elapsed().as_secs() > self.cfg.receipt_timeout {
+        return Err(format!("tx {:?} not confirmed after {}s", hashes, self.cfg.receipt_timeout));
+      }
+      tokio::time::sleep(time::Duration::from_secs(RECEIPT_POLL_INTERVAL)).await;
+    }
+  }
+
+  pub async fn send_and_confirm(&self, action: &str, amount: f64, to: &str, data: &str) -> Result<Option<String>, String> {
+    let hash = match self.send(action, amount, to, data).await? {
+      Some(hash) => hash,
+      None => return Ok(None)
+    };
+    let mined_hash = self.wait_receipt(&hash).await?;
+    notify::notify(Severity::ACTION, &self.chain.name, action, &format!("confirmed: {}", mined_hash)).await;
+    return Ok(Some(mined_hash));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use k256::ecdsa::{ RecoveryId, Signature, VerifyingKey };
+  use super::super::mock::MockRpc;
+
+  static TO: &str = "0x3535353535353535353535353535353535353535";
+
+  fn wallet(rpc: &MockRpc, max_gas_budget: f64) -> Wallet {
+    let cfg = WalletConfig {
+      chain: String::from("ethereum"),
+      key_env: default_key_env(),
+      max_fee_gwei: 500_f64,
+      priority_fee_gwei: 1_f64,
+      receipt_timeout: default_receipt_timeout(),
+      stuck_timeout: default_stuck_timeout(),
+      fee_bump_percent: default_fee_bump_percent(),
+      max_gas_budget
+    };
+    let signer = LocalSigner::from_hex("0x4646464646464646464646464646464646464646464646464646464646464646").unwrap();
+    return Wallet { cfg, chain: rpc.chain("ethereum"), signer, nonce: Mutex::new(None), pending: Mutex::new(Vec::new()) };
+  }
+
+  // baseFee 10 gwei, 小费 1 gwei, gas 估算 100000(加 20% 是 120000)
+  fn mock_fees(rpc: &MockRpc) {
+    rpc.set("eth_estimateGas", json!("0x186a0"));
+    rpc.set("eth_getBlockByNumber", json!({ "baseFeePerGas": "0x2540be400" }));
+    rpc.set("eth_maxPriorityFeePerGas", json!("0x3b9aca00"));
+    rpc.set("eth_getTransactionCount", json!("0x7"));
+    rpc.set("eth_sendRawTransaction", json!("0x01"));
+  }
+
+  #[tokio::test]
+  async fn inherit_keeps_nonce_of_same_address() {
+    let rpc = MockRpc::start().await;
+    let old = wallet(&rpc, 1_f64);
+    *old.nonce.lock().await = Some(7);
+    let new = wallet(&rpc, 2_f64);
+    new.inherit(&old).await;
+    assert_eq!(*new.nonce.lock().await, Some(7));
+    // 换了私钥的 wallet 重新从节点读取 nonce
+    let mut other = wallet(&rpc, 2_f64);
+    other.signer = LocalSigner::from_hex("0x0101010101010101010101010101010101010101010101010101010101010101").unwrap();
+    other.inherit(&old).await;
+    assert_eq!(*other.nonce.lock().await, None);
+  }
+
+  #[tokio::test]
+  async fn first_send_checks_gas_budget() {
+    let rpc = MockRpc::start().await;
+    mock_fees(&rpc);
+    // maxFee = 2 * 10 + 1 = 21 gwei, 最多 21 gwei * 120000 = 0.00252
+    let err = wallet(&rpc, 0.002).send("test", 1_f64, TO, "0x").await.unwrap_err();
+    assert!(err.contains("over budget 0.002"), "{}", err);
+    assert!(rpc.requests("eth_sendRawTransaction").is_empty());
+    // 预算够时走到 guard, 测试里是 dry run
+    assert_eq!(wallet(&rpc, 0.003).send("test", 1_f64, TO, "0x").await, Ok(None));
+  }
+
+  #[tokio::test]
+  async fn replacements_share_gas_budget() {
+    let rpc = MockRpc::start().await;
+    mock_fees(&rpc);
+    let wallet = wallet(&rpc, 0.006);
+    let tx = Eip1559Tx { chain_id: 1, nonce: 7, max_priority_fee: 1_000_000_000, max_fee: 21_000_000_000, gas_limit: 120000, to: [0x35; 20], value: 0, data: vec![] };
+    let pending = PendingTx { action: String::from("test"), hashes: vec![String::from("0x00")], sent_at: time::Instant::now(), gas_budget_used: max_gas_cost(&tx), tx };
+    // 提高 25% 后 maxFee 26.25 gwei, 累计 0.00252 + 0.00315 = 0.00567
+    let replaced = wallet.replace(&pending).await.unwrap().unwrap();
+    assert_eq!(replaced.tx.nonce, 7);
+    assert_eq!(replaced.tx.max_fee, 26_250_000_000);
+    assert!((replaced.gas_budget_used - 0.00567).abs() < 1e-12);
+    assert_eq!(replaced.hashes.len(), 2);
+    // 再提高一次单笔只要 0.0039, 但累计超出预算, 不再重发
+    assert!(wallet.replace(&replaced).await.unwrap().is_none());
+    assert_eq!(rpc.requests("eth_sendRawTransaction").len(), 1);
+  }
+
+  // EIP-1559 的编码: 0x02 || rlp([chainId, nonce, maxPriorityFeePerGas, maxFeePerGas, gasLimit, to, value, data, accessList]),
+  // 签名后在列表末尾追加 yParity, r, s. RLP 和签名本身由 rlp/signer 里的规范例子覆盖, 这里按规范手工拼出期望的字节
+  #[test]
+  fn eip1559_signing_payload_and_raw_tx() {
+    let signer = LocalSigner::from_hex("0x4646464646464646464646464646464646464646464646464646464646464646").unwrap();
+    let tx = Eip1559Tx { chain_id: 1, nonce: 0, max_priority_fee: 1_000_000_000, max_fee: 2_000_000_000, gas_limit: 21000, to: [0x35; 20], value: 0, data: vec![] };
+    let fields = format!("01{}{}{}{}94{}{}{}{}", "80", "843b9aca00", "8477359400", "825208", "35".repeat(20), "80", "80", "c0");
+    let unsigned = hex::decode(format!("02e7{}", fields)).unwrap();
+    let hash = tx.signing_hash();
+    assert_eq!(hash, abi::keccak256(&unsigned));
+
+    let raw = tx.encode_signed(&signer).unwrap();
+    let (parity, r, s) = signer.sign_hash(&hash).unwrap();
+    let signed = format!("{}0{}a0{}a0{}", fields, parity, hex::encode(r), hex::encode(s));
+    assert_eq!(hex::encode(&raw), format!("02f8{:02x}{}", signed.len() / 2, signed));
+    // 从签名恢复出的地址就是发送地址
+    let signature = Signature::from_scalars(r, s).unwrap();
+    let key = VerifyingKey::recover_from_prehash(&hash, &signature, RecoveryId::from_byte(parity).unwrap()).unwrap();
+    let recovered = abi::keccak256(&key.to_encoded_point(false).as_bytes()[1..]);
+    assert_eq!(format!("0x{}", hex::encode(&recovered[12..])), signer.address);
+  }
+}
@@ -3,29 +3,58 @@ pub mod config;
 pub mod binance;
 pub mod huobi;
 pub mod okex;
-use types::{ Exchanges, AccountInfo, OrderInfo, OrderSide, DepthInfo, LoanInfo };
+pub mod catalog;
+#[cfg(test)]
+pub mod mock;
+use serde::{Deserialize, Serialize};
+use crate::engine::position::LoanPosition;
+use crate::engine::journal::ActionJournal;
+use types::{ Exchanges, AccountInfo, OrderInfo, OrderSide, DepthInfo, TradeInfo, LoanInfo, NetworkInfo, Balances, AccountType, ApiPermissions, SubAccount };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Exchange {
   pub name: Exchanges, // 交易所的名称标识
   pub symbol: String, //对应的合约标的,  BTC, ETH ...
   pub currency: String, //合约本位货币
   pub host: String, // api host
   pub protocol: String,
-  pub config: String // 配置名称
+  pub config: String, // 配置名称
+  #[serde(default)]
+  pub protect: bool, // 是否用于保护操作(补充保证金, 还款, 调仓), false 表示只监控, 启动时据此检查 API key 权限
+  #[serde(default)]
+  pub subaccount: Option<SubAccount> // 仓位在子账户时配置
 }
 
 impl Exchange {
+  // okx 的借币信息接口已经下线
+  pub fn has_loan_info(&self) -> bool {
+    return !matches!(self.name, Exchanges::OKEX);
+  }
+
   pub async fn depth(&self) -> Result<DepthInfo, String> {
     match self.name {
       Exchanges::HUOBI => {
-        return Err(format!("{:?}::depth not_implemented", self.name));
+        return huobi::depth(self).await;
       }
       Exchanges::BINANCE => {
         return binance::depth(self).await;
       }
       Exchanges::OKEX => {
-        return Err(format!("{:?}::depth not_implemented", self.name));
+        return okex::depth(self).await;
+      }
+    }
+  }
+
+  pub async fn trades(&self) -> Result<Vec<TradeInfo>, String> {
+    match self.name {
+      Exchanges::HUOBI => {
+        return huobi::trades(self).await;
+      }
+      Exchanges::BINANCE => {
+        return binance::trades(self).await;
+      }
+      Exchanges::OKEX => {
+        return okex::trades(self).await;
       }
     }
   }
@@ -43,16 +72,29 @@ impl Exchange {
     }
   }
 
+  // 所有账户类型下的全部余额
+  pub async fn balances(&self) -> Result<Balances, String> {
+    let items = match self.name {
+      Exchanges::HUOBI => huobi::balances(self).await?,
+      Exchanges::BINANCE => binance::balances(self).await?,
+      Exchanges::OKEX => okex::balances(self).await?,
+    };
+    return Ok(Balances {
+      venue: self.name.clone(),
+      items
+    });
+  }
+
   pub async fn order_info(&self, order_id: String) -> Result<OrderInfo, String> {
     match self.name {
       Exchanges::HUOBI => {
-        return Err(format!("{:?}::order_info not_implemented", self.name));
+        return huobi::order_info(self, order_id).await;
       }
       Exchanges::BINANCE => {
         return binance::order_info(self, order_id).await;
       }
       Exchanges::OKEX => {
-        return Err(format!("{:?}::order_info not_implemented", self.name))
+        return okex::order_info(self, order_id).await;
       }
     }
   }
@@ -60,39 +102,39 @@ impl Exchange {
   pub async fn create_order(&self, side: OrderSide, price: f64, volume: f64) -> Result<String, String> {
     match self.name {
       Exchanges::HUOBI => {
-        return Err(format!("{:?}::create_order not_implemented", self.name));
+        return huobi::create_order(self, side, price, volume).await;
       }
       Exchanges::BINANCE => {
         return binance::create_order(self, side, price, volume).await;
       }
       Exchanges::OKEX => {
-        return Err(format!("{:?}::create_order not_implemented", self.name));
+        return okex::create_order(self, side, price, volume).await;
       }
     }
   }
   pub async fn cancel_order(&self, order_id: String) -> Result<bool, String> {
     match self.name {
       Exchanges::HUOBI => {
-        return Err(format!("{:?}::cancel_order not_implemented", self.name));
+        return huobi::cancel_order(self, order_id).await;
       }
       Exchanges::BINANCE => {
         return binance::cancel_order(self, order_id).await;
       }
       Exchanges::OKEX => {
-        return Err(format!("{:?}::cancel_order not_implemented", self.name));
+        return okex::cancel_order(self, order_id).await;
       }
     }
   }
   pub async fn cancel_all_order(&self) -> Result<bool, String> {
     match self.name {
       Exchanges::HUOBI => {
-        return Err(format!("{:?}::cancel_all_order not_implemented", self.name));
+        return huobi::cancel_all_order(self).await;
       }
       Exchanges::BINANCE => {
         return binance::cancel_all_order(self).await;
       }
       Exchanges::OKEX => {
-        return Err(format!("{:?}::cancel_all_order not_implemented", self.name));
+        return okex::cancel_all_order(self).await;
       }
     }
   }
@@ -110,6 +152,19 @@ impl Exchange {
       }
     }
   }
+  pub async fn position(&self) -> Result<LoanPosition, String> {
+    match self.name {
+      Exchanges::HUOBI => {
+        return huobi::position(self).await;
+      }
+      Exchanges::BINANCE => {
+        return binance::position(self).await;
+      }
+      Exchanges::OKEX => {
+        return okex::position(self).await;
+      }
+    }
+  }
   pub async fn withdraw(&self, asset: String, address: String, amount: f64) -> Result<String, String> {
     match self.name {
       Exchanges::HUOBI => {
@@ -123,4 +178,176 @@ impl Exchange {
       }
     }
   }
+  // amount 是从账户扣除的总数, 包含手续费, 到账 amount - fee, 不能小于 NetworkInfo 的 min_amount;
+  // client_id 为空时不传, 否则可以用 find_withdrawal 查询这笔提币是否已经提交
+  pub async fn withdraw_on_chain(&self, asset: String, network: String, address: String, amount: f64, client_id: String) -> Result<String, String> {
+    match self.name {
+      Exchanges::HUOBI => {
+        return huobi::withdraw_on_chain(self, asset, network, address, amount, client_id).await;
+      }
+      Exchanges::BINANCE => {
+        return binance::withdraw_on_chain(self, asset, network, address, amount, client_id).await;
+      }
+      Exchanges::OKEX => {
+        return okex::withdraw_on_chain(self, asset, network, address, amount, client_id).await;
+      }
+    }
+  }
+  pub async fn find_withdrawal(&self, asset: String, client_id: String) -> Result<Option<String>, String> {
+    match self.name {
+      Exchanges::HUOBI => {
+        return huobi::find_withdrawal(self, asset, client_id).await;
+      }
+      Exchanges::BINANCE => {
+        return binance::find_withdrawal(self, asset, client_id).await;
+      }
+      Exchanges::OKEX => {
+        return okex::find_withdrawal(self, asset, client_id).await;
+      }
+    }
+  }
+  pub async fn deposit_address(&self, asset: String, network: String) -> Result<String, String> {
+    match self.name {
+      Exchanges::HUOBI => {
+        return huobi::deposit_address(self, asset, network).await;
+      }
+      Exchanges::BINANCE => {
+        return binance::deposit_address(self, asset, network).await;
+      }
+      Exchanges::OKEX => {
+        return okex::deposit_address(self, asset, network).await;
+      }
+    }
+  }
+  pub async fn transfer(&self, asset: String, amount: f64, from: AccountType, to: AccountType) -> Result<String, String> {
+    match self.name {
+      Exchanges::HUOBI => {
+        return huobi::transfer(self, asset, amount, from, to).await;
+      }
+      Exchanges::BINANCE => {
+        return binance::transfer(self, asset, amount, from, to).await;
+      }
+      Exchanges::OKEX => {
+        return okex::transfer(self, asset, amount, from, to).await;
+      }
+    }
+  }
+  // 把到账的 currency 转入逐仓杠杆仓位作为保证金, OKEX 充值到资金账户, 需要先转到交易账户,
+  // 只转交易账户不足的部分, 中断后重新执行不会多转; 每次划转是日志里的一步
+  pub async fn top_up(&self, amount: f64, journal: &mut ActionJournal) -> Result<String, String> {
+    let pair = AccountType::ISOLATEDMARGIN(format!("{}{}", self.symbol.to_lowercase(), self.currency.to_lowercase()));
+    if self.name == Exchanges::OKEX {
+      let spot = self.balances().await?.get(&AccountType::SPOT, &self.currency).map(|b| b.free).unwrap_or(0_f64);
+      if spot < amount {
+        journal.step("transfer FUNDING -> SPOT", self.transfer(self.currency.clone(), amount - spot, AccountType::FUNDING, AccountType::SPOT)).await?;
+      }
+    }
+    return journal.step(&format!("transfer SPOT -> {}", pair), self.transfer(self.currency.clone(), amount, AccountType::SPOT, pair.clone())).await;
+  }
+  // 按划转记录查找 since(毫秒)之后数量为 amount 的一笔划转, 用于重启后核对补充保证金是否已经执行,
+  // 只支持现货和逐仓杠杆之间的划转
+  pub async fn find_transfer(&self, asset: String, amount: f64, from: AccountType, to: AccountType, since: i64) -> Result<Option<String>, String> {
+    match self.name {
+      Exchanges::HUOBI => {
+        return huobi::find_transfer(self, asset, amount, from, to, since).await;
+      }
+      Exchanges::BINANCE => {
+        return binance::find_transfer(self, asset, amount, from, to, since).await;
+      }
+      Exchanges::OKEX => {
+        return okex::find_transfer(self, asset, amount, from, to, since).await;
+      }
+    }
+  }
+  // 用逐仓账户里的 currency 还款
+  pub async fn repay(&self, amount: f64) -> Result<String, String> {
+    match self.name {
+      Exchanges::HUOBI => {
+        return huobi::repay(self, amount).await;
+      }
+      Exchanges::BINANCE => {
+        return binance::repay(self, amount).await;
+      }
+      Exchanges::OKEX => {
+        return Err(format!("{:?}::repay not_implemented", self.name));
+      }
+    }
+  }
+  fn sub(&self) -> Result<&SubAccount, String> {
+    self.subaccount.as_ref().ok_or(format!("{} {} is not a subaccount", self.name, self.config))
+  }
+  // 用母账户的 key 查询子账户的余额
+  pub async fn subaccount_balances(&self) -> Result<Balances, String> {
+    let sub = self.sub()?;
+    match self.name {
+      Exchanges::HUOBI => {
+        return Ok(Balances { venue: self.name.clone(), items: huobi::subaccount_balances(self, sub).await? });
+      }
+      Exchanges::BINANCE => {
+        return Ok(Balances { venue: self.name.clone(), items: binance::subaccount_balances(self, sub).await? });
+      }
+      Exchanges::OKEX => {
+        return Ok(Balances { venue: self.name.clone(), items: okex::subaccount_balances(self, sub).await? });
+      }
+    }
+  }
+  // 母账户(Binance, Huobi 现货, OKX 资金账户)和子账户的 account 之间划转, to_sub 为 false 时从子账户转回母账户,
+  // Huobi 只能划转到子账户的现货账户
+  pub async fn subaccount_transfer(&self, asset: String, amount: f64, to_sub: bool, account: AccountType) -> Result<String, String> {
+    let sub = self.sub()?;
+    match self.name {
+      Exchanges::HUOBI => {
+        return huobi::subaccount_transfer(self, sub, asset, amount, to_sub, account).await;
+      }
+      Exchanges::BINANCE => {
+        return binance::subaccount_transfer(self, sub, asset, amount, to_sub, account).await;
+      }
+      Exchanges::OKEX => {
+        return okex::subaccount_transfer(self, sub, asset, amount, to_sub, account).await;
+      }
+    }
+  }
+  // 从母账户给子账户的逐仓仓位补充保证金, Binance 可以直接转入逐仓账户,
+  // OKX 先转到子账户的资金账户, Huobi 先转到子账户的现货账户, 再用子账户的 key 转入仓位;
+  // 每次划转都是 journal 里的一步, 中断后由 journal::interrupted 通知
+  pub async fn refill(&self, amount: f64, journal: &mut ActionJournal) -> Result<String, String> {
+    let sub_account = match self.name {
+      Exchanges::OKEX => Some(AccountType::FUNDING),
+      Exchanges::HUOBI => Some(AccountType::SPOT),
+      Exchanges::BINANCE => None
+    };
+    if let Some(account) = sub_account {
+      let id = journal.step(&format!("master -> sub {}", account), self.subaccount_transfer(self.currency.clone(), amount, true, account.clone())).await?;
+      return Ok(format!("{}, {}", id, self.top_up(amount, journal).await?));
+    }
+    let pair = AccountType::ISOLATEDMARGIN(format!("{}{}", self.symbol.to_lowercase(), self.currency.to_lowercase()));
+    return journal.step(&format!("master -> sub {}", pair), self.subaccount_transfer(self.currency.clone(), amount, true, pair.clone())).await;
+  }
+  pub async fn api_permissions(&self) -> Result<ApiPermissions, String> {
+    match self.name {
+      Exchanges::HUOBI => {
+        return huobi::api_permissions(self).await;
+      }
+      Exchanges::BINANCE => {
+        return binance::api_permissions(self).await;
+      }
+      Exchanges::OKEX => {
+        return okex::api_permissions(self).await;
+      }
+    }
+  }
+  // 直接请求交易所, 一般通过 catalog::networks 使用缓存
+  pub async fn asset_networks(&self, asset: String) -> Result<Vec<NetworkInfo>, String> {
+    match self.name {
+      Exchanges::HUOBI => {
+        return huobi::asset_networks(self, asset).await;
+      }
+      Exchanges::BINANCE => {
+        return binance::asset_networks(self, asset).await;
+      }
+      Exchanges::OKEX => {
+        return okex::asset_networks(self, asset).await;
+      }
+    }
+  }
 }
\ No newline at end of file
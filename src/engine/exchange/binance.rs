@@ -1,13 +1,15 @@
  
 use std::{collections::HashMap};
 use super::config::{ BinanceConfig, BINANCE_USDT_WITHDRAW_CHAIN };
-use super::types::{ Tick, AccountInfo, OrderInfo, OrderStatus, OrderSide, DepthInfo, LoanInfo };
+use super::types::{ Tick, AccountInfo, OrderInfo, OrderStatus, OrderSide, DepthInfo, TradeInfo, LoanInfo, NetworkInfo, AccountType, Balance, ApiPermissions, SubAccount };
+use super::catalog;
+use crate::engine::position::LoanPosition;
 use serde_json::{ Value };
 use sha2::{Sha256};
 use hmac::{Hmac, Mac, NewMac};
 use url::form_urlencoded::Serializer;
 use super::Exchange;
-use crate::util::handle_body;
+use crate::util::{ timed_body, parse_json, value_f64, same_amount, depth_levels };
 
 // Create alias for HMAC-SHA256
 type HmacSha256 = Hmac<Sha256>;
@@ -15,12 +17,19 @@ type HmacSha256 = Hmac<Sha256>;
 // for binance sign
 pub async fn build_binance_sign(cfg: &BinanceConfig, protocol: &str, host: &str, params: Vec<[&str;2]>, body: Vec<[&str;2]>) -> Result<String, String> {
   // get server time
+  let started = std::time::Instant::now();
   let full_url = format!("{}://{}/api/v3/time", protocol, host);
-  let body_resp = reqwest::get(&full_url).await;
-  let body_text = handle_body(body_resp).await?;
-  let json_resp: Value = serde_json::from_str(body_text.as_str()).expect("json parse error");
-  let timestamp_str = json_resp["serverTime"].to_string();
+  let body_resp = reqwest::get(&full_url);
+  let body_text = timed_body("BINANCE", "server_time", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  let timestamp = json_resp["serverTime"].as_i64().ok_or(format!("BINANCE: read serverTime error: {}", json_resp))?;
+  crate::metrics::clock_drift("BINANCE", timestamp, started.elapsed().as_millis() as i64);
+  return Ok(sign_binance(cfg, params, body, timestamp));
+}
 
+// 签名本身不依赖网络, 时间戳由调用方传入, 可以用文档里的例子测试
+pub fn sign_binance(cfg: &BinanceConfig, params: Vec<[&str;2]>, body: Vec<[&str;2]>, timestamp: i64) -> String {
+  let timestamp_str = timestamp.to_string();
   let mut all_params: Vec<[&str;2]> = Vec::new();
   all_params.clone_from(&params);
   if body.len() == 0 {
@@ -48,32 +57,25 @@ pub async fn build_binance_sign(cfg: &BinanceConfig, protocol: &str, host: &str,
   let mut mac = HmacSha256::new_varkey(cfg.secret_key.as_bytes()).expect("HMAC can take key of any size");
   mac.update(full_str.as_bytes());
   let sign_bytes = mac.finalize().into_bytes();
-  let signature_str = hex::encode(sign_bytes.as_slice());
+  let signature_str = hex::encode(sign_bytes);
 
-  return Ok(param_str + "&signature=" + signature_str.as_str());
+  // 参数都在 body 里时 query 只有签名
+  if param_str.is_empty() {
+    return format!("signature={}", signature_str);
+  }
+  return param_str + "&signature=" + signature_str.as_str();
 }
 
 pub async fn depth(ex: &Exchange) -> Result<DepthInfo, String> {
   let full_url = format!("{}://{}/api/v3/depth?symbol={}{}&limit=5", ex.protocol, ex.host, ex.symbol.to_uppercase(), ex.currency.to_uppercase());
-  let body_resp = reqwest::get(full_url.as_str()).await;
-  let body_text = handle_body(body_resp).await?;
-  let json_resp: Value = serde_json::from_str(body_text.as_str()).expect("json parse error");
+  let body_resp = reqwest::get(full_url.as_str());
+  let body_text = timed_body("BINANCE", "depth", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
   if !json_resp["asks"].is_null() {
-    let asks = json_resp["asks"].as_array().expect("no asks");
-    let bids = json_resp["bids"].as_array().expect("no bids");
-    let mut asks_vec: Vec<[f64;2]> = Vec::new();
-    let mut bids_vec: Vec<[f64;2]> = Vec::new();
-    // 取5个深度
-    for i in 0..5 {
-      asks_vec.push([asks[i][0].as_str().expect("asks as_f64 error").parse::<f64>().expect("asks str -> f64 error"),
-      asks[i][1].as_str().expect("asks as_f64 error").parse::<f64>().expect("str -> f64 error")]);
-      bids_vec.push([bids[i][0].as_str().expect("bids as_f64 error").parse::<f64>().expect("bids str -> f64 error"),
-      bids[i][1].as_str().expect("bids as_f64 error").parse::<f64>().expect("bids str -> f64 error")]);
-    }
     let di = DepthInfo {
       tick: Tick {
-        asks: asks_vec,
-        bids: bids_vec
+        asks: depth_levels(&json_resp["asks"])?,
+        bids: depth_levels(&json_resp["bids"])?
       },
       // fake data
       ts: 0_i64,
@@ -84,15 +86,36 @@ pub async fn depth(ex: &Exchange) -> Result<DepthInfo, String> {
   }
 }
 
+// 最近成交, 从旧到新
+pub async fn trades(ex: &Exchange) -> Result<Vec<TradeInfo>, String> {
+  let full_url = format!("{}://{}/api/v3/trades?symbol={}{}&limit=100", ex.protocol, ex.host, ex.symbol.to_uppercase(), ex.currency.to_uppercase());
+  let body_resp = reqwest::get(full_url.as_str());
+  let body_text = timed_body("BINANCE", "trades", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  let list = json_resp.as_array().ok_or(format!("{}", json_resp))?;
+  let mut res: Vec<TradeInfo> = Vec::new();
+  for item in list.iter() {
+    res.push(TradeInfo {
+      id: item["id"].as_u64().ok_or(format!("BINANCE: invalid trade {}", item))?,
+      price: value_f64(&item["price"]),
+      volume: value_f64(&item["qty"]),
+      // isBuyerMaker 为 true 时主动方是卖方
+      buy: !item["isBuyerMaker"].as_bool().unwrap_or(false),
+      ts: item["time"].as_i64().unwrap_or(0)
+    });
+  }
+  return Ok(res);
+}
+
 pub async fn account_info(ex: &Exchange) -> Result<AccountInfo, String> {
-  let cfg: BinanceConfig = confy::load(&ex.config).expect("read binance config error");
+  let cfg = BinanceConfig::load(&ex.config)?;
   let param_str = build_binance_sign(&cfg, &ex.protocol, &ex.host, [].to_vec(), [].to_vec()).await?;
   let full_url = format!("{}://{}/api/v3/account?{}", ex.protocol, ex.host, param_str);
   let client = reqwest::Client::new();
-  let body_resp = client.get(full_url.as_str()).header("X-MBX-APIKEY", cfg.access_id)
-  .send().await;
-  let body_text = handle_body(body_resp).await?;
-  let json_resp: Value = serde_json::from_str(body_text.as_str()).expect("json parse error");
+  let body_resp = client.get(full_url.as_str()).header("X-MBX-APIKEY", cfg.access_id.as_str())
+  .send();
+  let body_text = timed_body("BINANCE", "account_info", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
   if !json_resp["balances"].is_null() {
     let balances = json_resp["balances"].as_array().expect("no balances");
     let symbol_item = balances.into_iter().find(|x| x["asset"] == format!("{}", ex.symbol.to_uppercase())).expect("find symbol item error");
@@ -110,17 +133,17 @@ pub async fn account_info(ex: &Exchange) -> Result<AccountInfo, String> {
 }
 
 pub async fn order_info(ex: &Exchange, order_id: String) -> Result<OrderInfo, String> {
-  let cfg: BinanceConfig = confy::load(&ex.config).expect("read binance config error");
+  let cfg = BinanceConfig::load(&ex.config)?;
   let param_str = build_binance_sign(&cfg, &ex.protocol, &ex.host, [
     ["symbol", &format!("{}{}", ex.symbol.to_uppercase(), ex.currency.to_uppercase())],
     ["orderId", order_id.as_str()]
   ].to_vec(), [].to_vec()).await?;
   let full_url = format!("{}://{}/api/v3/order?{}", ex.protocol, ex.host, param_str);
   let client = reqwest::Client::new();
-  let body_resp = client.get(full_url.as_str()).header("X-MBX-APIKEY", cfg.access_id)
-  .send().await;
-  let body_text = handle_body(body_resp).await?;
-  let obj: Value = serde_json::from_str(body_text.as_str()).expect("json parse error");
+  let body_resp = client.get(full_url.as_str()).header("X-MBX-APIKEY", cfg.access_id.as_str())
+  .send();
+  let body_text = timed_body("BINANCE", "order_info", body_resp).await?;
+  let obj: Value = parse_json(&body_text)?;
   let mut order_status_map = HashMap::new();
   order_status_map.insert("NEW", OrderStatus::NEW);
   order_status_map.insert("PARTIALLY_FILLED", OrderStatus::PARTIALLYFILLED);
@@ -149,7 +172,7 @@ pub async fn order_info(ex: &Exchange, order_id: String) -> Result<OrderInfo, St
 }
 
 pub async fn create_order(ex: &Exchange, side: OrderSide, price: f64, volume: f64) -> Result<String, String> {
-  let cfg: BinanceConfig = confy::load(&ex.config).expect("read binance config error");
+  let cfg = BinanceConfig::load(&ex.config)?;
   let param_str = build_binance_sign(&cfg, &ex.protocol, &ex.host, [
     ["symbol", &format!("{}{}", ex.symbol.to_uppercase(), ex.currency.to_uppercase())],
     ["side", &String::from(side.to_string())],
@@ -163,10 +186,10 @@ pub async fn create_order(ex: &Exchange, side: OrderSide, price: f64, volume: f6
   ex.host,
   param_str);
   let client = reqwest::Client::new();
-  let body_resp = client.post(full_url.as_str()).header("X-MBX-APIKEY", cfg.access_id)
-  .send().await;
-  let body_text = handle_body(body_resp).await?;
-  let json_resp: Value = serde_json::from_str(body_text.as_str()).expect("json parse error");
+  let body_resp = client.post(full_url.as_str()).header("X-MBX-APIKEY", cfg.access_id.as_str())
+  .send();
+  let body_text = timed_body("BINANCE", "create_order", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
   if !json_resp["orderId"].is_null() {
     return Ok(String::from(json_resp["orderId"].as_u64().expect("no orderId").to_string()));
   } else {
@@ -175,17 +198,17 @@ pub async fn create_order(ex: &Exchange, side: OrderSide, price: f64, volume: f6
 }
 
 pub async fn cancel_order(ex: &Exchange, order_id: String) -> Result<bool, String> {
-  let cfg: BinanceConfig = confy::load(&ex.config).expect("read binance config error");
+  let cfg = BinanceConfig::load(&ex.config)?;
   let param_str = build_binance_sign(&cfg, &ex.protocol, &ex.host, [
     ["symbol", &format!("{}{}", ex.symbol.to_uppercase(), ex.currency.to_uppercase())],
     ["orderId", &order_id],
   ].to_vec(), [].to_vec()).await?;
   let full_url = format!("{}://{}/api/v3/order?{}", ex.protocol, ex.host, param_str);
   let client = reqwest::Client::new();
-  let body_resp = client.delete(full_url.as_str()).header("X-MBX-APIKEY", cfg.access_id)
-  .send().await;
-  let body_text = handle_body(body_resp).await?;
-  let json_resp: Value = serde_json::from_str(body_text.as_str()).expect("json parse error");
+  let body_resp = client.delete(full_url.as_str()).header("X-MBX-APIKEY", cfg.access_id.as_str())
+  .send();
+  let body_text = timed_body("BINANCE", "cancel_order", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
   if json_resp["status"].is_null() {
     return Err(format!("{:?}", json_resp));
   } else {
@@ -194,17 +217,16 @@ pub async fn cancel_order(ex: &Exchange, order_id: String) -> Result<bool, Strin
 }
 
 pub async fn cancel_all_order(ex: &Exchange) -> Result<bool, String> {
-  let cfg: BinanceConfig = confy::load(&ex.config).expect("read binance config error");
+  let cfg = BinanceConfig::load(&ex.config)?;
   let param_str = build_binance_sign(&cfg, &ex.protocol, &ex.host, [
     ["symbol", &format!("{}{}", ex.symbol.to_uppercase(), ex.currency.to_uppercase())],
   ].to_vec(), [].to_vec()).await?;
   let full_url = format!("{}://{}/api/v3/openOrders?{}", ex.protocol, ex.host, param_str);
   let client = reqwest::Client::new();
-  let body_resp = client.delete(full_url.as_str()).header("X-MBX-APIKEY", cfg.access_id)
-  .send()
-  .await;
-  let body_text = handle_body(body_resp).await?;
-  let json_resp: Value = serde_json::from_str(body_text.as_str()).expect("json parse error");
+  let body_resp = client.delete(full_url.as_str()).header("X-MBX-APIKEY", cfg.access_id.as_str())
+  .send();
+  let body_text = timed_body("BINANCE", "cancel_all_order", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
   if !json_resp["code"].is_null() {
     return Err(format!("{:}", json_resp));
   } else {
@@ -221,16 +243,16 @@ pub async fn cancel_all_order(ex: &Exchange) -> Result<bool, String> {
 }
 
 pub async fn loan_info(ex: &Exchange) -> Result<LoanInfo, String> {
-  let cfg: BinanceConfig = confy::load(&ex.config).expect("read binance config error");
+  let cfg = BinanceConfig::load(&ex.config)?;
   let param_str = build_binance_sign(&cfg, &ex.protocol, &ex.host, [[
     "symbol", &format!("{}{}", ex.symbol.to_uppercase(), ex.currency.to_uppercase())
   ]].to_vec(), [].to_vec()).await?;
   let full_url = format!("{}://{}/sapi/v1/margin/isolated/pair?{}", ex.protocol, ex.host, param_str);
   let client = reqwest::Client::new();
-  let body_resp = client.get(full_url.as_str()).header("X-MBX-APIKEY", cfg.access_id)
-  .send().await;
-  let body_text = handle_body(body_resp).await?;
-  let json_resp: Value = serde_json::from_str(body_text.as_str()).expect("json parse error");
+  let body_resp = client.get(full_url.as_str()).header("X-MBX-APIKEY", cfg.access_id.as_str())
+  .send();
+  let body_text = timed_body("BINANCE", "loan_info", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
   if json_resp.is_object() {
     let asset_item = json_resp;
     if asset_item["code"].is_null() {
@@ -258,27 +280,461 @@ pub async fn loan_info(ex: &Exchange) -> Result<LoanInfo, String> {
 }
 
 pub async fn withdraw(ex: &Exchange, asset: String, address: String, amount: f64) -> Result<String, String> {
-  let cfg: BinanceConfig = confy::load(&ex.config).expect("read binance config error");
   let network: String = if asset.eq("usdt") {String::from(BINANCE_USDT_WITHDRAW_CHAIN)} else { asset.clone() };
-  let param_str = build_binance_sign(&cfg, &ex.protocol, &ex.host, [
+  return withdraw_on_chain(ex, asset, network, address, amount, String::new()).await;
+}
+
+pub async fn withdraw_on_chain(ex: &Exchange, asset: String, network: String, address: String, amount: f64, client_id: String) -> Result<String, String> {
+  let info = catalog::network(ex, &asset, &network).await?;
+  if amount < info.min_amount {
+    return Err(format!("{}: withdraw {} {} less than min amount {}", ex.name, amount, asset, info.min_amount));
+  }
+  let cfg = BinanceConfig::load(&ex.config)?;
+  let amount_str = amount.to_string();
+  let mut params: Vec<[&str;2]> = [
     ["coin", &asset],
     ["address", &address],
     ["network", &network],
-    ["amount", &amount.to_string()]
-  ].to_vec(), [].to_vec()).await?;
+    ["amount", &amount_str]
+  ].to_vec();
+  if !client_id.is_empty() {
+    params.push(["withdrawOrderId", &client_id]);
+  }
+  let param_str = build_binance_sign(&cfg, &ex.protocol, &ex.host, params, [].to_vec()).await?;
   let full_url = format!("{}://{}/sapi/v1/capital/withdraw/apply?{}",
   ex.protocol,
   ex.host,
   param_str);
   let client = reqwest::Client::new();
-  let body_resp = client.post(full_url.as_str()).header("X-MBX-APIKEY", cfg.access_id)
-  .send().await;
-  let body_text = handle_body(body_resp).await?;
+  let body_resp = client.post(full_url.as_str()).header("X-MBX-APIKEY", cfg.access_id.as_str())
+  .send();
+  let body_text = timed_body("BINANCE", "withdraw_on_chain", body_resp).await?;
   println!("{}", body_text);
-  let json_resp: Value = serde_json::from_str(body_text.as_str()).expect("json parse error");
+  let json_resp: Value = parse_json(&body_text)?;
   if !json_resp["id"].is_null() {
     return Ok(String::from(json_resp["id"].as_str().expect("read id error")));
   } else {
     return Err(format!("{:?}", json_resp));
   }
-}
\ No newline at end of file
+}
+
+// 按提币时传入的 withdrawOrderId 查询, 没有找到返回 None
+pub async fn find_withdrawal(ex: &Exchange, asset: String, client_id: String) -> Result<Option<String>, String> {
+  let cfg = BinanceConfig::load(&ex.config)?;
+  let json_resp = signed_get(ex, &cfg, "/sapi/v1/capital/withdraw/history", [
+    ["coin", &asset.to_uppercase()],
+    ["withdrawOrderId", &client_id]
+  ].to_vec()).await?;
+  return Ok(json_resp.as_array().and_then(|arr| arr.first()).and_then(|w| w["id"].as_str()).map(String::from));
+}
+
+pub async fn deposit_address(ex: &Exchange, asset: String, network: String) -> Result<String, String> {
+  let cfg = BinanceConfig::load(&ex.config)?;
+  let param_str = build_binance_sign(&cfg, &ex.protocol, &ex.host, [
+    ["coin", &asset.to_uppercase()],
+    ["network", &network.to_uppercase()]
+  ].to_vec(), [].to_vec()).await?;
+  let full_url = format!("{}://{}/sapi/v1/capital/deposit/address?{}", ex.protocol, ex.host, param_str);
+  let client = reqwest::Client::new();
+  let body_resp = client.get(full_url.as_str()).header("X-MBX-APIKEY", cfg.access_id.as_str())
+  .send();
+  let body_text = timed_body("BINANCE", "deposit_address", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  if !json_resp["address"].is_null() {
+    return Ok(String::from(json_resp["address"].as_str().expect("read address error")));
+  } else {
+    return Err(format!("{}: {:?}", ex.name, json_resp));
+  }
+}
+
+pub async fn asset_networks(ex: &Exchange, asset: String) -> Result<Vec<NetworkInfo>, String> {
+  let cfg = BinanceConfig::load(&ex.config)?;
+  let param_str = build_binance_sign(&cfg, &ex.protocol, &ex.host, [].to_vec(), [].to_vec()).await?;
+  let full_url = format!("{}://{}/sapi/v1/capital/config/getall?{}", ex.protocol, ex.host, param_str);
+  let client = reqwest::Client::new();
+  let body_resp = client.get(full_url.as_str()).header("X-MBX-APIKEY", cfg.access_id.as_str())
+  .send();
+  let body_text = timed_body("BINANCE", "asset_networks", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  if json_resp.is_array() {
+    let arr = json_resp.as_array().expect("as_array error");
+    let coin_item = arr.iter().find(|x| x["coin"] == asset.to_uppercase()).ok_or(format!("{}: no coin config for {}", ex.name, asset))?;
+    let networks = coin_item["networkList"].as_array().expect("read networkList error").iter().map(|n| {
+      let network = n["network"].as_str().expect("read network error");
+      NetworkInfo {
+        chain: catalog::canonical_chain(network),
+        network: String::from(network),
+        fee: n["withdrawFee"].as_str().expect("read withdrawFee error").parse::<f64>().expect("parse withdrawFee error"),
+        min_amount: n["withdrawMin"].as_str().expect("read withdrawMin error").parse::<f64>().expect("parse withdrawMin error"),
+        withdraw_enabled: n["withdrawEnable"].as_bool().unwrap_or(false),
+        confirmations: n["minConfirm"].as_u64().unwrap_or(0)
+      }
+    }).collect();
+    return Ok(networks);
+  } else {
+    return Err(format!("{}: {:?}", ex.name, json_resp));
+  }
+}
+
+async fn signed_get(ex: &Exchange, cfg: &BinanceConfig, path: &str, params: Vec<[&str;2]>) -> Result<Value, String> {
+  let param_str = build_binance_sign(cfg, &ex.protocol, &ex.host, params, [].to_vec()).await?;
+  let full_url = format!("{}://{}{}?{}", ex.protocol, ex.host, path, param_str);
+  let client = reqwest::Client::new();
+  let body_resp = client.get(full_url.as_str()).header("X-MBX-APIKEY", cfg.access_id.as_str())
+  .send();
+  let body_text = timed_body("BINANCE", path, body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  if !json_resp["code"].is_null() && json_resp["code"] != 200 {
+    return Err(format!("{}: {}", ex.name, json_resp));
+  }
+  return Ok(json_resp);
+}
+
+fn margin_balance(account: AccountType, item: &Value) -> Balance {
+  Balance {
+    account,
+    asset: item["asset"].as_str().expect("read asset error").to_lowercase(),
+    free: value_f64(&item["free"]),
+    locked: value_f64(&item["locked"]),
+    borrowed: value_f64(&item["borrowed"]),
+    interest: value_f64(&item["interest"])
+  }
+}
+
+// 现货, 资金, 全仓杠杆, 逐仓杠杆以及质押借币账户的全部余额
+pub async fn balances(ex: &Exchange) -> Result<Vec<Balance>, String> {
+  let cfg = BinanceConfig::load(&ex.config)?;
+  let mut items: Vec<Balance> = Vec::new();
+
+  let spot = signed_get(ex, &cfg, "/api/v3/account", [].to_vec()).await?;
+  for item in spot["balances"].as_array().expect("no balances").iter() {
+    let b = Balance {
+      account: AccountType::SPOT,
+      asset: item["asset"].as_str().expect("read asset error").to_lowercase(),
+      free: value_f64(&item["free"]),
+      locked: value_f64(&item["locked"]),
+      borrowed: 0_f64,
+      interest: 0_f64
+    };
+    if b.free > 0_f64 || b.locked > 0_f64 {
+      items.push(b);
+    }
+  }
+
+  // 资金账户接口只接受 POST
+  let param_str = build_binance_sign(&cfg, &ex.protocol, &ex.host, [].to_vec(), [].to_vec()).await?;
+  let full_url = format!("{}://{}/sapi/v1/asset/get-funding-asset?{}", ex.protocol, ex.host, param_str);
+  let client = reqwest::Client::new();
+  let body_resp = client.post(full_url.as_str()).header("X-MBX-APIKEY", cfg.access_id.as_str())
+  .send();
+  let body_text = timed_body("BINANCE", "balances", body_resp).await?;
+  let funding: Value = parse_json(&body_text)?;
+  if let Some(arr) = funding.as_array() {
+    for item in arr.iter() {
+      items.push(Balance {
+        account: AccountType::FUNDING,
+        asset: item["asset"].as_str().expect("read asset error").to_lowercase(),
+        free: value_f64(&item["free"]),
+        locked: value_f64(&item["locked"]) + value_f64(&item["freeze"]),
+        borrowed: 0_f64,
+        interest: 0_f64
+      });
+    }
+  } else {
+    return Err(format!("{}: {}", ex.name, funding));
+  }
+
+  let cross = signed_get(ex, &cfg, "/sapi/v1/margin/account", [].to_vec()).await?;
+  for item in cross["userAssets"].as_array().unwrap_or(&Vec::new()).iter() {
+    let b = margin_balance(AccountType::MARGIN, item);
+    if b.free > 0_f64 || b.locked > 0_f64 || b.borrowed > 0_f64 {
+      items.push(b);
+    }
+  }
+
+  let isolated = signed_get(ex, &cfg, "/sapi/v1/margin/isolated/account", [].to_vec()).await?;
+  for pair in isolated["assets"].as_array().unwrap_or(&Vec::new()).iter() {
+    let symbol = pair["symbol"].as_str().expect("read symbol error").to_lowercase();
+    for side in ["baseAsset", "quoteAsset"] {
+      let b = margin_balance(AccountType::ISOLATEDMARGIN(symbol.clone()), &pair[side]);
+      if b.free > 0_f64 || b.locked > 0_f64 || b.borrowed > 0_f64 {
+        items.push(b);
+      }
+    }
+  }
+
+  let loans = signed_get(ex, &cfg, "/sapi/v1/loan/ongoing/orders", [["limit", "100"]].to_vec()).await?;
+  for row in loans["rows"].as_array().unwrap_or(&Vec::new()).iter() {
+    items.push(Balance {
+      account: AccountType::LOAN,
+      asset: row["collateralCoin"].as_str().expect("read collateralCoin error").to_lowercase(),
+      free: 0_f64,
+      locked: value_f64(&row["collateralAmount"]),
+      borrowed: 0_f64,
+      interest: 0_f64
+    });
+    items.push(Balance {
+      account: AccountType::LOAN,
+      asset: row["loanCoin"].as_str().expect("read loanCoin error").to_lowercase(),
+      free: 0_f64,
+      locked: 0_f64,
+      borrowed: value_f64(&row["totalDebt"]),
+      interest: 0_f64
+    });
+  }
+  return Ok(items);
+}
+
+fn wallet_name(ex: &Exchange, account: &AccountType) -> Result<&'static str, String> {
+  match account {
+    AccountType::SPOT => Ok("MAIN"),
+    AccountType::FUNDING => Ok("FUNDING"),
+    AccountType::MARGIN => Ok("MARGIN"),
+    AccountType::USDSFUTURE => Ok("UMFUTURE"),
+    AccountType::ISOLATEDMARGIN(_) => Ok("ISOLATEDMARGIN"),
+    AccountType::LOAN => Err(format!("{}: can not transfer with LOAN account", ex.name))
+  }
+}
+
+// 现货 <-> 逐仓杠杆使用逐仓划转接口, 其余使用万向划转
+pub async fn transfer(ex: &Exchange, asset: String, amount: f64, from: AccountType, to: AccountType) -> Result<String, String> {
+  let cfg = BinanceConfig::load(&ex.config)?;
+  let amount_str = amount.to_string();
+  let asset_str = asset.to_uppercase();
+  let param_str = match (&from, &to) {
+    (AccountType::SPOT, AccountType::ISOLATEDMARGIN(pair)) | (AccountType::ISOLATEDMARGIN(pair), AccountType::SPOT) => {
+      let trans_from = if from == AccountType::SPOT { "SPOT" } else { "ISOLATED_MARGIN" };
+      let trans_to = if to == AccountType::SPOT { "SPOT" } else { "ISOLATED_MARGIN" };
+      let param_str = build_binance_sign(&cfg, &ex.protocol, &ex.host, [
+        ["asset", &asset_str],
+        ["symbol", &pair.to_uppercase()],
+        ["transFrom", trans_from],
+        ["transTo", trans_to],
+        ["amount", &amount_str]
+      ].to_vec(), [].to_vec()).await?;
+      format!("/sapi/v1/margin/isolated/transfer?{}", param_str)
+    }
+    _ => {
+      let transfer_type = format!("{}_{}", wallet_name(ex, &from)?, wallet_name(ex, &to)?);
+      let from_symbol = if let AccountType::ISOLATEDMARGIN(pair) = &from { pair.to_uppercase() } else { String::new() };
+      let to_symbol = if let AccountType::ISOLATEDMARGIN(pair) = &to { pair.to_uppercase() } else { String::new() };
+      let mut params: Vec<[&str;2]> = [
+        ["type", transfer_type.as_str()],
+        ["asset", &asset_str],
+        ["amount", &amount_str]
+      ].to_vec();
+      if !from_symbol.is_empty() {
+        params.push(["fromSymbol", &from_symbol]);
+      }
+      if !to_symbol.is_empty() {
+        params.push(["toSymbol", &to_symbol]);
+      }
+      let param_str = build_binance_sign(&cfg, &ex.protocol, &ex.host, params, [].to_vec()).await?;
+      format!("/sapi/v1/asset/transfer?{}", param_str)
+    }
+  };
+  let full_url = format!("{}://{}{}", ex.protocol, ex.host, param_str);
+  let client = reqwest::Client::new();
+  let body_resp = client.post(full_url.as_str()).header("X-MBX-APIKEY", cfg.access_id.as_str())
+  .send();
+  let body_text = timed_body("BINANCE", "transfer", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  if !json_resp["tranId"].is_null() {
+    return Ok(json_resp["tranId"].to_string());
+  } else {
+    return Err(format!("{}: {:?}", ex.name, json_resp));
+  }
+}
+
+// 逐仓杠杆的划转记录, 划转没有客户端 id, 按方向, 数量和时间匹配
+pub async fn find_transfer(ex: &Exchange, asset: String, amount: f64, from: AccountType, to: AccountType, since: i64) -> Result<Option<String>, String> {
+  let cfg = BinanceConfig::load(&ex.config)?;
+  let (pair, trans_from, trans_to) = match (&from, &to) {
+    (AccountType::SPOT, AccountType::ISOLATEDMARGIN(pair)) => (pair, "SPOT", "ISOLATED_MARGIN"),
+    (AccountType::ISOLATEDMARGIN(pair), AccountType::SPOT) => (pair, "ISOLATED_MARGIN", "SPOT"),
+    _ => return Err(format!("{}: transfer history from {} to {} not supported", ex.name, from, to))
+  };
+  let json_resp = signed_get(ex, &cfg, "/sapi/v1/margin/isolated/transfer", [
+    ["asset", &asset.to_uppercase()],
+    ["symbol", &pair.to_uppercase()],
+    ["transFrom", trans_from],
+    ["transTo", trans_to],
+    ["startTime", &since.to_string()]
+  ].to_vec()).await?;
+  let rows = json_resp["rows"].as_array().ok_or(format!("{}: {}", ex.name, json_resp))?;
+  return Ok(rows.iter()
+    .find(|r| r["status"] != "FAILED" && same_amount(value_f64(&r["amount"]), amount))
+    .map(|r| r["txId"].to_string()));
+}
+
+// 用逐仓账户里的 currency 归还借款
+pub async fn repay(ex: &Exchange, amount: f64) -> Result<String, String> {
+  let cfg = BinanceConfig::load(&ex.config)?;
+  let pair = format!("{}{}", ex.symbol.to_uppercase(), ex.currency.to_uppercase());
+  let param_str = build_binance_sign(&cfg, &ex.protocol, &ex.host, [
+    ["asset", &ex.currency.to_uppercase()],
+    ["isIsolated", "TRUE"],
+    ["symbol", &pair],
+    ["amount", &amount.to_string()]
+  ].to_vec(), [].to_vec()).await?;
+  let full_url = format!("{}://{}/sapi/v1/margin/repay?{}", ex.protocol, ex.host, param_str);
+  let client = reqwest::Client::new();
+  let body_resp = client.post(full_url.as_str()).header("X-MBX-APIKEY", cfg.access_id.as_str())
+  .send();
+  let body_text = timed_body("BINANCE", "repay", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  if !json_resp["tranId"].is_null() {
+    return Ok(json_resp["tranId"].to_string());
+  } else {
+    return Err(format!("{}: {:?}", ex.name, json_resp));
+  }
+}
+
+// 逐仓杠杆仓位, 以 currency 计价, 风险率(marginLevel)低于 1.1 强平
+pub async fn position(ex: &Exchange) -> Result<LoanPosition, String> {
+  let cfg = BinanceConfig::load(&ex.config)?;
+  let pair = format!("{}{}", ex.symbol.to_uppercase(), ex.currency.to_uppercase());
+  let json_resp = signed_get(ex, &cfg, "/sapi/v1/margin/isolated/account", [["symbols", &pair]].to_vec()).await?;
+  let item = json_resp["assets"].as_array().and_then(|arr| arr.iter().find(|x| x["symbol"] == pair.as_str()))
+  .ok_or(format!("{}: no isolated margin account for {}", ex.name, pair))?;
+  let price = value_f64(&item["indexPrice"]);
+  let base = &item["baseAsset"];
+  let quote = &item["quoteAsset"];
+  let collateral_value = value_f64(&base["totalAsset"]) * price + value_f64(&quote["totalAsset"]);
+  let debt_value = (value_f64(&base["borrowed"]) + value_f64(&base["interest"])) * price
+    + value_f64(&quote["borrowed"]) + value_f64(&quote["interest"]);
+  let mut pos = LoanPosition::new(format!("{}:{}", ex.name, pair.to_lowercase()), ex.name.to_string(), ex.currency.clone(), collateral_value, debt_value, 1_f64 / 1.1_f64);
+  let liquidate_price = value_f64(&item["liquidatePrice"]);
+  if liquidate_price > 0_f64 {
+    pos.liquidation_price = Some(liquidate_price);
+  }
+  return Ok(pos);
+}
+
+pub async fn api_permissions(ex: &Exchange) -> Result<ApiPermissions, String> {
+  let cfg = BinanceConfig::load(&ex.config)?;
+  let res = signed_get(ex, &cfg, "/sapi/v1/account/apiRestrictions", [].to_vec()).await?;
+  return Ok(ApiPermissions {
+    read: res["enableReading"].as_bool().unwrap_or(false),
+    trade: res["enableSpotAndMarginTrading"].as_bool().unwrap_or(false),
+    margin: res["enableMargin"].as_bool().unwrap_or(false),
+    withdraw: res["enableWithdrawals"].as_bool().unwrap_or(false),
+    ip_restricted: res["ipRestrict"].as_bool().unwrap_or(false)
+  });
+}
+
+async fn signed_post(ex: &Exchange, cfg: &BinanceConfig, path: &str, params: Vec<[&str;2]>) -> Result<Value, String> {
+  let param_str = build_binance_sign(cfg, &ex.protocol, &ex.host, params, [].to_vec()).await?;
+  let full_url = format!("{}://{}{}?{}", ex.protocol, ex.host, path, param_str);
+  let client = reqwest::Client::new();
+  let body_resp = client.post(full_url.as_str()).header("X-MBX-APIKEY", cfg.access_id.as_str())
+  .send();
+  let body_text = timed_body("BINANCE", path, body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  if !json_resp["code"].is_null() && json_resp["code"] != 200 {
+    return Err(format!("{}: {}", ex.name, json_resp));
+  }
+  return Ok(json_resp);
+}
+
+// 子账户的现货和全仓杠杆余额, 使用母账户的 key
+pub async fn subaccount_balances(ex: &Exchange, sub: &SubAccount) -> Result<Vec<Balance>, String> {
+  let cfg = BinanceConfig::load(&sub.master)?;
+  let mut items: Vec<Balance> = Vec::new();
+  let spot = signed_get(ex, &cfg, "/sapi/v3/sub-account/assets", [["email", sub.id.as_str()]].to_vec()).await?;
+  for item in spot["balances"].as_array().unwrap_or(&Vec::new()).iter() {
+    let b = Balance {
+      account: AccountType::SPOT,
+      asset: item["asset"].as_str().expect("read asset error").to_lowercase(),
+      free: value_f64(&item["free"]),
+      locked: value_f64(&item["locked"]),
+      borrowed: 0_f64,
+      interest: 0_f64
+    };
+    if b.free > 0_f64 || b.locked > 0_f64 {
+      items.push(b);
+    }
+  }
+  // 子账户没有开通杠杆时返回错误, 忽略
+  if let Ok(margin) = signed_get(ex, &cfg, "/sapi/v1/sub-account/margin/account", [["email", sub.id.as_str()]].to_vec()).await {
+    for item in margin["marginUserAssetVoList"].as_array().unwrap_or(&Vec::new()).iter() {
+      let b = margin_balance(AccountType::MARGIN, item);
+      if b.net() != 0_f64 || b.borrowed > 0_f64 {
+        items.push(b);
+      }
+    }
+  }
+  return Ok(items);
+}
+
+fn universal_account(ex: &Exchange, account: &AccountType) -> Result<&'static str, String> {
+  match account {
+    AccountType::SPOT => Ok("SPOT"),
+    AccountType::MARGIN => Ok("MARGIN"),
+    AccountType::ISOLATEDMARGIN(_) => Ok("ISOLATED_MARGIN"),
+    AccountType::USDSFUTURE => Ok("USDT_FUTURE"),
+    _ => Err(format!("{}: can not transfer with {} account of subaccount", ex.name, account))
+  }
+}
+
+// 母账户现货 <-> 子账户的 account, 使用母账户的 key 调用万向划转
+pub async fn subaccount_transfer(ex: &Exchange, sub: &SubAccount, asset: String, amount: f64, to_sub: bool, account: AccountType) -> Result<String, String> {
+  let cfg = BinanceConfig::load(&sub.master)?;
+  let amount_str = amount.to_string();
+  let asset_str = asset.to_uppercase();
+  let sub_account = universal_account(ex, &account)?;
+  let symbol = if let AccountType::ISOLATEDMARGIN(pair) = &account { pair.to_uppercase() } else { String::new() };
+  let (email_key, from_type, to_type) = if to_sub { ("toEmail", "SPOT", sub_account) } else { ("fromEmail", sub_account, "SPOT") };
+  let mut params: Vec<[&str;2]> = [
+    [email_key, sub.id.as_str()],
+    ["fromAccountType", from_type],
+    ["toAccountType", to_type],
+    ["asset", &asset_str],
+    ["amount", &amount_str]
+  ].to_vec();
+  if !symbol.is_empty() {
+    params.push(["symbol", &symbol]);
+  }
+  let json_resp = signed_post(ex, &cfg, "/sapi/v1/sub-account/universalTransfer", params).await?;
+  if json_resp["tranId"].is_null() {
+    return Err(format!("{}: {:?}", ex.name, json_resp));
+  }
+  return Ok(json_resp["tranId"].to_string());
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // 文档 SIGNED Endpoint Examples 里的 key 和请求
+  fn doc_config() -> BinanceConfig {
+    BinanceConfig {
+      access_id: String::from("vmPUZE6mv9SD5VNHk4HlWFsOr6aKE2zvsw0MuIgwCIPy6utIco14y7Ju91duEh8A"),
+      secret_key: String::from("NhqPtmdSJYdKjVHjA7PZj4Mge3R5YNiP1e3UZjInClVN65XAbvqqM6A7H5fATj0j")
+    }
+  }
+
+  fn order_params() -> Vec<[&'static str;2]> {
+    [["symbol", "LTCBTC"], ["side", "BUY"], ["type", "LIMIT"], ["timeInForce", "GTC"], ["quantity", "1"], ["price", "0.1"]].to_vec()
+  }
+
+  #[test]
+  fn sign_query_string() {
+    let query = sign_binance(&doc_config(), order_params(), [].to_vec(), 1499827319559);
+    assert_eq!(query, "symbol=LTCBTC&side=BUY&type=LIMIT&timeInForce=GTC&quantity=1&price=0.1&recvWindow=5000&timestamp=1499827319559\
+      &signature=c8db56825ae71d6d79447849e617115f4a920fa2acdcab2b053c4b2838bd6b71");
+  }
+
+  #[test]
+  fn sign_request_body() {
+    let query = sign_binance(&doc_config(), [].to_vec(), order_params(), 1499827319559);
+    assert_eq!(query, "signature=c8db56825ae71d6d79447849e617115f4a920fa2acdcab2b053c4b2838bd6b71");
+  }
+
+  #[test]
+  fn sign_mixed_query_and_body() {
+    let params = order_params();
+    let query = sign_binance(&doc_config(), params[..4].to_vec(), params[4..].to_vec(), 1499827319559);
+    assert_eq!(query, "symbol=LTCBTC&side=BUY&type=LIMIT&timeInForce=GTC&signature=0fd168b8ddb4876a0358a8d14d0c9f3da0e9b20c5d52b2a00fcf7d1c602f9a77");
+  }
+}
@@ -0,0 +1,102 @@
+use std::sync::Mutex;
+use std::time;
+use super::types::{ Exchanges, NetworkInfo };
+use super::Exchange;
+
+// 提币参数缓存时间
+static CATALOG_TTL: u64 = 600_u64;
+// 交易所处理提币的大致耗时, 加上链上确认时间就是预计到账时间
+static WITHDRAW_PROCESS_SECS: u64 = 120_u64;
+
+struct CatalogEntry {
+  venue: Exchanges,
+  host: String,
+  asset: String,
+  networks: Vec<NetworkInfo>,
+  fetched_at: time::Instant
+}
+
+static CATALOG: Mutex<Vec<CatalogEntry>> = Mutex::new(Vec::new());
+
+// 各交易所对同一条链的叫法不同, 统一成一个名称方便跨交易所匹配
+pub fn canonical_chain(name: &str) -> String {
+  let upper = name.to_uppercase();
+  let upper = upper.trim_start_matches("USDT-").trim_end_matches("USDT").trim_start_matches("USDT");
+  match upper {
+    "TRX" | "TRC20" => String::from("TRC20"),
+    "ETH" | "ERC20" => String::from("ERC20"),
+    "BSC" | "BEP20" => String::from("BEP20"),
+    "MATIC" | "POLYGON" => String::from("POLYGON"),
+    "ARBITRUM" | "ARBITRUM ONE" | "ARB" | "ARBITRUMONE" => String::from("ARBITRUM"),
+    "OPTIMISM" | "OP" => String::from("OPTIMISM"),
+    "" | "OMNI" => String::from("OMNI"),
+    _ => String::from(upper)
+  }
+}
+
+// 出块时间, 用于估算到账时间
+fn block_secs(chain: &str) -> u64 {
+  match chain {
+    "TRC20" => 3,
+    "ERC20" => 12,
+    "BEP20" => 3,
+    "POLYGON" => 2,
+    _ => 10
+  }
+}
+
+pub fn eta_secs(info: &NetworkInfo) -> u64 {
+  WITHDRAW_PROCESS_SECS + info.confirmations * block_secs(&info.chain)
+}
+
+fn cached(ex: &Exchange, asset: &str, max_age: u64) -> Option<Vec<NetworkInfo>> {
+  let catalog = CATALOG.lock().expect("catalog lock error");
+  catalog.iter()
+  .find(|e| e.venue == ex.name && e.host == ex.host && e.asset == asset && e.fetched_at.elapsed().as_secs() < max_age)
+  .map(|e| e.networks.clone())
+}
+
+// 从交易所重新拉取提币参数并更新缓存
+pub async fn refresh(ex: &Exchange, asset: &str) -> Result<Vec<NetworkInfo>, String> {
+  let asset = asset.to_lowercase();
+  let networks = ex.asset_networks(asset.clone()).await?;
+  let mut catalog = CATALOG.lock().expect("catalog lock error");
+  catalog.retain(|e| !(e.venue == ex.name && e.host == ex.host && e.asset == asset));
+  catalog.push(CatalogEntry {
+    venue: ex.name.clone(),
+    host: ex.host.clone(),
+    asset,
+    networks: networks.clone(),
+    fetched_at: time::Instant::now()
+  });
+  return Ok(networks);
+}
+
+// 优先使用缓存, 过期后刷新, 刷新失败时退回到过期的缓存
+pub async fn networks(ex: &Exchange, asset: &str) -> Result<Vec<NetworkInfo>, String> {
+  let asset = asset.to_lowercase();
+  if let Some(networks) = cached(ex, &asset, CATALOG_TTL) {
+    return Ok(networks);
+  }
+  match refresh(ex, &asset).await {
+    Ok(networks) => Ok(networks),
+    Err(err) => {
+      if let Some(networks) = cached(ex, &asset, u64::MAX) {
+        log::warn!("refresh {} {} networks error, use stale cache: {}", ex.name, asset, err);
+        return Ok(networks);
+      }
+      Err(err)
+    }
+  }
+}
+
+// 查找可以提币的网络, 网络名称不区分大小写
+pub async fn network(ex: &Exchange, asset: &str, network: &str) -> Result<NetworkInfo, String> {
+  let networks = networks(ex, asset).await?;
+  let info = networks.into_iter().find(|n| n.network.eq_ignore_ascii_case(network))
+  .ok_or(format!("{}: no {} withdraw network {}", ex.name, asset, network))?;
+  if !info.withdraw_enabled {
+    return Err(format!("{}: {} withdraw on {} is suspended", ex.name, asset, network));
+  }
+  return Ok(info);
+}
@@ -1,4 +1,8 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use zeroize::{ Zeroize, Zeroizing };
+use crate::keystore;
+use super::types::Exchanges;
 
 pub static HUOBI_USDT_WITHDRAW_CHAIN: &str = "trc20usdt";
 pub static OKEX_USDT_WITHDRAW_CHAIN: &str = "USDT-TRC20";
@@ -58,3 +62,88 @@ impl ::std::default::Default for OkexConfig {
     }
   }
 }
+
+// 凭证从 keystore 或者环境变量读取 (见 keystore::credential), 旧的 confy 明文文件只用于
+// `monitor keystore import` 迁移
+pub fn credential_fields(name: &Exchanges) -> &'static [&'static str] {
+  match name {
+    Exchanges::HUOBI => &["access_id", "secret_key", "account_id", "signature_method", "signature_version"],
+    Exchanges::BINANCE => &["access_id", "secret_key"],
+    Exchanges::OKEX => &["access_id", "secret_key", "trade_pwd", "passphrase"]
+  }
+}
+
+fn to_fields<T: Serialize>(cfg: &T) -> Result<BTreeMap<String, Zeroizing<String>>, String> {
+  let value = Zeroizing::new(serde_json::to_string(cfg).map_err(|e| format!("serialize credential error: {}", e))?);
+  let fields: BTreeMap<String, String> = serde_json::from_str(&value).map_err(|e| format!("serialize credential error: {}", e))?;
+  return Ok(fields.into_iter().filter(|(_, v)| !v.is_empty()).map(|(k, v)| (k, Zeroizing::new(v))).collect());
+}
+
+// 读取旧的 confy 明文配置, 用于导入 keystore
+pub fn load_legacy(name: &Exchanges, config: &str) -> Result<BTreeMap<String, Zeroizing<String>>, String> {
+  let fields = match name {
+    Exchanges::HUOBI => to_fields(&confy::load::<HuobiConfig>(config).map_err(|e| format!("read {} error: {}", config, e))?)?,
+    Exchanges::BINANCE => to_fields(&confy::load::<BinanceConfig>(config).map_err(|e| format!("read {} error: {}", config, e))?)?,
+    Exchanges::OKEX => to_fields(&confy::load::<OkexConfig>(config).map_err(|e| format!("read {} error: {}", config, e))?)?
+  };
+  if !fields.contains_key("secret_key") {
+    return Err(format!("confy config {} has no secret_key", config));
+  }
+  return Ok(fields);
+}
+
+impl HuobiConfig {
+  pub fn load(name: &str) -> Result<HuobiConfig, String> {
+    let cred = keystore::credential(name, credential_fields(&Exchanges::HUOBI))?;
+    return Ok(HuobiConfig {
+      access_id: cred.get("access_id")?,
+      secret_key: cred.get("secret_key")?,
+      account_id: cred.get_or("account_id", ""),
+      signature_method: cred.get_or("signature_method", "HmacSHA256"),
+      signature_version: cred.get_or("signature_version", "2")
+    });
+  }
+}
+
+impl BinanceConfig {
+  pub fn load(name: &str) -> Result<BinanceConfig, String> {
+    let cred = keystore::credential(name, credential_fields(&Exchanges::BINANCE))?;
+    return Ok(BinanceConfig {
+      access_id: cred.get("access_id")?,
+      secret_key: cred.get("secret_key")?
+    });
+  }
+}
+
+impl OkexConfig {
+  pub fn load(name: &str) -> Result<OkexConfig, String> {
+    let cred = keystore::credential(name, credential_fields(&Exchanges::OKEX))?;
+    return Ok(OkexConfig {
+      access_id: cred.get("access_id")?,
+      secret_key: cred.get("secret_key")?,
+      trade_pwd: cred.get_or("trade_pwd", ""),
+      passphrase: cred.get("passphrase")?
+    });
+  }
+}
+
+// 用完即清零
+impl Drop for HuobiConfig {
+  fn drop(&mut self) {
+    self.secret_key.zeroize();
+  }
+}
+
+impl Drop for BinanceConfig {
+  fn drop(&mut self) {
+    self.secret_key.zeroize();
+  }
+}
+
+impl Drop for OkexConfig {
+  fn drop(&mut self) {
+    self.secret_key.zeroize();
+    self.trade_pwd.zeroize();
+    self.passphrase.zeroize();
+  }
+}
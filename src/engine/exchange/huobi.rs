@@ -1,31 +1,46 @@
 use super::config::{ HuobiConfig, HUOBI_USDT_WITHDRAW_CHAIN };
-use super::types::{ LoanInfo, AccountInfo, AccountType };
-use serde_json::{ Value };
+use super::types::{ LoanInfo, AccountInfo, AccountType, NetworkInfo, Balance, ApiPermissions, SubAccount, DepthInfo, Tick, TradeInfo, OrderInfo, OrderStatus, OrderSide };
+use super::catalog;
+use crate::engine::position::LoanPosition;
+use serde_json::{ Value, json };
 use std::{collections::HashMap};
 use base64::{ encode };
 use sha2::{Sha256};
 use hmac::{Hmac, Mac, NewMac};
 use chrono::offset::Utc;
-use chrono::{ DateTime, NaiveDateTime };
+use chrono::{ DateTime };
 use url::form_urlencoded::Serializer;
 use super::Exchange;
-use crate::util::{ handle_body, huobi_withdraw_fee };
+use crate::util::{ timed_body, parse_json, value_f64, same_amount, depth_levels };
 
 // Create alias for HMAC-SHA256
 type HmacSha256 = Hmac<Sha256>;
 
 // for huobi sign
 pub async fn build_huobi_sign(cfg: &HuobiConfig, protocol: &str, host: &str,  timestamp_host: &str, method: &str, path: &str, params: Vec<[&str;2]>) -> Result<String, String> {
-  let mut all_params: Vec<[&str;2]> = Vec::new();
   // get server time
+  let started = std::time::Instant::now();
   let full_url = format!("{}://{}/v1/common/timestamp", protocol, timestamp_host);
-  let body_resp = reqwest::get(&full_url).await;
-  let body_text = handle_body(body_resp).await?;
-  let json_resp: Value = serde_json::from_str(body_text.as_str()).expect("json parse error");
-  let timestamp: i64 = json_resp["data"].as_i64().expect("read ts error");
-  let dt = NaiveDateTime::from_timestamp(timestamp / 1000, 0);
-   // Create a normal DateTime from the NaiveDateTime
-  let datetime: DateTime<Utc> = DateTime::from_utc(dt, Utc);
+  let body_resp = reqwest::get(&full_url);
+  let body_text = timed_body("HUOBI", "server_time", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  let timestamp: i64 = json_resp["data"].as_i64().ok_or(format!("HUOBI: read ts error: {}", json_resp))?;
+  crate::metrics::clock_drift("HUOBI", timestamp, started.elapsed().as_millis() as i64);
+  return Ok(sign_huobi(cfg, host, method, path, params, timestamp));
+}
+
+// 签名本身不依赖网络, 时间戳(毫秒)由调用方传入, 可以用文档里的例子测试
+pub fn sign_huobi(cfg: &HuobiConfig, host: &str, method: &str, path: &str, params: Vec<[&str;2]>, timestamp: i64) -> String {
+  let (param_str, payload) = huobi_payload(cfg, host, method, path, params, timestamp);
+  let mut serilizer: Serializer<String> = Serializer::new(String::new());
+  serilizer.append_pair("Signature", hmac_base64(&cfg.secret_key, &payload).as_str());
+  return param_str + "&" + &serilizer.finish();
+}
+
+// 返回 (排序编码后的参数, 待签名字符串)
+fn huobi_payload(cfg: &HuobiConfig, host: &str, method: &str, path: &str, params: Vec<[&str;2]>, timestamp: i64) -> (String, String) {
+  let mut all_params: Vec<[&str;2]> = Vec::new();
+  let datetime: DateTime<Utc> = DateTime::from_timestamp(timestamp / 1000, 0).unwrap_or_default();
   all_params.clone_from(&params);
   all_params.push(["AccessKeyId", cfg.access_id.as_str()]);
   all_params.push(["SignatureMethod", cfg.signature_method.as_str()]);
@@ -38,24 +53,25 @@ pub async fn build_huobi_sign(cfg: &HuobiConfig, protocol: &str, host: &str,  ti
     serilizer.append_pair(item[0], item[1]);
   }
   let param_str = serilizer.finish();
-  let full_str = format!("{}\n{}\n{}\n{}", method.to_uppercase(), host, path, param_str);
-  let mut mac = HmacSha256::new_varkey(cfg.secret_key.as_bytes()).expect("HMAC can take key of any size");
-  mac.update(full_str.as_bytes());
-  let signature = encode(mac.finalize().into_bytes());
-  let mut serilizer: Serializer<String> = Serializer::new(String::new());
-  serilizer.append_pair("Signature", signature.as_str());
-  return Ok(param_str + "&" + &serilizer.finish());
+  let payload = format!("{}\n{}\n{}\n{}", method.to_uppercase(), host, path, param_str);
+  return (param_str, payload);
+}
+
+fn hmac_base64(secret: &str, payload: &str) -> String {
+  let mut mac = HmacSha256::new_varkey(secret.as_bytes()).expect("HMAC can take key of any size");
+  mac.update(payload.as_bytes());
+  return encode(mac.finalize().into_bytes());
 }
 
 pub async fn account_info(ex: &Exchange) -> Result<AccountInfo, String> {
-  let cfg: HuobiConfig = confy::load(&ex.config).expect("read huobi config error");
+  let cfg = HuobiConfig::load(&ex.config)?;
   let param_str = build_huobi_sign(&cfg, &ex.protocol, &ex.host, &ex.host, "GET", &format!("/v1/account/accounts/{}/balance", cfg.account_id),
   [].to_vec()).await?;
   let full_url = format!("{}://{}/v1/account/accounts/{}/balance?{}", ex.protocol, ex.host, cfg.account_id, param_str);
   let client = reqwest::Client::new();
-  let body_resp = client.get(full_url.as_str()).send().await;
-  let body_text = handle_body(body_resp).await?;
-  let json_resp: Value = serde_json::from_str(body_text.as_str()).expect("json parse error");
+  let body_resp = client.get(full_url.as_str()).send();
+  let body_text = timed_body("HUOBI", "account_info", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
   if json_resp["status"] == "ok" {
     let obj = &json_resp["data"];
     let list = obj["list"].as_array().expect("read list error");
@@ -78,14 +94,14 @@ pub async fn account_info(ex: &Exchange) -> Result<AccountInfo, String> {
 
 
 pub async fn loan_info(ex: &Exchange) -> Result<LoanInfo, String> {
-  let cfg: HuobiConfig = confy::load(&ex.config).expect("read huobi config error");
+  let cfg = HuobiConfig::load(&ex.config)?;
   let symbols = format!("{}{}", ex.symbol.to_lowercase(), ex.currency.to_lowercase());
   let param_str = build_huobi_sign(&cfg, &ex.protocol, &ex.host, &ex.host, "GET", "/v1/margin/loan-info",
   [["symbols", &symbols]].to_vec()).await?;
   let full_url = format!("{}://{}/v1/margin/loan-info?{}", ex.protocol, ex.host, param_str);
-  let body_resp = reqwest::get(full_url.as_str()).await;
-  let body_text = handle_body(body_resp).await?;
-  let json_resp: Value = serde_json::from_str(body_text.as_str()).expect("json parse error");
+  let body_resp = reqwest::get(full_url.as_str());
+  let body_text = timed_body("HUOBI", "loan_info", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
   if json_resp["status"] == "ok" {
     let arr = json_resp["data"].as_array().expect("data as array error");
     if arr.len() > 0 { 
@@ -104,12 +120,20 @@ pub async fn loan_info(ex: &Exchange) -> Result<LoanInfo, String> {
 }
 
 pub async fn withdraw(ex: &Exchange, asset: String, address: String, amount: f64) -> Result<String, String> {
-  let cfg: HuobiConfig = confy::load(&ex.config).expect("read huobi config error");
+  let network: String = if asset.eq("usdt") { String::from(HUOBI_USDT_WITHDRAW_CHAIN) } else { asset.clone() };
+  return withdraw_on_chain(ex, asset, network, address, amount, String::new()).await;
+}
+
+pub async fn withdraw_on_chain(ex: &Exchange, asset: String, network: String, address: String, amount: f64, client_id: String) -> Result<String, String> {
+  let cfg = HuobiConfig::load(&ex.config)?;
   let param_str = build_huobi_sign(&cfg, &ex.protocol, &ex.host, &ex.host, "POST", "/v1/dw/withdraw/api/create",
   [].to_vec()).await?;
   let full_url = format!("{}://{}/v1/dw/withdraw/api/create?{}", ex.protocol, ex.host, param_str);
-  let network: String = if asset.eq("usdt") { String::from(HUOBI_USDT_WITHDRAW_CHAIN) } else { asset.clone() };
-  let fee = huobi_withdraw_fee(&asset);
+  let info = catalog::network(ex, &asset, &network).await?;
+  if amount < info.min_amount {
+    return Err(format!("{}: withdraw {} {} less than min amount {}", ex.name, amount, asset, info.min_amount));
+  }
+  let fee = info.fee;
   let client = reqwest::Client::new();
   let mut map = HashMap::new();
   map.insert("address", address.clone());
@@ -117,13 +141,450 @@ pub async fn withdraw(ex: &Exchange, asset: String, address: String, amount: f64
   map.insert("currency", asset.clone());
   map.insert("chain", network.clone());
   map.insert("fee", fee.to_string());
-  let body_resp = client.post(full_url.as_str()).json(&map).send().await;
-  let body_text = handle_body(body_resp).await?;
-  let json_resp: Value = serde_json::from_str(body_text.as_str()).expect("json parse error");
+  if !client_id.is_empty() {
+    map.insert("client-order-id", client_id.clone());
+  }
+  let body_resp = client.post(full_url.as_str()).json(&map).send();
+  let body_text = timed_body("HUOBI", "withdraw_on_chain", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
   if !json_resp["data"].is_null() {
     let id = json_resp["data"].as_u64().expect("read data error").to_string();
     return Ok(String::from(id));
   } else {
     return Err(format!("{}: {:?}", ex.name, json_resp));
   }
-}
\ No newline at end of file
+}
+
+// 按提币时传入的 client-order-id 查询, 没有找到返回 None
+pub async fn find_withdrawal(ex: &Exchange, _asset: String, client_id: String) -> Result<Option<String>, String> {
+  let cfg = HuobiConfig::load(&ex.config)?;
+  let json_resp = signed_get(ex, &cfg, "/v1/query/withdraw/client-order-id", [["clientOrderId", &client_id]].to_vec()).await?;
+  return Ok(json_resp["data"]["id"].as_u64().map(|id| id.to_string()));
+}
+
+pub async fn deposit_address(ex: &Exchange, asset: String, network: String) -> Result<String, String> {
+  let cfg = HuobiConfig::load(&ex.config)?;
+  let param_str = build_huobi_sign(&cfg, &ex.protocol, &ex.host, &ex.host, "GET", "/v2/account/deposit/address",
+  [["currency", &asset.to_lowercase()]].to_vec()).await?;
+  let full_url = format!("{}://{}/v2/account/deposit/address?{}", ex.protocol, ex.host, param_str);
+  let body_resp = reqwest::get(full_url.as_str());
+  let body_text = timed_body("HUOBI", "deposit_address", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  if json_resp["code"] == 200 {
+    let arr = json_resp["data"].as_array().expect("data as array error");
+    let item_opt = arr.iter().find(|x| x["chain"] == network.as_str());
+    if let Some(item) = item_opt {
+      return Ok(String::from(item["address"].as_str().expect("read address error")));
+    } else {
+      return Err(format!("no deposit address for {} on {}", asset, network));
+    }
+  } else {
+    return Err(format!("{}: {:?}", ex.name, json_resp));
+  }
+}
+
+pub async fn asset_networks(ex: &Exchange, asset: String) -> Result<Vec<NetworkInfo>, String> {
+  let full_url = format!("{}://{}/v2/reference/currencies?currency={}", ex.protocol, ex.host, asset.to_lowercase());
+  let body_resp = reqwest::get(full_url.as_str());
+  let body_text = timed_body("HUOBI", "asset_networks", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  if json_resp["code"] == 200 {
+    let arr = json_resp["data"].as_array().expect("data as array error");
+    let currency_item = arr.iter().find(|x| x["currency"] == asset.to_lowercase()).ok_or(format!("{}: no reference for {}", ex.name, asset))?;
+    let networks = currency_item["chains"].as_array().expect("read chains error").iter().map(|c| {
+      let protocol = c["baseChainProtocol"].as_str().or_else(|| c["displayName"].as_str()).unwrap_or("");
+      let fee = c["transactFeeWithdraw"].as_str().unwrap_or("0").parse::<f64>().expect("parse transactFeeWithdraw error");
+      NetworkInfo {
+        chain: catalog::canonical_chain(if protocol.is_empty() { c["chain"].as_str().unwrap_or("") } else { protocol }),
+        network: String::from(c["chain"].as_str().expect("read chain error")),
+        fee,
+        // 火币的最小提币数量不含手续费
+        min_amount: c["minWithdrawAmt"].as_str().unwrap_or("0").parse::<f64>().expect("parse minWithdrawAmt error") + fee,
+        withdraw_enabled: c["withdrawStatus"] == "allowed",
+        confirmations: c["numOfConfirmations"].as_u64().unwrap_or(0)
+      }
+    }).collect();
+    return Ok(networks);
+  } else {
+    return Err(format!("{}: {:?}", ex.name, json_resp));
+  }
+}
+
+async fn signed_get(ex: &Exchange, cfg: &HuobiConfig, path: &str, params: Vec<[&str;2]>) -> Result<Value, String> {
+  let param_str = build_huobi_sign(cfg, &ex.protocol, &ex.host, &ex.host, "GET", path, params).await?;
+  let full_url = format!("{}://{}{}?{}", ex.protocol, ex.host, path, param_str);
+  let body_resp = reqwest::get(full_url.as_str());
+  let body_text = timed_body("HUOBI", path, body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  if json_resp["status"] == "ok" || json_resp["code"] == 200 {
+    return Ok(json_resp);
+  } else {
+    return Err(format!("{}: {:?}", ex.name, json_resp));
+  }
+}
+
+// POST 的参数放在 json body 里, 签名只包含公共参数
+async fn signed_post(ex: &Exchange, cfg: &HuobiConfig, path: &str, body: Value) -> Result<Value, String> {
+  let param_str = build_huobi_sign(cfg, &ex.protocol, &ex.host, &ex.host, "POST", path, [].to_vec()).await?;
+  let full_url = format!("{}://{}{}?{}", ex.protocol, ex.host, path, param_str);
+  let client = reqwest::Client::new();
+  let body_resp = client.post(full_url.as_str()).json(&body).send();
+  let body_text = timed_body("HUOBI", path, body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  if json_resp["status"] == "ok" || json_resp["code"] == 200 {
+    return Ok(json_resp);
+  } else {
+    return Err(format!("{}: {:?}", ex.name, json_resp));
+  }
+}
+
+// 所有账户(现货, 逐仓, 全仓, otc)的余额, 同一币种的 trade/frozen/loan/interest 合并成一条
+pub async fn balances(ex: &Exchange) -> Result<Vec<Balance>, String> {
+  let cfg = HuobiConfig::load(&ex.config)?;
+  let accounts = signed_get(ex, &cfg, "/v1/account/accounts", [].to_vec()).await?;
+  let mut items: Vec<Balance> = Vec::new();
+  for account in accounts["data"].as_array().expect("data as array error").iter() {
+    let account_type = match account_type(account) {
+      Some(t) => t,
+      None => continue
+    };
+    let id = account["id"].to_string();
+    let balance = signed_get(ex, &cfg, &format!("/v1/account/accounts/{}/balance", id), [].to_vec()).await?;
+    items.extend(merge_list(account_type, &balance["data"]["list"]));
+  }
+  return Ok(items);
+}
+
+fn account_type(account: &Value) -> Option<AccountType> {
+  match account["type"].as_str().unwrap_or("") {
+    "spot" => Some(AccountType::SPOT),
+    "otc" => Some(AccountType::FUNDING),
+    "super-margin" => Some(AccountType::MARGIN),
+    "margin" => Some(AccountType::ISOLATEDMARGIN(String::from(account["subtype"].as_str().unwrap_or("")))),
+    _ => None
+  }
+}
+
+// 同一个币种的 trade, frozen, loan, interest 合并成一条
+fn merge_list(account_type: AccountType, list: &Value) -> Vec<Balance> {
+  let mut merged: HashMap<String, Balance> = HashMap::new();
+  for item in list.as_array().unwrap_or(&Vec::new()).iter() {
+    let currency = String::from(item["currency"].as_str().expect("read currency error"));
+    let amount = value_f64(&item["balance"]);
+    let b = merged.entry(currency.clone()).or_insert(Balance {
+      account: account_type.clone(),
+      asset: currency,
+      free: 0_f64,
+      locked: 0_f64,
+      borrowed: 0_f64,
+      interest: 0_f64
+    });
+    match item["type"].as_str().unwrap_or("") {
+      "trade" => b.free += amount,
+      "frozen" => b.locked += amount,
+      // 借币和利息是负数
+      "loan" => b.borrowed += amount.abs(),
+      "interest" => b.interest += amount.abs(),
+      _ => {}
+    }
+  }
+  return merged.into_values().filter(|b| b.free > 0_f64 || b.locked > 0_f64 || b.borrowed > 0_f64).collect();
+}
+
+// 母账户的 key 一次查询子账户所有账户的余额, sub.id 是子账户的 uid
+pub async fn subaccount_balances(ex: &Exchange, sub: &SubAccount) -> Result<Vec<Balance>, String> {
+  let cfg = HuobiConfig::load(&sub.master)?;
+  let accounts = signed_get(ex, &cfg, &format!("/v1/account/accounts/{}", sub.id), [].to_vec()).await?;
+  let mut items: Vec<Balance> = Vec::new();
+  for account in accounts["data"].as_array().ok_or(format!("{}: {}", ex.name, accounts))?.iter() {
+    if let Some(account_type) = account_type(account) {
+      items.extend(merge_list(account_type, &account["list"]));
+    }
+  }
+  return Ok(items);
+}
+
+// 母子账户之间只能在现货账户之间划转, 子账户再用自己的 key 转入逐仓
+pub async fn subaccount_transfer(ex: &Exchange, sub: &SubAccount, asset: String, amount: f64, to_sub: bool, account: AccountType) -> Result<String, String> {
+  if account != AccountType::SPOT {
+    return Err(format!("{}: subaccount transfer to {} not supported, only SPOT", ex.name, account));
+  }
+  let cfg = HuobiConfig::load(&sub.master)?;
+  let json_resp = signed_post(ex, &cfg, "/v1/subuser/transfer", json!({
+    "sub-uid": sub.id,
+    "currency": asset.to_lowercase(),
+    "amount": amount.to_string(),
+    "type": if to_sub { "master-transfer-out" } else { "master-transfer-in" }
+  })).await?;
+  return Ok(json_resp["data"].to_string());
+}
+
+// 只支持现货和杠杆账户(逐仓, 全仓)之间的划转
+pub async fn transfer(ex: &Exchange, asset: String, amount: f64, from: AccountType, to: AccountType) -> Result<String, String> {
+  let cfg = HuobiConfig::load(&ex.config)?;
+  let mut map = HashMap::new();
+  map.insert("currency", asset.to_lowercase());
+  map.insert("amount", amount.to_string());
+  let path = match (&from, &to) {
+    (AccountType::SPOT, AccountType::ISOLATEDMARGIN(pair)) => {
+      map.insert("symbol", pair.to_lowercase());
+      "/v1/dw/transfer-in/margin"
+    }
+    (AccountType::ISOLATEDMARGIN(pair), AccountType::SPOT) => {
+      map.insert("symbol", pair.to_lowercase());
+      "/v1/dw/transfer-out/margin"
+    }
+    (AccountType::SPOT, AccountType::MARGIN) => "/v1/cross-margin/transfer-in",
+    (AccountType::MARGIN, AccountType::SPOT) => "/v1/cross-margin/transfer-out",
+    _ => {
+      return Err(format!("{}: transfer from {} to {} not supported", ex.name, from, to));
+    }
+  };
+  let param_str = build_huobi_sign(&cfg, &ex.protocol, &ex.host, &ex.host, "POST", path,
+  [].to_vec()).await?;
+  let full_url = format!("{}://{}{}?{}", ex.protocol, ex.host, path, param_str);
+  let client = reqwest::Client::new();
+  let body_resp = client.post(full_url.as_str()).json(&map).send();
+  let body_text = timed_body("HUOBI", "transfer", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  if json_resp["status"] == "ok" {
+    return Ok(json_resp["data"].to_string());
+  } else {
+    return Err(format!("{}: {:?}", ex.name, json_resp));
+  }
+}
+
+// 现货账户的流水里转出为负数, 转入为正数, 划转没有客户端 id, 按数量和时间匹配
+pub async fn find_transfer(ex: &Exchange, asset: String, amount: f64, from: AccountType, to: AccountType, since: i64) -> Result<Option<String>, String> {
+  let cfg = HuobiConfig::load(&ex.config)?;
+  let sign = match (&from, &to) {
+    (AccountType::SPOT, AccountType::ISOLATEDMARGIN(_)) => -1_f64,
+    (AccountType::ISOLATEDMARGIN(_), AccountType::SPOT) => 1_f64,
+    _ => return Err(format!("{}: transfer history from {} to {} not supported", ex.name, from, to))
+  };
+  let json_resp = signed_get(ex, &cfg, "/v1/account/history", [
+    ["account-id", &cfg.account_id],
+    ["currency", &asset.to_lowercase()],
+    ["transact-types", "transfer"],
+    ["start-time", &since.to_string()]
+  ].to_vec()).await?;
+  let records = json_resp["data"].as_array().ok_or(format!("{}: {}", ex.name, json_resp))?;
+  return Ok(records.iter()
+    .find(|r| same_amount(value_f64(&r["transact-amt"]), sign * amount))
+    .map(|r| r["record-id"].to_string()));
+}
+
+// 用逐仓账户里的 currency 归还借款, 需要先找到交易对对应的逐仓账户 id
+pub async fn repay(ex: &Exchange, amount: f64) -> Result<String, String> {
+  let cfg = HuobiConfig::load(&ex.config)?;
+  let pair = format!("{}{}", ex.symbol.to_lowercase(), ex.currency.to_lowercase());
+  let accounts = signed_get(ex, &cfg, "/v1/account/accounts", [].to_vec()).await?;
+  let account_id = accounts["data"].as_array().and_then(|arr| arr.iter().find(|a| a["type"] == "margin" && a["subtype"] == pair.as_str()))
+  .map(|a| a["id"].to_string())
+  .ok_or(format!("{}: no margin account for {}", ex.name, pair))?;
+  let param_str = build_huobi_sign(&cfg, &ex.protocol, &ex.host, &ex.host, "POST", "/v2/account/repayment",
+  [].to_vec()).await?;
+  let full_url = format!("{}://{}/v2/account/repayment?{}", ex.protocol, ex.host, param_str);
+  let mut map = HashMap::new();
+  map.insert("accountId", account_id);
+  map.insert("currency", ex.currency.to_lowercase());
+  map.insert("amount", amount.to_string());
+  let client = reqwest::Client::new();
+  let body_resp = client.post(full_url.as_str()).json(&map).send();
+  let body_text = timed_body("HUOBI", "repay", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  if json_resp["code"] == 200 {
+    let repay_id = &json_resp["data"][0]["repayId"];
+    return Ok(repay_id.as_str().map(String::from).unwrap_or(repay_id.to_string()));
+  } else {
+    return Err(format!("{}: {:?}", ex.name, json_resp));
+  }
+}
+
+// 逐仓杠杆仓位, 以 currency 计价, 风险率(risk-rate)低于 1.1 强平
+pub async fn position(ex: &Exchange) -> Result<LoanPosition, String> {
+  let cfg = HuobiConfig::load(&ex.config)?;
+  let pair = format!("{}{}", ex.symbol.to_lowercase(), ex.currency.to_lowercase());
+  let json_resp = signed_get(ex, &cfg, "/v1/margin/accounts/balance", [["symbol", &pair]].to_vec()).await?;
+  let item = json_resp["data"].as_array().and_then(|arr| arr.iter().find(|x| x["symbol"] == pair.as_str()))
+  .ok_or(format!("{}: no margin account for {}", ex.name, pair))?;
+  let ticker_url = format!("{}://{}/market/detail/merged?symbol={}", ex.protocol, ex.host, pair);
+  let body_resp = reqwest::get(ticker_url.as_str());
+  let body_text = timed_body("HUOBI", "position", body_resp).await?;
+  let ticker: Value = parse_json(&body_text)?;
+  let price = value_f64(&ticker["tick"]["close"]);
+  let mut collateral_value = 0_f64;
+  let mut debt_value = 0_f64;
+  for b in item["list"].as_array().expect("read list error").iter() {
+    let value = value_f64(&b["balance"]) * if b["currency"] == ex.currency.to_lowercase() { 1_f64 } else { price };
+    match b["type"].as_str().unwrap_or("") {
+      "trade" | "frozen" => collateral_value += value,
+      "loan" | "interest" => debt_value += value.abs(),
+      _ => {}
+    }
+  }
+  let mut pos = LoanPosition::new(format!("{}:{}", ex.name, pair), ex.name.to_string(), ex.currency.clone(), collateral_value, debt_value, 1_f64 / 1.1_f64);
+  let fl_price = value_f64(&item["fl-price"]);
+  if fl_price > 0_f64 {
+    pos.liquidation_price = Some(fl_price);
+  }
+  return Ok(pos);
+}
+
+// 需要先查询 uid, permission 形如 "readOnly,trade,withdraw", 杠杆借贷包含在 trade 里
+pub async fn api_permissions(ex: &Exchange) -> Result<ApiPermissions, String> {
+  let cfg = HuobiConfig::load(&ex.config)?;
+  let uid = signed_get(ex, &cfg, "/v2/user/uid", [].to_vec()).await?["data"].to_string();
+  let res = signed_get(ex, &cfg, "/v2/user/api-key", [["accessKey", cfg.access_id.as_str()], ["uid", uid.as_str()]].to_vec()).await?;
+  let key = res["data"].as_array().and_then(|keys| keys.iter().find(|k| k["accessKey"] == cfg.access_id.as_str()))
+    .ok_or(format!("{}: api key not found in {}", ex.name, res))?;
+  let permission: Vec<&str> = key["permission"].as_str().unwrap_or("").split(',').collect();
+  let trade = permission.contains(&"trade");
+  return Ok(ApiPermissions {
+    read: permission.contains(&"readOnly"),
+    trade,
+    margin: trade,
+    withdraw: permission.contains(&"withdraw"),
+    ip_restricted: !key["ipAddresses"].as_str().unwrap_or("").is_empty()
+  });
+}
+
+pub async fn depth(ex: &Exchange) -> Result<DepthInfo, String> {
+  let full_url = format!("{}://{}/market/depth?symbol={}{}&type=step0&depth=5", ex.protocol, ex.host, ex.symbol.to_lowercase(), ex.currency.to_lowercase());
+  let body_resp = reqwest::get(full_url.as_str());
+  let body_text = timed_body("HUOBI", "depth", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  if json_resp["status"] == "ok" {
+    return Ok(DepthInfo {
+      tick: Tick {
+        asks: depth_levels(&json_resp["tick"]["asks"])?,
+        bids: depth_levels(&json_resp["tick"]["bids"])?
+      },
+      ts: json_resp["ts"].as_i64().unwrap_or(0)
+    });
+  } else {
+    return Err(format!("{}: {:?}", ex.name, json_resp));
+  }
+}
+
+// 最近成交按批次从新到旧返回, 每批里可能有多笔, 展开后按成交 id 从旧到新排列
+pub async fn trades(ex: &Exchange) -> Result<Vec<TradeInfo>, String> {
+  let full_url = format!("{}://{}/market/history/trade?symbol={}{}&size=100", ex.protocol, ex.host, ex.symbol.to_lowercase(), ex.currency.to_lowercase());
+  let body_resp = reqwest::get(full_url.as_str());
+  let body_text = timed_body("HUOBI", "trades", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  if json_resp["status"] != "ok" {
+    return Err(format!("{}: {:?}", ex.name, json_resp));
+  }
+  let mut res: Vec<TradeInfo> = Vec::new();
+  for batch in json_resp["data"].as_array().ok_or(format!("{}: {}", ex.name, json_resp))?.iter() {
+    for item in batch["data"].as_array().unwrap_or(&Vec::new()).iter() {
+      res.push(TradeInfo {
+        id: item["trade-id"].as_u64().ok_or(format!("HUOBI: invalid trade {}", item))?,
+        price: value_f64(&item["price"]),
+        volume: value_f64(&item["amount"]),
+        buy: item["direction"] == "buy",
+        ts: item["ts"].as_i64().unwrap_or(0)
+      });
+    }
+  }
+  res.sort_by_key(|t| t.id);
+  return Ok(res);
+}
+
+// 限价单下在现货账户(cfg.account_id)
+pub async fn create_order(ex: &Exchange, side: OrderSide, price: f64, volume: f64) -> Result<String, String> {
+  let cfg = HuobiConfig::load(&ex.config)?;
+  let order_type = match side {
+    OrderSide::BUY => "buy-limit",
+    OrderSide::SELL => "sell-limit"
+  };
+  let json_resp = signed_post(ex, &cfg, "/v1/order/orders/place", json!({
+    "account-id": cfg.account_id,
+    "symbol": format!("{}{}", ex.symbol.to_lowercase(), ex.currency.to_lowercase()),
+    "type": order_type,
+    "amount": volume.to_string(),
+    "price": price.to_string(),
+    "source": "spot-api"
+  })).await?;
+  return Ok(String::from(json_resp["data"].as_str().ok_or(format!("{}: {}", ex.name, json_resp))?));
+}
+
+pub async fn order_info(ex: &Exchange, order_id: String) -> Result<OrderInfo, String> {
+  let cfg = HuobiConfig::load(&ex.config)?;
+  let json_resp = signed_get(ex, &cfg, &format!("/v1/order/orders/{}", order_id), [].to_vec()).await?;
+  let obj = &json_resp["data"];
+  let status = match obj["state"].as_str().unwrap_or("") {
+    "created" | "submitted" | "canceling" => OrderStatus::NEW,
+    "partial-filled" => OrderStatus::PARTIALLYFILLED,
+    "filled" => OrderStatus::FILLED,
+    "partial-canceled" | "canceled" => OrderStatus::CANCELED,
+    _ => return Err(format!("{}: unknown order state {}", ex.name, obj))
+  };
+  // 旧版接口的成交量字段是 field-amount
+  let filled = if obj["filled-amount"].is_null() { &obj["field-amount"] } else { &obj["filled-amount"] };
+  let filled_cash = if obj["filled-cash-amount"].is_null() { &obj["field-cash-amount"] } else { &obj["filled-cash-amount"] };
+  let trade_volume = value_f64(filled);
+  return Ok(OrderInfo {
+    id: order_id,
+    volume: value_f64(&obj["amount"]),
+    price: value_f64(&obj["price"]),
+    status,
+    side: if obj["type"].as_str().unwrap_or("").starts_with("buy") { OrderSide::BUY } else { OrderSide::SELL },
+    created_at: obj["created-at"].as_u64().unwrap_or(0),
+    trade_volume,
+    trade_avg_price: if trade_volume > 0_f64 { value_f64(filled_cash) / trade_volume } else { 0_f64 }
+  });
+}
+
+pub async fn cancel_order(ex: &Exchange, order_id: String) -> Result<bool, String> {
+  let cfg = HuobiConfig::load(&ex.config)?;
+  let json_resp = signed_post(ex, &cfg, &format!("/v1/order/orders/{}/submitcancel", order_id), json!({})).await?;
+  return Ok(json_resp["data"].as_str() == Some(order_id.as_str()));
+}
+
+// 批量撤销现货账户里这个交易对的挂单, 有撤销失败的返回 false
+pub async fn cancel_all_order(ex: &Exchange) -> Result<bool, String> {
+  let cfg = HuobiConfig::load(&ex.config)?;
+  let json_resp = signed_post(ex, &cfg, "/v1/order/orders/batchCancelOpenOrders", json!({
+    "account-id": cfg.account_id,
+    "symbol": format!("{}{}", ex.symbol.to_lowercase(), ex.currency.to_lowercase())
+  })).await?;
+  let failed = json_resp["data"]["failed-count"].as_u64().unwrap_or(0);
+  if failed > 0 {
+    log::warn!("{}: {} orders not cancelled", ex.name, failed);
+  }
+  return Ok(failed == 0);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn doc_config() -> HuobiConfig {
+    HuobiConfig {
+      access_id: String::from("e2xxxxxx-99xxxxxx-84xxxxxx-7xxxx"),
+      secret_key: String::from("b0xxxxxx-c6xxxxxx-94xxxxxx-dxxxx"),
+      account_id: String::new(),
+      signature_method: String::from("HmacSHA256"),
+      signature_version: String::from("2")
+    }
+  }
+
+  // 文档签名步骤里的请求和待签名字符串; 文档的 key 是打码的, 公布的签名无法复现, 只对照待签名字符串
+  #[test]
+  fn doc_pre_signed_text() {
+    // 2017-05-11T15:19:30 UTC, 毫秒部分不参与签名
+    let (param_str, payload) = huobi_payload(&doc_config(), "api.huobi.pro", "get", "/v1/order/orders", [["order-id", "1234567890"]].to_vec(), 1494515970123);
+    assert_eq!(payload, "GET\napi.huobi.pro\n/v1/order/orders\n\
+      AccessKeyId=e2xxxxxx-99xxxxxx-84xxxxxx-7xxxx&SignatureMethod=HmacSHA256&SignatureVersion=2&Timestamp=2017-05-11T15%3A19%3A30&order-id=1234567890");
+    let query = sign_huobi(&doc_config(), "api.huobi.pro", "get", "/v1/order/orders", [["order-id", "1234567890"]].to_vec(), 1494515970123);
+    assert!(query.starts_with(&format!("{}&Signature=", param_str)));
+  }
+
+  // RFC 4231 test case 2, 文档要求的 HmacSHA256 再 base64
+  #[test]
+  fn signature_is_base64_hmac_sha256() {
+    assert_eq!(hmac_base64("Jefe", "what do ya want for nothing?"), "W9zBRr9gdU5qBCQmCJV1x1oAPwidJzmDnexYuWTsOEM=");
+  }
+}
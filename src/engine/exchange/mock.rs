@@ -0,0 +1,712 @@
+// 测试用的本地交易所, 模拟 binance, huobi, okx 的 REST 接口, 用测试 key 校验签名,
+// 可以按路径安排错误, 延迟和非 json 的响应, 不联网就能测试 adapter 和监控循环.
+// 默认响应按 crv/usdt 交易对构造, 需要别的数据时用 set 覆盖
+use std::collections::{ HashMap, VecDeque };
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{ Arc, Mutex };
+use std::time;
+use hyper::{ Body, HeaderMap, Request, Response, Server };
+use hyper::service::{ make_service_fn, service_fn };
+use serde_json::{ json, Value };
+use sha2::{ Sha256 };
+use hmac::{ Hmac, Mac, NewMac };
+use super::types::{ Exchanges, SubAccount, AccountType, OrderSide, OrderStatus };
+use super::Exchange;
+use crate::keystore;
+use crate::engine::journal::ActionJournal;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub static TEST_KEY: &str = "mock-access-key";
+pub static TEST_SECRET: &str = "mock-secret-key";
+pub static TEST_PASSPHRASE: &str = "mock-passphrase";
+pub static HUOBI_ACCOUNT_ID: &str = "100009";
+
+// 对某个路径安排的响应, 按顺序每个请求消耗一个
+pub enum Script {
+  Error(u16, Value), // 返回指定的 http 状态码和交易所格式的错误
+  Delay(u64), // 等待毫秒数后正常处理
+  Body(String) // 原样返回, 用于构造非 json 或者截断的响应
+}
+
+#[derive(Debug, Clone)]
+pub struct Recorded {
+  pub method: String,
+  pub path: String,
+  pub query: String,
+  pub body: String
+}
+
+struct State {
+  venue: Exchanges,
+  fixtures: HashMap<String, Value>,
+  scripts: HashMap<String, VecDeque<Script>>,
+  requests: Vec<Recorded>
+}
+
+pub struct MockExchange {
+  pub addr: SocketAddr,
+  venue: Exchanges,
+  state: Arc<Mutex<State>>
+}
+
+impl MockExchange {
+  // 监听 127.0.0.1 的随机端口, 服务跟随测试的 runtime 退出
+  pub async fn start(venue: Exchanges) -> MockExchange {
+    let state = Arc::new(Mutex::new(State {
+      venue: venue.clone(),
+      fixtures: HashMap::new(),
+      scripts: HashMap::new(),
+      requests: Vec::new()
+    }));
+    let svc_state = state.clone();
+    let make_svc = make_service_fn(move |_| {
+      let state = svc_state.clone();
+      async move {
+        Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req)))
+      }
+    });
+    let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    MockExchange { addr, venue, state }
+  }
+
+  // 指向这个 mock 的交易所配置, 测试凭证直接放进 keystore 缓存
+  pub fn exchange(&self, symbol: &str, currency: &str) -> Exchange {
+    let config = format!("mock.{}.{}", self.venue.to_string().to_lowercase(), self.addr.port());
+    keystore::insert(&config, &keystore_fields(&self.venue));
+    Exchange {
+      name: self.venue.clone(),
+      symbol: String::from(symbol),
+      currency: String::from(currency),
+      host: self.addr.to_string(),
+      protocol: String::from("http"),
+      config,
+      protect: false,
+      subaccount: None
+    }
+  }
+
+  // 子账户的仓位, 子账户和母账户的测试凭证都放进 keystore 缓存
+  pub fn subaccount(&self, symbol: &str, currency: &str, id: &str) -> Exchange {
+    let ex = self.exchange(symbol, currency);
+    let master = format!("{}.master", ex.config);
+    keystore::insert(&master, &keystore_fields(&self.venue));
+    Exchange { subaccount: Some(SubAccount { id: String::from(id), master }), ..ex }
+  }
+
+  // 覆盖默认响应, key 是 "GET /path" 或者只写路径(不区分方法)
+  pub fn set(&self, key: &str, value: Value) {
+    self.state.lock().unwrap().fixtures.insert(String::from(key), value);
+  }
+
+  pub fn script(&self, path: &str, script: Script) {
+    self.state.lock().unwrap().scripts.entry(String::from(path)).or_default().push_back(script);
+  }
+
+  // 收到的某个路径的请求, 用于检查 adapter 发出的参数
+  pub fn requests(&self, path: &str) -> Vec<Recorded> {
+    self.state.lock().unwrap().requests.iter().filter(|r| r.path == path).cloned().collect()
+  }
+}
+
+fn keystore_fields(venue: &Exchanges) -> Vec<[&'static str;2]> {
+  match venue {
+    Exchanges::BINANCE => vec![["access_id", TEST_KEY], ["secret_key", TEST_SECRET]],
+    Exchanges::HUOBI => vec![["access_id", TEST_KEY], ["secret_key", TEST_SECRET], ["account_id", HUOBI_ACCOUNT_ID]],
+    Exchanges::OKEX => vec![["access_id", TEST_KEY], ["secret_key", TEST_SECRET], ["passphrase", TEST_PASSPHRASE], ["trade_pwd", "mock-trade-pwd"]]
+  }
+}
+
+async fn handle(state: Arc<Mutex<State>>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+  let method = req.method().to_string();
+  let path = String::from(req.uri().path());
+  let query = String::from(req.uri().query().unwrap_or(""));
+  let headers = req.headers().clone();
+  let bytes = hyper::body::to_bytes(req.into_body()).await.unwrap_or_default();
+  let body = String::from_utf8_lossy(&bytes).to_string();
+  let (venue, script) = {
+    let mut s = state.lock().unwrap();
+    s.requests.push(Recorded { method: method.clone(), path: path.clone(), query: query.clone(), body: body.clone() });
+    let script = s.scripts.get_mut(&path).and_then(|q| q.pop_front());
+    (s.venue.clone(), script)
+  };
+  match script {
+    Some(Script::Error(status, value)) => return Ok(reply(status, value.to_string())),
+    Some(Script::Body(text)) => return Ok(reply(200, text)),
+    Some(Script::Delay(ms)) => tokio::time::sleep(time::Duration::from_millis(ms)).await,
+    None => {}
+  }
+  if !is_public(&venue, &path) {
+    if let Err((status, value)) = verify(&venue, &method, &path, &query, &headers, &body) {
+      return Ok(reply(status, value.to_string()));
+    }
+  }
+  let fixture = {
+    let s = state.lock().unwrap();
+    s.fixtures.get(&format!("{} {}", method, path)).or(s.fixtures.get(&path)).cloned()
+  };
+  match fixture.or_else(|| default_response(&venue, &method, &path)) {
+    Some(value) => Ok(reply(200, value.to_string())),
+    None => Ok(reply(404, not_found(&venue, &path).to_string()))
+  }
+}
+
+fn reply(status: u16, text: String) -> Response<Body> {
+  Response::builder()
+  .status(status)
+  .header("Content-Type", "application/json")
+  .body(Body::from(text))
+  .unwrap()
+}
+
+fn is_public(venue: &Exchanges, path: &str) -> bool {
+  match venue {
+    Exchanges::BINANCE => path == "/api/v3/time" || path == "/api/v3/depth" || path == "/api/v3/trades",
+    Exchanges::HUOBI => path == "/v1/common/timestamp" || path.starts_with("/market/") || path == "/v2/reference/currencies",
+    Exchanges::OKEX => path.starts_with("/api/v5/public/") || path.starts_with("/api/v5/market/")
+  }
+}
+
+fn hmac(payload: &str) -> Vec<u8> {
+  let mut mac = HmacSha256::new_varkey(TEST_SECRET.as_bytes()).expect("HMAC can take key of any size");
+  mac.update(payload.as_bytes());
+  mac.finalize().into_bytes().to_vec()
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> &'a str {
+  headers.get(name).and_then(|v| v.to_str().ok()).unwrap_or("")
+}
+
+// 按各交易所的规则重新计算签名, 返回和交易所一致的错误格式
+fn verify(venue: &Exchanges, method: &str, path: &str, query: &str, headers: &HeaderMap, body: &str) -> Result<(), (u16, Value)> {
+  match venue {
+    Exchanges::BINANCE => {
+      if header(headers, "X-MBX-APIKEY") != TEST_KEY {
+        return Err((401, json!({ "code": -2015, "msg": "Invalid API-key, IP, or permissions for action." })));
+      }
+      let (payload, signature) = query.split_once("&signature=")
+      .ok_or((400, json!({ "code": -1102, "msg": "Mandatory parameter 'signature' was not sent, was empty/null, or malformed." })))?;
+      if !payload.contains("timestamp=") || hex::encode(hmac(&format!("{}{}", payload, body))) != signature {
+        return Err((400, json!({ "code": -1022, "msg": "Signature for this request is not valid." })));
+      }
+      Ok(())
+    }
+    Exchanges::HUOBI => {
+      let invalid = json!({ "status": "error", "err-code": "api-signature-not-valid", "err-msg": "Signature not valid: Verification failure [校验失败]", "data": null });
+      let (params, encoded) = query.split_once("&Signature=").ok_or((200, invalid.clone()))?;
+      let signature: String = url::form_urlencoded::parse(format!("Signature={}", encoded).as_bytes())
+      .next().map(|(_, v)| v.to_string()).unwrap_or_default();
+      let access_key = url::form_urlencoded::parse(params.as_bytes()).find(|(k, _)| k == "AccessKeyId").map(|(_, v)| v.to_string());
+      if access_key.as_deref() != Some(TEST_KEY) {
+        return Err((200, json!({ "status": "error", "err-code": "invalid-access-key", "err-msg": "Abnormal service, please try again later", "data": null })));
+      }
+      let payload = format!("{}\n{}\n{}\n{}", method, header(headers, "host"), path, params);
+      if base64::encode(hmac(&payload)) != signature {
+        return Err((200, invalid));
+      }
+      Ok(())
+    }
+    Exchanges::OKEX => {
+      if header(headers, "OK-ACCESS-KEY") != TEST_KEY {
+        return Err((401, json!({ "code": "50111", "msg": "Invalid OK-ACCESS-KEY", "data": [] })));
+      }
+      if header(headers, "OK-ACCESS-PASSPHRASE") != TEST_PASSPHRASE {
+        return Err((401, json!({ "code": "50105", "msg": "Invalid OK-ACCESS-PASSPHRASE", "data": [] })));
+      }
+      let path_with_query = if query.is_empty() { String::from(path) } else { format!("{}?{}", path, query) };
+      let payload = format!("{}{}{}{}", header(headers, "OK-ACCESS-TIMESTAMP"), method, path_with_query, body);
+      if base64::encode(hmac(&payload)) != header(headers, "OK-ACCESS-SIGN") {
+        return Err((401, json!({ "code": "50113", "msg": "Invalid Sign", "data": [] })));
+      }
+      Ok(())
+    }
+  }
+}
+
+fn not_found(venue: &Exchanges, path: &str) -> Value {
+  match venue {
+    Exchanges::BINANCE => json!({ "code": -1000, "msg": format!("mock: no route {}", path) }),
+    Exchanges::HUOBI => json!({ "status": "error", "err-code": "invalid-parameter", "err-msg": format!("mock: no route {}", path), "data": null }),
+    Exchanges::OKEX => json!({ "code": "50000", "msg": format!("mock: no route {}", path), "data": [] })
+  }
+}
+
+fn default_response(venue: &Exchanges, method: &str, path: &str) -> Option<Value> {
+  let now = chrono::Utc::now().timestamp_millis();
+  let value = match (venue, method, path) {
+    (Exchanges::BINANCE, "GET", "/api/v3/time") => json!({ "serverTime": now }),
+    (Exchanges::BINANCE, "GET", "/api/v3/depth") => json!({
+      "lastUpdateId": 1027024,
+      "asks": [["0.5010", "120"], ["0.5020", "80"], ["0.5030", "300"], ["0.5040", "50"], ["0.5050", "900"]],
+      "bids": [["0.5000", "100"], ["0.4990", "200"], ["0.4980", "150"], ["0.4970", "400"], ["0.4960", "700"]]
+    }),
+    (Exchanges::BINANCE, "GET", "/api/v3/trades") => json!([
+      { "id": 28457, "price": "0.5000", "qty": "12", "quoteQty": "6", "time": now - 2000, "isBuyerMaker": true, "isBestMatch": true },
+      { "id": 28458, "price": "0.5010", "qty": "30", "quoteQty": "15.03", "time": now - 1000, "isBuyerMaker": false, "isBestMatch": true }
+    ]),
+    (Exchanges::BINANCE, "GET", "/api/v3/account") => json!({
+      "balances": [
+        { "asset": "CRV", "free": "1000.00000000", "locked": "0.00000000" },
+        { "asset": "USDT", "free": "500.00000000", "locked": "10.00000000" }
+      ]
+    }),
+    (Exchanges::BINANCE, "POST", "/api/v3/order") => json!({ "symbol": "CRVUSDT", "orderId": 28, "status": "NEW" }),
+    (Exchanges::BINANCE, "GET", "/api/v3/order") => json!({
+      "symbol": "CRVUSDT", "orderId": 28, "price": "0.50000000", "origQty": "10.00000000", "executedQty": "4.00000000",
+      "status": "PARTIALLY_FILLED", "side": "BUY", "time": 1499827319559_u64
+    }),
+    (Exchanges::BINANCE, "DELETE", "/api/v3/order") => json!({ "symbol": "CRVUSDT", "orderId": 28, "status": "CANCELED" }),
+    (Exchanges::BINANCE, "DELETE", "/api/v3/openOrders") => json!([{ "symbol": "CRVUSDT", "orderId": 28, "status": "CANCELED" }]),
+    (Exchanges::BINANCE, "GET", "/sapi/v1/margin/isolated/pair") => json!({ "symbol": "CRVUSDT", "base": "CRV", "quote": "USDT", "isMarginTrade": true }),
+    (Exchanges::BINANCE, "GET", "/sapi/v1/margin/isolated/account") => json!({
+      "assets": [{
+        "symbol": "CRVUSDT", "indexPrice": "0.50000000", "liquidatePrice": "0.25000000",
+        "baseAsset": { "asset": "CRV", "totalAsset": "1000", "borrowed": "0", "interest": "0", "free": "1000", "locked": "0" },
+        "quoteAsset": { "asset": "USDT", "totalAsset": "0", "borrowed": "200", "interest": "0", "free": "0", "locked": "0" }
+      }]
+    }),
+    (Exchanges::BINANCE, "GET", "/sapi/v1/capital/config/getall") => json!([{
+      "coin": "USDT",
+      "networkList": [{ "network": "TRX", "withdrawFee": "1", "withdrawMin": "10", "withdrawEnable": true, "minConfirm": 1 }]
+    }]),
+    (Exchanges::BINANCE, "POST", "/sapi/v1/capital/withdraw/apply") => json!({ "id": "7213fea8e94b4a5593d507237e5a555b" }),
+    (Exchanges::BINANCE, "GET", "/sapi/v1/capital/withdraw/history") => json!([]),
+    (Exchanges::BINANCE, "GET", "/sapi/v3/sub-account/assets") => json!({
+      "balances": [{ "asset": "USDT", "free": 300, "locked": 0 }, { "asset": "CRV", "free": 0, "locked": 0 }]
+    }),
+    (Exchanges::BINANCE, "GET", "/sapi/v1/sub-account/margin/account") => json!({
+      "email": "sub@mock", "marginLevel": "999",
+      "marginUserAssetVoList": [{ "asset": "USDT", "borrowed": "50", "free": "20", "interest": "0.1", "locked": "0", "netAsset": "-30.1" }]
+    }),
+    (Exchanges::BINANCE, "POST", "/sapi/v1/sub-account/universalTransfer") => json!({ "tranId": 11945860693_u64, "clientTranId": "" }),
+    (Exchanges::BINANCE, "POST", "/sapi/v1/margin/isolated/transfer") => json!({ "tranId": 13526853623_u64 }),
+    (Exchanges::BINANCE, "GET", "/sapi/v1/margin/isolated/transfer") => json!({ "rows": [], "total": 0 }),
+    (Exchanges::HUOBI, "GET", "/v1/common/timestamp") => json!({ "status": "ok", "data": now }),
+    (Exchanges::HUOBI, "GET", "/v1/margin/loan-info") => json!({
+      "status": "ok",
+      "data": [{ "symbol": "crvusdt", "currencies": [{ "currency": "crv", "min-loan-amt": "10" }, { "currency": "usdt", "min-loan-amt": "10" }] }]
+    }),
+    (Exchanges::HUOBI, "GET", "/v1/margin/accounts/balance") => json!({
+      "status": "ok",
+      "data": [{
+        "id": 18264, "type": "margin", "symbol": "crvusdt", "fl-price": "0.25",
+        "list": [
+          { "currency": "crv", "type": "trade", "balance": "1000" },
+          { "currency": "usdt", "type": "loan", "balance": "-200" },
+          { "currency": "usdt", "type": "interest", "balance": "-0.5" }
+        ]
+      }]
+    }),
+    (Exchanges::HUOBI, "GET", "/market/detail/merged") => json!({ "status": "ok", "ch": "market.crvusdt.detail.merged", "tick": { "close": 0.5 } }),
+    (Exchanges::HUOBI, "GET", "/v2/reference/currencies") => json!({
+      "code": 200,
+      "data": [{
+        "currency": "usdt",
+        "chains": [{ "chain": "trc20usdt", "baseChainProtocol": "TRC20", "transactFeeWithdraw": "1", "minWithdrawAmt": "10", "withdrawStatus": "allowed", "numOfConfirmations": 1 }]
+      }]
+    }),
+    (Exchanges::HUOBI, "GET", "/market/depth") => json!({
+      "status": "ok", "ch": "market.crvusdt.depth.step0", "ts": now,
+      "tick": {
+        "asks": [[0.501, 120.0], [0.502, 80.0], [0.503, 300.0], [0.504, 50.0], [0.505, 900.0]],
+        "bids": [[0.5, 100.0], [0.499, 200.0], [0.498, 150.0], [0.497, 400.0], [0.496, 700.0]],
+        "ts": now
+      }
+    }),
+    // 按批次从新到旧
+    (Exchanges::HUOBI, "GET", "/market/history/trade") => json!({
+      "status": "ok", "ch": "market.crvusdt.trade.detail", "ts": now,
+      "data": [
+        { "id": 31459998, "ts": now - 1000, "data": [{ "id": 17592256642623_u64, "trade-id": 100050305348_u64, "amount": 30, "price": 0.501, "direction": "buy", "ts": now - 1000 }] },
+        { "id": 31459997, "ts": now - 2000, "data": [{ "id": 17592256642622_u64, "trade-id": 100050305347_u64, "amount": 12, "price": 0.5, "direction": "sell", "ts": now - 2000 }] }
+      ]
+    }),
+    (Exchanges::HUOBI, "POST", "/v1/order/orders/place") => json!({ "status": "ok", "data": "356501383558845" }),
+    (Exchanges::HUOBI, "POST", "/v1/order/orders/batchCancelOpenOrders") => json!({
+      "status": "ok", "data": { "success-count": 2, "failed-count": 0, "next-id": 5454600 }
+    }),
+    (Exchanges::HUOBI, "POST", p) if p.starts_with("/v1/order/orders/") && p.ends_with("/submitcancel") => json!({
+      "status": "ok", "data": p.trim_start_matches("/v1/order/orders/").trim_end_matches("/submitcancel")
+    }),
+    (Exchanges::HUOBI, "GET", p) if p.starts_with("/v1/order/orders/") => json!({
+      "status": "ok",
+      "data": {
+        "id": p.trim_start_matches("/v1/order/orders/").parse::<u64>().unwrap_or(0), "symbol": "crvusdt", "account-id": 100009,
+        "amount": "10.000000000000000000", "price": "0.500000000000000000", "created-at": 1494901162595_u64, "type": "buy-limit",
+        "field-amount": "4.000000000000000000", "field-cash-amount": "1.960000000000000000", "state": "partial-filled"
+      }
+    }),
+    (Exchanges::HUOBI, "GET", "/v1/account/accounts") => json!({
+      "status": "ok",
+      "data": [
+        { "id": 100009, "type": "spot", "subtype": "", "state": "working" },
+        { "id": 18264, "type": "margin", "subtype": "crvusdt", "state": "working" }
+      ]
+    }),
+    (Exchanges::HUOBI, "GET", "/v1/account/accounts/18264/balance") => json!({
+      "status": "ok",
+      "data": {
+        "id": 18264, "type": "margin", "state": "working",
+        "list": [
+          { "currency": "crv", "type": "trade", "balance": "1000" },
+          { "currency": "usdt", "type": "loan", "balance": "-200" },
+          { "currency": "usdt", "type": "interest", "balance": "-0.5" }
+        ]
+      }
+    }),
+    (Exchanges::HUOBI, "POST", "/v2/account/repayment") => json!({ "code": 200, "data": [{ "repayId": "1174424", "repayTime": now }] }),
+    (Exchanges::HUOBI, "POST", "/v1/dw/withdraw/api/create") => json!({ "status": "ok", "data": 700 }),
+    (Exchanges::HUOBI, "POST", "/v1/subuser/transfer") => json!({ "status": "ok", "data": 12345 }),
+    (Exchanges::HUOBI, "GET", p) if p.starts_with("/v1/account/accounts/") && !p.ends_with("/balance") => json!({
+      "status": "ok",
+      "data": [
+        { "id": 9910049, "type": "spot", "list": [{ "currency": "usdt", "type": "trade", "balance": "300" }, { "currency": "crv", "type": "trade", "balance": "0" }] },
+        { "id": 9910050, "type": "margin", "subtype": "crvusdt", "list": [
+          { "currency": "crv", "type": "trade", "balance": "1000" },
+          { "currency": "usdt", "type": "loan", "balance": "-200" }
+        ] },
+        { "id": 9910051, "type": "point", "list": [] }
+      ]
+    }),
+    (Exchanges::HUOBI, "POST", "/v1/dw/transfer-in/margin") => json!({ "status": "ok", "data": 1000 }),
+    (Exchanges::HUOBI, "GET", "/v1/account/history") => json!({ "status": "ok", "data": [] }),
+    (Exchanges::HUOBI, "GET", p) if p == format!("/v1/account/accounts/{}/balance", HUOBI_ACCOUNT_ID) => json!({
+      "status": "ok",
+      "data": {
+        "id": 100009, "type": "spot", "state": "working",
+        "list": [
+          { "currency": "crv", "type": "trade", "balance": "1000" },
+          { "currency": "crv", "type": "frozen", "balance": "0" },
+          { "currency": "usdt", "type": "trade", "balance": "500" },
+          { "currency": "usdt", "type": "frozen", "balance": "10" }
+        ]
+      }
+    }),
+    (Exchanges::OKEX, "GET", "/api/v5/public/time") => json!({ "code": "0", "msg": "", "data": [{ "ts": now.to_string() }] }),
+    (Exchanges::OKEX, "GET", "/api/v5/asset/balances") => json!({
+      "code": "0", "msg": "",
+      "data": [{ "ccy": "CRV", "bal": "1000", "availBal": "1000", "frozenBal": "0" }, { "ccy": "USDT", "bal": "510", "availBal": "500", "frozenBal": "10" }]
+    }),
+    (Exchanges::OKEX, "GET", "/api/v5/account/balance") => json!({ "code": "0", "msg": "", "data": [{ "details": [] }] }),
+    (Exchanges::OKEX, "GET", "/api/v5/account/positions") => json!({
+      "code": "0", "msg": "",
+      "data": [{
+        "instType": "MARGIN", "instId": "CRV-USDT", "mgnMode": "isolated", "posSide": "net",
+        "pos": "1000", "posCcy": "CRV", "margin": "100", "ccy": "USDT", "liab": "-200", "liabCcy": "USDT", "interest": "0.5",
+        "last": "0.5", "liqPx": "0.25", "mgnRatio": "1.5"
+      }]
+    }),
+    (Exchanges::OKEX, "GET", "/api/v5/market/books") => json!({
+      "code": "0", "msg": "",
+      "data": [{
+        "asks": [["0.501", "120", "0", "3"], ["0.502", "80", "0", "1"], ["0.503", "300", "0", "2"], ["0.504", "50", "0", "1"], ["0.505", "900", "0", "4"]],
+        "bids": [["0.5", "100", "0", "2"], ["0.499", "200", "0", "1"], ["0.498", "150", "0", "1"], ["0.497", "400", "0", "3"], ["0.496", "700", "0", "5"]],
+        "ts": now.to_string()
+      }]
+    }),
+    // 从新到旧
+    (Exchanges::OKEX, "GET", "/api/v5/market/trades") => json!({
+      "code": "0", "msg": "",
+      "data": [
+        { "instId": "CRV-USDT", "tradeId": "242720721", "px": "0.501", "sz": "30", "side": "buy", "ts": (now - 1000).to_string() },
+        { "instId": "CRV-USDT", "tradeId": "242720720", "px": "0.5", "sz": "12", "side": "sell", "ts": (now - 2000).to_string() }
+      ]
+    }),
+    (Exchanges::OKEX, "POST", "/api/v5/trade/order") => json!({
+      "code": "0", "msg": "",
+      "data": [{ "ordId": "312269865356374016", "clOrdId": "", "tag": "", "sCode": "0", "sMsg": "" }]
+    }),
+    (Exchanges::OKEX, "GET", "/api/v5/trade/order") => json!({
+      "code": "0", "msg": "",
+      "data": [{
+        "instId": "CRV-USDT", "ordId": "312269865356374016", "px": "0.5", "sz": "10", "accFillSz": "4", "avgPx": "0.49",
+        "state": "partially_filled", "side": "buy", "ordType": "limit", "cTime": "1597026383085"
+      }]
+    }),
+    (Exchanges::OKEX, "POST", "/api/v5/trade/cancel-order") => json!({
+      "code": "0", "msg": "",
+      "data": [{ "ordId": "312269865356374016", "clOrdId": "", "sCode": "0", "sMsg": "" }]
+    }),
+    (Exchanges::OKEX, "GET", "/api/v5/trade/orders-pending") => json!({
+      "code": "0", "msg": "",
+      "data": [{ "instId": "CRV-USDT", "ordId": "312269865356374016", "state": "partially_filled" }, { "instId": "CRV-USDT", "ordId": "312269865356374017", "state": "live" }]
+    }),
+    (Exchanges::OKEX, "POST", "/api/v5/trade/cancel-batch-orders") => json!({
+      "code": "0", "msg": "",
+      "data": [{ "ordId": "312269865356374016", "sCode": "0", "sMsg": "" }, { "ordId": "312269865356374017", "sCode": "0", "sMsg": "" }]
+    }),
+    (Exchanges::OKEX, "GET", "/api/v5/asset/currencies") => json!({
+      "code": "0", "msg": "",
+      "data": [{ "ccy": "USDT", "chain": "USDT-TRC20", "minFee": "0.8", "minWd": "2", "canWd": true, "minWdUnlockConfirm": "2" }]
+    }),
+    (Exchanges::OKEX, "GET", "/api/v5/asset/subaccount/balances") => json!({
+      "code": "0", "msg": "",
+      "data": [{ "ccy": "USDT", "bal": "300", "availBal": "300", "frozenBal": "0" }]
+    }),
+    (Exchanges::OKEX, "GET", "/api/v5/account/subaccount/balances") => json!({
+      "code": "0", "msg": "",
+      "data": [{ "details": [{ "ccy": "CRV", "availBal": "1000", "frozenBal": "0", "liab": "0", "interest": "0" }, { "ccy": "USDT", "availBal": "0", "frozenBal": "0", "liab": "200", "interest": "0.5" }] }]
+    }),
+    (Exchanges::OKEX, "GET", "/api/v5/account/bills") => json!({ "code": "0", "msg": "", "data": [] }),
+    (Exchanges::OKEX, "POST", "/api/v5/account/position/margin-balance") => json!({
+      "code": "0", "msg": "",
+      "data": [{ "instId": "CRV-USDT", "posSide": "net", "amt": "10", "type": "add" }]
+    }),
+    (Exchanges::OKEX, "POST", "/api/v5/asset/transfer") => json!({
+      "code": "0", "msg": "",
+      "data": [{ "transId": "754147", "ccy": "USDT", "from": "6", "to": "18", "amt": "10" }]
+    }),
+    (Exchanges::OKEX, "POST", "/api/v5/asset/withdrawal") => json!({
+      "code": "0", "msg": "",
+      "data": [{ "wdId": "67485", "ccy": "USDT", "chain": "USDT-TRC20", "clientId": "" }]
+    }),
+    _ => return None
+  };
+  Some(value)
+}
+
+// 整个模块只在测试时编译, 各交易所 adapter 对着 mock 的测试直接放在这里
+#[tokio::test]
+async fn binance_market_and_account() {
+  let mock = MockExchange::start(Exchanges::BINANCE).await;
+  let ex = mock.exchange("crv", "usdt");
+  let depth = ex.depth().await.unwrap();
+  assert_eq!(depth.tick.asks[0], [0.501, 120_f64]);
+  assert_eq!(depth.tick.bids.len(), 5);
+  let trades = ex.trades().await.unwrap();
+  assert_eq!(trades.len(), 2);
+  assert!(!trades[0].buy && trades[1].buy);
+  assert_eq!(trades[1].volume, 30_f64);
+  let account = ex.account_info().await.unwrap();
+  assert_eq!(account.available_symbol, 1000_f64);
+  assert_eq!(account.frozen_currency, 10_f64);
+  assert_eq!(ex.loan_info().await.unwrap().symbol, "crv");
+  let pos = ex.position().await.unwrap();
+  assert_eq!(pos.collateral_value, 500_f64);
+  assert_eq!(pos.debt_value, 200_f64);
+  assert_eq!(pos.liquidation_price, Some(0.25));
+}
+
+#[tokio::test]
+async fn binance_orders() {
+  let mock = MockExchange::start(Exchanges::BINANCE).await;
+  let ex = mock.exchange("crv", "usdt");
+  let id = ex.create_order(OrderSide::BUY, 0.5, 10_f64).await.unwrap();
+  assert_eq!(id, "28");
+  let sent = &mock.requests("/api/v3/order")[0];
+  assert_eq!(sent.method, "POST");
+  assert!(sent.query.contains("symbol=CRVUSDT&side=BUY&type=LIMIT"));
+  let info = ex.order_info(id.clone()).await.unwrap();
+  assert_eq!(info.status, OrderStatus::PARTIALLYFILLED);
+  assert_eq!(info.trade_volume, 4_f64);
+  assert!(ex.cancel_order(id).await.unwrap());
+  assert!(ex.cancel_all_order().await.unwrap());
+}
+
+#[tokio::test]
+async fn huobi_and_okex_market_and_orders() {
+  let huobi = MockExchange::start(Exchanges::HUOBI).await;
+  let ex = huobi.exchange("crv", "usdt");
+  let depth = ex.depth().await.unwrap();
+  assert_eq!(depth.tick.asks[0], [0.501, 120_f64]);
+  assert_eq!(depth.tick.bids.len(), 5);
+  let trades = ex.trades().await.unwrap();
+  assert_eq!(trades.iter().map(|t| (t.id, t.buy)).collect::<Vec<_>>(), vec![(100050305347, false), (100050305348, true)]);
+  let id = ex.create_order(OrderSide::SELL, 0.5, 10_f64).await.unwrap();
+  assert_eq!(id, "356501383558845");
+  let body: Value = serde_json::from_str(&huobi.requests("/v1/order/orders/place")[0].body).unwrap();
+  assert_eq!((body["account-id"].as_str(), body["type"].as_str(), body["amount"].as_str()), (Some(HUOBI_ACCOUNT_ID), Some("sell-limit"), Some("10")));
+  let info = ex.order_info(id.clone()).await.unwrap();
+  assert_eq!(info.status, OrderStatus::PARTIALLYFILLED);
+  assert_eq!((info.trade_volume, info.trade_avg_price), (4_f64, 0.49));
+  assert!(ex.cancel_order(id).await.unwrap());
+  assert!(ex.cancel_all_order().await.unwrap());
+
+  let okex = MockExchange::start(Exchanges::OKEX).await;
+  let ex = okex.exchange("crv", "usdt");
+  let depth = ex.depth().await.unwrap();
+  assert_eq!(depth.tick.bids[0], [0.5, 100_f64]);
+  assert_eq!(depth.tick.asks.len(), 5);
+  let trades = ex.trades().await.unwrap();
+  assert_eq!(trades.iter().map(|t| (t.id, t.buy)).collect::<Vec<_>>(), vec![(242720720, false), (242720721, true)]);
+  let id = ex.create_order(OrderSide::BUY, 0.5, 10_f64).await.unwrap();
+  let body: Value = serde_json::from_str(&okex.requests("/api/v5/trade/order")[0].body).unwrap();
+  assert_eq!(body, json!({ "instId": "CRV-USDT", "tdMode": "cash", "side": "buy", "ordType": "limit", "px": "0.5", "sz": "10" }));
+  let info = ex.order_info(id.clone()).await.unwrap();
+  assert_eq!((info.status, info.trade_volume, info.trade_avg_price), (OrderStatus::PARTIALLYFILLED, 4_f64, 0.49));
+  assert!(ex.cancel_order(id).await.unwrap());
+  assert!(ex.cancel_all_order().await.unwrap());
+  let body: Value = serde_json::from_str(&okex.requests("/api/v5/trade/cancel-batch-orders")[0].body).unwrap();
+  assert_eq!(body.as_array().unwrap().len(), 2);
+  // 单个订单失败时整体请求仍然返回 code 0
+  okex.set("POST /api/v5/trade/order", json!({ "code": "1", "msg": "", "data": [{ "ordId": "", "sCode": "51008", "sMsg": "Insufficient balance" }] }));
+  assert!(ex.create_order(OrderSide::BUY, 0.5, 10_f64).await.unwrap_err().contains("51008"));
+}
+
+#[tokio::test]
+async fn huobi_and_okex_margin() {
+  let huobi = MockExchange::start(Exchanges::HUOBI).await;
+  let ex = huobi.exchange("crv", "usdt");
+  assert_eq!(ex.repay(10_f64).await.unwrap(), "1174424");
+  let body: Value = serde_json::from_str(&huobi.requests("/v2/account/repayment")[0].body).unwrap();
+  assert_eq!(body, json!({ "accountId": "18264", "currency": "usdt", "amount": "10" }));
+  let balances = ex.balances().await.unwrap();
+  let isolated = AccountType::ISOLATEDMARGIN(String::from("crvusdt"));
+  assert_eq!(balances.get(&isolated, "usdt").unwrap().interest, 0.5);
+  assert_eq!(balances.get(&AccountType::SPOT, "usdt").unwrap().locked, 10_f64);
+
+  let okex = MockExchange::start(Exchanges::OKEX).await;
+  let ex = okex.exchange("crv", "usdt");
+  let pos = ex.position().await.unwrap();
+  assert_eq!(pos.id, "OKEX:crvusdt");
+  assert_eq!((pos.collateral_value, pos.debt_value), (600_f64, 200.5));
+  assert_eq!(pos.liquidation_price, Some(0.25));
+  let balances = ex.balances().await.unwrap();
+  assert_eq!(balances.get(&AccountType::ISOLATEDMARGIN(String::from("crvusdt")), "usdt").unwrap().borrowed, 200_f64);
+}
+
+#[tokio::test]
+async fn withdraw_on_each_venue() {
+  let binance = MockExchange::start(Exchanges::BINANCE).await;
+  let id = binance.exchange("crv", "usdt").withdraw(String::from("usdt"), String::from("TXaddress"), 100_f64).await.unwrap();
+  assert_eq!(id, "7213fea8e94b4a5593d507237e5a555b");
+  assert!(binance.requests("/sapi/v1/capital/withdraw/apply")[0].query.contains("network=trx"));
+
+  let huobi = MockExchange::start(Exchanges::HUOBI).await;
+  let id = huobi.exchange("crv", "usdt").withdraw(String::from("usdt"), String::from("TXaddress"), 100_f64).await.unwrap();
+  assert_eq!(id, "700");
+  let body: Value = serde_json::from_str(&huobi.requests("/v1/dw/withdraw/api/create")[0].body).unwrap();
+  // 火币的提币数量不含手续费
+  assert_eq!(body["amount"], "99");
+
+  let okex = MockExchange::start(Exchanges::OKEX).await;
+  let id = okex.exchange("crv", "usdt").withdraw(String::from("usdt"), String::from("TXaddress"), 100_f64).await.unwrap();
+  assert_eq!(id, "67485");
+  let body: Value = serde_json::from_str(&okex.requests("/api/v5/asset/withdrawal")[0].body).unwrap();
+  assert_eq!(body["chain"], "USDT-TRC20");
+  assert_eq!(body["amt"], "99.2");
+  assert_eq!(body["fee"], "0.8");
+}
+
+// 所有交易所的 amount 都包含手续费, 等于 min_amount 时可以提币, 和调仓计划的数量一致
+#[tokio::test]
+async fn withdraw_min_amount_includes_fee() {
+  for venue in [Exchanges::BINANCE, Exchanges::HUOBI, Exchanges::OKEX] {
+    let mock = MockExchange::start(venue.clone()).await;
+    let ex = mock.exchange("crv", "usdt");
+    let networks = crate::engine::exchange::catalog::refresh(&ex, "usdt").await.unwrap();
+    let min = networks[0].min_amount;
+    let network = networks[0].network.clone();
+    assert!(ex.withdraw_on_chain(String::from("usdt"), network.clone(), String::from("TXaddress"), min, String::new()).await.is_ok(), "{}", venue);
+    let err = ex.withdraw_on_chain(String::from("usdt"), network, String::from("TXaddress"), min - 0.5, String::new()).await.unwrap_err();
+    assert!(err.contains("less than min amount"), "{}: {}", venue, err);
+  }
+}
+
+#[tokio::test]
+async fn huobi_and_okex_account() {
+  let huobi = MockExchange::start(Exchanges::HUOBI).await;
+  let ex = huobi.exchange("crv", "usdt");
+  assert_eq!(ex.account_info().await.unwrap().available_currency, 500_f64);
+  assert_eq!(ex.loan_info().await.unwrap().min_volume, 10_f64);
+  let pos = ex.position().await.unwrap();
+  assert_eq!(pos.collateral_value, 500_f64);
+  assert_eq!(pos.debt_value, 200.5);
+
+  let okex = MockExchange::start(Exchanges::OKEX).await;
+  let account = okex.exchange("crv", "usdt").account_info().await.unwrap();
+  assert_eq!(account.available_symbol, 1000_f64);
+  assert_eq!(account.frozen_currency, 10_f64);
+}
+
+#[tokio::test]
+async fn wrong_secret_is_rejected() {
+  for venue in [Exchanges::BINANCE, Exchanges::HUOBI, Exchanges::OKEX] {
+    let mock = MockExchange::start(venue.clone()).await;
+    let ex = mock.exchange("crv", "usdt");
+    keystore::insert(&ex.config, &[["access_id", TEST_KEY], ["secret_key", "wrong"], ["account_id", HUOBI_ACCOUNT_ID], ["passphrase", TEST_PASSPHRASE]]);
+    let err = ex.account_info().await.unwrap_err();
+    let expected = match venue {
+      Exchanges::BINANCE => "-1022",
+      Exchanges::HUOBI => "api-signature-not-valid",
+      Exchanges::OKEX => "50113"
+    };
+    assert!(err.contains(expected), "{}: {}", venue, err);
+  }
+}
+
+#[tokio::test]
+async fn scripted_failures() {
+  let mock = MockExchange::start(Exchanges::BINANCE).await;
+  let ex = mock.exchange("crv", "usdt");
+  mock.script("/sapi/v1/margin/isolated/account", Script::Error(429, json!({ "code": -1003, "msg": "Too many requests." })));
+  assert!(ex.position().await.unwrap_err().contains("-1003"));
+  // 网关错误页和截断的响应返回 Err 而不是 panic
+  mock.script("/sapi/v1/margin/isolated/account", Script::Body(String::from("<html>502 Bad Gateway</html>")));
+  assert!(ex.position().await.unwrap_err().contains("JSON ERROR"));
+  mock.script("/api/v3/time", Script::Body(String::from("{\"serverTime\":")));
+  assert!(ex.account_info().await.is_err());
+  // 脚本用完后恢复默认响应
+  assert!(ex.position().await.is_ok());
+}
+
+#[tokio::test]
+async fn delayed_response() {
+  let mock = MockExchange::start(Exchanges::OKEX).await;
+  let ex = mock.exchange("crv", "usdt");
+  mock.script("/api/v5/asset/balances", Script::Delay(500));
+  let slow = tokio::time::timeout(time::Duration::from_millis(100), ex.account_info()).await;
+  assert!(slow.is_err());
+  mock.script("/api/v5/asset/balances", Script::Delay(50));
+  let started = time::Instant::now();
+  assert!(ex.account_info().await.is_ok());
+  assert!(started.elapsed() >= time::Duration::from_millis(50));
+}
+
+#[tokio::test]
+async fn subaccount_balances_and_refill_on_each_venue() {
+  let binance = MockExchange::start(Exchanges::BINANCE).await;
+  let ex = binance.subaccount("crv", "usdt", "sub@mock");
+  let balances = ex.subaccount_balances().await.unwrap();
+  assert_eq!(balances.get(&AccountType::SPOT, "usdt").unwrap().free, 300_f64);
+  assert_eq!(balances.get(&AccountType::MARGIN, "usdt").unwrap().borrowed, 50_f64);
+  let mut journal = ActionJournal::untracked("binance-sub", "refill", 10_f64);
+  assert_eq!(ex.refill(10_f64, &mut journal).await.unwrap(), "11945860693");
+  let sent = &binance.requests("/sapi/v1/sub-account/universalTransfer")[0];
+  assert!(sent.query.contains("toEmail=sub%40mock&fromAccountType=SPOT&toAccountType=ISOLATED_MARGIN&asset=USDT&amount=10&symbol=CRVUSDT"), "{}", sent.query);
+  assert_eq!(journal.steps.len(), 1);
+
+  // 火币先转到子账户的现货账户, 再用子账户的 key 转入逐仓
+  let huobi = MockExchange::start(Exchanges::HUOBI).await;
+  let ex = huobi.subaccount("crv", "usdt", "146507");
+  let balances = ex.subaccount_balances().await.unwrap();
+  assert_eq!(balances.get(&AccountType::SPOT, "usdt").unwrap().free, 300_f64);
+  let isolated = AccountType::ISOLATEDMARGIN(String::from("crvusdt"));
+  assert_eq!(balances.get(&isolated, "usdt").unwrap().borrowed, 200_f64);
+  assert_eq!(huobi.requests("/v1/account/accounts/146507").len(), 1);
+  let mut journal = ActionJournal::untracked("huobi-sub", "refill", 10_f64);
+  assert_eq!(ex.refill(10_f64, &mut journal).await.unwrap(), "12345, 1000");
+  let body: Value = serde_json::from_str(&huobi.requests("/v1/subuser/transfer")[0].body).unwrap();
+  assert_eq!(body, json!({ "sub-uid": "146507", "currency": "usdt", "amount": "10", "type": "master-transfer-out" }));
+  let body: Value = serde_json::from_str(&huobi.requests("/v1/dw/transfer-in/margin")[0].body).unwrap();
+  assert_eq!(body["symbol"], "crvusdt");
+  assert_eq!(journal.steps.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), vec!["master -> sub SPOT", "transfer SPOT -> ISOLATEDMARGIN:crvusdt"]);
+  assert!(ex.subaccount_transfer(String::from("usdt"), 10_f64, true, isolated).await.unwrap_err().contains("only SPOT"));
+
+  // OKX 先转到子账户的资金账户, 再转到交易账户和仓位
+  let okex = MockExchange::start(Exchanges::OKEX).await;
+  let ex = okex.subaccount("crv", "usdt", "mocksub");
+  let balances = ex.subaccount_balances().await.unwrap();
+  assert_eq!(balances.get(&AccountType::FUNDING, "usdt").unwrap().free, 300_f64);
+  assert_eq!(balances.get(&AccountType::MARGIN, "usdt").unwrap().borrowed, 200_f64);
+  let mut journal = ActionJournal::untracked("okex-sub", "refill", 10_f64);
+  assert_eq!(ex.refill(10_f64, &mut journal).await.unwrap(), "754147, CRV-USDT");
+  let transfers = okex.requests("/api/v5/asset/transfer");
+  assert_eq!(transfers.len(), 2);
+  let body: Value = serde_json::from_str(&transfers[0].body).unwrap();
+  assert_eq!((body["subAcct"].as_str(), body["type"].as_str()), (Some("mocksub"), Some("1")));
+  assert_eq!(journal.steps.len(), 3);
+}
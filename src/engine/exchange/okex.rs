@@ -1,82 +1,75 @@
 use std::{collections::HashMap};
 use super::config::{ OkexConfig, OKEX_USDT_WITHDRAW_CHAIN };
-use super::types::{ MarketInfo, MarketStatus, DepthInfo, Tick, AccountInfo, OrderInfo, OrderStatus, OrderSide, LoanInfo };
-use serde_json::{ Value };
+use super::types::{ MarketInfo, MarketStatus, DepthInfo, Tick, TradeInfo, AccountInfo, OrderInfo, OrderStatus, OrderSide, LoanInfo, NetworkInfo, AccountType, Balance, ApiPermissions, SubAccount };
+use super::catalog;
+use crate::engine::position::LoanPosition;
+use serde_json::{ json, Value };
 use base64::{ encode };
 use sha2::{Sha256};
 use hmac::{Hmac, Mac, NewMac};
 use chrono::offset::Utc;
-use chrono::{ DateTime, NaiveDateTime };
+use chrono::{ DateTime };
 use super::Exchange;
-use crate::util::handle_body;
+use crate::util::{ timed_body, parse_json, value_f64, same_amount, depth_levels };
 
 // Create alias for HMAC-SHA256
 type HmacSha256 = Hmac<Sha256>;
 
 // for okex sign
-async fn build_okex_sign(cfg: &OkexConfig, protocol: &str, host: &str, method: &str, path: &str, body: Vec<[&str;2]>, is_array: bool) -> Result<(String, String, String), String> {
+async fn build_okex_sign(cfg: &OkexConfig, protocol: &str, host: &str, method: &str, path: &str, body: &Value) -> Result<(String, String, String), String> {
   // get server time
+  let started = std::time::Instant::now();
   let full_url = format!("{}://{}/api/v5/public/time", protocol, host);
-  let body_resp = reqwest::get(&full_url).await;
-  let body_text = handle_body(body_resp).await?;
-  let json_resp: Value = serde_json::from_str(body_text.as_str()).expect("json parse error");
-  let ts_str: String = json_resp["data"][0]["ts"].as_str().expect("read ts error").to_string();
-  let timestamp: i64 = ts_str.parse::<i64>().expect("parse time_str error");
-  let dt = NaiveDateTime::from_timestamp(timestamp / 1000, (timestamp as f64 % 1000_f64) as u32 * 1_000_000);
-  let datetime: DateTime<Utc> = DateTime::from_utc(dt, Utc);
-  let mut body_serilizer = if body.len() > 0 && !is_array { String::from("{") } else { String::from("") };
-  for (i, item) in body.iter().enumerate() {
-    if i == body.len() - 1 {
-      // 最后一项
-      if item[1].chars().nth(0).unwrap() == '[' {
-        if item[0] == "" {
-          body_serilizer += item[1];
-        } else {
-          body_serilizer += &format!("\"{}\":{}}}", item[0], item[1]);
-        }
-      } else {
-        body_serilizer += &format!("\"{}\":\"{}\"}}", item[0], item[1]);
-      }
-    } else {
-      // 非最后一项
-      if item[1].chars().nth(0).unwrap() == '[' {
-        if item[0] == "" {
-          body_serilizer += item[1];
-        } else {
-          body_serilizer += &format!("\"{}\":{},", item[0], item[1]);
-        }
-      } else {
-        body_serilizer += &format!("\"{}\":\"{}\",", item[0], item[1]);
-      }
-    }
-  }
-  let time_str = datetime.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
-  let full_str = format!("{}{}{}{}", time_str, method.to_uppercase(), path, body_serilizer);
-  let mut mac = HmacSha256::new_varkey(cfg.secret_key.as_bytes()).expect("HMAC can take key of any size");
-  mac.update(full_str.as_bytes());
-  let signature = encode(mac.finalize().into_bytes());
-  return Ok((
-    signature.to_string(),
+  let body_resp = reqwest::get(&full_url);
+  let body_text = timed_body("OKEX", "server_time", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  let timestamp: i64 = json_resp["data"][0]["ts"].as_str().and_then(|ts| ts.parse::<i64>().ok())
+  .ok_or(format!("OKEX: read ts error: {}", json_resp))?;
+  crate::metrics::clock_drift("OKEX", timestamp, started.elapsed().as_millis() as i64);
+  return Ok(sign_okex(cfg, method, path, body, timestamp));
+}
+
+// 签名本身不依赖网络, 时间戳(毫秒)由调用方传入, 可以用文档里的例子测试;
+// 返回 (签名, 时间, body), 发送的 body 就是签名用的字符串, Null 表示没有 body
+pub fn sign_okex(cfg: &OkexConfig, method: &str, path: &str, body: &Value, timestamp: i64) -> (String, String, String) {
+  let (payload, time_str, body_str) = okex_payload(method, path, body, timestamp);
+  return (
+    hmac_base64(&cfg.secret_key, &payload),
     time_str,
-    body_serilizer
-  ));
+    body_str
+  );
+}
+
+fn hmac_base64(secret: &str, payload: &str) -> String {
+  let mut mac = HmacSha256::new_varkey(secret.as_bytes()).expect("HMAC can take key of any size");
+  mac.update(payload.as_bytes());
+  return encode(mac.finalize().into_bytes());
+}
+
+// 待签名字符串是 时间 + 大写方法 + 带参数的路径 + body
+fn okex_payload(method: &str, path: &str, body: &Value, timestamp: i64) -> (String, String, String) {
+  let datetime: DateTime<Utc> = DateTime::from_timestamp(timestamp / 1000, (timestamp % 1000) as u32 * 1_000_000).unwrap_or_default();
+  let body_str = if body.is_null() { String::new() } else { body.to_string() };
+  let time_str = datetime.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+  let payload = format!("{}{}{}{}", time_str, method.to_uppercase(), path, body_str);
+  return (payload, time_str, body_str);
 }
 
 // api desprated
 pub async fn loan_info(ex: &Exchange) -> Result<LoanInfo, String> {
-  let cfg: OkexConfig = confy::load(&ex.config).expect("read okex config error");
+  let cfg = OkexConfig::load(&ex.config)?;
   let path = format!("/api/margin/v3/accounts/{}-{}/availability", ex.symbol.to_uppercase(), ex.currency.to_uppercase());
-  let (sign, timestamp, _body) = build_okex_sign(&cfg, &ex.protocol, &ex.host, "GET", &path, [].to_vec(), false).await?;
+  let (sign, timestamp, _body) = build_okex_sign(&cfg, &ex.protocol, &ex.host, "GET", &path, &Value::Null).await?;
   let full_url = format!("{}://{}{}", ex.protocol, ex.host, path);
   let client = reqwest::Client::new();
   let body_resp = client.get(full_url.as_str())
-  .header("OK-ACCESS-KEY", cfg.access_id)
+  .header("OK-ACCESS-KEY", cfg.access_id.as_str())
   .header("OK-ACCESS-SIGN", sign)
   .header("OK-ACCESS-TIMESTAMP", timestamp)
-  .header("OK-ACCESS-PASSPHRASE", cfg.passphrase)
-  .send().await;
-  let body_text = handle_body(body_resp).await?;
-  let json_resp: Value = serde_json::from_str(body_text.as_str()).expect("json parse error");
+  .header("OK-ACCESS-PASSPHRASE", cfg.passphrase.as_str())
+  .send();
+  let body_text = timed_body("OKEX", "loan_info", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
   let key = format!("currency:{}", ex.symbol.to_uppercase());
   if json_resp.is_array() {
     let asset_item: &Value = &json_resp[0];
@@ -94,21 +87,21 @@ pub async fn loan_info(ex: &Exchange) -> Result<LoanInfo, String> {
   }
 }
 
-// 查询资金账户，OKEX还有个交易账户
+// 查询资金账户，OKEX还有个交易账户, 全部账户的余额见 balances
 pub async fn account_info(ex: &Exchange) -> Result<AccountInfo, String> {
-  let cfg: OkexConfig = confy::load(&ex.config).expect("read okex config error");
+  let cfg = OkexConfig::load(&ex.config)?;
   let path = format!("/api/v5/asset/balances?ccy={},{}", ex.symbol.to_uppercase(), ex.currency.to_uppercase());
-  let (sign, timestamp, _body) = build_okex_sign(&cfg, &ex.protocol, &ex.host, "GET", &path, [].to_vec(), false).await?;
+  let (sign, timestamp, _body) = build_okex_sign(&cfg, &ex.protocol, &ex.host, "GET", &path, &Value::Null).await?;
   let full_url = format!("{}://{}{}", ex.protocol, ex.host, path);
   let client = reqwest::Client::new();
   let body_resp = client.get(full_url.as_str())
-  .header("OK-ACCESS-KEY", cfg.access_id)
+  .header("OK-ACCESS-KEY", cfg.access_id.as_str())
   .header("OK-ACCESS-SIGN", sign)
   .header("OK-ACCESS-TIMESTAMP", timestamp)
-  .header("OK-ACCESS-PASSPHRASE", cfg.passphrase)
-  .send().await;
-  let body_text = handle_body(body_resp).await?;
-  let json_resp: Value = serde_json::from_str(body_text.as_str()).expect("json parse error");
+  .header("OK-ACCESS-PASSPHRASE", cfg.passphrase.as_str())
+  .send();
+  let body_text = timed_body("OKEX", "account_info", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
   if json_resp["code"] == "0" {
     let arr= json_resp["data"].as_array().expect("read details error");
     let symbol_item_opt = arr.iter().find(|x| x["ccy"] == ex.symbol.to_uppercase());
@@ -140,59 +133,526 @@ pub async fn account_info(ex: &Exchange) -> Result<AccountInfo, String> {
 }
 
 pub async fn withdraw(ex: &Exchange, asset: String, address: String, amount: f64) -> Result<String, String> {
-  let cfg: OkexConfig = confy::load(&ex.config).expect("read okex config error");
   let mut currency = asset.clone();
   if asset.to_uppercase().eq("USDT") {
     currency = String::from(OKEX_USDT_WITHDRAW_CHAIN);
   }
-  // get fee
-  let path = format!("/api/v5/asset/currencies");
-  let (sign, timestamp, _body) = build_okex_sign(&cfg, &ex.protocol, &ex.host, "GET", &path, [].to_vec(), false).await?;
+  return withdraw_on_chain(ex, asset, currency, address, amount, String::new()).await;
+}
+
+pub async fn withdraw_on_chain(ex: &Exchange, asset: String, currency: String, address: String, amount: f64, client_id: String) -> Result<String, String> {
+  let cfg = OkexConfig::load(&ex.config)?;
+  let info = catalog::network(ex, &asset, &currency).await?;
+  if amount < info.min_amount {
+    return Err(format!("{}: withdraw {} {} less than min amount {}", ex.name, amount, asset, info.min_amount));
+  }
+  let path = "/api/v5/asset/withdrawal";
+  // amt 不含手续费, 手续费另外从资金账户扣除
+  let mut body = json!({
+    "amt": (amount - info.fee).to_string(),
+    "ccy": asset.to_uppercase(),
+    "chain": info.network,
+    "dest": "4",
+    "toAddr": address,
+    "pwd": cfg.trade_pwd.as_str(),
+    "fee": info.fee.to_string()
+  });
+  if !client_id.is_empty() {
+    body["clientId"] = json!(client_id);
+  }
+  let (sign, timestamp, body) = build_okex_sign(&cfg, &ex.protocol, &ex.host, "POST", &path, &body).await?;
   let full_url = format!("{}://{}{}", ex.protocol, ex.host, path);
   let client = reqwest::Client::new();
-  let body_resp = client.get(full_url.as_str())
+  let req = client.post(full_url.as_str())
   .header("OK-ACCESS-KEY", cfg.access_id.clone())
   .header("OK-ACCESS-SIGN", sign)
   .header("OK-ACCESS-TIMESTAMP", timestamp)
   .header("OK-ACCESS-PASSPHRASE", cfg.passphrase.clone())
-  .send().await;
-  let body_text = handle_body(body_resp).await?;
-  let json_resp: Value = serde_json::from_str(body_text.as_str()).expect("json parse error");
+  .header("Content-Type", "application/json; charset=utf-8")
+  .body(body);
+  let body_resp = req.send();
+  let body_text = timed_body("OKEX", "withdraw_on_chain", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
   if json_resp["code"] == "0" {
-    let asset_item: &Value = json_resp["data"].as_array().expect("json_resp['data'] as_array error").into_iter()
-    .find(|x| x["ccy"].as_str().expect("read ccy error").eq(&asset.to_uppercase()) &&x["chain"].as_str().expect("read chain error").eq(&currency)).expect("find chain error");
-    let fee_str = asset_item["minFee"].as_str().expect("read minFee error");
-    let path = "/api/v5/asset/withdrawal";
-    println!("fee_str: {}", fee_str);
-    let (sign, timestamp, body) = build_okex_sign(&cfg, &ex.protocol, &ex.host, "POST", &path, [
-      ["amt", &amount.to_string()],
-      ["ccy", &asset.to_uppercase()],
-      ["chain", &currency],
-      ["dest", "4"],
-      ["toAddr", &address],
-      ["pwd", &cfg.trade_pwd],
-      ["fee", &fee_str]
-    ].to_vec(), false).await?;
-    let full_url = format!("{}://{}{}", ex.protocol, ex.host, path);
-    let client = reqwest::Client::new();
-    let req = client.post(full_url.as_str())
-    .header("OK-ACCESS-KEY", cfg.access_id.clone())
-    .header("OK-ACCESS-SIGN", sign)
-    .header("OK-ACCESS-TIMESTAMP", timestamp)
-    .header("OK-ACCESS-PASSPHRASE", cfg.passphrase.clone())
-    .header("Content-Type", "application/json; charset=utf-8")
-    .body(body);
-    let body_resp = req.send().await;
-    let body_text = handle_body(body_resp).await?;
-    let json_resp: Value = serde_json::from_str(body_text.as_str()).expect("json parse error");
-    if json_resp["code"] == "0" {
-      let data_item = &json_resp["data"][0];
-      let id = data_item["wdId"].as_str().expect("read wdId error"); 
-      return Ok(String::from(id));
+    let data_item = &json_resp["data"][0];
+    let id = data_item["wdId"].as_str().expect("read wdId error"); 
+    return Ok(String::from(id));
+  } else {
+    return Err(format!("{}: {:?}", ex.name, json_resp));
+  }
+}
+
+// 按提币时传入的 clientId 查询, 没有找到返回 None
+pub async fn find_withdrawal(ex: &Exchange, asset: String, client_id: String) -> Result<Option<String>, String> {
+  let cfg = OkexConfig::load(&ex.config)?;
+  let path = format!("/api/v5/asset/withdrawal-history?ccy={}&clientId={}", asset.to_uppercase(), client_id);
+  let json_resp = signed_get(ex, &cfg, &path).await?;
+  return Ok(json_resp["data"].as_array().and_then(|arr| arr.first()).and_then(|w| w["wdId"].as_str()).map(String::from));
+}
+
+pub async fn asset_networks(ex: &Exchange, asset: String) -> Result<Vec<NetworkInfo>, String> {
+  let cfg = OkexConfig::load(&ex.config)?;
+  let path = format!("/api/v5/asset/currencies?ccy={}", asset.to_uppercase());
+  let (sign, timestamp, _body) = build_okex_sign(&cfg, &ex.protocol, &ex.host, "GET", &path, &Value::Null).await?;
+  let full_url = format!("{}://{}{}", ex.protocol, ex.host, path);
+  let client = reqwest::Client::new();
+  let body_resp = client.get(full_url.as_str())
+  .header("OK-ACCESS-KEY", cfg.access_id.as_str())
+  .header("OK-ACCESS-SIGN", sign)
+  .header("OK-ACCESS-TIMESTAMP", timestamp)
+  .header("OK-ACCESS-PASSPHRASE", cfg.passphrase.as_str())
+  .send();
+  let body_text = timed_body("OKEX", "asset_networks", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  if json_resp["code"] == "0" {
+    let networks = json_resp["data"].as_array().expect("json_resp['data'] as_array error").iter()
+    .filter(|x| x["ccy"] == asset.to_uppercase())
+    .map(|x| {
+      let chain = x["chain"].as_str().expect("read chain error");
+      let fee = x["minFee"].as_str().expect("read minFee error").parse::<f64>().expect("parse minFee error");
+      NetworkInfo {
+        chain: catalog::canonical_chain(chain),
+        network: String::from(chain),
+        fee,
+        // minWd 是不含手续费的 amt
+        min_amount: x["minWd"].as_str().unwrap_or("0").parse::<f64>().expect("parse minWd error") + fee,
+        withdraw_enabled: x["canWd"].as_bool().unwrap_or(false),
+        confirmations: x["minWdUnlockConfirm"].as_str().unwrap_or("0").parse::<u64>().unwrap_or(0)
+      }
+    }).collect();
+    return Ok(networks);
+  } else {
+    return Err(format!("{}: {:?}", ex.name, json_resp));
+  }
+}
+
+pub async fn deposit_address(ex: &Exchange, asset: String, network: String) -> Result<String, String> {
+  let cfg = OkexConfig::load(&ex.config)?;
+  let path = format!("/api/v5/asset/deposit-address?ccy={}", asset.to_uppercase());
+  let (sign, timestamp, _body) = build_okex_sign(&cfg, &ex.protocol, &ex.host, "GET", &path, &Value::Null).await?;
+  let full_url = format!("{}://{}{}", ex.protocol, ex.host, path);
+  let client = reqwest::Client::new();
+  let body_resp = client.get(full_url.as_str())
+  .header("OK-ACCESS-KEY", cfg.access_id.as_str())
+  .header("OK-ACCESS-SIGN", sign)
+  .header("OK-ACCESS-TIMESTAMP", timestamp)
+  .header("OK-ACCESS-PASSPHRASE", cfg.passphrase.as_str())
+  .send();
+  let body_text = timed_body("OKEX", "deposit_address", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  if json_resp["code"] == "0" {
+    let arr = json_resp["data"].as_array().expect("read data error");
+    let item_opt = arr.iter().find(|x| x["chain"] == network.as_str());
+    if let Some(item) = item_opt {
+      return Ok(String::from(item["addr"].as_str().expect("read addr error")));
     } else {
-      return Err(format!("{}: {:?}", ex.name, json_resp));
+      return Err(format!("no deposit address for {} on {}", asset, network));
     }
   } else {
     return Err(format!("{}: {:?}", ex.name, json_resp));
   }
-}
\ No newline at end of file
+}
+
+// 通用的签名请求按路径(不含参数)记录耗时
+async fn signed_get(ex: &Exchange, cfg: &OkexConfig, path: &str) -> Result<Value, String> {
+  let (sign, timestamp, _body) = build_okex_sign(cfg, &ex.protocol, &ex.host, "GET", path, &Value::Null).await?;
+  let full_url = format!("{}://{}{}", ex.protocol, ex.host, path);
+  let client = reqwest::Client::new();
+  let body_resp = client.get(full_url.as_str())
+  .header("OK-ACCESS-KEY", cfg.access_id.as_str())
+  .header("OK-ACCESS-SIGN", sign)
+  .header("OK-ACCESS-TIMESTAMP", timestamp)
+  .header("OK-ACCESS-PASSPHRASE", cfg.passphrase.as_str())
+  .send();
+  let body_text = timed_body("OKEX", path.split('?').next().unwrap_or(path), body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  if json_resp["code"] == "0" {
+    return Ok(json_resp);
+  } else {
+    return Err(format!("{}: {:?}", ex.name, json_resp));
+  }
+}
+
+// 资金账户和交易账户(统一账户, 现货和杠杆在一起)的全部余额, 逐仓杠杆仓位的保证金单独列出
+pub async fn balances(ex: &Exchange) -> Result<Vec<Balance>, String> {
+  let cfg = OkexConfig::load(&ex.config)?;
+  let mut items: Vec<Balance> = Vec::new();
+
+  let funding = signed_get(ex, &cfg, "/api/v5/asset/balances").await?;
+  for item in funding["data"].as_array().expect("read data error").iter() {
+    items.push(Balance {
+      account: AccountType::FUNDING,
+      asset: item["ccy"].as_str().expect("read ccy error").to_lowercase(),
+      free: value_f64(&item["availBal"]),
+      locked: value_f64(&item["frozenBal"]),
+      borrowed: 0_f64,
+      interest: 0_f64
+    });
+  }
+
+  let trading = signed_get(ex, &cfg, "/api/v5/account/balance").await?;
+  for item in trading["data"][0]["details"].as_array().unwrap_or(&Vec::new()).iter() {
+    let liab = value_f64(&item["liab"]);
+    items.push(Balance {
+      account: if liab > 0_f64 { AccountType::MARGIN } else { AccountType::SPOT },
+      asset: item["ccy"].as_str().expect("read ccy error").to_lowercase(),
+      free: value_f64(&item["availBal"]),
+      locked: value_f64(&item["frozenBal"]),
+      borrowed: liab,
+      interest: value_f64(&item["interest"])
+    });
+  }
+
+  let positions = signed_get(ex, &cfg, "/api/v5/account/positions?instType=MARGIN").await?;
+  for pos in positions["data"].as_array().unwrap_or(&Vec::new()).iter() {
+    if pos["mgnMode"] != "isolated" {
+      continue;
+    }
+    let pair = pos["instId"].as_str().expect("read instId error").replace('-', "").to_lowercase();
+    items.push(Balance {
+      account: AccountType::ISOLATEDMARGIN(pair.clone()),
+      asset: pos["ccy"].as_str().unwrap_or("").to_lowercase(),
+      free: 0_f64,
+      locked: value_f64(&pos["margin"]),
+      borrowed: value_f64(&pos["liab"]).abs(),
+      interest: value_f64(&pos["interest"])
+    });
+  }
+  return Ok(items);
+}
+
+async fn signed_post(ex: &Exchange, cfg: &OkexConfig, path: &str, body: Value) -> Result<Value, String> {
+  let (sign, timestamp, body) = build_okex_sign(cfg, &ex.protocol, &ex.host, "POST", path, &body).await?;
+  let full_url = format!("{}://{}{}", ex.protocol, ex.host, path);
+  let client = reqwest::Client::new();
+  let body_resp = client.post(full_url.as_str())
+  .header("OK-ACCESS-KEY", cfg.access_id.as_str())
+  .header("OK-ACCESS-SIGN", sign)
+  .header("OK-ACCESS-TIMESTAMP", timestamp)
+  .header("OK-ACCESS-PASSPHRASE", cfg.passphrase.as_str())
+  .header("Content-Type", "application/json; charset=utf-8")
+  .body(body)
+  .send();
+  let body_text = timed_body("OKEX", path, body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  if json_resp["code"] == "0" {
+    return Ok(json_resp);
+  } else {
+    return Err(format!("{}: {:?}", ex.name, json_resp));
+  }
+}
+
+// 资金账户是 6, 交易账户是 18, 现货和全仓杠杆都在交易账户里
+fn wallet_id(ex: &Exchange, account: &AccountType) -> Result<&'static str, String> {
+  match account {
+    AccountType::FUNDING => Ok("6"),
+    AccountType::SPOT | AccountType::MARGIN => Ok("18"),
+    _ => Err(format!("{}: {} is not a wallet", ex.name, account))
+  }
+}
+
+// 资金账户 <-> 交易账户使用资金划转, 交易账户 <-> 逐仓仓位使用调整保证金
+pub async fn transfer(ex: &Exchange, asset: String, amount: f64, from: AccountType, to: AccountType) -> Result<String, String> {
+  let cfg = OkexConfig::load(&ex.config)?;
+  let amount_str = amount.to_string();
+  let isolated = match (&from, &to) {
+    (AccountType::ISOLATEDMARGIN(pair), AccountType::SPOT | AccountType::MARGIN) => Some((pair, "reduce")),
+    (AccountType::SPOT | AccountType::MARGIN, AccountType::ISOLATEDMARGIN(pair)) => Some((pair, "add")),
+    _ => None
+  };
+  if let Some((pair, adjust_type)) = isolated {
+    if !pair.eq_ignore_ascii_case(&format!("{}{}", ex.symbol, ex.currency)) {
+      return Err(format!("{}: isolated pair {} does not match {}{}", ex.name, pair, ex.symbol, ex.currency));
+    }
+    let inst_id = inst_id(ex);
+    let json_resp = signed_post(ex, &cfg, "/api/v5/account/position/margin-balance", json!({
+      "instId": inst_id,
+      "posSide": "net",
+      "type": adjust_type,
+      "amt": amount_str,
+      "ccy": asset.to_uppercase()
+    })).await?;
+    return Ok(String::from(json_resp["data"][0]["instId"].as_str().unwrap_or("")));
+  }
+  let from_id = wallet_id(ex, &from)?;
+  let to_id = wallet_id(ex, &to)?;
+  if from_id == to_id {
+    return Ok(String::from(""));
+  }
+  let json_resp = signed_post(ex, &cfg, "/api/v5/asset/transfer", json!({
+    "ccy": asset.to_uppercase(),
+    "amt": amount_str,
+    "from": from_id,
+    "to": to_id,
+    "type": "0"
+  })).await?;
+  return Ok(String::from(json_resp["data"][0]["transId"].as_str().expect("read transId error")));
+}
+
+// 逐仓保证金的增减记在交易账户的账单里, type 6 是保证金划转, subType 160 增加, 161 减少
+pub async fn find_transfer(ex: &Exchange, asset: String, amount: f64, from: AccountType, to: AccountType, since: i64) -> Result<Option<String>, String> {
+  let cfg = OkexConfig::load(&ex.config)?;
+  let sub_type = match (&from, &to) {
+    (AccountType::SPOT | AccountType::MARGIN, AccountType::ISOLATEDMARGIN(_)) => "160",
+    (AccountType::ISOLATEDMARGIN(_), AccountType::SPOT | AccountType::MARGIN) => "161",
+    _ => return Err(format!("{}: transfer history from {} to {} not supported", ex.name, from, to))
+  };
+  let inst_id = inst_id(ex);
+  let path = format!("/api/v5/account/bills?instType=MARGIN&ccy={}&type=6&begin={}", asset.to_uppercase(), since);
+  let json_resp = signed_get(ex, &cfg, &path).await?;
+  let bills = json_resp["data"].as_array().ok_or(format!("{}: {}", ex.name, json_resp))?;
+  return Ok(bills.iter()
+    .find(|b| b["instId"] == inst_id.as_str() && b["subType"] == sub_type && same_amount(value_f64(&b["balChg"]).abs(), amount))
+    .and_then(|b| b["billId"].as_str())
+    .map(String::from));
+}
+
+// 逐仓杠杆仓位, 以 currency 计价; 仓位(pos)和保证金(margin)可能是 symbol 或 currency, 负债(liab)是负数
+pub async fn position(ex: &Exchange) -> Result<LoanPosition, String> {
+  let cfg = OkexConfig::load(&ex.config)?;
+  let inst_id = inst_id(ex);
+  let json_resp = signed_get(ex, &cfg, &format!("/api/v5/account/positions?instType=MARGIN&instId={}", inst_id)).await?;
+  let item = json_resp["data"].as_array().and_then(|arr| arr.iter().find(|x| x["instId"] == inst_id.as_str() && x["mgnMode"] == "isolated"))
+  .ok_or(format!("{}: no isolated margin position for {}", ex.name, inst_id))?;
+  let price = value_f64(&item["last"]);
+  let symbol = ex.symbol.to_uppercase();
+  let in_currency = |v: f64, ccy: &Value| if ccy == symbol.as_str() { v * price } else { v };
+  let collateral_value = in_currency(value_f64(&item["pos"]), &item["posCcy"]) + in_currency(value_f64(&item["margin"]), &item["ccy"]);
+  let debt_value = in_currency(value_f64(&item["liab"]).abs() + value_f64(&item["interest"]), &item["liabCcy"]);
+  let mut pos = LoanPosition::new(format!("{}:{}{}", ex.name, ex.symbol.to_lowercase(), ex.currency.to_lowercase()), ex.name.to_string(), ex.currency.clone(), collateral_value, debt_value, 1_f64 / 1.1_f64);
+  let liq_price = value_f64(&item["liqPx"]);
+  if liq_price > 0_f64 {
+    pos.liquidation_price = Some(liq_price);
+  }
+  return Ok(pos);
+}
+
+// perm 形如 "read_only,trade,withdraw", ip 为空表示没有绑定
+pub async fn api_permissions(ex: &Exchange) -> Result<ApiPermissions, String> {
+  let cfg = OkexConfig::load(&ex.config)?;
+  let res = signed_get(ex, &cfg, "/api/v5/account/config").await?;
+  let data = &res["data"][0];
+  let perm: Vec<&str> = data["perm"].as_str().unwrap_or("").split(',').collect();
+  let trade = perm.contains(&"trade");
+  return Ok(ApiPermissions {
+    read: perm.contains(&"read_only"),
+    trade,
+    margin: trade,
+    withdraw: perm.contains(&"withdraw"),
+    ip_restricted: !data["ip"].as_str().unwrap_or("").is_empty()
+  });
+}
+
+// 子账户的资金账户和交易账户余额, 使用母账户的 key
+pub async fn subaccount_balances(ex: &Exchange, sub: &SubAccount) -> Result<Vec<Balance>, String> {
+  let cfg = OkexConfig::load(&sub.master)?;
+  let mut items: Vec<Balance> = Vec::new();
+
+  let funding = signed_get(ex, &cfg, &format!("/api/v5/asset/subaccount/balances?subAcct={}", sub.id)).await?;
+  for item in funding["data"].as_array().unwrap_or(&Vec::new()).iter() {
+    items.push(Balance {
+      account: AccountType::FUNDING,
+      asset: item["ccy"].as_str().expect("read ccy error").to_lowercase(),
+      free: value_f64(&item["availBal"]),
+      locked: value_f64(&item["frozenBal"]),
+      borrowed: 0_f64,
+      interest: 0_f64
+    });
+  }
+
+  let trading = signed_get(ex, &cfg, &format!("/api/v5/account/subaccount/balances?subAcct={}", sub.id)).await?;
+  for item in trading["data"][0]["details"].as_array().unwrap_or(&Vec::new()).iter() {
+    let liab = value_f64(&item["liab"]);
+    items.push(Balance {
+      account: if liab > 0_f64 { AccountType::MARGIN } else { AccountType::SPOT },
+      asset: item["ccy"].as_str().expect("read ccy error").to_lowercase(),
+      free: value_f64(&item["availBal"]),
+      locked: value_f64(&item["frozenBal"]),
+      borrowed: liab,
+      interest: value_f64(&item["interest"])
+    });
+  }
+  return Ok(items);
+}
+
+// 母账户资金账户 <-> 子账户的资金/交易账户, type 1 母转子, 2 子转母
+pub async fn subaccount_transfer(ex: &Exchange, sub: &SubAccount, asset: String, amount: f64, to_sub: bool, account: AccountType) -> Result<String, String> {
+  let cfg = OkexConfig::load(&sub.master)?;
+  let amount_str = amount.to_string();
+  let sub_id = wallet_id(ex, &account)?;
+  let (from_id, to_id, transfer_type) = if to_sub { ("6", sub_id, "1") } else { (sub_id, "6", "2") };
+  let json_resp = signed_post(ex, &cfg, "/api/v5/asset/transfer", json!({
+    "ccy": asset.to_uppercase(),
+    "amt": amount_str,
+    "from": from_id,
+    "to": to_id,
+    "type": transfer_type,
+    "subAcct": sub.id.as_str()
+  })).await?;
+  return Ok(String::from(json_resp["data"][0]["transId"].as_str().unwrap_or("")));
+}
+
+fn inst_id(ex: &Exchange) -> String {
+  return format!("{}-{}", ex.symbol.to_uppercase(), ex.currency.to_uppercase());
+}
+
+pub async fn depth(ex: &Exchange) -> Result<DepthInfo, String> {
+  let full_url = format!("{}://{}/api/v5/market/books?instId={}&sz=5", ex.protocol, ex.host, inst_id(ex));
+  let body_resp = reqwest::get(full_url.as_str());
+  let body_text = timed_body("OKEX", "depth", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  if json_resp["code"] == "0" {
+    let book = &json_resp["data"][0];
+    return Ok(DepthInfo {
+      tick: Tick {
+        asks: depth_levels(&book["asks"])?,
+        bids: depth_levels(&book["bids"])?
+      },
+      ts: value_f64(&book["ts"]) as i64
+    });
+  } else {
+    return Err(format!("{}: {:?}", ex.name, json_resp));
+  }
+}
+
+// 最近成交从新到旧返回, 倒过来从旧到新
+pub async fn trades(ex: &Exchange) -> Result<Vec<TradeInfo>, String> {
+  let full_url = format!("{}://{}/api/v5/market/trades?instId={}&limit=100", ex.protocol, ex.host, inst_id(ex));
+  let body_resp = reqwest::get(full_url.as_str());
+  let body_text = timed_body("OKEX", "trades", body_resp).await?;
+  let json_resp: Value = parse_json(&body_text)?;
+  if json_resp["code"] != "0" {
+    return Err(format!("{}: {:?}", ex.name, json_resp));
+  }
+  let mut res: Vec<TradeInfo> = Vec::new();
+  for item in json_resp["data"].as_array().ok_or(format!("{}: {}", ex.name, json_resp))?.iter().rev() {
+    res.push(TradeInfo {
+      id: item["tradeId"].as_str().and_then(|id| id.parse::<u64>().ok()).ok_or(format!("OKEX: invalid trade {}", item))?,
+      price: value_f64(&item["px"]),
+      volume: value_f64(&item["sz"]),
+      buy: item["side"] == "buy",
+      ts: value_f64(&item["ts"]) as i64
+    });
+  }
+  return Ok(res);
+}
+
+// 现货限价单, 整体请求成功时单个订单仍可能失败, 看 sCode
+pub async fn create_order(ex: &Exchange, side: OrderSide, price: f64, volume: f64) -> Result<String, String> {
+  let cfg = OkexConfig::load(&ex.config)?;
+  let json_resp = signed_post(ex, &cfg, "/api/v5/trade/order", json!({
+    "instId": inst_id(ex),
+    "tdMode": "cash",
+    "side": side.to_string().to_lowercase(),
+    "ordType": "limit",
+    "px": price.to_string(),
+    "sz": volume.to_string()
+  })).await?;
+  let order = &json_resp["data"][0];
+  if order["sCode"] == "0" {
+    return Ok(String::from(order["ordId"].as_str().expect("read ordId error")));
+  } else {
+    return Err(format!("{}: {:?}", ex.name, json_resp));
+  }
+}
+
+pub async fn order_info(ex: &Exchange, order_id: String) -> Result<OrderInfo, String> {
+  let cfg = OkexConfig::load(&ex.config)?;
+  let json_resp = signed_get(ex, &cfg, &format!("/api/v5/trade/order?instId={}&ordId={}", inst_id(ex), order_id)).await?;
+  let obj = &json_resp["data"][0];
+  let status = match obj["state"].as_str().unwrap_or("") {
+    "live" => OrderStatus::NEW,
+    "partially_filled" => OrderStatus::PARTIALLYFILLED,
+    "filled" => OrderStatus::FILLED,
+    "canceled" | "mmp_canceled" => OrderStatus::CANCELED,
+    _ => return Err(format!("{}: unknown order state {}", ex.name, obj))
+  };
+  return Ok(OrderInfo {
+    id: order_id,
+    volume: value_f64(&obj["sz"]),
+    price: value_f64(&obj["px"]),
+    status,
+    side: if obj["side"] == "buy" { OrderSide::BUY } else { OrderSide::SELL },
+    created_at: value_f64(&obj["cTime"]) as u64,
+    trade_volume: value_f64(&obj["accFillSz"]),
+    trade_avg_price: value_f64(&obj["avgPx"])
+  });
+}
+
+pub async fn cancel_order(ex: &Exchange, order_id: String) -> Result<bool, String> {
+  let cfg = OkexConfig::load(&ex.config)?;
+  let json_resp = signed_post(ex, &cfg, "/api/v5/trade/cancel-order", json!({
+    "instId": inst_id(ex),
+    "ordId": order_id
+  })).await?;
+  return Ok(json_resp["data"][0]["sCode"] == "0");
+}
+
+// 先查当前挂单再批量撤销, 没有挂单时直接返回
+pub async fn cancel_all_order(ex: &Exchange) -> Result<bool, String> {
+  let cfg = OkexConfig::load(&ex.config)?;
+  let pending = signed_get(ex, &cfg, &format!("/api/v5/trade/orders-pending?instType=SPOT&instId={}", inst_id(ex))).await?;
+  let orders: Vec<Value> = pending["data"].as_array().unwrap_or(&Vec::new()).iter()
+    .map(|o| json!({ "instId": o["instId"], "ordId": o["ordId"] }))
+    .collect();
+  if orders.is_empty() {
+    return Ok(true);
+  }
+  let json_resp = signed_post(ex, &cfg, "/api/v5/trade/cancel-batch-orders", Value::Array(orders)).await?;
+  let mut all_cancelled = true;
+  for item in json_resp["data"].as_array().unwrap_or(&Vec::new()).iter() {
+    if item["sCode"] != "0" {
+      all_cancelled = false;
+      log::warn!("cancel failed: {:}", item);
+    }
+  }
+  return Ok(all_cancelled);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // 2020-12-08T09:08:57.715Z, 文档签名示例里的时间
+  static TS: i64 = 1607418537715;
+
+  // 文档的签名示例: timestamp + 'GET' + '/api/v5/account/balance?ccy=BTC', 文档没有公开 key 和签名的对照, 只对照待签名字符串
+  #[test]
+  fn doc_prehash_with_query() {
+    let (payload, time_str, body) = okex_payload("get", "/api/v5/account/balance?ccy=BTC", &Value::Null, TS);
+    assert_eq!(payload, "2020-12-08T09:08:57.715ZGET/api/v5/account/balance?ccy=BTC");
+    assert_eq!(time_str, "2020-12-08T09:08:57.715Z");
+    assert_eq!(body, "");
+  }
+
+  // 文档的 POST 示例: timestamp + 'POST' + '/api/v5/account/set-leverage' + body
+  #[test]
+  fn doc_prehash_with_body() {
+    let (payload, _, body) = okex_payload("POST", "/api/v5/account/set-leverage", &json!({ "instId": "BTC-USDT", "lever": "5", "mgnMode": "isolated" }), TS);
+    assert_eq!(body, r#"{"instId":"BTC-USDT","lever":"5","mgnMode":"isolated"}"#);
+    assert_eq!(payload, r#"2020-12-08T09:08:57.715ZPOST/api/v5/account/set-leverage{"instId":"BTC-USDT","lever":"5","mgnMode":"isolated"}"#);
+  }
+
+  // 引号和括号要转义, 发送的 body 和签名的是同一个字符串
+  #[test]
+  fn body_with_special_chars() {
+    let (payload, _, body) = okex_payload("POST", "/api/v5/asset/withdrawal", &json!({ "amt": "1", "pwd": "a\"b}c" }), TS);
+    assert_eq!(body, r#"{"amt":"1","pwd":"a\"b}c"}"#);
+    assert_eq!(serde_json::from_str::<Value>(&body).unwrap()["pwd"], "a\"b}c");
+    assert!(payload.ends_with(&body));
+    let (_, _, body) = okex_payload("POST", "/api/v5/trade/batch-orders", &json!([{ "instId": "BTC-USDT" }, { "instId": "ETH-USDT" }]), TS);
+    assert_eq!(body, r#"[{"instId":"BTC-USDT"},{"instId":"ETH-USDT"}]"#);
+  }
+
+  #[test]
+  fn timestamp_keeps_millis() {
+    let (_, time_str, _) = okex_payload("GET", "/api/v5/account/balance", &Value::Null, 1607418537005);
+    assert_eq!(time_str, "2020-12-08T09:08:57.005Z");
+  }
+
+  // RFC 4231 test case 2, 文档要求的 HmacSHA256 再 base64
+  #[test]
+  fn signature_is_base64_hmac_sha256() {
+    assert_eq!(hmac_base64("Jefe", "what do ya want for nothing?"), "W9zBRr9gdU5qBCQmCJV1x1oAPwidJzmDnexYuWTsOEM=");
+  }
+}
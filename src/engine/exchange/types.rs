@@ -58,6 +58,16 @@ pub struct DepthInfo {
   pub ts: i64
 }
 
+// 逐笔成交, buy 表示主动买
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradeInfo {
+  pub id: u64,
+  pub price: f64,
+  pub volume: f64,
+  pub buy: bool,
+  pub ts: i64
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AccountInfo {
   pub available_symbol: f64,
@@ -96,8 +106,92 @@ pub struct LoanInfo {
   pub min_volume: f64
 }
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+// 某个币种在某条链上的提币参数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkInfo {
+  pub chain: String, // 通用链名称, TRC20, ERC20 ...
+  pub network: String, // 交易所自己的网络名称
+  pub fee: f64,
+  pub min_amount: f64, // 最小提币数量, 和 withdraw 的 amount 一样包含手续费
+  pub withdraw_enabled: bool,
+  pub confirmations: u64
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum AccountType {
   USDSFUTURE,
-  SPOT
-}
\ No newline at end of file
+  SPOT,
+  FUNDING, // 资金账户
+  MARGIN, // 全仓杠杆
+  ISOLATEDMARGIN(String), // 逐仓杠杆, 交易对 e.g. crvusdt
+  LOAN // 质押借币
+}
+
+impl fmt::Display for AccountType {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      AccountType::USDSFUTURE => write!(f, "USDSFUTURE"),
+      AccountType::SPOT => write!(f, "SPOT"),
+      AccountType::FUNDING => write!(f, "FUNDING"),
+      AccountType::MARGIN => write!(f, "MARGIN"),
+      AccountType::ISOLATEDMARGIN(pair) => write!(f, "ISOLATEDMARGIN:{}", pair),
+      AccountType::LOAN => write!(f, "LOAN"),
+    }
+  }
+}
+
+// 某个账户里某个币种的余额, asset 统一小写
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Balance {
+  pub account: AccountType,
+  pub asset: String,
+  pub free: f64,
+  pub locked: f64, // 冻结, 或者作为质押物
+  pub borrowed: f64,
+  pub interest: f64
+}
+
+impl Balance {
+  pub fn net(&self) -> f64 {
+    self.free + self.locked - self.borrowed - self.interest
+  }
+}
+
+// 一个交易所下所有账户的余额
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Balances {
+  pub venue: Exchanges,
+  pub items: Vec<Balance>
+}
+
+impl Balances {
+  pub fn get(&self, account: &AccountType, asset: &str) -> Option<&Balance> {
+    self.items.iter().find(|b| &b.account == account && b.asset.eq_ignore_ascii_case(asset))
+  }
+
+  // 可以直接归集出来提币补仓的数量: 现货和资金账户中的可用余额,
+  // 杠杆和质押借币账户里的资产在支撑仓位, 不计入
+  pub fn mobilizable(&self, asset: &str) -> f64 {
+    self.items.iter()
+    .filter(|b| matches!(b.account, AccountType::SPOT | AccountType::FUNDING) && b.asset.eq_ignore_ascii_case(asset))
+    .map(|b| b.free)
+    .sum()
+  }
+}
+// API key 实际拥有的权限, 启动时自检用
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ApiPermissions {
+  pub read: bool,
+  pub trade: bool, // 现货交易
+  pub margin: bool, // 杠杆借贷和划转
+  pub withdraw: bool,
+  pub ip_restricted: bool // 是否绑定了 IP 白名单
+}
+
+// 子账户仓位: 交易和查询仓位使用子账户自己的 API key (Exchange.config),
+// 查询子账户余额和母子账户划转使用母账户的 API key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubAccount {
+  pub id: String, // Binance 为子账户邮箱, OKX 为子账户名称, Huobi 为子账户 uid
+  pub master: String // 母账户 API key 的凭证名称
+}
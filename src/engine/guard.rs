@@ -0,0 +1,65 @@
+use std::sync::RwLock;
+use serde::{Deserialize, Serialize};
+use super::exchange::Exchange;
+
+// 所有会动用资金的操作(交易所划转/提币, 链上还款/补充抵押物)都要先过这个检查
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ActionGuard {
+  pub dry_run: bool, // 只打印, 不执行
+  pub max_amount: f64, // 单次操作的最大数量, 0 表示不限制
+  pub confirmations: u64 // 链上交易需要等待的确认数
+}
+
+impl ::std::default::Default for ActionGuard {
+  fn default() -> Self {
+    Self {
+      dry_run: true,
+      max_amount: 0_f64,
+      confirmations: 2_u64
+    }
+  }
+}
+
+impl ActionGuard {
+  // Ok(true) 执行, Ok(false) 是 dry run, 超过限额返回错误
+  pub fn check(&self, action: &str, amount: f64) -> Result<bool, String> {
+    if self.max_amount > 0_f64 && amount > self.max_amount {
+      return Err(format!("{} amount {} exceeds limit {}", action, amount, self.max_amount));
+    }
+    if self.dry_run {
+      log::warn!("[DRY RUN] {} amount {}", action, amount);
+      return Ok(false);
+    }
+    return Ok(true);
+  }
+
+  // 交易所的操作先检查 protect, 只监控的仓位在 dry run 时也拒绝
+  pub fn check_exchange(&self, ex: &Exchange, action: &str, amount: f64) -> Result<bool, String> {
+    protected(ex, action)?;
+    return self.check(action, amount);
+  }
+}
+
+// protect = false 的交易所账户只用于监控, 不划转, 不还款, 不提币
+pub fn protected(ex: &Exchange, action: &str) -> Result<(), String> {
+  if !ex.protect {
+    return Err(format!("{} refused: {} {} is monitoring only, set protect = true to allow it", action, ex.name, ex.config));
+  }
+  return Ok(());
+}
+
+// 运行中使用的 guard, 启动时从配置初始化, dry run 可以通过 telegram 命令切换
+static CURRENT: RwLock<Option<ActionGuard>> = RwLock::new(None);
+
+pub fn init(guard: &ActionGuard) {
+  *CURRENT.write().unwrap() = Some(guard.clone());
+}
+
+pub fn current() -> ActionGuard {
+  CURRENT.read().unwrap().clone().unwrap_or_default()
+}
+
+// 在同一个写锁里修改, 不会覆盖同时发生的 init
+pub fn set_dry_run(dry_run: bool) {
+  CURRENT.write().unwrap().get_or_insert_with(ActionGuard::default).dry_run = dry_run;
+}
@@ -0,0 +1,144 @@
+use std::future::Future;
+use std::sync::atomic::{ AtomicU64, Ordering };
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use super::rebalance::StepStatus;
+use crate::notify::{ self, Severity };
+use crate::store;
+
+// 补充保证金, 还款, 子账户补充保证金和链上 approve + supply 这类多步操作的日志: 每一步执行前写入 Running,
+// 结束后写入结果. 进程中途退出时无法确定正在执行的那一步是否已经生效, 启动时把没有结束的操作通知出来,
+// 由人工和交易所或者链上的记录核对, 不自动重试. 跨交易所调仓有自己的 workflow 和核对逻辑, 见 rebalance
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ActionJournal {
+  pub id: String,
+  pub target: String,
+  pub action: String,
+  pub amount: f64,
+  pub steps: Vec<(String, StepStatus)>,
+  #[serde(skip)]
+  write: bool
+}
+
+static JOURNAL_KIND: &str = "action";
+// 同一秒内的多个操作用序号区分
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+impl ActionJournal {
+  // 没有配置 store 时只执行不记录, 单次的保护操作不能因为没有日志而不做
+  pub fn new(target: &str, action: &str, amount: f64) -> ActionJournal {
+    ActionJournal {
+      id: format!("{}-{}-{}-{}", action, target, Local::now().format("%Y%m%d%H%M%S"), SEQ.fetch_add(1, Ordering::Relaxed)),
+      target: String::from(target),
+      action: String::from(action),
+      amount,
+      steps: Vec::new(),
+      write: store::enabled()
+    }
+  }
+
+  // 已经记录在别的日志里的操作, 例如调仓 workflow 的 TopUp 步骤, 只执行不写入
+  pub fn untracked(target: &str, action: &str, amount: f64) -> ActionJournal {
+    ActionJournal { write: false, ..ActionJournal::new(target, action, amount) }
+  }
+
+  fn save(&self, status: &str) -> Result<(), String> {
+    if !self.write {
+      return Ok(());
+    }
+    let state = serde_json::to_string(self).map_err(|e| format!("[{}] serialize error: {}", self.id, e))?;
+    return store::journal_write(&self.id, JOURNAL_KIND, &state, status);
+  }
+
+  // 执行一步, 开始前的日志写不进去时不执行; 失败的一步结束整个操作
+  pub async fn step<T: std::fmt::Debug>(&mut self, name: &str, f: impl Future<Output = Result<T, String>>) -> Result<T, String> {
+    self.steps.push((String::from(name), StepStatus::Running));
+    if let Err(err) = self.save("running") {
+      self.steps.pop();
+      return Err(err);
+    }
+    let res = f.await;
+    let (status, journal_status) = match &res {
+      Ok(v) => (StepStatus::Done(format!("{:?}", v)), "running"),
+      Err(err) => (StepStatus::Failed(err.clone()), "failed")
+    };
+    if let Some(last) = self.steps.last_mut() {
+      last.1 = status;
+    }
+    if let Err(err) = self.save(journal_status) {
+      log::error!("{}", err);
+    }
+    return res;
+  }
+
+  // 所有步骤都成功后调用, 没有执行任何步骤(dry run)时不写入
+  pub fn close(&self) {
+    if self.steps.is_empty() || self.steps.iter().any(|(_, s)| matches!(s, StepStatus::Failed(_))) {
+      return;
+    }
+    if let Err(err) = self.save("done") {
+      log::error!("{}", err);
+    }
+  }
+}
+
+// 启动时调用: 上次退出时没有结束的操作标记为 interrupted 并发出通知
+pub async fn interrupted() -> Vec<ActionJournal> {
+  let mut res: Vec<ActionJournal> = Vec::new();
+  for state in store::journal_open(JOURNAL_KIND).iter() {
+    let mut journal: ActionJournal = match serde_json::from_str(state) {
+      Ok(journal) => journal,
+      Err(err) => {
+        log::error!("invalid action journal entry: {}", err);
+        continue;
+      }
+    };
+    journal.write = true;
+    let steps: Vec<String> = journal.steps.iter().map(|(name, status)| format!("{}: {:?}", name, status)).collect();
+    let message = format!("{} {} was interrupted by a restart, check it manually before retrying:\n{}", journal.action, journal.amount, steps.join("\n"));
+    notify::notify(Severity::CRITICAL, &journal.target, "action interrupted", &message).await;
+    if let Err(err) = journal.save("interrupted") {
+      log::error!("{}", err);
+    }
+    res.push(journal);
+  }
+  return res;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn steps_are_journaled_and_interrupted_ones_reported() {
+    let _lock = store::init_temp("journal").await;
+    // 第一步完成, 第二步开始后进程退出
+    let mut journal = ActionJournal::new("test-interrupted", "top_up", 10_f64);
+    assert_eq!(journal.step("FUNDING -> SPOT", async { Ok::<_, String>(String::from("754147")) }).await, Ok(String::from("754147")));
+    journal.steps.push((String::from("SPOT -> ISOLATEDMARGIN"), StepStatus::Running));
+    journal.save("running").unwrap();
+
+    // 失败和完成的操作不会被当作中断
+    let mut failed = ActionJournal::new("test-failed", "repay", 1_f64);
+    assert!(failed.step("repay", async { Err::<String, String>(String::from("insufficient balance")) }).await.is_err());
+    let mut done = ActionJournal::new("test-done", "refill", 1_f64);
+    done.step("master -> sub", async { Ok::<_, String>(1) }).await.unwrap();
+    done.close();
+
+    let interrupted = interrupted().await;
+    assert_eq!(interrupted.len(), 1);
+    assert_eq!(interrupted[0].id, journal.id);
+    assert_eq!(interrupted[0].steps[0], (String::from("FUNDING -> SPOT"), StepStatus::Done(String::from("\"754147\""))));
+    assert_eq!(interrupted[0].steps[1].1, StepStatus::Running);
+    // 只通知一次
+    assert!(super::interrupted().await.is_empty());
+  }
+
+  #[tokio::test]
+  async fn untracked_journal_does_not_write() {
+    let mut journal = ActionJournal::untracked("test-untracked", "top_up", 1_f64);
+    assert_eq!(journal.step("top up", async { Ok::<_, String>(1) }).await, Ok(1));
+    journal.close();
+    assert_eq!(journal.steps.len(), 1);
+  }
+}
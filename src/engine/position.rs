@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+
+// 单个抵押物, liquidation_price 是其他资产价格不变时该抵押物的清算价
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollateralAsset {
+  pub asset: String,
+  pub amount: f64,
+  pub value: f64,
+  pub liquidation_threshold: f64,
+  pub liquidation_price: Option<f64>
+}
+
+// 交易所杠杆仓位和链上借贷仓位统一成同一个模型, 价值都以 base 计价
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoanPosition {
+  pub id: String,
+  pub venue: String, // BINANCE, HUOBI, aave-v3 ...
+  pub base: String, // 计价单位, usdt, usd, eth
+  pub collateral_value: f64,
+  pub debt_value: f64,
+  pub ltv: f64, // debt_value / collateral_value
+  pub liquidation_ltv: f64, // ltv 达到这个值会被清算
+  pub health_factor: f64, // liquidation_ltv / ltv, 小于 1 会被清算
+  pub liquidation_price: Option<f64>,
+  #[serde(default)]
+  pub collaterals: Vec<CollateralAsset>,
+  #[serde(default)]
+  pub next_health_factor: Option<f64>, // 下一次预言机更新后的健康因子, 目前只有 Maker 的 OSM 能提前知道
+  #[serde(default)]
+  pub next_update_at: Option<i64> // 下一次预言机更新的时间戳, 秒
+}
+
+impl LoanPosition {
+  pub fn new(id: String, venue: String, base: String, collateral_value: f64, debt_value: f64, liquidation_ltv: f64) -> LoanPosition {
+    // 没有借款时健康因子是无穷大; 有借款没有抵押物时 ltv 是无穷大, 健康因子是 0
+    let ltv = if collateral_value > 0_f64 { debt_value / collateral_value } else if debt_value > 0_f64 { f64::INFINITY } else { 0_f64 };
+    let health_factor = if ltv > 0_f64 { liquidation_ltv / ltv } else { f64::INFINITY };
+    LoanPosition {
+      id,
+      venue,
+      base,
+      collateral_value,
+      debt_value,
+      ltv,
+      liquidation_ltv,
+      health_factor,
+      liquidation_price: None,
+      collaterals: Vec::new(),
+      next_health_factor: None,
+      next_update_at: None
+    }
+  }
+
+  // 距离清算还差多少, 0.2 表示抵押物价值再下跌 20% 就会被清算
+  pub fn distance_to_liquidation(&self) -> f64 {
+    if self.health_factor.is_infinite() {
+      return 1_f64;
+    }
+    return 1_f64 - 1_f64 / self.health_factor;
+  }
+
+  // 告警和 telegram 回复里用的一行摘要
+  pub fn summary(&self) -> String {
+    let mut text = format!("ltv {:.4}/{:.4}, health factor {:.4}, liquidation price {:?}", self.ltv, self.liquidation_ltv, self.health_factor, self.liquidation_price);
+    if let Some(next) = self.next_health_factor {
+      text = format!("{}, next health factor {:.4} at {:?}", text, next, self.next_update_at);
+    }
+    return text;
+  }
+}
+
+// 按清算线加权后的抵押物价值等于债务时, 算出每个抵押物各自的清算价, price 为 value / amount
+pub fn collateral_liquidation_prices(collaterals: &mut [CollateralAsset], debt_value: f64) {
+  let weighted: f64 = collaterals.iter().map(|c| c.value * c.liquidation_threshold).sum();
+  for c in collaterals.iter_mut() {
+    let own = c.value * c.liquidation_threshold;
+    let others = weighted - own;
+    c.liquidation_price = if own > 0_f64 && c.amount > 0_f64 && debt_value > others {
+      Some((debt_value - others) / (c.amount * c.liquidation_threshold))
+    } else {
+      None
+    };
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn position(collateral_value: f64, debt_value: f64) -> LoanPosition {
+    LoanPosition::new(String::from("test"), String::from("TEST"), String::from("usdt"), collateral_value, debt_value, 0.8)
+  }
+
+  #[test]
+  fn debt_without_collateral_is_unhealthy() {
+    let pos = position(0_f64, 100_f64);
+    assert_eq!(pos.ltv, f64::INFINITY);
+    assert_eq!(pos.health_factor, 0_f64);
+    assert!(pos.distance_to_liquidation() < 0_f64);
+    let empty = position(0_f64, 0_f64);
+    assert_eq!((empty.ltv, empty.health_factor), (0_f64, f64::INFINITY));
+    assert_eq!(empty.distance_to_liquidation(), 1_f64);
+    let pos = position(1000_f64, 400_f64);
+    assert!((pos.health_factor - 2_f64).abs() < 1e-9);
+  }
+}
@@ -0,0 +1,605 @@
+use std::time;
+use std::sync::atomic::{ AtomicU64, Ordering };
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use super::guard::{ self, ActionGuard };
+use super::journal::ActionJournal;
+use super::exchange::{ Exchange, catalog, types::{ NetworkInfo, AccountType, Exchanges } };
+use crate::notify::{ self, Severity };
+use crate::metrics;
+use crate::store;
+
+// 等待到账时轮询余额的间隔
+static DEPOSIT_POLL_INTERVAL: u64 = 15_u64;
+// 同一秒内的多个流程用序号区分
+static SEQ: AtomicU64 = AtomicU64::new(0);
+// 超过预计到账时间多少倍后放弃等待
+static DEPOSIT_TIMEOUT_FACTOR: u64 = 4_u64;
+// 重启后查询提币记录的次数和间隔, 交易所的提币记录可能延迟出现
+static WITHDRAWAL_LOOKUP_RETRIES: u32 = 5_u32;
+#[cfg(not(test))]
+static WITHDRAWAL_LOOKUP_INTERVAL: u64 = 30_u64;
+#[cfg(test)]
+static WITHDRAWAL_LOOKUP_INTERVAL: u64 = 0_u64;
+// 按划转记录核对时向前多查的时间, 容忍本地和交易所的时钟误差
+static TRANSFER_LOOKUP_MARGIN_MS: i64 = 300_000_i64;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RebalancePlan {
+  pub source: Exchange,
+  pub target: Exchange,
+  pub asset: String,
+  pub chain: String, // 通用链名称
+  pub source_network: String,
+  pub target_network: String,
+  pub withdraw_amount: f64, // 从源交易所转出的总数, 包含手续费
+  pub receive_amount: f64, // 目标交易所预计到账
+  pub fee: f64,
+  pub eta_secs: u64,
+  pub meets_deadline: bool
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum RebalanceStep {
+  Prepare, // 记录目标交易所提币前的余额, 获取充值地址
+  Gather,
+  Withdraw,
+  WaitDeposit,
+  TopUp
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum StepStatus {
+  Pending,
+  Running, // 已经写入日志, 开始执行, 重启后看到这个状态需要先和交易所核对
+  Done(String),
+  Failed(String)
+}
+
+// 每一步执行前后都写入操作日志, 重启后从日志恢复
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RebalanceWorkflow {
+  pub id: String,
+  pub plan: RebalancePlan,
+  pub steps: Vec<(RebalanceStep, StepStatus)>,
+  pub before: Option<f64>, // 提币前目标交易所的余额, 用来判断是否到账
+  pub address: Option<String>, // 目标交易所的充值地址
+  #[serde(default)]
+  pub step_started_at: Option<i64> // 当前步骤开始的时间(毫秒), 重启后按这个时间查询交易所的记录
+}
+
+static JOURNAL_KIND: &str = "rebalance";
+
+impl RebalanceWorkflow {
+  pub fn new(plan: RebalancePlan) -> RebalanceWorkflow {
+    RebalanceWorkflow {
+      id: format!("rebalance-{}-{}-{}", plan.target.name, Local::now().format("%Y%m%d%H%M%S"), SEQ.fetch_add(1, Ordering::Relaxed)),
+      plan,
+      steps: vec![
+        (RebalanceStep::Prepare, StepStatus::Pending),
+        (RebalanceStep::Gather, StepStatus::Pending),
+        (RebalanceStep::Withdraw, StepStatus::Pending),
+        (RebalanceStep::WaitDeposit, StepStatus::Pending),
+        (RebalanceStep::TopUp, StepStatus::Pending),
+      ],
+      before: None,
+      address: None,
+      step_started_at: None
+    }
+  }
+
+  // 提币时传给交易所的客户端 id, OKEX 只允许 32 位以内的字母和数字
+  pub fn client_id(&self) -> String {
+    self.id.chars().filter(|c| c.is_ascii_alphanumeric()).take(32).collect()
+  }
+
+  pub fn is_failed(&self) -> bool {
+    self.steps.iter().any(|(_, status)| matches!(status, StepStatus::Failed(_)))
+  }
+
+  fn journal(&self) -> Result<(), String> {
+    let status = if self.is_done() { "done" } else if self.is_failed() { "failed" } else { "running" };
+    let state = serde_json::to_string(self).map_err(|e| format!("[{}] serialize error: {}", self.id, e))?;
+    return store::journal_write(&self.id, JOURNAL_KIND, &state, status);
+  }
+
+  pub fn is_done(&self) -> bool {
+    self.steps.iter().all(|(_, status)| matches!(status, StepStatus::Done(_)))
+  }
+
+  fn set_status(&mut self, step: RebalanceStep, status: StepStatus) {
+    let venue = self.plan.target.name.to_string();
+    let action = format!("rebalance {:?}", step);
+    let request = format!("{} {} {} -> {} via {}", self.plan.withdraw_amount, self.plan.asset, self.plan.source.name, self.plan.target.name, self.plan.chain);
+    match &status {
+      StepStatus::Done(msg) => {
+        store::action(&self.id, &action, &request, msg, "ok");
+        if step == RebalanceStep::TopUp {
+          metrics::protection_action(&venue, "rebalance", "ok");
+          notify::spawn(Severity::ACTION, &self.id, "rebalance done", &format!("{:?} done: {}", step, msg));
+        } else {
+          log::info!("[{}] {:?} done: {}", self.id, step, msg);
+        }
+      }
+      StepStatus::Failed(msg) => {
+        store::action(&self.id, &action, &request, msg, "error");
+        metrics::protection_action(&venue, "rebalance", "error");
+        notify::spawn(Severity::CRITICAL, &self.id, "rebalance failed", &format!("{:?} failed: {}", step, msg));
+      }
+      StepStatus::Pending | StepStatus::Running => {}
+    }
+    if let Some(item) = self.steps.iter_mut().find(|(s, _)| *s == step) {
+      item.1 = status;
+    }
+  }
+}
+
+// 提币从哪个账户扣款, OKEX 从资金账户, 其他从现货账户
+fn withdraw_wallet(ex: &Exchange) -> AccountType {
+  if ex.name == Exchanges::OKEX { AccountType::FUNDING } else { AccountType::SPOT }
+}
+
+// 把现货/资金账户里的余额划转到提币账户, 凑够 amount
+async fn gather(ex: &Exchange, asset: &str, amount: f64) -> Result<String, String> {
+  let balances = ex.balances().await?;
+  let wallet = withdraw_wallet(ex);
+  let mut have = balances.get(&wallet, asset).map(|b| b.free).unwrap_or(0_f64);
+  let mut transfers: Vec<String> = Vec::new();
+  for from in [AccountType::SPOT, AccountType::FUNDING].into_iter().filter(|a| *a != wallet) {
+    if have >= amount {
+      break;
+    }
+    let free = balances.get(&from, asset).map(|b| b.free).unwrap_or(0_f64);
+    let moving = crate::util::min_f64(free, amount - have);
+    if moving <= 0_f64 {
+      continue;
+    }
+    let id = ex.transfer(String::from(asset), moving, from.clone(), wallet.clone()).await?;
+    transfers.push(format!("{} {} {} -> {}: {}", moving, asset, from, wallet, id));
+    have += moving;
+  }
+  if have < amount {
+    return Err(format!("{} only has {} {} to withdraw, need {}", ex.name, have, asset, amount));
+  }
+  return Ok(transfers.join(", "));
+}
+
+fn find_chain<'a>(networks: &'a [NetworkInfo], chain: &str) -> Option<&'a NetworkInfo> {
+  networks.iter().find(|n| n.chain == chain)
+}
+
+// 一个源交易所可以使用的所有路线: 两边都支持的链, 源交易所可以提币, 余额够提币数量(含手续费, 不低于最小提币数量)
+fn routes(target: &Exchange, source: &Exchange, source_available: f64, source_networks: &[NetworkInfo], target_networks: &[NetworkInfo], need: f64, deadline_secs: u64) -> Vec<RebalancePlan> {
+  let mut res: Vec<RebalancePlan> = Vec::new();
+  for source_network in source_networks.iter().filter(|n| n.withdraw_enabled) {
+    let target_network = match find_chain(target_networks, &source_network.chain) {
+      Some(n) => n,
+      None => continue
+    };
+    let withdraw_amount = crate::util::max_f64(need + source_network.fee, source_network.min_amount);
+    if source_available < withdraw_amount {
+      continue;
+    }
+    let eta_secs = catalog::eta_secs(source_network);
+    res.push(RebalancePlan {
+      source: source.clone(),
+      target: target.clone(),
+      asset: target.currency.clone(),
+      chain: source_network.chain.clone(),
+      source_network: source_network.network.clone(),
+      target_network: target_network.network.clone(),
+      withdraw_amount,
+      receive_amount: withdraw_amount - source_network.fee,
+      fee: source_network.fee,
+      eta_secs,
+      meets_deadline: eta_secs <= deadline_secs
+    });
+  }
+  return res;
+}
+
+// 优先选择能在 deadline 之前到账的最便宜路线, 手续费相同时选更快的, 如果都赶不上则选最快的路线
+fn best(candidates: Vec<RebalancePlan>) -> Option<RebalancePlan> {
+  if candidates.iter().any(|c| c.meets_deadline) {
+    return candidates.into_iter().filter(|c| c.meets_deadline).min_by(|a, b| a.fee.total_cmp(&b.fee).then(a.eta_secs.cmp(&b.eta_secs)));
+  }
+  return candidates.into_iter().min_by(|a, b| a.eta_secs.cmp(&b.eta_secs).then(a.fee.total_cmp(&b.fee)));
+}
+
+// 目标交易所的 currency 不足 shortfall 时, 从 sources 里找一个余额充足的交易所和一条链,
+// deadline_secs 是距离强平的预估时间
+pub async fn plan(target: &Exchange, sources: &[Exchange], shortfall: f64, deadline_secs: u64) -> Result<Option<RebalancePlan>, String> {
+  let target_account = target.account_info().await?;
+  if target_account.available_currency >= shortfall {
+    log::info!("{} has enough {} for top up: {}", target.name, target.currency, target_account.available_currency);
+    return Ok(None);
+  }
+  let need = shortfall - target_account.available_currency;
+  let target_networks = catalog::networks(target, &target.currency).await?;
+  let mut candidates: Vec<RebalancePlan> = Vec::new();
+  for source in sources.iter().filter(|s| s.name != target.name) {
+    let source_available = match source.balances().await {
+      Ok(balances) => balances.mobilizable(&target.currency),
+      Err(err) => {
+        log::warn!("skip {} as rebalance source: {}", source.name, err);
+        continue;
+      }
+    };
+    let source_networks = match catalog::networks(source, &target.currency).await {
+      Ok(networks) => networks,
+      Err(err) => {
+        log::warn!("skip {} as rebalance source: {}", source.name, err);
+        continue;
+      }
+    };
+    candidates.extend(routes(target, source, source_available, &source_networks, &target_networks, need, deadline_secs));
+  }
+  let best = best(candidates).ok_or(format!("no rebalance source has {} {} available", need, target.currency))?;
+  if !best.meets_deadline {
+    log::warn!("fastest rebalance route {} -> {} via {} takes {}s, over deadline {}s", best.source.name, best.target.name, best.chain, best.eta_secs, deadline_secs);
+  }
+  return Ok(Some(best));
+}
+
+// 归集余额 -> 提币 -> 等待到账 -> 补充保证金, 任何一步失败即停止, 返回的 workflow 记录每一步的结果,
+// dry run 时所有步骤保持 Pending
+pub async fn execute(plan: RebalancePlan, guard: &ActionGuard) -> RebalanceWorkflow {
+  let mut wf = RebalanceWorkflow::new(plan.clone());
+  let action = format!("rebalance {} -> {} {}", plan.source.name, plan.target.name, plan.asset);
+  match guard::protected(&plan.target, &action).and_then(|_| guard.check_exchange(&plan.source, &action, plan.withdraw_amount)) {
+    Ok(true) => {}
+    Ok(false) => return wf,
+    Err(err) => {
+      wf.set_status(RebalanceStep::Gather, StepStatus::Failed(err));
+      return wf;
+    }
+  }
+  log::info!("[{}] start: {:?}", wf.id, plan);
+  return run(wf).await;
+}
+
+// 把某一步标记为 Running 并写入日志, 日志写失败时不执行
+// 没有配置 store 时 journal_write 返回错误, 调仓不会开始
+fn begin(wf: &mut RebalanceWorkflow, step: RebalanceStep) -> bool {
+  if let Some(item) = wf.steps.iter_mut().find(|(s, _)| *s == step) {
+    // 恢复的步骤保留第一次开始的时间
+    if item.1 != StepStatus::Running {
+      wf.step_started_at = Some(Local::now().timestamp_millis());
+    }
+    item.1 = StepStatus::Running;
+  }
+  if let Err(err) = wf.journal() {
+    wf.set_status(step, StepStatus::Failed(err));
+    return false;
+  }
+  return true;
+}
+
+fn finish(wf: &mut RebalanceWorkflow, step: RebalanceStep, status: StepStatus) {
+  wf.set_status(step, status);
+  if let Err(err) = wf.journal() {
+    log::error!("{}", err);
+  }
+}
+
+fn status_of(wf: &RebalanceWorkflow, step: &RebalanceStep) -> StepStatus {
+  wf.steps.iter().find(|(s, _)| s == step).map(|(_, status)| status.clone()).unwrap_or(StepStatus::Pending)
+}
+
+async fn run_step(wf: &mut RebalanceWorkflow, step: &RebalanceStep, resumed: bool) -> Result<String, String> {
+  let plan = wf.plan.clone();
+  match step {
+    // 只读取, 重复执行是安全的
+    RebalanceStep::Prepare => {
+      let before = plan.target.account_info().await?.available_currency;
+      let address = plan.target.deposit_address(plan.asset.clone(), plan.target_network.clone()).await?;
+      wf.before = Some(before);
+      wf.address = Some(address.clone());
+      Ok(format!("{} {} before deposit, address {}", before, plan.asset, address))
+    }
+    // 只转入不足的部分, 重复执行是安全的
+    RebalanceStep::Gather => {
+      guard::protected(&plan.source, "rebalance gather")?;
+      gather(&plan.source, &plan.asset, plan.withdraw_amount).await
+    }
+    RebalanceStep::Withdraw => {
+      guard::protected(&plan.source, "rebalance withdraw")?;
+      let client_id = wf.client_id();
+      if resumed {
+        // 重启前可能已经提交, 按客户端 id 查询; 查不到时不能确定没有提交, 不重新提币
+        for i in 0..WITHDRAWAL_LOOKUP_RETRIES {
+          if i > 0 {
+            tokio::time::sleep(time::Duration::from_secs(WITHDRAWAL_LOOKUP_INTERVAL)).await;
+          }
+          match plan.source.find_withdrawal(plan.asset.clone(), client_id.clone()).await {
+            Ok(Some(id)) => return Ok(format!("{} (reconciled)", id)),
+            Ok(None) => {}
+            Err(err) => log::warn!("[{}] find withdrawal {} error: {}", wf.id, client_id, err)
+          }
+        }
+        return Err(format!("withdrawal {} was interrupted and not found on {}, check it manually", client_id, plan.source.name));
+      }
+      let address = wf.address.clone().ok_or("no deposit address")?;
+      plan.source.withdraw_on_chain(plan.asset.clone(), plan.source_network.clone(), address, plan.withdraw_amount, client_id).await
+    }
+    RebalanceStep::WaitDeposit => {
+      let before = wf.before.ok_or("no balance before withdraw")?;
+      let timeout = time::Duration::from_secs(plan.eta_secs * DEPOSIT_TIMEOUT_FACTOR);
+      let started = time::Instant::now();
+      loop {
+        match plan.target.account_info().await {
+          Ok(ai) if ai.available_currency - before >= plan.receive_amount * 0.999 => {
+            return Ok(format!("{} arrived", ai.available_currency - before));
+          }
+          Ok(_) => {}
+          Err(err) => log::warn!("[{}] poll {} balance error: {}", wf.id, plan.target.name, err)
+        }
+        if started.elapsed() > timeout {
+          return Err(format!("deposit not arrived after {}s", timeout.as_secs()));
+        }
+        tokio::time::sleep(time::Duration::from_secs(DEPOSIT_POLL_INTERVAL)).await;
+      }
+    }
+    RebalanceStep::TopUp => {
+      guard::protected(&plan.target, "rebalance top up")?;
+      // 日志记录在 workflow 里, top_up 内部的划转不再单独记录
+      let mut journal = ActionJournal::untracked(&wf.id, "top_up", plan.receive_amount);
+      if resumed {
+        // 重启前可能已经划转, 按交易所的划转记录核对
+        let pair = AccountType::ISOLATEDMARGIN(format!("{}{}", plan.target.symbol.to_lowercase(), plan.target.currency.to_lowercase()));
+        let since = wf.step_started_at.ok_or("top up was interrupted without a start time, check it manually")? - TRANSFER_LOOKUP_MARGIN_MS;
+        if let Some(id) = plan.target.find_transfer(plan.target.currency.clone(), plan.receive_amount, AccountType::SPOT, pair, since).await? {
+          return Ok(format!("{} (reconciled)", id));
+        }
+      }
+      plan.target.top_up(plan.receive_amount, &mut journal).await
+    }
+  }
+}
+
+// 从第一个没有完成的步骤继续执行, 新建的和从日志恢复的 workflow 都走这里
+pub async fn run(mut wf: RebalanceWorkflow) -> RebalanceWorkflow {
+  let steps: Vec<RebalanceStep> = wf.steps.iter().map(|(s, _)| s.clone()).collect();
+  for step in steps.iter() {
+    let resumed = match status_of(&wf, step) {
+      StepStatus::Done(_) => continue,
+      StepStatus::Failed(_) => return wf,
+      StepStatus::Running => true,
+      StepStatus::Pending => false
+    };
+    if !begin(&mut wf, step.clone()) {
+      return wf;
+    }
+    match run_step(&mut wf, step, resumed).await {
+      Ok(msg) => finish(&mut wf, step.clone(), StepStatus::Done(msg)),
+      Err(err) => {
+        finish(&mut wf, step.clone(), StepStatus::Failed(err));
+        return wf;
+      }
+    }
+  }
+  return wf;
+}
+
+// 启动时恢复日志里没有结束的 workflow, dry run 时只打印, 保留在日志里
+pub async fn resume() -> Vec<RebalanceWorkflow> {
+  let mut resumed: Vec<RebalanceWorkflow> = Vec::new();
+  for state in store::journal_open(JOURNAL_KIND).iter() {
+    let wf: RebalanceWorkflow = match serde_json::from_str(state) {
+      Ok(wf) => wf,
+      Err(err) => {
+        log::error!("invalid rebalance journal entry: {}", err);
+        continue;
+      }
+    };
+    if guard::current().dry_run {
+      log::warn!("[{}] unfinished rebalance not resumed in dry run: {:?}", wf.id, wf.steps);
+      continue;
+    }
+    log::warn!("[{}] resume unfinished rebalance: {:?}", wf.id, wf.steps);
+    resumed.push(run(wf).await);
+  }
+  return resumed;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+  use crate::engine::exchange::mock::{ MockExchange, Script };
+
+  fn exchange(name: Exchanges) -> Exchange {
+    Exchange {
+      name,
+      symbol: String::from("crv"),
+      currency: String::from("usdt"),
+      host: String::from("127.0.0.1"),
+      protocol: String::from("http"),
+      config: String::from("rebalance.test"),
+      protect: true,
+      subaccount: None
+    }
+  }
+
+  fn network(chain: &str, fee: f64, withdraw_enabled: bool, confirmations: u64) -> NetworkInfo {
+    NetworkInfo { chain: String::from(chain), network: chain.to_lowercase(), fee, min_amount: 10_f64, withdraw_enabled, confirmations }
+  }
+
+  fn candidates(deadline_secs: u64) -> Vec<RebalancePlan> {
+    let target = exchange(Exchanges::BINANCE);
+    let target_networks = vec![network("TRC20", 1_f64, true, 1), network("ERC20", 5_f64, true, 12), network("BEP20", 0.3, true, 15)];
+    // TRC20 123s, ERC20 264s, BEP20 165s
+    let huobi = vec![network("TRC20", 1_f64, true, 1), network("ERC20", 1_f64, true, 12)];
+    let okex = vec![network("BEP20", 0.5, true, 15)];
+    let mut res = routes(&target, &exchange(Exchanges::HUOBI), 1000_f64, &huobi, &target_networks, 100_f64, deadline_secs);
+    res.extend(routes(&target, &exchange(Exchanges::OKEX), 1000_f64, &okex, &target_networks, 100_f64, deadline_secs));
+    return res;
+  }
+
+  #[test]
+  fn source_selection() {
+    let target = exchange(Exchanges::BINANCE);
+    let target_networks = vec![network("TRC20", 1_f64, true, 1), network("ERC20", 5_f64, true, 12)];
+    let source_networks = vec![network("TRC20", 1_f64, false, 1), network("ERC20", 5_f64, true, 12), network("SOL", 0.1, true, 1)];
+    // 暂停提币的链和目标交易所不支持的链跳过
+    let res = routes(&target, &exchange(Exchanges::OKEX), 500_f64, &source_networks, &target_networks, 100_f64, 3600);
+    assert_eq!(res.len(), 1);
+    assert_eq!(res[0].chain, "ERC20");
+    assert_eq!(res[0].withdraw_amount, 105_f64);
+    assert_eq!(res[0].receive_amount, 100_f64);
+    // 余额不够提币数量加手续费
+    assert!(routes(&target, &exchange(Exchanges::HUOBI), 104_f64, &source_networks, &target_networks, 100_f64, 3600).is_empty());
+    // 不足最小提币数量时按最小数量提
+    let small = routes(&target, &exchange(Exchanges::OKEX), 500_f64, &source_networks, &target_networks, 2_f64, 3600);
+    assert_eq!(small[0].withdraw_amount, 10_f64);
+    assert!(best(vec![]).is_none());
+  }
+
+  #[test]
+  fn cheapest_route_in_time_then_fastest() {
+    // 都能按时到账时选手续费最低的 BEP20
+    let plan = best(candidates(3600)).unwrap();
+    assert_eq!((plan.source.name, plan.chain.as_str()), (Exchanges::OKEX, "BEP20"));
+    // BEP20 赶不上时, 手续费相同的 TRC20 和 ERC20 里选更快的
+    let plan = best(candidates(150)).unwrap();
+    assert_eq!((plan.source.name, plan.chain.as_str()), (Exchanges::HUOBI, "TRC20"));
+    assert!(plan.meets_deadline);
+  }
+
+  #[test]
+  fn missed_deadline_takes_fastest() {
+    let plan = best(candidates(60)).unwrap();
+    assert_eq!(plan.chain, "TRC20");
+    assert_eq!(plan.eta_secs, 123);
+    assert!(!plan.meets_deadline);
+    // 同一秒内创建的流程 id 也不同
+    assert_ne!(RebalanceWorkflow::new(plan.clone()).id, RebalanceWorkflow::new(plan).id);
+  }
+
+  // 从日志恢复的 workflow: 前面的步骤都已完成, step 在重启时处于 Running
+  fn interrupted_at(source: Exchange, target: Exchange, step: RebalanceStep) -> RebalanceWorkflow {
+    let mut wf = RebalanceWorkflow::new(RebalancePlan {
+      source: Exchange { protect: true, ..source },
+      target: Exchange { protect: true, ..target },
+      asset: String::from("usdt"),
+      chain: String::from("TRC20"),
+      source_network: String::from("trx"),
+      target_network: String::from("trx"),
+      withdraw_amount: 101_f64,
+      receive_amount: 100_f64,
+      fee: 1_f64,
+      eta_secs: 60,
+      meets_deadline: true
+    });
+    let mut reached = false;
+    for item in wf.steps.iter_mut() {
+      reached = reached || item.0 == step;
+      item.1 = if item.0 == step { StepStatus::Running } else if reached { StepStatus::Pending } else { StepStatus::Done(String::new()) };
+    }
+    wf.before = Some(0_f64);
+    wf.address = Some(String::from("TXaddress"));
+    wf.step_started_at = Some(Local::now().timestamp_millis());
+    return wf;
+  }
+
+  #[tokio::test]
+  async fn resumed_top_up_reconciles_with_transfer_history() {
+    let _lock = store::init_temp("rebalance-top-up").await;
+    let mock = MockExchange::start(Exchanges::BINANCE).await;
+    let ex = mock.exchange("crv", "usdt");
+    mock.set("GET /sapi/v1/margin/isolated/transfer", json!({
+      "rows": [
+        { "amount": "50", "asset": "USDT", "status": "CONFIRMED", "txId": 1001_u64, "transFrom": "SPOT", "transTo": "ISOLATED_MARGIN" },
+        { "amount": "100", "asset": "USDT", "status": "CONFIRMED", "txId": 1002_u64, "transFrom": "SPOT", "transTo": "ISOLATED_MARGIN" }
+      ],
+      "total": 2
+    }));
+    let wf = run(interrupted_at(ex.clone(), ex, RebalanceStep::TopUp)).await;
+    assert!(wf.is_done(), "{:?}", wf.steps);
+    assert_eq!(status_of(&wf, &RebalanceStep::TopUp), StepStatus::Done(String::from("1002 (reconciled)")));
+    let lookup = &mock.requests("/sapi/v1/margin/isolated/transfer")[0];
+    assert_eq!(lookup.method, "GET");
+    assert!(lookup.query.contains("symbol=CRVUSDT&transFrom=SPOT&transTo=ISOLATED_MARGIN"));
+    // 已经划转过, 不再提交
+    assert!(mock.requests("/sapi/v1/margin/isolated/transfer").iter().all(|r| r.method == "GET"));
+  }
+
+  #[tokio::test]
+  async fn resumed_top_up_transfers_when_history_has_none() {
+    let _lock = store::init_temp("rebalance-top-up-missing").await;
+    let mock = MockExchange::start(Exchanges::BINANCE).await;
+    let ex = mock.exchange("crv", "usdt");
+    let wf = run(interrupted_at(ex.clone(), ex, RebalanceStep::TopUp)).await;
+    assert_eq!(status_of(&wf, &RebalanceStep::TopUp), StepStatus::Done(String::from("13526853623")));
+    let posts: Vec<_> = mock.requests("/sapi/v1/margin/isolated/transfer").into_iter().filter(|r| r.method == "POST").collect();
+    assert_eq!(posts.len(), 1);
+    assert!(posts[0].query.contains("amount=100"));
+  }
+
+  #[tokio::test]
+  async fn resumed_withdraw_is_never_submitted_again() {
+    let _lock = store::init_temp("rebalance-withdraw").await;
+    let mock = MockExchange::start(Exchanges::BINANCE).await;
+    let ex = mock.exchange("crv", "usdt");
+    // 第一次查询出错, 第二次查不到, 第三次查到
+    mock.script("/sapi/v1/capital/withdraw/history", Script::Error(500, json!({ "code": -1000, "msg": "internal error" })));
+    mock.script("/sapi/v1/capital/withdraw/history", Script::Body(String::from("[]")));
+    mock.script("/sapi/v1/capital/withdraw/history", Script::Body(json!([{ "id": "b6ae22b3aa844210a7041aee7589627c", "withdrawOrderId": "x" }]).to_string()));
+    let wf = run(interrupted_at(ex.clone(), ex.clone(), RebalanceStep::Withdraw)).await;
+    assert_eq!(status_of(&wf, &RebalanceStep::Withdraw), StepStatus::Done(String::from("b6ae22b3aa844210a7041aee7589627c (reconciled)")));
+    assert_eq!(mock.requests("/sapi/v1/capital/withdraw/history").len(), 3);
+
+    // 一直查不到时失败, 由人工核对
+    let mock = MockExchange::start(Exchanges::BINANCE).await;
+    let ex = mock.exchange("crv", "usdt");
+    let wf = run(interrupted_at(ex.clone(), ex, RebalanceStep::Withdraw)).await;
+    assert!(matches!(status_of(&wf, &RebalanceStep::Withdraw), StepStatus::Failed(err) if err.contains("check it manually")));
+    assert_eq!(mock.requests("/sapi/v1/capital/withdraw/history").len(), WITHDRAWAL_LOOKUP_RETRIES as usize);
+    assert!(mock.requests("/sapi/v1/capital/withdraw/apply").is_empty());
+    assert_eq!(status_of(&wf, &RebalanceStep::WaitDeposit), StepStatus::Pending);
+  }
+
+  #[tokio::test]
+  async fn transfer_history_on_huobi_and_okex() {
+    let since = Local::now().timestamp_millis();
+    let pair = AccountType::ISOLATEDMARGIN(String::from("crvusdt"));
+    let huobi = MockExchange::start(Exchanges::HUOBI).await;
+    huobi.set("/v1/account/history", json!({
+      "status": "ok",
+      "data": [{ "account-id": 100009, "currency": "usdt", "transact-amt": "-100", "transact-type": "transfer", "record-id": 89373, "transact-time": since }]
+    }));
+    let ex = huobi.exchange("crv", "usdt");
+    assert_eq!(ex.find_transfer(String::from("usdt"), 100_f64, AccountType::SPOT, pair.clone(), since).await, Ok(Some(String::from("89373"))));
+    // 方向相反的划转不算
+    assert_eq!(ex.find_transfer(String::from("usdt"), 100_f64, pair.clone(), AccountType::SPOT, since).await, Ok(None));
+
+    let okex = MockExchange::start(Exchanges::OKEX).await;
+    okex.set("/api/v5/account/bills", json!({
+      "code": "0", "msg": "",
+      "data": [
+        { "billId": "623950854533513219", "instId": "CRV-USDT", "ccy": "USDT", "type": "6", "subType": "160", "balChg": "100" },
+        { "billId": "623950854533513220", "instId": "BTC-USDT", "ccy": "USDT", "type": "6", "subType": "160", "balChg": "50" }
+      ]
+    }));
+    let ex = okex.exchange("crv", "usdt");
+    assert_eq!(ex.find_transfer(String::from("usdt"), 100_f64, AccountType::SPOT, pair.clone(), since).await, Ok(Some(String::from("623950854533513219"))));
+    assert_eq!(ex.find_transfer(String::from("usdt"), 50_f64, AccountType::SPOT, pair, since).await, Ok(None));
+    assert!(ex.find_transfer(String::from("usdt"), 50_f64, AccountType::FUNDING, AccountType::SPOT, since).await.is_err());
+  }
+
+  #[tokio::test]
+  async fn monitoring_only_exchanges_are_refused() {
+    let _lock = store::init_temp("rebalance-protect").await;
+    let mock = MockExchange::start(Exchanges::BINANCE).await;
+    let ex = mock.exchange("crv", "usdt");
+    let mut wf = interrupted_at(ex.clone(), ex.clone(), RebalanceStep::TopUp);
+    wf.plan.target.protect = false;
+    let wf = run(wf).await;
+    assert!(matches!(status_of(&wf, &RebalanceStep::TopUp), StepStatus::Failed(err) if err.contains("monitoring only")));
+    assert!(mock.requests("/sapi/v1/margin/isolated/transfer").is_empty());
+
+    let plan = interrupted_at(ex.clone(), ex, RebalanceStep::Gather).plan;
+    let wf = execute(RebalancePlan { source: Exchange { protect: false, ..plan.source.clone() }, ..plan }, &ActionGuard::default()).await;
+    assert!(matches!(status_of(&wf, &RebalanceStep::Gather), StepStatus::Failed(err) if err.contains("monitoring only")));
+  }
+}
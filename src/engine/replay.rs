@@ -0,0 +1,211 @@
+// 回放用的历史行情, 每行一条, 时间戳都是毫秒:
+//   K 线: open_time,open,high,low,close,... (binance 公开数据的格式, 多余的列忽略)
+//   深度: ts,depth,bids,asks, 每档写成 price:volume, 档之间用 | 分隔, 从最优价开始
+//   成交: ts,trade,price,volume,buy|sell
+// 空行, # 开头的行和表头跳过. .gz 结尾的文件(recorder 写入的)先解压.
+// .parquet 文件的列和 CSV 一样, 每行按列的顺序当作一行 CSV 解析
+use std::fs;
+use std::io::Read;
+use flate2::read::MultiGzDecoder;
+use parquet::file::reader::{ FileReader, SerializedFileReader };
+use parquet::record::Field;
+use std::path::{ Path, PathBuf };
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MarketEvent {
+  Kline { ts: i64, open: f64, high: f64, low: f64, close: f64 },
+  Depth { ts: i64, bids: Vec<[f64;2]>, asks: Vec<[f64;2]> },
+  Trade { ts: i64, price: f64, volume: f64, buy: bool }
+}
+
+impl MarketEvent {
+  pub fn ts(&self) -> i64 {
+    match self {
+      MarketEvent::Kline { ts, .. } | MarketEvent::Depth { ts, .. } | MarketEvent::Trade { ts, .. } => *ts
+    }
+  }
+}
+
+fn number(field: &str, line_no: usize) -> Result<f64, String> {
+  field.trim().parse::<f64>().map_err(|_| format!("line {}: invalid number {}", line_no, field))
+}
+
+// 2025 年以后的 binance 公开数据是微秒
+fn millis(field: &str, line_no: usize) -> Result<i64, String> {
+  let ts = field.trim().parse::<i64>().map_err(|_| format!("line {}: invalid timestamp {}", line_no, field))?;
+  return Ok(if ts > 100_000_000_000_000_i64 { ts / 1000 } else { ts });
+}
+
+fn levels(field: &str, line_no: usize) -> Result<Vec<[f64;2]>, String> {
+  let mut res: Vec<[f64;2]> = Vec::new();
+  for level in field.split('|').filter(|l| !l.trim().is_empty()) {
+    let (price, volume) = level.split_once(':').ok_or(format!("line {}: invalid depth level {}", line_no, level))?;
+    res.push([number(price, line_no)?, number(volume, line_no)?]);
+  }
+  return Ok(res);
+}
+
+pub fn parse_line(line: &str, line_no: usize) -> Result<Option<MarketEvent>, String> {
+  let line = line.trim();
+  if line.is_empty() || line.starts_with('#') {
+    return Ok(None);
+  }
+  let fields: Vec<&str> = line.split(',').collect();
+  // 表头
+  if fields[0].trim().parse::<f64>().is_err() {
+    return Ok(None);
+  }
+  let ts = millis(fields[0], line_no)?;
+  match fields.get(1).map(|f| f.trim()) {
+    Some("depth") if fields.len() >= 4 => Ok(Some(MarketEvent::Depth {
+      ts,
+      bids: levels(fields[2], line_no)?,
+      asks: levels(fields[3], line_no)?
+    })),
+    Some("trade") if fields.len() >= 5 => Ok(Some(MarketEvent::Trade {
+      ts,
+      price: number(fields[2], line_no)?,
+      volume: number(fields[3], line_no)?,
+      buy: fields[4].trim().eq_ignore_ascii_case("buy")
+    })),
+    _ if fields.len() >= 5 => Ok(Some(MarketEvent::Kline {
+      ts,
+      open: number(fields[1], line_no)?,
+      high: number(fields[2], line_no)?,
+      low: number(fields[3], line_no)?,
+      close: number(fields[4], line_no)?
+    })),
+    _ => Err(format!("line {}: unknown record {}", line_no, line))
+  }
+}
+
+pub fn parse(text: &str) -> Result<Vec<MarketEvent>, String> {
+  let mut events: Vec<MarketEvent> = Vec::new();
+  for (i, line) in text.lines().enumerate() {
+    if let Some(event) = parse_line(line, i + 1)? {
+      events.push(event);
+    }
+  }
+  return Ok(events);
+}
+
+// 进程被杀掉时最后一段 gzip 不完整, 保留已经解压出来的完整行
+fn read_gz(path: &Path) -> Result<String, String> {
+  let file = fs::File::open(path).map_err(|e| format!("read {} error: {}", path.display(), e))?;
+  let mut data: Vec<u8> = Vec::new();
+  if let Err(err) = MultiGzDecoder::new(file).read_to_end(&mut data) {
+    log::warn!("{} truncated: {}", path.display(), err);
+    let end = data.iter().rposition(|b| *b == b'\n').map(|i| i + 1).unwrap_or(0);
+    data.truncate(end);
+  }
+  return String::from_utf8(data).map_err(|e| format!("read {} error: {}", path.display(), e));
+}
+
+// 字符串不加引号, 时间戳列取毫秒数, 其他类型按显示的值
+fn field_text(field: &Field) -> String {
+  match field {
+    Field::Str(s) => s.clone(),
+    Field::TimestampMillis(ts) => ts.to_string(),
+    Field::TimestampMicros(ts) => (ts / 1000).to_string(),
+    Field::Null => String::new(),
+    other => other.to_string()
+  }
+}
+
+fn read_parquet(path: &Path) -> Result<Vec<MarketEvent>, String> {
+  let file = fs::File::open(path).map_err(|e| format!("read {} error: {}", path.display(), e))?;
+  let reader = SerializedFileReader::new(file).map_err(|e| format!("read {} error: {}", path.display(), e))?;
+  let rows = reader.get_row_iter(None).map_err(|e| format!("read {} error: {}", path.display(), e))?;
+  let mut events: Vec<MarketEvent> = Vec::new();
+  for (i, row) in rows.enumerate() {
+    let row = row.map_err(|e| format!("{}: row {}: {}", path.display(), i + 1, e))?;
+    let line = row.get_column_iter().map(|(_, field)| field_text(field)).collect::<Vec<String>>().join(",");
+    if let Some(event) = parse_line(&line, i + 1).map_err(|e| format!("{}: {}", path.display(), e))? {
+      events.push(event);
+    }
+  }
+  return Ok(events);
+}
+
+pub fn load(path: &Path) -> Result<Vec<MarketEvent>, String> {
+  if path.extension().map(|e| e == "parquet").unwrap_or(false) {
+    return read_parquet(path);
+  }
+  let text = if path.extension().map(|e| e == "gz").unwrap_or(false) {
+    read_gz(path)?
+  } else {
+    fs::read_to_string(path).map_err(|e| format!("read {} error: {}", path.display(), e))?
+  };
+  return parse(&text).map_err(|e| format!("{}: {}", path.display(), e));
+}
+
+// 多个文件合并后按时间排序, 同一时间保持文件里的顺序
+pub fn load_all(paths: &[PathBuf]) -> Result<Vec<MarketEvent>, String> {
+  let mut events: Vec<MarketEvent> = Vec::new();
+  for path in paths.iter() {
+    events.extend(load(path)?);
+  }
+  events.sort_by_key(|e| e.ts());
+  return Ok(events);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_all_formats() {
+    let text = "open_time,open,high,low,close\n\
+      # 2021-05-19\n\
+      1621382400000000,2.5,2.6,2.1,2.2,100,1621385999999\n\
+      1621382401000,depth,2.19:10|2.18:20,2.21:5\n\
+      1621382402000,trade,2.2,3,sell\n";
+    let events = parse(text).unwrap();
+    assert_eq!(events, vec![
+      MarketEvent::Kline { ts: 1621382400000, open: 2.5, high: 2.6, low: 2.1, close: 2.2 },
+      MarketEvent::Depth { ts: 1621382401000, bids: vec![[2.19, 10_f64], [2.18, 20_f64]], asks: vec![[2.21, 5_f64]] },
+      MarketEvent::Trade { ts: 1621382402000, price: 2.2, volume: 3_f64, buy: false }
+    ]);
+    assert!(parse("1621382400000,depth,2.19-10,2.21:5").unwrap_err().contains("line 1"));
+  }
+
+  #[test]
+  fn load_parquet_trades() {
+    use std::sync::Arc;
+    use parquet::data_type::{ ByteArray, ByteArrayType, DoubleType, Int64Type };
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::parser::parse_message_type;
+
+    let schema = parse_message_type("message trades {
+      REQUIRED INT64 ts (TIMESTAMP(MILLIS, true));
+      REQUIRED BYTE_ARRAY kind (UTF8);
+      REQUIRED DOUBLE price;
+      REQUIRED DOUBLE volume;
+      REQUIRED BYTE_ARRAY side (UTF8);
+    }").unwrap();
+    let path = std::env::temp_dir().join(format!("replay-{}.parquet", std::process::id()));
+    let mut writer = SerializedFileWriter::new(fs::File::create(&path).unwrap(), Arc::new(schema), Default::default()).unwrap();
+    let mut group = writer.next_row_group().unwrap();
+    let text = |values: &[&str]| values.iter().map(|v| ByteArray::from(*v)).collect::<Vec<ByteArray>>();
+    let mut index = 0;
+    while let Some(mut column) = group.next_column().unwrap() {
+      match index {
+        0 => column.typed::<Int64Type>().write_batch(&[1621382402000, 1621382403000], None, None).unwrap(),
+        1 => column.typed::<ByteArrayType>().write_batch(&text(&["trade", "trade"]), None, None).unwrap(),
+        2 => column.typed::<DoubleType>().write_batch(&[2.2, 2.1], None, None).unwrap(),
+        3 => column.typed::<DoubleType>().write_batch(&[3_f64, 0.5], None, None).unwrap(),
+        _ => column.typed::<ByteArrayType>().write_batch(&text(&["sell", "buy"]), None, None).unwrap()
+      };
+      column.close().unwrap();
+      index += 1;
+    }
+    group.close().unwrap();
+    writer.close().unwrap();
+    let events = load(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(events, vec![
+      MarketEvent::Trade { ts: 1621382402000, price: 2.2, volume: 3_f64, buy: false },
+      MarketEvent::Trade { ts: 1621382403000, price: 2.1, volume: 0.5, buy: true }
+    ]);
+  }
+}
@@ -0,0 +1,109 @@
+// 保护策略: 健康因子低于 trigger 时算出需要补充或者偿还的数量, 使健康因子回到 target.
+// 监控循环和回放用同一个 decide, 回放的结果才能说明实际运行时的表现
+use serde::{Deserialize, Serialize};
+use super::position::LoanPosition;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Strategy {
+  // 只告警不操作
+  #[default]
+  AlertOnly,
+  // 补充计价币保证金, 目标交易所余额不足时跨交易所调仓;
+  // delay_secs 和 fee 只在回放时使用, 模拟到账时间和每次的固定手续费
+  TopUp {
+    trigger: f64,
+    target: f64,
+    #[serde(default)]
+    delay_secs: i64,
+    #[serde(default)]
+    fee: f64,
+    #[serde(default)]
+    budget: f64 // 最多投入的资金, 0 表示不限制
+  },
+  // 用外部资金还款
+  Repay {
+    trigger: f64,
+    target: f64,
+    #[serde(default)]
+    budget: f64
+  },
+  // 卖出抵押物还款, 目前只在回放中模拟
+  Deleverage {
+    trigger: f64,
+    target: f64
+  }
+}
+
+impl Strategy {
+  pub fn name(&self) -> String {
+    match self {
+      Strategy::AlertOnly => String::from("alert only"),
+      Strategy::TopUp { trigger, target, .. } => format!("top up {} -> {}", trigger, target),
+      Strategy::Repay { trigger, target, .. } => format!("repay {} -> {}", trigger, target),
+      Strategy::Deleverage { trigger, target } => format!("deleverage {} -> {}", trigger, target)
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+  TopUp(f64), // 补充的保证金, 以仓位的 base 计价
+  Repay(f64),
+  Deleverage(f64) // 目标健康因子, 卖出数量取决于深度
+}
+
+// spent 是这个仓位已经投入的外部资金, 用于 budget 限制
+pub fn decide(strategy: &Strategy, pos: &LoanPosition, spent: f64) -> Option<Decision> {
+  let l = pos.liquidation_ltv;
+  match strategy {
+    Strategy::TopUp { trigger, target, budget, .. } if pos.health_factor < *trigger => {
+      let mut amount = target * pos.debt_value / l - pos.collateral_value;
+      if *budget > 0_f64 {
+        amount = amount.min(budget - spent);
+      }
+      if amount > 0_f64 { Some(Decision::TopUp(amount)) } else { None }
+    }
+    Strategy::Repay { trigger, target, budget } if pos.health_factor < *trigger => {
+      let mut amount = pos.debt_value - l * pos.collateral_value / target;
+      if *budget > 0_f64 {
+        amount = amount.min(budget - spent);
+      }
+      if amount > 0_f64 { Some(Decision::Repay(amount)) } else { None }
+    }
+    Strategy::Deleverage { trigger, target } if pos.health_factor < *trigger => Some(Decision::Deleverage(*target)),
+    _ => None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn position(collateral_value: f64, debt_value: f64) -> LoanPosition {
+    LoanPosition::new(String::from("test"), String::from("TEST"), String::from("usdt"), collateral_value, debt_value, 0.8)
+  }
+
+  #[test]
+  fn amounts_restore_target_health_factor() {
+    // hf = 0.8 / (700 / 1000) = 1.14
+    let pos = position(1000_f64, 700_f64);
+    let top_up = Strategy::TopUp { trigger: 1.3, target: 1.6, delay_secs: 0, fee: 0_f64, budget: 0_f64 };
+    let amount = match decide(&top_up, &pos, 0_f64) { Some(Decision::TopUp(a)) => a, other => panic!("{:?}", other) };
+    assert!((position(1000_f64 + amount, 700_f64).health_factor - 1.6).abs() < 1e-9);
+    let repay = Strategy::Repay { trigger: 1.3, target: 1.6, budget: 0_f64 };
+    let amount = match decide(&repay, &pos, 0_f64) { Some(Decision::Repay(a)) => a, other => panic!("{:?}", other) };
+    assert!((position(1000_f64, 700_f64 - amount).health_factor - 1.6).abs() < 1e-9);
+    assert_eq!(decide(&Strategy::AlertOnly, &pos, 0_f64), None);
+    // 高于 trigger 不操作
+    assert_eq!(decide(&top_up, &position(1000_f64, 500_f64), 0_f64), None);
+  }
+
+  #[test]
+  fn budget_limits_spending() {
+    let pos = position(1000_f64, 700_f64);
+    let top_up = Strategy::TopUp { trigger: 1.3, target: 1.6, delay_secs: 0, fee: 0_f64, budget: 300_f64 };
+    assert_eq!(decide(&top_up, &pos, 250_f64), Some(Decision::TopUp(50_f64)));
+    assert_eq!(decide(&top_up, &pos, 300_f64), None);
+  }
+}
@@ -0,0 +1,394 @@
+use std::collections::{ BTreeMap, HashMap };
+use std::path::Path;
+use std::sync::RwLock;
+use chacha20poly1305::{ ChaCha20Poly1305, Key, KeyInit, Nonce };
+use chacha20poly1305::aead::{ Aead, AeadCore, OsRng, rand_core::RngCore };
+use serde::{Deserialize, Serialize};
+use zeroize::{ Zeroize, Zeroizing };
+
+// 交易所 API key 的加密存储, 启动时用口令或者环境变量里的密钥解锁, 解密后只保存在内存里,
+// 进程内的副本 drop 时清零. 容器部署时也可以不用 keystore, 从环境变量或者文件读取
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeystoreConfig {
+  pub path: String, // 为空时只从环境变量读取
+  pub passphrase_env: String, // 解锁口令所在的环境变量, 没有设置时在终端输入
+  pub key_env: String // 或者直接提供 32 字节的十六进制密钥, 跳过 scrypt
+}
+
+impl ::std::default::Default for KeystoreConfig {
+  fn default() -> Self {
+    Self {
+      path: String::from("crypto-loan-monitor.keystore"),
+      passphrase_env: String::from("MONITOR_KEYSTORE_PASSPHRASE"),
+      key_env: String::from("MONITOR_KEYSTORE_KEY")
+    }
+  }
+}
+
+// scrypt 参数, log_n 15 大约 100ms
+static SCRYPT_LOG_N: u8 = 15;
+static SCRYPT_R: u32 = 8;
+static SCRYPT_P: u32 = 1;
+
+// 写到磁盘上的格式, 明文是 name -> { field -> value } 的 json
+#[derive(Serialize, Deserialize, Debug)]
+struct KeystoreFile {
+  version: u32,
+  kdf: String, // scrypt 或者 raw (使用 key_env 里的密钥)
+  log_n: u8,
+  r: u32,
+  p: u32,
+  salt: String,
+  nonce: String,
+  ciphertext: String
+}
+
+// 一组凭证, 例如一个交易所账户的 access_id, secret_key
+#[derive(Clone, Default)]
+pub struct Credential {
+  name: String,
+  fields: HashMap<String, Zeroizing<String>>
+}
+
+impl Credential {
+  pub fn get(&self, field: &str) -> Result<String, String> {
+    self.fields.get(field).map(|v| v.to_string()).ok_or(format!("credential {} has no {}", self.name, field))
+  }
+
+  pub fn get_or(&self, field: &str, default: &str) -> String {
+    self.fields.get(field).map(|v| v.to_string()).unwrap_or(String::from(default))
+  }
+}
+
+impl std::fmt::Debug for Credential {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    let mut fields: Vec<&String> = self.fields.keys().collect();
+    fields.sort();
+    write!(f, "Credential({}: {:?})", self.name, fields)
+  }
+}
+
+type Entries = BTreeMap<String, BTreeMap<String, Zeroizing<String>>>;
+
+// 解锁后的 keystore, 以及用来写回的密钥
+struct Unlocked {
+  path: String,
+  key: Zeroizing<[u8; 32]>,
+  kdf: String,
+  salt: Vec<u8>,
+  params: (u8, u32, u32), // scrypt 的 log_n, r, p
+  entries: Entries
+}
+
+static KEYSTORE: RwLock<Option<Unlocked>> = RwLock::new(None);
+// 解析过的凭证, 避免每次请求都读环境变量和文件
+static CACHE: RwLock<Option<HashMap<String, Credential>>> = RwLock::new(None);
+
+fn derive_key(passphrase: &str, salt: &[u8], log_n: u8, r: u32, p: u32) -> Result<Zeroizing<[u8; 32]>, String> {
+  let params = scrypt::Params::new(log_n, r, p, 32).map_err(|e| format!("invalid scrypt params: {}", e))?;
+  let mut key = Zeroizing::new([0_u8; 32]);
+  scrypt::scrypt(passphrase.as_bytes(), salt, &params, &mut key[..]).map_err(|e| format!("derive keystore key error: {}", e))?;
+  return Ok(key);
+}
+
+fn raw_key(cfg: &KeystoreConfig) -> Result<Option<Zeroizing<[u8; 32]>>, String> {
+  let hex_key = match std::env::var(&cfg.key_env) {
+    Ok(v) => Zeroizing::new(v),
+    Err(_) => return Ok(None)
+  };
+  let bytes = Zeroizing::new(hex::decode(hex_key.trim()).map_err(|e| format!("invalid keystore key in {}: {}", cfg.key_env, e))?);
+  if bytes.len() != 32 {
+    return Err(format!("keystore key in {} must be 32 bytes", cfg.key_env));
+  }
+  let mut key = Zeroizing::new([0_u8; 32]);
+  key.copy_from_slice(&bytes);
+  return Ok(Some(key));
+}
+
+fn passphrase(cfg: &KeystoreConfig, prompt: &str) -> Result<Zeroizing<String>, String> {
+  if let Ok(v) = std::env::var(&cfg.passphrase_env) {
+    return Ok(Zeroizing::new(v));
+  }
+  let v = rpassword::prompt_password(prompt).map_err(|e| format!("read keystore passphrase error: {}", e))?;
+  return Ok(Zeroizing::new(v));
+}
+
+fn decrypt(file: &KeystoreFile, key: &[u8; 32]) -> Result<Entries, String> {
+  let nonce = hex::decode(&file.nonce).map_err(|e| format!("invalid keystore nonce: {}", e))?;
+  let ciphertext = hex::decode(&file.ciphertext).map_err(|e| format!("invalid keystore ciphertext: {}", e))?;
+  let nonce: [u8; 12] = nonce.try_into().map_err(|_| String::from("invalid keystore nonce length"))?;
+  let cipher = ChaCha20Poly1305::new(&Key::from(*key));
+  let plain = Zeroizing::new(cipher.decrypt(&Nonce::from(nonce), ciphertext.as_ref()).map_err(|_| String::from("unlock keystore error: wrong passphrase or key, or the file is corrupted"))?);
+  return serde_json::from_slice(&plain).map_err(|e| format!("invalid keystore content: {}", e));
+}
+
+fn write(store: &Unlocked) -> Result<(), String> {
+  let plain = Zeroizing::new(serde_json::to_vec(&store.entries).map_err(|e| format!("serialize keystore error: {}", e))?);
+  let cipher = ChaCha20Poly1305::new(&Key::from(*store.key));
+  let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+  let ciphertext = cipher.encrypt(&nonce, plain.as_ref()).map_err(|_| String::from("encrypt keystore error"))?;
+  let file = KeystoreFile {
+    version: 1,
+    kdf: store.kdf.clone(),
+    log_n: store.params.0,
+    r: store.params.1,
+    p: store.params.2,
+    salt: hex::encode(&store.salt),
+    nonce: hex::encode(nonce),
+    ciphertext: hex::encode(ciphertext)
+  };
+  let text = serde_json::to_string_pretty(&file).map_err(|e| format!("serialize keystore error: {}", e))?;
+  // 先写临时文件再 rename, 写到一半时不会损坏原来的 keystore
+  let tmp = format!("{}.tmp", store.path);
+  std::fs::write(&tmp, text).map_err(|e| format!("write keystore {} error: {}", tmp, e))?;
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o600)).map_err(|e| format!("chmod keystore error: {}", e))?;
+  }
+  std::fs::rename(&tmp, &store.path).map_err(|e| format!("write keystore {} error: {}", store.path, e))?;
+  return Ok(());
+}
+
+// 读取并解锁 keystore, 文件不存在时 create 为 true 则新建一个空的, 否则返回错误:
+// 配置了 keystore 却找不到文件时不能悄悄改用环境变量里的凭证
+fn open(cfg: &KeystoreConfig, create: bool) -> Result<Option<Unlocked>, String> {
+  if cfg.path.is_empty() {
+    return Ok(None);
+  }
+  if !Path::new(&cfg.path).exists() {
+    if !create {
+      return Err(format!("keystore {} not found, create it with `monitor keystore add` or set keystore.path = \"\" to read credentials from env only", cfg.path));
+    }
+    let mut salt = vec![0_u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let (kdf, key) = match raw_key(cfg)? {
+      Some(key) => (String::from("raw"), key),
+      None => {
+        let first = passphrase(cfg, "new keystore passphrase: ")?;
+        if std::env::var(&cfg.passphrase_env).is_err() && *first != *passphrase(cfg, "repeat passphrase: ")? {
+          return Err(String::from("passphrases do not match"));
+        }
+        (String::from("scrypt"), derive_key(&first, &salt, SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P)?)
+      }
+    };
+    return Ok(Some(Unlocked { path: cfg.path.clone(), key, kdf, salt, params: (SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P), entries: BTreeMap::new() }));
+  }
+  let text = std::fs::read_to_string(&cfg.path).map_err(|e| format!("read keystore {} error: {}", cfg.path, e))?;
+  let file: KeystoreFile = serde_json::from_str(&text).map_err(|e| format!("invalid keystore {}: {}", cfg.path, e))?;
+  let salt = hex::decode(&file.salt).map_err(|e| format!("invalid keystore salt: {}", e))?;
+  let key = match file.kdf.as_str() {
+    "raw" => raw_key(cfg)?.ok_or(format!("keystore {} is locked with a raw key, set {}", cfg.path, cfg.key_env))?,
+    "scrypt" => derive_key(&passphrase(cfg, "keystore passphrase: ")?, &salt, file.log_n, file.r, file.p)?,
+    kdf => return Err(format!("unsupported keystore kdf {}", kdf))
+  };
+  let entries = decrypt(&file, &key)?;
+  return Ok(Some(Unlocked { path: cfg.path.clone(), key, kdf: file.kdf, salt, params: (file.log_n, file.r, file.p), entries }));
+}
+
+// 启动时调用一次, 之后的凭证都从内存读取
+pub fn init(cfg: &KeystoreConfig) -> Result<usize, String> {
+  let store = open(cfg, false)?;
+  let count = store.as_ref().map(|s| s.entries.len()).unwrap_or(0);
+  *KEYSTORE.write().unwrap() = store;
+  *CACHE.write().unwrap() = None;
+  return Ok(count);
+}
+
+// 重新加载配置时调用, 文件和之前是同一个 keystore 时用内存里的密钥解密, 不需要再输入口令;
+// 换了 keystore 时只能从环境变量取得口令或者密钥, 否则返回错误, 继续使用之前的凭证
+pub fn reload(cfg: &KeystoreConfig) -> Result<usize, String> {
+  let reopened = {
+    let current = KEYSTORE.read().unwrap();
+    match current.as_ref() {
+      Some(store) if store.path == cfg.path => {
+        let text = std::fs::read_to_string(&cfg.path).map_err(|e| format!("read keystore {} error: {}", cfg.path, e))?;
+        let file: KeystoreFile = serde_json::from_str(&text).map_err(|e| format!("invalid keystore {}: {}", cfg.path, e))?;
+        if file.kdf == store.kdf && file.salt == hex::encode(&store.salt) {
+          let entries = decrypt(&file, &store.key)?;
+          Some(Unlocked { path: store.path.clone(), key: store.key.clone(), kdf: file.kdf, salt: store.salt.clone(), params: (file.log_n, file.r, file.p), entries })
+        } else {
+          None
+        }
+      }
+      _ => None
+    }
+  };
+  let store = match reopened {
+    Some(store) => Some(store),
+    None if cfg.path.is_empty() || std::env::var(&cfg.key_env).is_ok() || std::env::var(&cfg.passphrase_env).is_ok() => open(cfg, false)?,
+    None => return Err(format!("keystore {} changed, set {} or {}, or restart to enter the passphrase", cfg.path, cfg.passphrase_env, cfg.key_env))
+  };
+  let count = store.as_ref().map(|s| s.entries.len()).unwrap_or(0);
+  *KEYSTORE.write().unwrap() = store;
+  *CACHE.write().unwrap() = None;
+  return Ok(count);
+}
+
+// 环境变量名: 凭证名称转大写, 非字母数字替换为 _, 例如 binance.18520833073 的 secret_key
+// 是 BINANCE_18520833073_SECRET_KEY, 加上 _FILE 后缀则从该文件读取 (docker/k8s secret)
+pub fn env_name(name: &str, field: &str) -> String {
+  format!("{}_{}", name, field).chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' }).collect()
+}
+
+fn from_env(name: &str, field: &str) -> Result<Option<Zeroizing<String>>, String> {
+  let env = env_name(name, field);
+  if let Ok(v) = std::env::var(&env) {
+    return Ok(Some(Zeroizing::new(v)));
+  }
+  if let Ok(path) = std::env::var(format!("{}_FILE", env)) {
+    let v = std::fs::read_to_string(&path).map_err(|e| format!("read {} from {} error: {}", env, path, e))?;
+    return Ok(Some(Zeroizing::new(v.trim_end_matches(['\r', '\n']).to_string())));
+  }
+  return Ok(None);
+}
+
+// 按 keystore, 环境变量, 文件的顺序查找, 同一个名称只解析一次
+pub fn credential(name: &str, fields: &[&str]) -> Result<Credential, String> {
+  if let Some(cred) = CACHE.read().unwrap().as_ref().and_then(|c| c.get(name)) {
+    return Ok(cred.clone());
+  }
+  let mut cred = Credential { name: String::from(name), fields: HashMap::new() };
+  if let Some(entry) = KEYSTORE.read().unwrap().as_ref().and_then(|s| s.entries.get(name)) {
+    for (k, v) in entry.iter() {
+      cred.fields.insert(k.clone(), v.clone());
+    }
+  }
+  for field in fields.iter() {
+    if cred.fields.contains_key(*field) {
+      continue;
+    }
+    if let Some(v) = from_env(name, field)? {
+      cred.fields.insert(String::from(*field), v);
+    }
+  }
+  if cred.fields.is_empty() {
+    return Err(format!("credential {} not found in keystore or env {}", name, env_name(name, fields.first().unwrap_or(&""))));
+  }
+  CACHE.write().unwrap().get_or_insert_with(HashMap::new).insert(String::from(name), cred.clone());
+  return Ok(cred);
+}
+
+// 测试用, 直接放入缓存, 不经过 keystore 文件和环境变量
+#[cfg(test)]
+pub fn insert(name: &str, fields: &[[&str;2]]) {
+  let mut cred = Credential { name: String::from(name), fields: HashMap::new() };
+  for [k, v] in fields.iter() {
+    cred.fields.insert(String::from(*k), Zeroizing::new(String::from(*v)));
+  }
+  CACHE.write().unwrap().get_or_insert_with(HashMap::new).insert(String::from(name), cred);
+}
+
+// 以下供命令行管理 keystore 使用, 修改后需要重启监控进程才能生效
+
+pub fn list(cfg: &KeystoreConfig) -> Result<Vec<(String, Vec<String>)>, String> {
+  let store = open(cfg, false)?.ok_or(format!("keystore {} does not exist", cfg.path))?;
+  return Ok(store.entries.iter().map(|(name, fields)| (name.clone(), fields.keys().cloned().collect())).collect());
+}
+
+// 新增或者替换(轮换)一组凭证
+pub fn put(cfg: &KeystoreConfig, name: &str, fields: BTreeMap<String, Zeroizing<String>>) -> Result<bool, String> {
+  let mut store = open(cfg, true)?.ok_or(String::from("keystore path is empty"))?;
+  let existed = store.entries.insert(String::from(name), fields).is_some();
+  write(&store)?;
+  return Ok(existed);
+}
+
+pub fn remove(cfg: &KeystoreConfig, name: &str) -> Result<(), String> {
+  let mut store = open(cfg, false)?.ok_or(format!("keystore {} does not exist", cfg.path))?;
+  if store.entries.remove(name).is_none() {
+    return Err(format!("credential {} not found in keystore", name));
+  }
+  return write(&store);
+}
+
+// 终端输入一个字段的值, 不回显
+pub fn prompt(name: &str, field: &str) -> Result<Zeroizing<String>, String> {
+  let mut v = rpassword::prompt_password(format!("{} {}: ", name, field)).map_err(|e| format!("read {} error: {}", field, e))?;
+  let value = Zeroizing::new(v.trim().to_string());
+  v.zeroize();
+  return Ok(value);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // 每个测试用自己的文件和环境变量, 不碰进程里已经解锁的 keystore
+  fn temp_config(name: &str) -> KeystoreConfig {
+    let path = std::env::temp_dir().join(format!("crypto-loan-monitor-test-{}-{}.keystore", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    KeystoreConfig {
+      path: path.to_string_lossy().to_string(),
+      passphrase_env: format!("MONITOR_KEYSTORE_PASSPHRASE_TEST_{}", name.to_uppercase()),
+      key_env: format!("MONITOR_KEYSTORE_KEY_TEST_{}", name.to_uppercase())
+    }
+  }
+
+  fn fields(pairs: &[[&str;2]]) -> BTreeMap<String, Zeroizing<String>> {
+    pairs.iter().map(|[k, v]| (String::from(*k), Zeroizing::new(String::from(*v)))).collect()
+  }
+
+  #[test]
+  fn encrypt_then_decrypt_with_passphrase() {
+    let cfg = temp_config("scrypt");
+    std::env::set_var(&cfg.passphrase_env, "correct horse battery staple");
+    assert_eq!(put(&cfg, "binance.test", fields(&[["access_id", "ak"], ["secret_key", "sk"]])), Ok(false));
+    assert_eq!(put(&cfg, "okex.test", fields(&[["passphrase", "pp"]])), Ok(false));
+    // 轮换替换所有字段
+    assert_eq!(put(&cfg, "binance.test", fields(&[["access_id", "ak2"], ["secret_key", "sk2"]])), Ok(true));
+
+    // 文件里没有明文
+    let text = std::fs::read_to_string(&cfg.path).unwrap();
+    assert!(!text.contains("sk2") && !text.contains("binance.test"));
+    let file: KeystoreFile = serde_json::from_str(&text).unwrap();
+    assert_eq!((file.kdf.as_str(), file.log_n), ("scrypt", SCRYPT_LOG_N));
+
+    let store = open(&cfg, false).unwrap().unwrap();
+    assert_eq!(store.entries.len(), 2);
+    assert_eq!(*store.entries["binance.test"]["secret_key"], "sk2");
+    assert_eq!(list(&cfg).unwrap(), vec![
+      (String::from("binance.test"), vec![String::from("access_id"), String::from("secret_key")]),
+      (String::from("okex.test"), vec![String::from("passphrase")])
+    ]);
+    remove(&cfg, "okex.test").unwrap();
+    assert!(remove(&cfg, "okex.test").unwrap_err().contains("not found"));
+    assert_eq!(open(&cfg, false).unwrap().unwrap().entries.len(), 1);
+    let _ = std::fs::remove_file(&cfg.path);
+  }
+
+  #[test]
+  fn wrong_passphrase_or_key_is_rejected() {
+    let cfg = temp_config("wrong");
+    std::env::set_var(&cfg.passphrase_env, "right");
+    put(&cfg, "binance.test", fields(&[["secret_key", "sk"]])).unwrap();
+    std::env::set_var(&cfg.passphrase_env, "wrong");
+    assert!(open(&cfg, false).err().unwrap().contains("wrong passphrase or key"));
+
+    // 用 key_env 里的密钥加密的文件, 没有密钥或者密钥不对都不能解锁
+    let raw = temp_config("raw");
+    std::env::set_var(&raw.key_env, "11".repeat(32));
+    put(&raw, "huobi.test", fields(&[["secret_key", "sk"]])).unwrap();
+    assert_eq!(*open(&raw, false).unwrap().unwrap().entries["huobi.test"]["secret_key"], "sk");
+    std::env::set_var(&raw.key_env, "22".repeat(32));
+    assert!(open(&raw, false).err().unwrap().contains("wrong passphrase or key"));
+    std::env::set_var(&raw.key_env, "22");
+    assert!(open(&raw, false).err().unwrap().contains("must be 32 bytes"));
+    std::env::remove_var(&raw.key_env);
+    assert!(open(&raw, false).err().unwrap().contains(&format!("set {}", raw.key_env)));
+    let _ = std::fs::remove_file(&cfg.path);
+    let _ = std::fs::remove_file(&raw.path);
+  }
+
+  #[test]
+  fn missing_keystore_fails_loudly() {
+    let cfg = temp_config("missing");
+    let err = open(&cfg, false).err().unwrap();
+    assert!(err.contains("not found") && err.contains(&cfg.path), "{}", err);
+    assert!(list(&cfg).is_err());
+    assert!(remove(&cfg, "binance.test").is_err());
+    // path 为空时只从环境变量读取
+    assert!(open(&KeystoreConfig { path: String::new(), ..cfg }, false).unwrap().is_none());
+  }
+}
@@ -4,7 +4,7 @@ use env_logger::Builder;
 use log::{LevelFilter, Level};
 use env_logger::fmt::Color;
 
-pub fn init_log () {
+pub fn init_log (level: LevelFilter) {
   // for log format
   Builder::new()
     .format(|buf, record| {
@@ -32,6 +32,6 @@ pub fn init_log () {
         level_style.value(record.args())
       )
     })
-    .filter(None, LevelFilter::Info)
+    .filter(None, level)
     .init();
 }
\ No newline at end of file
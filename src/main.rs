@@ -3,26 +3,83 @@ mod log_util;
 mod util;
 mod config;
 mod monitor;
-use engine::exchange::{types::Exchanges, Exchange};
+mod notify;
+mod metrics;
+mod store;
+mod cli;
+mod keystore;
+mod recorder;
+use std::sync::Arc;
+use clap::Parser;
 use monitor::main::main_loop;
 
 
 #[tokio::main]
 // This is the main function
 async fn main() {
-  log_util::init_log();
+  let args = cli::Cli::parse();
+  log_util::init_log(args.log_level);
+  config::set_path(args.config);
+  config::force_dry_run(args.dry_run);
+  match args.command {
+    None | Some(cli::Command::Run) => run().await,
+    Some(command) => match cli::execute(command).await {
+      Ok(output) => println!("{}", output),
+      Err(err) => {
+        eprintln!("{}", err);
+        std::process::exit(1);
+      }
+    }
+  }
+}
 
-  // load exchange instance
-  let binance_exchange: Exchange = Exchange {
-    config: String::from("binance.18520833073"),
-    name: Exchanges::BINANCE,
-    symbol: String::from("crv"),
-    currency: String::from("usdt"),
-    host: String::from("api.binance.com"),
-    protocol: String::from("https"),
-  };
+// 常驻运行监控
+async fn run() {
+  // load monitor targets
+  let cfg = config::load();
+  // 解锁失败时不启动, 否则所有交易所请求都会失败
+  match keystore::init(&cfg.keystore) {
+    Ok(count) => log::info!("keystore unlocked, {} credentials", count),
+    Err(err) => {
+      log::error!("{}", err);
+      std::process::exit(1);
+    }
+  }
+  engine::guard::init(&cfg.guard);
+  metrics::init();
+  if let Err(err) = store::init(&cfg.store) {
+    log::error!("{}", err);
+  }
+  if !store::enabled() {
+    log::warn!("store is disabled, actions are not journaled and rebalance cannot start");
+  }
+  notify::init(&cfg.notifiers);
+  if let Err(err) = recorder::init(&cfg.recorder) {
+    log::error!("{}", err);
+  }
+  tokio::spawn(recorder::run(cfg.exchanges.clone(), cfg.recorder.clone()));
+  if let Err(err) = monitor::selfcheck::run(&cfg.exchanges, &cfg.selfcheck).await {
+    log::error!("{}", err);
+    std::process::exit(1);
+  }
+  // 上次退出时没有完成的调仓和保护操作, 依赖 store 里的操作日志; 调仓按交易所的记录核对后继续,
+  // 保护操作只通知, 由人工核对
+  tokio::spawn(engine::rebalance::resume());
+  tokio::spawn(engine::journal::interrupted());
+  let ctx = Arc::new(monitor::context::Context::new(&cfg));
+  for notifier in cfg.notifiers.iter().filter(|n| n.kind == notify::Channels::TELEGRAM) {
+    tokio::spawn(monitor::bot::run(notifier.clone(), ctx.clone()));
+  }
+  if let Some(api) = cfg.api.clone() {
+    let api_ctx = ctx.clone();
+    tokio::spawn(async move {
+      if let Err(err) = monitor::api::serve(api, api_ctx).await {
+        log::error!("{}", err);
+      }
+    });
+  }
 
-  let res = main_loop(&binance_exchange).await;
+  let res = main_loop(ctx).await;
   if res.is_err() {
     log::error!("{}", res.unwrap_err());
   }
@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+// Prometheus 文本格式的指标, 只实现用到的 gauge, counter 和 histogram
+
+// 秒
+static BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+enum Series {
+  Value(f64),
+  Histogram { counts: Vec<u64>, sum: f64, count: u64 }
+}
+
+struct Family {
+  kind: &'static str,
+  help: &'static str,
+  series: BTreeMap<String, Series> // key 是渲染好的 label, e.g. {venue="BINANCE"}
+}
+
+static REGISTRY: Mutex<BTreeMap<&'static str, Family>> = Mutex::new(BTreeMap::new());
+
+fn escape(value: &str) -> String {
+  value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn format_value(v: f64) -> String {
+  if v.is_nan() {
+    return String::from("NaN");
+  }
+  if v.is_infinite() {
+    return String::from(if v > 0_f64 { "+Inf" } else { "-Inf" });
+  }
+  return v.to_string();
+}
+
+fn render_labels(labels: &[(&str, &str)]) -> String {
+  if labels.is_empty() {
+    return String::new();
+  }
+  let items: Vec<String> = labels.iter().map(|(k, v)| format!("{}=\"{}\"", k, escape(v))).collect();
+  return format!("{{{}}}", items.join(","));
+}
+
+fn with_series(name: &'static str, kind: &'static str, help: &'static str, labels: &[(&str, &str)], f: impl FnOnce(&mut Series)) {
+  let mut registry = REGISTRY.lock().unwrap();
+  let family = registry.entry(name).or_insert(Family { kind, help, series: BTreeMap::new() });
+  let series = family.series.entry(render_labels(labels)).or_insert(if kind == "histogram" {
+    Series::Histogram { counts: vec![0; BUCKETS.len()], sum: 0_f64, count: 0 }
+  } else {
+    Series::Value(0_f64)
+  });
+  f(series);
+}
+
+// 只声明指标, 还没有数据时也会输出 HELP 和 TYPE
+pub fn describe(name: &'static str, kind: &'static str, help: &'static str) {
+  REGISTRY.lock().unwrap().entry(name).or_insert(Family { kind, help, series: BTreeMap::new() });
+}
+
+pub fn set_gauge(name: &'static str, help: &'static str, labels: &[(&str, &str)], value: f64) {
+  with_series(name, "gauge", help, labels, |s| *s = Series::Value(value));
+}
+
+pub fn inc_counter(name: &'static str, help: &'static str, labels: &[(&str, &str)]) {
+  with_series(name, "counter", help, labels, |s| if let Series::Value(v) = s { *v += 1_f64 });
+}
+
+pub fn observe(name: &'static str, help: &'static str, labels: &[(&str, &str)], value: f64) {
+  with_series(name, "histogram", help, labels, |s| if let Series::Histogram { counts, sum, count } = s {
+    for (i, bound) in BUCKETS.iter().enumerate() {
+      if value <= *bound {
+        counts[i] += 1;
+      }
+    }
+    *sum += value;
+    *count += 1;
+  });
+}
+
+// histogram 的 le label 要插到已有 label 里
+fn with_le(labels: &str, le: &str) -> String {
+  if labels.is_empty() {
+    return format!("{{le=\"{}\"}}", le);
+  }
+  return format!("{},le=\"{}\"}}", labels.trim_end_matches('}'), le);
+}
+
+pub fn render() -> String {
+  let registry = REGISTRY.lock().unwrap();
+  let mut out = String::new();
+  for (name, family) in registry.iter() {
+    out += &format!("# HELP {} {}\n# TYPE {} {}\n", name, family.help, name, family.kind);
+    for (labels, series) in family.series.iter() {
+      match series {
+        Series::Value(v) => out += &format!("{}{} {}\n", name, labels, format_value(*v)),
+        Series::Histogram { counts, sum, count } => {
+          for (i, bound) in BUCKETS.iter().enumerate() {
+            out += &format!("{}_bucket{} {}\n", name, with_le(labels, &bound.to_string()), counts[i]);
+          }
+          out += &format!("{}_bucket{} {}\n", name, with_le(labels, "+Inf"), count);
+          out += &format!("{}_sum{} {}\n", name, labels, format_value(*sum));
+          out += &format!("{}_count{} {}\n", name, labels, count);
+        }
+      }
+    }
+  }
+  return out;
+}
+
+// 错误分类, 交易所和节点的错误都是字符串
+pub fn error_kind(err: &str) -> &'static str {
+  let lower = err.to_lowercase();
+  if lower.contains("429") || lower.contains("-1003") || lower.contains("too many") || lower.contains("rate limit") {
+    return "rate_limit";
+  }
+  if lower.contains("timed out") || lower.contains("timeout") {
+    return "timeout";
+  }
+  if lower.contains("[reqwest error]") || lower.contains("connect") {
+    return "network";
+  }
+  if lower.contains("signature") || lower.contains("api-key") || lower.contains("-2015") || lower.contains("-1022") || lower.contains("unauthorized") {
+    return "auth";
+  }
+  return "api";
+}
+
+pub fn request(venue: &str, op: &str, secs: f64, result: Result<(), &str>) {
+  observe("venue_request_duration_seconds", "Request latency by venue and operation", &[("venue", venue), ("op", op)], secs);
+  if let Err(err) = result {
+    inc_counter("venue_errors_total", "Errors by venue and kind", &[("venue", venue), ("kind", error_kind(err))]);
+  }
+}
+
+// 服务器时间大约是请求往返的中间时刻生成的, 和收到响应时的本地时间相比要减去一半的往返时间
+fn drift_secs(server_ms: i64, local_ms: i64, rtt_ms: i64) -> f64 {
+  (server_ms - (local_ms - rtt_ms / 2)) as f64 / 1000_f64
+}
+
+// 交易所服务器时间减去本地时间, rtt_ms 是读取服务器时间的请求的往返时间
+pub fn clock_drift(venue: &str, server_ms: i64, rtt_ms: i64) {
+  let drift = drift_secs(server_ms, chrono::Utc::now().timestamp_millis(), rtt_ms);
+  set_gauge("clock_drift_seconds", "Venue server time minus local time", &[("venue", venue)], drift);
+}
+
+pub fn rate_limit_weight(venue: &str, weight: f64) {
+  set_gauge("rate_limit_weight_used", "Request weight used in the current rate limit window", &[("venue", venue)], weight);
+}
+
+pub fn protection_action(venue: &str, action: &str, result: &str) {
+  inc_counter("protection_actions_total", "Protection actions by venue, action and result", &[("venue", venue), ("action", action), ("result", result)]);
+}
+
+pub fn position(id: &str, venue: &str, pos: &crate::engine::position::LoanPosition) {
+  let labels = [("id", id), ("venue", venue)];
+  set_gauge("loan_ltv", "Debt value / collateral value", &labels, pos.ltv);
+  set_gauge("loan_liquidation_ltv", "LTV at which the position is liquidated", &labels, pos.liquidation_ltv);
+  // 没有债务时健康因子是无穷大, Prometheus 可以表示 +Inf
+  set_gauge("loan_health_factor", "Liquidation LTV / LTV", &labels, pos.health_factor);
+  set_gauge("loan_distance_to_liquidation", "Collateral drop that triggers liquidation", &labels, pos.distance_to_liquidation());
+  set_gauge("loan_collateral_value", "Collateral value in the position's base", &labels, pos.collateral_value);
+  set_gauge("loan_debt_value", "Debt value in the position's base", &labels, pos.debt_value);
+}
+
+// 删除的仓位不再输出, 否则会一直保留最后一次的值
+pub fn remove_position(id: &str) {
+  let prefix = render_labels(&[("id", id)]).trim_end_matches('}').to_string() + ",";
+  let mut registry = REGISTRY.lock().unwrap();
+  for family in registry.values_mut() {
+    family.series.retain(|labels, _| !labels.starts_with(&prefix));
+  }
+}
+
+pub fn init() {
+  describe("protection_actions_total", "counter", "Protection actions by venue, action and result");
+  describe("venue_errors_total", "counter", "Errors by venue and kind");
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::engine::exchange::mock::MockExchange;
+  use crate::engine::exchange::types::Exchanges;
+
+  #[test]
+  fn with_le_adds_label() {
+    assert_eq!(with_le("", "0.5"), "{le=\"0.5\"}");
+    assert_eq!(with_le("{venue=\"BINANCE\"}", "+Inf"), "{venue=\"BINANCE\",le=\"+Inf\"}");
+  }
+
+  #[test]
+  fn error_kind_classifies_messages() {
+    assert_eq!(error_kind("429 Too Many Requests"), "rate_limit");
+    assert_eq!(error_kind("{\"code\":-1003,\"msg\":\"Too much request weight used\"}"), "rate_limit");
+    assert_eq!(error_kind("[REQWEST ERROR]: operation timed out"), "timeout");
+    assert_eq!(error_kind("[REQWEST ERROR]: error trying to connect"), "network");
+    assert_eq!(error_kind("{\"code\":-2015,\"msg\":\"Invalid API-key, IP, or permissions for action.\"}"), "auth");
+    assert_eq!(error_kind("{\"code\":-1121,\"msg\":\"Invalid symbol.\"}"), "api");
+  }
+
+  #[test]
+  fn drift_subtracts_half_round_trip() {
+    // 请求 400ms, 服务器在发出后 200ms 生成时间戳, 时钟一致时没有偏差
+    assert_eq!(drift_secs(1_000_200, 1_000_400, 400), 0_f64);
+    assert_eq!(drift_secs(1_001_200, 1_000_400, 400), 1_f64);
+  }
+
+  #[test]
+  fn render_formats_families() {
+    set_gauge("test_render_gauge", "Test gauge", &[("id", "a\"b")], f64::INFINITY);
+    inc_counter("test_render_counter", "Test counter", &[]);
+    inc_counter("test_render_counter", "Test counter", &[]);
+    observe("test_render_seconds", "Test histogram", &[("venue", "TEST")], 0.3);
+    observe("test_render_seconds", "Test histogram", &[("venue", "TEST")], 100_f64);
+    let text = render();
+    assert!(text.contains("# HELP test_render_gauge Test gauge\n# TYPE test_render_gauge gauge\ntest_render_gauge{id=\"a\\\"b\"} +Inf\n"));
+    assert!(text.contains("# TYPE test_render_counter counter\ntest_render_counter 2\n"));
+    assert!(text.contains("test_render_seconds_bucket{venue=\"TEST\",le=\"0.25\"} 0\n"));
+    assert!(text.contains("test_render_seconds_bucket{venue=\"TEST\",le=\"0.5\"} 1\n"));
+    assert!(text.contains("test_render_seconds_bucket{venue=\"TEST\",le=\"60\"} 1\n"));
+    assert!(text.contains("test_render_seconds_bucket{venue=\"TEST\",le=\"+Inf\"} 2\n"));
+    assert!(text.contains("test_render_seconds_sum{venue=\"TEST\"} 100.3\n"));
+    assert!(text.contains("test_render_seconds_count{venue=\"TEST\"} 2\n"));
+  }
+
+  #[tokio::test]
+  async fn exchange_requests_are_timed() {
+    let mock = MockExchange::start(Exchanges::BINANCE).await;
+    mock.exchange("crv", "usdt").depth().await.unwrap();
+    assert!(render().contains("venue_request_duration_seconds_count{venue=\"BINANCE\",op=\"depth\"}"));
+  }
+}
@@ -1 +1,9 @@
-pub mod main;
\ No newline at end of file
+pub mod main;
+pub mod target;
+pub mod alert;
+pub mod bot;
+pub mod context;
+pub mod api;
+pub mod selfcheck;
+pub mod backtest;
+pub mod protect;
\ No newline at end of file
@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use crate::engine::position::LoanPosition;
+use crate::notify::{ self, Severity };
+use crate::store;
+
+// 健康因子低于 warning_health_factor 相当于追加保证金线, 低于 critical_health_factor 视为接近清算
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AlertConfig {
+  pub warning_health_factor: f64,
+  pub critical_health_factor: f64,
+  #[serde(default = "default_remind_secs")]
+  pub remind_secs: i64, // 没有确认的告警每隔多久再提醒一次, 0 表示不提醒
+  #[serde(default = "default_fetch_failures")]
+  pub fetch_failures: u64, // 连续多少次读取仓位失败后告警, 0 表示不告警
+  #[serde(default = "default_stale_secs")]
+  pub stale_secs: i64 // 仓位超过这个时间没有读取成功就告警, 0 表示不告警
+}
+
+fn default_remind_secs() -> i64 { 1800_i64 }
+fn default_fetch_failures() -> u64 { 3_u64 }
+fn default_stale_secs() -> i64 { 900_i64 }
+
+impl ::std::default::Default for AlertConfig {
+  fn default() -> Self {
+    Self {
+      warning_health_factor: 1.3_f64,
+      critical_health_factor: 1.1_f64,
+      remind_secs: default_remind_secs(),
+      fetch_failures: default_fetch_failures(),
+      stale_secs: default_stale_secs()
+    }
+  }
+}
+
+impl AlertConfig {
+  // 下一次预言机更新后会被清算也算 CRITICAL
+  pub fn severity(&self, pos: &LoanPosition) -> Severity {
+    let next_below_one = pos.next_health_factor.map(|hf| hf < 1_f64).unwrap_or(false);
+    if pos.health_factor < self.critical_health_factor || next_below_one {
+      return Severity::CRITICAL;
+    }
+    if pos.health_factor < self.warning_health_factor {
+      return Severity::WARNING;
+    }
+    return Severity::INFO;
+  }
+
+  // 看不到仓位时无法判断健康因子, 按 WARNING 告警
+  pub fn fetch_severity(&self, failures: u64) -> Severity {
+    if self.fetch_failures > 0 && failures >= self.fetch_failures { Severity::WARNING } else { Severity::INFO }
+  }
+
+  // updated_at 是最后一次读取成功的时间, 从来没有成功过时传监控启动的时间
+  pub fn stale_severity(&self, updated_at: i64, now: i64) -> Severity {
+    if self.stale_secs > 0 && now - updated_at > self.stale_secs { Severity::WARNING } else { Severity::INFO }
+  }
+}
+
+// 每个仓位每种条件一条告警, 只在状态变化时发送
+#[derive(Serialize, Debug, Clone)]
+pub struct Alert {
+  pub target: String,
+  pub condition: String,
+  pub severity: Severity,
+  pub fired_at: i64,
+  pub last_sent: i64,
+  pub acked: bool
+}
+
+static ALERTS: Mutex<Vec<Alert>> = Mutex::new(Vec::new());
+// 每个仓位连续读取失败的次数
+static FAILURES: Mutex<Option<HashMap<String, u64>>> = Mutex::new(None);
+
+enum Change {
+  Fire,
+  Escalate,
+  Remind,
+  Resolve(Severity),
+  Nothing
+}
+
+fn update(target: &str, condition: &str, severity: Severity, remind_secs: i64) -> Change {
+  let now = Local::now().timestamp();
+  let mut alerts = ALERTS.lock().unwrap();
+  let index = alerts.iter().position(|a| a.target == target && a.condition == condition);
+  match index {
+    None if severity == Severity::INFO => Change::Nothing,
+    None => {
+      alerts.push(Alert { target: String::from(target), condition: String::from(condition), severity, fired_at: now, last_sent: now, acked: false });
+      Change::Fire
+    }
+    Some(i) if severity == Severity::INFO => Change::Resolve(alerts.remove(i).severity),
+    Some(i) => {
+      let alert = &mut alerts[i];
+      if severity > alert.severity {
+        // 升级后之前的确认失效
+        alert.severity = severity;
+        alert.acked = false;
+        alert.last_sent = now;
+        return Change::Escalate;
+      }
+      // 降级不通知, 之后再升级时重新发送
+      alert.severity = severity;
+      if !alert.acked && remind_secs > 0 && now - alert.last_sent >= remind_secs {
+        alert.last_sent = now;
+        return Change::Remind;
+      }
+      Change::Nothing
+    }
+  }
+}
+
+// 按当前严重程度更新告警状态, 只在首次触发, 升级, 到达提醒周期和恢复时发送通知
+pub async fn evaluate(cfg: &AlertConfig, target: &str, condition: &str, severity: Severity, message: &str) {
+  let change = update(target, condition, severity, cfg.remind_secs);
+  let transition = match change {
+    Change::Fire => "fire",
+    Change::Escalate => "escalate",
+    Change::Remind => "remind",
+    Change::Resolve(_) => "resolve",
+    Change::Nothing => return
+  };
+  store::alert(target, condition, transition, &format!("{:?}", severity), message);
+  match change {
+    Change::Fire => notify::notify(severity, target, condition, message).await,
+    Change::Escalate => notify::notify(severity, target, &format!("{} escalated", condition), message).await,
+    Change::Remind => notify::notify(severity, target, &format!("{} (reminder, /ack {} to silence)", condition, target), message).await,
+    // 恢复通知用原来的级别发送, 保证和告警走同样的渠道
+    Change::Resolve(prev) => notify::notify(prev, target, &format!("{} resolved", condition), message).await,
+    Change::Nothing => {}
+  }
+}
+
+// 每次读取仓位后调用, 连续失败 fetch_failures 次时告警, 读取成功后恢复
+pub async fn fetch_result(cfg: &AlertConfig, target: &str, result: Result<(), &str>) {
+  let failures = {
+    let mut guard = FAILURES.lock().unwrap();
+    let failures = guard.get_or_insert_with(HashMap::new).entry(String::from(target)).or_insert(0_u64);
+    *failures = if result.is_ok() { 0 } else { *failures + 1 };
+    *failures
+  };
+  let message = match result {
+    Ok(_) => String::from("position fetched"),
+    Err(err) => format!("{} consecutive failures, last error: {}", failures, err)
+  };
+  evaluate(cfg, target, "position fetch failed", cfg.fetch_severity(failures), &message).await;
+}
+
+// 确认某个仓位的所有告警, 直到下一次升级前不再提醒, 返回被确认的数量
+pub fn ack(target: &str) -> usize {
+  let mut alerts = ALERTS.lock().unwrap();
+  let mut count = 0_usize;
+  for alert in alerts.iter_mut().filter(|a| a.target == target && !a.acked) {
+    alert.acked = true;
+    count += 1;
+    store::alert(target, &alert.condition, "ack", &format!("{:?}", alert.severity), "");
+  }
+  return count;
+}
+
+pub fn active() -> Vec<Alert> {
+  ALERTS.lock().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn config(remind_secs: i64) -> AlertConfig {
+    AlertConfig { remind_secs, ..AlertConfig::default() }
+  }
+
+  // 告警和通知都是全局的, 每个测试用不同的仓位 id
+  fn sent(target: &str) -> Vec<String> {
+    notify::recent(None).into_iter().filter(|n| n.target == target).map(|n| n.title).collect()
+  }
+
+  fn age(target: &str, secs: i64) {
+    for alert in ALERTS.lock().unwrap().iter_mut().filter(|a| a.target == target) {
+      alert.last_sent -= secs;
+    }
+  }
+
+  #[tokio::test]
+  async fn dedupe_escalate_and_resolve() {
+    let id = "alert-test-dedupe";
+    let cfg = config(0);
+    evaluate(&cfg, id, "health factor low", Severity::WARNING, "hf 1.2").await;
+    evaluate(&cfg, id, "health factor low", Severity::WARNING, "hf 1.2").await;
+    evaluate(&cfg, id, "health factor low", Severity::CRITICAL, "hf 1.05").await;
+    // 降级不通知
+    evaluate(&cfg, id, "health factor low", Severity::WARNING, "hf 1.2").await;
+    assert_eq!(active().iter().filter(|a| a.target == id).count(), 1);
+    evaluate(&cfg, id, "health factor low", Severity::INFO, "hf 1.5").await;
+    assert!(!active().iter().any(|a| a.target == id));
+    assert_eq!(sent(id), vec!["health factor low", "health factor low escalated", "health factor low resolved"]);
+  }
+
+  #[tokio::test]
+  async fn ack_silences_reminders_until_escalation() {
+    let id = "alert-test-ack";
+    let cfg = config(60);
+    evaluate(&cfg, id, "health factor low", Severity::WARNING, "hf 1.2").await;
+    age(id, 60);
+    evaluate(&cfg, id, "health factor low", Severity::WARNING, "hf 1.2").await;
+    assert_eq!(sent(id).len(), 2);
+    assert!(sent(id)[1].contains("reminder"));
+
+    assert_eq!(ack(id), 1);
+    assert_eq!(ack(id), 0);
+    age(id, 60);
+    evaluate(&cfg, id, "health factor low", Severity::WARNING, "hf 1.2").await;
+    assert_eq!(sent(id).len(), 2);
+    // 升级后确认失效
+    evaluate(&cfg, id, "health factor low", Severity::CRITICAL, "hf 1.05").await;
+    assert!(!active().iter().find(|a| a.target == id).unwrap().acked);
+    assert_eq!(sent(id).len(), 3);
+    evaluate(&cfg, id, "health factor low", Severity::INFO, "hf 1.5").await;
+  }
+
+  #[tokio::test]
+  async fn fetch_failures_and_stale_positions() {
+    let id = "alert-test-fetch";
+    let cfg = config(0);
+    for _ in 0..2 {
+      fetch_result(&cfg, id, Err("timeout")).await;
+    }
+    assert!(!active().iter().any(|a| a.target == id));
+    fetch_result(&cfg, id, Err("timeout")).await;
+    fetch_result(&cfg, id, Err("timeout")).await;
+    let alert = active().into_iter().find(|a| a.target == id).unwrap();
+    assert_eq!(alert.condition, "position fetch failed");
+    assert!(matches!(alert.severity, Severity::WARNING));
+    fetch_result(&cfg, id, Ok(())).await;
+    assert!(!active().iter().any(|a| a.target == id));
+    assert_eq!(sent(id), vec!["position fetch failed", "position fetch failed resolved"]);
+
+    assert!(matches!(cfg.stale_severity(1000, 1000 + 900), Severity::INFO));
+    assert!(matches!(cfg.stale_severity(1000, 1000 + 901), Severity::WARNING));
+    assert!(matches!(AlertConfig { stale_secs: 0, ..cfg }.stale_severity(0, 1000000), Severity::INFO));
+  }
+}
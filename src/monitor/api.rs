@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{ Arc, Mutex };
+use std::time;
+use chacha20poly1305::aead::{ OsRng, rand_core::RngCore };
+use hyper::{ Body, Method, Request, Response, Server, StatusCode };
+use hyper::service::{ make_service_fn, service_fn };
+use serde::{Deserialize, Serialize};
+use serde_json::{ json, Value };
+use crate::engine::guard;
+use crate::notify::{ self, Severity };
+use crate::metrics;
+use super::alert;
+use super::bot;
+use super::context::Context;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiConfig {
+  pub listen: String, // e.g. 127.0.0.1:8080
+  #[serde(default = "default_token_env")]
+  pub token_env: String // Bearer token 所在的环境变量, 没有设置时拒绝所有请求
+}
+
+fn default_token_env() -> String { String::from("MONITOR_API_TOKEN") }
+
+#[derive(Deserialize, Debug)]
+struct ActionRequest {
+  id: String,
+  action: String, // topup, repay 或 refill
+  amount: f64
+}
+
+// POST /action 只返回 nonce, 在 bot::CONFIRM_TIMEOUT 内 POST /confirm/<nonce> 才执行, 和 telegram 的 /confirm 一样
+struct Pending {
+  command: String,
+  at: time::Instant
+}
+
+static PENDING: Mutex<Option<HashMap<String, Pending>>> = Mutex::new(None);
+
+fn new_nonce() -> String {
+  let mut bytes = [0_u8; 16];
+  OsRng.fill_bytes(&mut bytes);
+  return hex::encode(bytes);
+}
+
+// 检查参数, 保存待确认的操作
+fn prepare(ctx: &Context, action: &ActionRequest) -> Result<Value, String> {
+  let command = match action.action.as_str() {
+    "topup" | "repay" | "refill" => format!("/{}", action.action),
+    other => return Err(format!("unknown action {}", other))
+  };
+  let amount = action.amount.to_string();
+  let summary = bot::prepare(ctx, &command, &[&action.id, &amount])?;
+  let nonce = new_nonce();
+  let mut pending = PENDING.lock().unwrap();
+  let pending = pending.get_or_insert_with(HashMap::new);
+  pending.retain(|_, p| p.at.elapsed().as_secs() <= bot::CONFIRM_TIMEOUT);
+  pending.insert(nonce.clone(), Pending { command: format!("{} {} {}", command, action.id, amount), at: time::Instant::now() });
+  return Ok(json!({ "confirm": nonce, "action": summary, "expires_in": bot::CONFIRM_TIMEOUT }));
+}
+
+async fn confirm(ctx: &Context, nonce: &str) -> Result<Value, String> {
+  let p = match PENDING.lock().unwrap().as_mut().and_then(|m| m.remove(nonce)) {
+    Some(p) if p.at.elapsed().as_secs() <= bot::CONFIRM_TIMEOUT => p,
+    Some(_) => return Err(String::from("confirmation expired")),
+    None => return Err(String::from("nothing to confirm"))
+  };
+  let parts: Vec<&str> = p.command.split_whitespace().collect();
+  let res = bot::execute(ctx, parts[0], &parts[1..]).await;
+  let severity = if res.is_ok() { Severity::ACTION } else { Severity::WARNING };
+  let message = format!("{}: {:?}", p.command.trim_start_matches('/'), res);
+  notify::notify(severity, parts[1], "http action", &message).await;
+  return res.map(|r| json!({ "result": r }));
+}
+
+fn reply(status: StatusCode, body: Value) -> Response<Body> {
+  Response::builder()
+    .status(status)
+    .header("Content-Type", "application/json")
+    .body(Body::from(body.to_string()))
+    .unwrap()
+}
+
+fn result(res: Result<Value, String>) -> Response<Body> {
+  match res {
+    Ok(body) => reply(StatusCode::OK, body),
+    Err(err) => reply(StatusCode::BAD_REQUEST, json!({ "error": err }))
+  }
+}
+
+fn authorized(cfg: &ApiConfig, req: &Request<Body>) -> bool {
+  let token = match std::env::var(&cfg.token_env) {
+    Ok(token) if !token.is_empty() => token,
+    _ => return false
+  };
+  let header = req.headers().get("Authorization").and_then(|v| v.to_str().ok()).unwrap_or("");
+  return header == format!("Bearer {}", token);
+}
+
+async fn handle(cfg: Arc<ApiConfig>, ctx: Arc<Context>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+  let method = req.method().clone();
+  let path = String::from(req.uri().path().trim_end_matches('/'));
+  let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+  if !authorized(&cfg, &req) {
+    return Ok(reply(StatusCode::UNAUTHORIZED, json!({ "error": "unauthorized" })));
+  }
+  let resp = match (&method, segments.as_slice()) {
+    (&Method::GET, ["metrics"]) => Response::builder()
+      .header("Content-Type", "text/plain; version=0.0.4")
+      .body(Body::from(metrics::render()))
+      .unwrap(),
+    (&Method::GET, ["positions"]) => reply(StatusCode::OK, json!(ctx.positions())),
+    (&Method::GET, ["ltv"]) => {
+      let ltv: serde_json::Map<String, Value> = ctx.positions().into_iter().map(|(id, p)| (id, json!(p.position.ltv))).collect();
+      reply(StatusCode::OK, Value::Object(ltv))
+    }
+    (&Method::GET, ["actions"]) => reply(StatusCode::OK, json!(notify::recent(Some(Severity::ACTION)))),
+    (&Method::GET, ["notifications"]) => reply(StatusCode::OK, json!(notify::recent(None))),
+    (&Method::GET, ["alerts"]) => reply(StatusCode::OK, json!(alert::active())),
+    (&Method::GET, ["venues"]) => reply(StatusCode::OK, json!(ctx.venues())),
+    (&Method::GET, ["status"]) => {
+      let targets: Vec<Value> = ctx.targets().iter().map(|t| json!({ "id": t.id(), "venue": t.venue(), "paused": ctx.is_paused(&t.id()) })).collect();
+      reply(StatusCode::OK, json!({ "targets": targets, "interval": ctx.interval(), "guard": guard::current() }))
+    }
+    (&Method::POST, ["pause", id]) => result(ctx.set_paused(id, true).map(|_| json!({ "paused": id }))),
+    (&Method::POST, ["resume", id]) => result(ctx.set_paused(id, false).map(|_| json!({ "resumed": id }))),
+    (&Method::POST, ["ack", id]) => reply(StatusCode::OK, json!({ "acked": alert::ack(id) })),
+    (&Method::POST, ["reload"]) => result(ctx.reload().await.map(|count| json!({ "targets": count }))),
+    (&Method::POST, ["action"]) => {
+      let bytes = hyper::body::to_bytes(req.into_body()).await.unwrap_or_default();
+      let res = match serde_json::from_slice::<ActionRequest>(&bytes) {
+        Ok(action) => prepare(&ctx, &action),
+        Err(e) => Err(format!("invalid body: {}", e))
+      };
+      result(res)
+    }
+    (&Method::POST, ["confirm", nonce]) => result(confirm(&ctx, nonce).await),
+    _ => reply(StatusCode::NOT_FOUND, json!({ "error": format!("{} {} not found", method, path) }))
+  };
+  return Ok(resp);
+}
+
+pub async fn serve(cfg: ApiConfig, ctx: Arc<Context>) -> Result<(), String> {
+  let addr: SocketAddr = cfg.listen.parse().map_err(|e| format!("invalid listen address {}: {}", cfg.listen, e))?;
+  let cfg = Arc::new(cfg);
+  let make_svc = make_service_fn(move |_| {
+    let cfg = cfg.clone();
+    let ctx = ctx.clone();
+    async move {
+      Ok::<_, Infallible>(service_fn(move |req| handle(cfg.clone(), ctx.clone(), req)))
+    }
+  });
+  log::info!("http api listening on {}", addr);
+  Server::try_bind(&addr).map_err(|e| format!("bind {} error: {}", addr, e))?
+    .serve(make_svc).await.map_err(|e| format!("http server error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::MonitorConfig;
+  use crate::engine::exchange::Exchange;
+  use crate::engine::exchange::mock::MockExchange;
+  use crate::engine::exchange::types::Exchanges;
+  use super::super::target;
+
+  static TOKEN_ENV: &str = "MONITOR_API_TOKEN_TEST";
+
+  fn request(method: Method, path: &str, token: Option<&str>, body: Value) -> Request<Body> {
+    let mut builder = Request::builder().method(method).uri(path);
+    if let Some(token) = token {
+      builder = builder.header("Authorization", format!("Bearer {}", token));
+    }
+    builder.body(Body::from(body.to_string())).unwrap()
+  }
+
+  async fn call(ctx: &Arc<Context>, req: Request<Body>) -> (StatusCode, Value) {
+    let cfg = Arc::new(ApiConfig { listen: String::from("127.0.0.1:0"), token_env: String::from(TOKEN_ENV) });
+    let resp = handle(cfg, ctx.clone(), req).await.unwrap();
+    let status = resp.status();
+    let bytes = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+  }
+
+  #[tokio::test]
+  async fn actions_need_token_and_confirmation() {
+    std::env::set_var(TOKEN_ENV, "secret");
+    let mock = MockExchange::start(Exchanges::BINANCE).await;
+    let ex = Exchange { protect: true, ..mock.exchange("crv", "usdt") };
+    let ctx = Arc::new(Context::new(&MonitorConfig { exchanges: vec![ex], ..Default::default() }));
+    let id = ctx.targets()[0].id();
+
+    // 读取接口也需要 token
+    assert_eq!(call(&ctx, request(Method::GET, "/positions", None, Value::Null)).await.0, StatusCode::UNAUTHORIZED);
+    assert_eq!(call(&ctx, request(Method::GET, "/positions", Some("wrong"), Value::Null)).await.0, StatusCode::UNAUTHORIZED);
+    assert_eq!(call(&ctx, request(Method::GET, "/positions", Some("secret"), Value::Null)).await.0, StatusCode::OK);
+
+    // /action 只返回 nonce, 确认后才执行, nonce 只能用一次
+    let (status, body) = call(&ctx, request(Method::POST, "/action", Some("secret"), json!({ "id": id, "action": "topup", "amount": 10 }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["action"], json!(format!("topup {} 10", id)));
+    let nonce = body["confirm"].as_str().unwrap().to_string();
+    let path = format!("/confirm/{}", nonce);
+    let (status, body) = call(&ctx, request(Method::POST, &path, Some("secret"), Value::Null)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["result"], json!(target::DRY_RUN));
+    let (status, body) = call(&ctx, request(Method::POST, &path, Some("secret"), Value::Null)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], json!("nothing to confirm"));
+
+    // 参数错误时不生成 nonce
+    let (status, body) = call(&ctx, request(Method::POST, "/action", Some("secret"), json!({ "id": id, "action": "topup", "amount": -1 }))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["confirm"].is_null());
+    let (status, _) = call(&ctx, request(Method::POST, "/action", Some("secret"), json!({ "id": "unknown", "action": "repay", "amount": 1 }))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+  }
+}
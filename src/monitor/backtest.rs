@@ -0,0 +1,289 @@
+// 用历史行情回放保护策略: 仓位用同样的 LoanPosition 计算, 告警用同样的阈值,
+// 补仓, 还款和卖出在模拟交易所里执行, 统计每个策略操作的次数, 成本和是否会被清算
+use serde::{Deserialize, Serialize};
+use crate::engine::position::LoanPosition;
+use crate::engine::replay::MarketEvent;
+use crate::engine::strategy::{ decide, Decision, Strategy };
+use crate::notify::Severity;
+use super::alert::AlertConfig;
+
+static YEAR_MS: f64 = 365_f64 * 86400_f64 * 1000_f64;
+
+// 模拟的仓位从第一条行情开始, 抵押物按当时的价格换算成数量, 借款是计价币
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BacktestConfig {
+  pub collateral_value: f64,
+  pub ltv: f64, // 开始时的 ltv
+  pub liquidation_ltv: f64,
+  pub interest_rate: f64, // 借款年化利率
+  pub taker_fee: f64, // 卖出抵押物的手续费率
+  pub slippage: f64, // 没有深度数据时卖出的滑点
+  pub strategies: Vec<Strategy>
+}
+
+impl ::std::default::Default for BacktestConfig {
+  fn default() -> Self {
+    Self {
+      collateral_value: 10000_f64,
+      ltv: 0.5_f64,
+      liquidation_ltv: 1_f64 / 1.1_f64,
+      interest_rate: 0.1_f64,
+      taker_fee: 0.001_f64,
+      slippage: 0.002_f64,
+      strategies: vec![
+        Strategy::AlertOnly,
+        Strategy::TopUp { trigger: 1.3_f64, target: 1.6_f64, delay_secs: 0, fee: 0_f64, budget: 0_f64 },
+        Strategy::Repay { trigger: 1.3_f64, target: 1.6_f64, budget: 0_f64 },
+        Strategy::Deleverage { trigger: 1.3_f64, target: 1.6_f64 }
+      ]
+    }
+  }
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct StrategyReport {
+  pub strategy: String,
+  pub actions: u64,
+  pub capital: f64, // 从外部投入的资金(补仓, 还款)
+  pub sold: f64, // 卖出的抵押物数量
+  pub fees: f64,
+  pub slippage: f64,
+  pub interest: f64,
+  pub alerts: u64, // 进入 WARNING 或者升级到 CRITICAL 的次数
+  pub min_health_factor: f64,
+  pub liquidated_at: Option<i64>,
+  pub liquidation_price: Option<f64>,
+  pub equity: f64 // 结束时抵押物价值减去借款, 被清算时为 0
+}
+
+// 模拟的逐仓杠杆账户
+pub struct SimExchange {
+  pub collateral: f64, // 抵押物数量
+  pub margin: f64, // 补充的计价币保证金
+  pub debt: f64,
+  pub bids: Vec<[f64;2]>, // 最近一次的深度
+  liquidation_ltv: f64,
+  taker_fee: f64,
+  slippage: f64
+}
+
+impl SimExchange {
+  pub fn new(cfg: &BacktestConfig, price: f64) -> SimExchange {
+    SimExchange {
+      collateral: cfg.collateral_value / price,
+      margin: 0_f64,
+      debt: cfg.collateral_value * cfg.ltv,
+      bids: Vec::new(),
+      liquidation_ltv: cfg.liquidation_ltv,
+      taker_fee: cfg.taker_fee,
+      slippage: cfg.slippage
+    }
+  }
+
+  pub fn position(&self, price: f64) -> LoanPosition {
+    LoanPosition::new(String::from("backtest"), String::from("BACKTEST"), String::from("quote"), self.collateral * price + self.margin, self.debt, self.liquidation_ltv)
+  }
+
+  // 按时间计息, 返回新增的利息
+  pub fn accrue(&mut self, rate: f64, ms: i64) -> f64 {
+    let interest = self.debt * rate * ms.max(0) as f64 / YEAR_MS;
+    self.debt += interest;
+    return interest;
+  }
+
+  // 卖出抵押物, 先吃深度, 深度不够或者没有深度时按 slippage 成交, 返回 (扣除手续费后的所得, 手续费, 滑点成本)
+  pub fn sell(&mut self, volume: f64, price: f64) -> (f64, f64, f64) {
+    let mut left = volume;
+    let mut gross = 0_f64;
+    let mut last = price;
+    for [bid, size] in self.bids.iter() {
+      if left <= 0_f64 {
+        break;
+      }
+      let fill = left.min(*size);
+      gross += fill * bid;
+      left -= fill;
+      last = *bid;
+    }
+    if left > 0_f64 {
+      gross += left * last * (1_f64 - self.slippage);
+    }
+    let fee = gross * self.taker_fee;
+    self.collateral -= volume;
+    return (gross - fee, fee, volume * price - gross);
+  }
+}
+
+// 一条行情经过的价格, K 线先到最低价再到最高价, 对抵押物是偏保守的顺序
+fn prices(event: &MarketEvent) -> Vec<f64> {
+  match event {
+    MarketEvent::Kline { open, high, low, close, .. } => vec![*open, *low, *high, *close],
+    MarketEvent::Depth { bids, asks, .. } => match (bids.first(), asks.first()) {
+      (Some(bid), Some(ask)) => vec![(bid[0] + ask[0]) / 2_f64],
+      (Some(level), None) | (None, Some(level)) => vec![level[0]],
+      (None, None) => vec![]
+    },
+    MarketEvent::Trade { price, .. } => vec![*price]
+  }
+}
+
+// 数量和监控循环一样由 strategy::decide 算出, 这里只在模拟交易所里执行, 返回等待到账的补仓
+fn act(strategy: &Strategy, sim: &mut SimExchange, pos: &LoanPosition, price: f64, ts: i64, report: &mut StrategyReport) -> Option<(i64, f64)> {
+  match decide(strategy, pos, report.capital)? {
+    Decision::TopUp(amount) => {
+      let (delay_secs, fee) = match strategy {
+        Strategy::TopUp { delay_secs, fee, .. } => (*delay_secs, *fee),
+        _ => (0, 0_f64)
+      };
+      report.actions += 1;
+      report.capital += amount;
+      report.fees += fee;
+      if delay_secs > 0 {
+        return Some((ts + delay_secs * 1000, amount));
+      }
+      sim.margin += amount;
+      None
+    }
+    Decision::Repay(amount) => {
+      report.actions += 1;
+      report.capital += amount;
+      sim.debt -= amount;
+      None
+    }
+    Decision::Deleverage(target) => {
+      // 卖出 q 个抵押物, 所得 q * net 用于还款: l * (C - q * price) / (D - q * net) = target
+      let l = pos.liquidation_ltv;
+      let best = sim.bids.first().map(|b| b[0]).unwrap_or(price * (1_f64 - sim.slippage));
+      let net = best * (1_f64 - sim.taker_fee);
+      let divisor = target * net - l * price;
+      if divisor <= 0_f64 {
+        return None;
+      }
+      let volume = ((target * pos.debt_value - l * pos.collateral_value) / divisor).min(sim.collateral);
+      if volume <= 0_f64 {
+        return None;
+      }
+      let (proceeds, fee, slippage) = sim.sell(volume, price);
+      let repaid = proceeds.min(sim.debt);
+      sim.debt -= repaid;
+      sim.margin += proceeds - repaid;
+      report.actions += 1;
+      report.sold += volume;
+      report.fees += fee;
+      report.slippage += slippage;
+      None
+    }
+  }
+}
+
+fn simulate(cfg: &BacktestConfig, alert: &AlertConfig, strategy: &Strategy, events: &[MarketEvent], start_price: f64) -> StrategyReport {
+  let mut sim = SimExchange::new(cfg, start_price);
+  let mut report = StrategyReport { strategy: strategy.name(), min_health_factor: f64::INFINITY, ..Default::default() };
+  let mut pending: Vec<(i64, f64)> = Vec::new();
+  let mut severity = Severity::INFO;
+  let mut last_ts = events[0].ts();
+  let mut last_price = start_price;
+  for event in events.iter() {
+    let ts = event.ts();
+    report.interest += sim.accrue(cfg.interest_rate, ts - last_ts);
+    last_ts = ts;
+    if let MarketEvent::Depth { bids, .. } = event {
+      sim.bids = bids.clone();
+    }
+    for price in prices(event) {
+      last_price = price;
+      sim.margin += pending.iter().filter(|(due, _)| *due <= ts).map(|(_, amount)| amount).sum::<f64>();
+      pending.retain(|(due, _)| *due > ts);
+      let pos = sim.position(price);
+      report.min_health_factor = report.min_health_factor.min(pos.health_factor);
+      if pos.health_factor < 1_f64 {
+        report.liquidated_at = Some(ts);
+        report.liquidation_price = Some(price);
+        return report;
+      }
+      let level = alert.severity(&pos);
+      if level > severity && level >= Severity::WARNING {
+        report.alerts += 1;
+      }
+      severity = level;
+      if pending.is_empty() {
+        pending.extend(act(strategy, &mut sim, &pos, price, ts, &mut report));
+      }
+    }
+  }
+  report.equity = sim.collateral * last_price + sim.margin - sim.debt;
+  return report;
+}
+
+// 每个策略各自从同样的初始仓位开始回放
+pub fn run(cfg: &BacktestConfig, alert: &AlertConfig, events: &[MarketEvent]) -> Result<Vec<StrategyReport>, String> {
+  let start_price = events.iter().flat_map(prices).next().ok_or(String::from("no market data to replay"))?;
+  if cfg.strategies.is_empty() {
+    return Err(String::from("no backtest strategies configured"));
+  }
+  return Ok(cfg.strategies.iter().map(|s| simulate(cfg, alert, s, events, start_price)).collect());
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn kline(ts: i64, open: f64, low: f64, close: f64) -> MarketEvent {
+    MarketEvent::Kline { ts, open, high: open.max(close), low, close }
+  }
+
+  // 价格从 2 跌到 1.05 再回到 1.5, ltv 0.5 的仓位不操作会在 1.1 以下被清算
+  fn crash() -> Vec<MarketEvent> {
+    vec![kline(0, 2_f64, 1.9, 1.9), kline(3_600_000, 1.9, 1.4, 1.5), kline(7_200_000, 1.5, 1.05, 1.2), kline(10_800_000, 1.2, 1.2, 1.5)]
+  }
+
+  fn config(strategies: Vec<Strategy>) -> BacktestConfig {
+    BacktestConfig { interest_rate: 0_f64, strategies, ..Default::default() }
+  }
+
+  #[test]
+  fn alert_only_is_liquidated() {
+    let reports = run(&config(vec![Strategy::AlertOnly]), &AlertConfig::default(), &crash()).unwrap();
+    assert_eq!(reports[0].liquidated_at, Some(7_200_000));
+    assert_eq!(reports[0].liquidation_price, Some(1.05));
+    assert_eq!(reports[0].alerts, 1);
+    assert_eq!(reports[0].equity, 0_f64);
+  }
+
+  #[test]
+  fn protective_strategies_survive() {
+    let strategies = vec![
+      Strategy::TopUp { trigger: 1.3, target: 1.6, delay_secs: 0, fee: 1_f64, budget: 0_f64 },
+      Strategy::Repay { trigger: 1.3, target: 1.6, budget: 0_f64 },
+      Strategy::Deleverage { trigger: 1.3, target: 1.6 }
+    ];
+    let reports = run(&config(strategies), &AlertConfig::default(), &crash()).unwrap();
+    for r in reports.iter() {
+      assert!(r.liquidated_at.is_none(), "{:?}", r);
+      assert!(r.actions > 0 && r.min_health_factor > 1_f64, "{:?}", r);
+    }
+    assert_eq!(reports[0].fees, reports[0].actions as f64);
+    assert!(reports[2].sold > 0_f64 && reports[2].slippage > 0_f64 && reports[2].capital == 0_f64);
+  }
+
+  // 补仓到账前价格继续下跌, 仍然会被清算
+  #[test]
+  fn slow_top_up_can_be_too_late() {
+    let strategies = vec![Strategy::TopUp { trigger: 1.3, target: 1.6, delay_secs: 7200, fee: 0_f64, budget: 0_f64 }];
+    let reports = run(&config(strategies), &AlertConfig::default(), &crash()).unwrap();
+    assert_eq!(reports[0].actions, 1);
+    assert!(reports[0].liquidated_at.is_some());
+  }
+
+  #[test]
+  fn deleverage_walks_the_book() {
+    let mut sim = SimExchange::new(&config(vec![]), 2_f64);
+    sim.bids = vec![[1.99, 100_f64], [1.98, 100_f64]];
+    let (proceeds, fee, slippage) = sim.sell(150_f64, 2_f64);
+    let gross = 100_f64 * 1.99 + 50_f64 * 1.98;
+    assert!((fee - gross * 0.001).abs() < 1e-9);
+    assert!((proceeds - (gross - fee)).abs() < 1e-9);
+    assert!((slippage - (300_f64 - gross)).abs() < 1e-9);
+    assert!((sim.collateral - 4850_f64).abs() < 1e-9);
+  }
+}
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time;
+use serde_json::Value;
+use crate::engine::guard;
+use crate::notify::{ self, NotifierConfig, Severity, telegram };
+use super::alert;
+use super::context::Context;
+
+// 修改状态的命令需要在这个时间内 /confirm
+pub static CONFIRM_TIMEOUT: u64 = 60_u64;
+
+static HELP: &str = "/status\n/position <id>\n/topup <id> <amount>\n/repay <id> <amount>\n/refill <id> <amount>\n/pause <id>\n/resume <id>\n/dryrun on|off\n/ack <id>\n/confirm\n/cancel";
+
+fn parse_amount(arg: Option<&str>) -> Result<f64, String> {
+  let amount = arg.ok_or("missing amount")?.parse::<f64>().map_err(|e| format!("invalid amount: {}", e))?;
+  if amount <= 0_f64 {
+    return Err(String::from("amount must be positive"));
+  }
+  return Ok(amount);
+}
+
+async fn status(ctx: &Context) -> String {
+  let mut lines: Vec<String> = Vec::new();
+  for target in ctx.targets().iter() {
+    let id = target.id();
+    if ctx.is_paused(&id) {
+      lines.push(format!("{}: paused", id));
+      continue;
+    }
+    match target.position().await {
+      Ok(pos) => lines.push(format!("{}: ltv {:.4}, liquidation price {:?}", id, pos.ltv, pos.liquidation_price)),
+      Err(err) => lines.push(format!("{}: error {}", id, err))
+    }
+  }
+  lines.push(format!("dry run: {}", guard::current().dry_run));
+  return lines.join("\n");
+}
+
+async fn position(ctx: &Context, id: &str) -> Result<String, String> {
+  let pos = ctx.find(id)?.position().await?;
+  let mut lines = vec![format!("{} ({})", id, pos.venue), pos.summary(), format!("collateral {} {}, debt {} {}", pos.collateral_value, pos.base, pos.debt_value, pos.base)];
+  for c in pos.collaterals.iter() {
+    lines.push(format!("{}: {} ({} {}), liquidation price {:?}", c.asset, c.amount, c.value, pos.base, c.liquidation_price));
+  }
+  return Ok(lines.join("\n"));
+}
+
+// 检查参数, 返回确认提示, 真正执行在 /confirm 之后
+pub fn prepare(ctx: &Context, command: &str, args: &[&str]) -> Result<String, String> {
+  match command {
+    "/topup" | "/repay" | "/refill" => {
+      let id = args.first().ok_or("missing id")?;
+      let target = ctx.find(id)?;
+      if command == "/repay" && !target.can_repay() {
+        return Err(format!("{} does not support repay, use /topup", id));
+      }
+      let amount = parse_amount(args.get(1).copied())?;
+      return Ok(format!("{} {} {}", command.trim_start_matches('/'), id, amount));
+    }
+    "/pause" | "/resume" => {
+      let id = args.first().ok_or("missing id")?;
+      ctx.find(id)?;
+      return Ok(format!("{} {}", command.trim_start_matches('/'), id));
+    }
+    "/dryrun" => match args.first().copied() {
+      Some("on") | Some("off") => Ok(format!("dry run {}", args[0])),
+      _ => Err(String::from("usage: /dryrun on|off"))
+    },
+    _ => Err(format!("unknown command: {}\n{}", command, HELP))
+  }
+}
+
+// 执行已经确认的命令, 和自动策略走同一套 Target 操作和 guard; http 接口的 /confirm 也调用这里
+pub async fn execute(ctx: &Context, command: &str, args: &[&str]) -> Result<String, String> {
+  match command {
+    "/topup" => ctx.top_up(args[0], parse_amount(args.get(1).copied())?).await,
+    "/repay" => ctx.repay(args[0], parse_amount(args.get(1).copied())?).await,
+    "/refill" => ctx.refill(args[0], parse_amount(args.get(1).copied())?).await,
+    "/pause" => ctx.set_paused(args[0], true).map(|_| format!("{} paused", args[0])),
+    "/resume" => ctx.set_paused(args[0], false).map(|_| format!("{} resumed", args[0])),
+    "/dryrun" => {
+      guard::set_dry_run(args[0] == "on");
+      Ok(format!("dry run {}", args[0]))
+    }
+    _ => Err(format!("unknown command: {}", command))
+  }
+}
+
+struct Pending {
+  text: String,
+  at: time::Instant
+}
+
+async fn handle(ctx: &Context, pending: &mut HashMap<String, Pending>, chat_id: &str, message: &Value) -> String {
+  let text = message["text"].as_str().unwrap_or("").trim();
+  let parts: Vec<&str> = text.split_whitespace().collect();
+  let command = parts.first().copied().unwrap_or("");
+  // 群里的命令可能带 @botname
+  let command = command.split('@').next().unwrap_or("");
+  let args = if parts.len() > 1 { &parts[1..] } else { &[] };
+  match command {
+    "/start" | "/help" => String::from(HELP),
+    "/status" => status(ctx).await,
+    "/position" => match args.first() {
+      Some(id) => position(ctx, id).await.unwrap_or_else(|e| e),
+      None => String::from("usage: /position <id>")
+    },
+    "/ack" | "ack" => {
+      // 直接回复告警消息时可以不带仓位 id
+      let target = args.first().map(|s| String::from(*s))
+        .or_else(|| message["reply_to_message"]["text"].as_str().and_then(telegram::target_of));
+      match target {
+        Some(target) => format!("acked {} alert(s) of {}", alert::ack(&target), target),
+        None => String::from("usage: /ack <id>, or reply ack to an alert")
+      }
+    }
+    "/confirm" => {
+      let p = match pending.remove(chat_id) {
+        Some(p) if p.at.elapsed().as_secs() <= CONFIRM_TIMEOUT => p,
+        Some(_) => return String::from("confirmation expired"),
+        None => return String::from("nothing to confirm")
+      };
+      let parts: Vec<&str> = p.text.split_whitespace().collect();
+      let result = execute(ctx, parts[0], &parts[1..]).await;
+      let (severity, reply) = match result {
+        Ok(msg) => (Severity::ACTION, format!("{}: {}", p.text, msg)),
+        Err(err) => (Severity::WARNING, format!("{} failed: {}", p.text, err))
+      };
+      notify::notify(severity, &format!("telegram:{}", chat_id), "manual command", &reply).await;
+      reply
+    }
+    "/cancel" => match pending.remove(chat_id) {
+      Some(p) => format!("cancelled {}", p.text),
+      None => String::from("nothing to cancel")
+    },
+    _ => match prepare(ctx, command, args) {
+      Ok(summary) => {
+        let reply = format!("{}?\nsend /confirm within {}s", summary, CONFIRM_TIMEOUT);
+        let normalized: Vec<&str> = std::iter::once(command).chain(args.iter().copied()).collect();
+        pending.insert(String::from(chat_id), Pending { text: normalized.join(" "), at: time::Instant::now() });
+        reply
+      }
+      Err(err) => err
+    }
+  }
+}
+
+// 轮询 telegram 消息, 只处理白名单里的 chat 发来的命令
+pub async fn run(notifier: NotifierConfig, ctx: Arc<Context>) {
+  let mut offset = 0_i64;
+  let mut pending: HashMap<String, Pending> = HashMap::new();
+  loop {
+    match telegram::get_updates(&notifier, offset).await {
+      Ok(updates) => {
+        for update in updates.iter() {
+          offset = update["update_id"].as_i64().unwrap_or(offset) + 1;
+          let message = &update["message"];
+          let chat_id = message["chat"]["id"].to_string();
+          if !notifier.allows_chat(&chat_id) {
+            log::warn!("telegram {}: ignore message from chat {}", notifier.name, chat_id);
+            continue;
+          }
+          let reply = handle(&ctx, &mut pending, &chat_id, message).await;
+          if let Err(err) = telegram::send_text(&notifier, &chat_id, &reply).await {
+            log::error!("telegram {} reply error: {}", notifier.name, err);
+          }
+        }
+      }
+      Err(err) => {
+        log::error!("telegram {} poll error: {}", notifier.name, err);
+        tokio::time::sleep(time::Duration::from_secs(10)).await;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::MonitorConfig;
+  use crate::engine::exchange::Exchange;
+  use crate::engine::exchange::types::Exchanges;
+
+  fn exchange(name: Exchanges) -> Exchange {
+    Exchange {
+      name,
+      symbol: String::from("crv"),
+      currency: String::from("usdt"),
+      host: String::from("127.0.0.1"),
+      protocol: String::from("http"),
+      config: String::from("bot.test"),
+      protect: false,
+      subaccount: None
+    }
+  }
+
+  #[test]
+  fn parse_amount_rejects_bad_input() {
+    assert_eq!(parse_amount(Some("12.5")), Ok(12.5));
+    assert!(parse_amount(None).unwrap_err().contains("missing"));
+    assert!(parse_amount(Some("abc")).unwrap_err().contains("invalid amount"));
+    assert!(parse_amount(Some("0")).unwrap_err().contains("positive"));
+    assert!(parse_amount(Some("-3")).unwrap_err().contains("positive"));
+  }
+
+  #[test]
+  fn prepare_checks_arguments() {
+    let ctx = Context::new(&MonitorConfig { exchanges: vec![exchange(Exchanges::BINANCE), exchange(Exchanges::OKEX)], ..Default::default() });
+    assert_eq!(prepare(&ctx, "/topup", &["BINANCE:crvusdt", "100"]), Ok(String::from("topup BINANCE:crvusdt 100")));
+    assert_eq!(prepare(&ctx, "/repay", &["BINANCE:crvusdt", "1e2"]), Ok(String::from("repay BINANCE:crvusdt 100")));
+    assert_eq!(prepare(&ctx, "/pause", &["OKEX:crvusdt"]), Ok(String::from("pause OKEX:crvusdt")));
+    assert_eq!(prepare(&ctx, "/dryrun", &["off"]), Ok(String::from("dry run off")));
+    assert!(prepare(&ctx, "/topup", &[]).unwrap_err().contains("missing id"));
+    assert!(prepare(&ctx, "/topup", &["BINANCE:crvusdt"]).unwrap_err().contains("missing amount"));
+    assert!(prepare(&ctx, "/topup", &["HUOBI:crvusdt", "1"]).unwrap_err().contains("unknown position"));
+    assert!(prepare(&ctx, "/repay", &["OKEX:crvusdt", "1"]).unwrap_err().contains("does not support repay"));
+    assert!(prepare(&ctx, "/dryrun", &["maybe"]).unwrap_err().contains("usage"));
+    assert!(prepare(&ctx, "/withdraw", &[]).unwrap_err().contains("unknown command"));
+  }
+}
@@ -0,0 +1,234 @@
+use std::collections::{ HashMap, HashSet };
+use std::sync::{ Arc, Mutex, RwLock };
+use std::sync::atomic::{ AtomicU64, Ordering };
+use chrono::Local;
+use serde::Serialize;
+use crate::config::{ self, MonitorConfig };
+use crate::engine::defi::tx::Wallet;
+use crate::engine::guard;
+use crate::engine::position::LoanPosition;
+use crate::keystore;
+use crate::notify;
+use crate::metrics;
+use crate::store;
+use super::alert::AlertConfig;
+use super::protect::ProtectionConfig;
+use super::target::{ self, Target };
+
+// 最近一次读取到的仓位
+#[derive(Serialize, Debug, Clone)]
+pub struct PositionState {
+  pub position: LoanPosition,
+  pub updated_at: i64
+}
+
+// 交易所或者链的可用状态, 按最近一次读取仓位的结果统计
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct VenueHealth {
+  pub venue: String,
+  pub ok: bool,
+  pub last_ok: Option<i64>,
+  pub last_error: Option<String>,
+  pub failures: u64 // 连续失败次数
+}
+
+fn wallets(cfg: &MonitorConfig) -> Vec<Arc<Wallet>> {
+  let mut wallets: Vec<Arc<Wallet>> = Vec::new();
+  for wallet_cfg in cfg.wallets.iter() {
+    match cfg.chain(&wallet_cfg.chain).and_then(|chain| Wallet::new(wallet_cfg.clone(), chain.clone())) {
+      Ok(wallet) => wallets.push(Arc::new(wallet)),
+      Err(e) => log::error!("wallet on {}: {}", wallet_cfg.chain, e)
+    }
+  }
+  return wallets;
+}
+
+// 运行时共享的状态, 监控循环, telegram 命令和 http 接口使用同一份 targets 和 wallets(nonce 在 wallet 里维护)
+pub struct Context {
+  targets: RwLock<Vec<Target>>,
+  alert: RwLock<AlertConfig>,
+  protection: RwLock<ProtectionConfig>,
+  interval: AtomicU64,
+  started_at: i64,
+  wallets: RwLock<Vec<Arc<Wallet>>>,
+  paused: Mutex<HashSet<String>>,
+  positions: Mutex<HashMap<String, PositionState>>,
+  venues: Mutex<HashMap<String, VenueHealth>>
+}
+
+impl Context {
+  pub fn new(cfg: &MonitorConfig) -> Context {
+    Context {
+      targets: RwLock::new(target::from_config(cfg)),
+      alert: RwLock::new(cfg.alert.clone()),
+      protection: RwLock::new(cfg.protection.clone()),
+      interval: AtomicU64::new(cfg.interval),
+      started_at: Local::now().timestamp(),
+      wallets: RwLock::new(wallets(cfg)),
+      paused: Mutex::new(HashSet::new()),
+      positions: Mutex::new(HashMap::new()),
+      venues: Mutex::new(HashMap::new())
+    }
+  }
+
+  // 重新读取配置, 更新仓位列表, 告警阈值, 保护策略, 轮询间隔, 通知渠道, guard, wallet 和 keystore;
+  // telegram 命令轮询需要重启进程才能生效
+  pub async fn reload(&self) -> Result<usize, String> {
+    let cfg = config::try_load()?;
+    keystore::reload(&cfg.keystore)?;
+    return Ok(self.apply(&cfg).await);
+  }
+
+  // 删除的仓位不再保留读取结果, 暂停状态和交易所状态; 同一个地址的 wallet 沿用 nonce 和待打包的交易
+  pub async fn apply(&self, cfg: &MonitorConfig) -> usize {
+    let targets = target::from_config(cfg);
+    let ids: HashSet<String> = targets.iter().map(|t| t.id()).collect();
+    let venues: HashSet<String> = targets.iter().map(|t| t.venue()).collect();
+    self.positions.lock().unwrap().retain(|id, _| {
+      if !ids.contains(id) {
+        metrics::remove_position(id);
+      }
+      ids.contains(id)
+    });
+    self.paused.lock().unwrap().retain(|id| ids.contains(id));
+    self.venues.lock().unwrap().retain(|venue, _| venues.contains(venue));
+    let count = targets.len();
+    *self.targets.write().unwrap() = targets;
+    *self.alert.write().unwrap() = cfg.alert.clone();
+    *self.protection.write().unwrap() = cfg.protection.clone();
+    self.interval.store(cfg.interval, Ordering::Relaxed);
+    let new_wallets = wallets(cfg);
+    let old_wallets = self.wallets.read().unwrap().clone();
+    for wallet in new_wallets.iter() {
+      if let Some(old) = old_wallets.iter().find(|w| w.chain.name == wallet.chain.name) {
+        wallet.inherit(old).await;
+      }
+    }
+    *self.wallets.write().unwrap() = new_wallets;
+    notify::init(&cfg.notifiers);
+    guard::init(&cfg.guard);
+    return count;
+  }
+
+  pub fn targets(&self) -> Vec<Target> {
+    self.targets.read().unwrap().clone()
+  }
+
+  pub fn alert(&self) -> AlertConfig {
+    self.alert.read().unwrap().clone()
+  }
+
+  pub fn protection(&self) -> ProtectionConfig {
+    self.protection.read().unwrap().clone()
+  }
+
+  pub fn interval(&self) -> u64 {
+    self.interval.load(Ordering::Relaxed)
+  }
+
+  pub fn find(&self, id: &str) -> Result<Target, String> {
+    self.targets.read().unwrap().iter().find(|t| t.id() == id).cloned().ok_or(format!("unknown position {}", id))
+  }
+
+  pub fn wallet(&self, target: &Target) -> Option<Arc<Wallet>> {
+    let chain = target.chain()?;
+    self.wallets.read().unwrap().iter().find(|w| w.chain.name == chain.name).cloned()
+  }
+
+  // 暂停后不再读取仓位, 也不会告警或者自动操作
+  pub fn set_paused(&self, id: &str, paused: bool) -> Result<(), String> {
+    self.find(id)?;
+    let mut set = self.paused.lock().unwrap();
+    if paused { set.insert(String::from(id)); } else { set.remove(id); }
+    return Ok(());
+  }
+
+  pub fn is_paused(&self, id: &str) -> bool {
+    self.paused.lock().unwrap().contains(id)
+  }
+
+  pub fn record(&self, target: &Target, result: &Result<LoanPosition, String>, secs: f64) {
+    let now = Local::now().timestamp();
+    let venue = target.venue();
+    metrics::request(&venue, "position", secs, result.as_ref().map(|_| ()).map_err(|e| e.as_str()));
+    if let Ok(pos) = result {
+      metrics::position(&target.id(), &venue, pos);
+      store::snapshot(&target.id(), pos);
+    }
+    let mut venues = self.venues.lock().unwrap();
+    let health = venues.entry(venue.clone()).or_insert(VenueHealth { venue, ..Default::default() });
+    match result {
+      Ok(pos) => {
+        health.ok = true;
+        health.last_ok = Some(now);
+        health.failures = 0;
+        self.positions.lock().unwrap().insert(target.id(), PositionState { position: pos.clone(), updated_at: now });
+      }
+      Err(err) => {
+        health.ok = false;
+        health.last_error = Some(err.clone());
+        health.failures += 1;
+      }
+    }
+  }
+
+  // 最后一次读取成功的时间, 还没有成功过时是启动时间, 用于判断仓位是否过期
+  pub fn updated_at(&self, id: &str) -> i64 {
+    self.positions.lock().unwrap().get(id).map(|p| p.updated_at).unwrap_or(self.started_at)
+  }
+
+  pub fn positions(&self) -> HashMap<String, PositionState> {
+    self.positions.lock().unwrap().clone()
+  }
+
+  pub fn venues(&self) -> Vec<VenueHealth> {
+    self.venues.lock().unwrap().values().cloned().collect()
+  }
+
+  pub async fn top_up(&self, id: &str, amount: f64) -> Result<String, String> {
+    let target = self.find(id)?;
+    return target.top_up(amount, self.wallet(&target).as_deref()).await;
+  }
+
+  pub async fn repay(&self, id: &str, amount: f64) -> Result<String, String> {
+    let target = self.find(id)?;
+    return target.repay(amount, self.wallet(&target).as_deref()).await;
+  }
+
+  pub async fn refill(&self, id: &str, amount: f64) -> Result<String, String> {
+    return self.find(id)?.refill(amount).await;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::engine::exchange::mock::MockExchange;
+  use crate::engine::exchange::types::Exchanges;
+
+  #[tokio::test]
+  async fn apply_drops_removed_targets() {
+    let binance = MockExchange::start(Exchanges::BINANCE).await;
+    let huobi = MockExchange::start(Exchanges::HUOBI).await;
+    let cfg = MonitorConfig { exchanges: vec![binance.exchange("crv", "usdt"), huobi.exchange("crv", "usdt")], ..Default::default() };
+    let ctx = Context::new(&cfg);
+    let targets = ctx.targets();
+    for target in targets.iter() {
+      let res = target.position().await;
+      ctx.record(target, &res, 0_f64);
+      ctx.set_paused(&target.id(), true).unwrap();
+    }
+    assert_eq!(ctx.positions().len(), 2);
+    assert_eq!(ctx.venues().len(), 2);
+
+    let count = ctx.apply(&MonitorConfig { exchanges: vec![binance.exchange("crv", "usdt")], ..cfg }).await;
+    assert_eq!(count, 1);
+    let (kept, removed) = (targets[0].id(), targets[1].id());
+    assert!(ctx.positions().contains_key(&kept));
+    assert!(!ctx.positions().contains_key(&removed));
+    assert!(ctx.is_paused(&kept));
+    assert!(!ctx.is_paused(&removed));
+    assert_eq!(ctx.venues().iter().map(|v| v.venue.clone()).collect::<Vec<String>>(), vec![targets[0].venue()]);
+    assert!(!metrics::render().contains(&format!("id=\"{}\"", removed)));
+  }
+}
@@ -1,27 +1,136 @@
-use std::{thread, time};
-use crate::engine::exchange::{types::Exchanges, Exchange};
+use std::time;
+use chrono::TimeZone;
+use crate::engine::exchange::Exchange;
+use std::sync::Arc;
+use super::target::Target;
+use super::alert;
+use super::protect;
+use super::context::Context;
+use crate::store;
 
-static INTERVAL: u64 = 10_u64;
-
-pub async fn main_loop(ex: &Exchange) -> Result<String, String> {
-  loop {
-    let depth_res = ex.depth().await;
-    let loan_info_res = ex.loan_info().await;
-    if !depth_res.is_err() && !loan_info_res.is_err() {
-      let depth = depth_res.unwrap();
-      let loan_info = loan_info_res.unwrap();
-      let ask_price_volume = depth.tick.asks[0];
-      
-      log::info!("loan_info: {:#?}", loan_info);
-      log::info!("{}/{}, price & volume: {:?}", ex.symbol, ex.currency, ask_price_volume);
-    } else {
-      if depth_res.is_err() {
-        log::error!("ex.depth error: {}", depth_res.unwrap_err());
+// okx 没有借币信息接口, 不请求, 否则每个周期都会报错
+async fn log_market(ex: &Exchange) {
+  if ex.has_loan_info() {
+    match ex.loan_info().await {
+      Ok(loan_info) => log::info!("loan_info: {:#?}", loan_info),
+      Err(err) => log::error!("ex.loan_info error: {}", err)
+    }
+  }
+  match ex.depth().await {
+    Ok(depth) => {
+      crate::recorder::depth(ex, &depth);
+      match depth.tick.asks.first() {
+        Some(ask_price_volume) => log::info!("{}/{}, price & volume: {:?}", ex.symbol, ex.currency, ask_price_volume),
+        None => log::warn!("{}/{} order book has no asks", ex.symbol, ex.currency)
       }
-      if loan_info_res.is_err() {
-        log::error!("ex.loan_info error: {}", loan_info_res.unwrap_err());
+    }
+    Err(err) => log::error!("ex.depth error: {}", err)
+  }
+}
+
+// 余额快照按 store.snapshot_interval 读取, 同一个账户的多个仓位只读取一次; 没有配置 store 时不读取
+async fn record_balances(ex: &Exchange) {
+  let key = format!("{:?}:{}", ex.name, ex.config);
+  if !store::balances_due(&key) {
+    return;
+  }
+  match ex.balances().await {
+    Ok(balances) => store::balances(&key, &balances),
+    Err(err) => log::warn!("{} balances error: {}", key, err)
+  }
+}
+
+// 读取一遍所有仓位, 记录状态, 更新告警并按策略自动保护
+pub async fn poll(ctx: &Context) {
+  let alert_cfg = ctx.alert();
+  for target in ctx.targets().iter() {
+    if ctx.is_paused(&target.id()) {
+      log::info!("{} paused", target.id());
+      continue;
+    }
+    if let Target::Exchange(ex) = target {
+      log_market(ex).await;
+      record_balances(ex).await;
+    }
+    let prev = ctx.positions().get(&target.id()).cloned();
+    let started = time::Instant::now();
+    let res = target.position().await;
+    ctx.record(target, &res, started.elapsed().as_secs_f64());
+    alert::fetch_result(&alert_cfg, &target.id(), res.as_ref().map(|_| ()).map_err(|e| e.as_str())).await;
+    let updated_at = ctx.updated_at(&target.id());
+    let now = chrono::Local::now().timestamp();
+    let stale = format!("last position at {}", chrono::Local.timestamp_opt(updated_at, 0).single().map(|t| t.to_rfc3339()).unwrap_or_default());
+    alert::evaluate(&alert_cfg, &target.id(), "position stale", alert_cfg.stale_severity(updated_at, now), &stale).await;
+    match res {
+      Ok(pos) => {
+        log::info!("{} ltv: {:.4}/{:.4}, health factor: {:.4}, liquidation price: {:?}", target.id(), pos.ltv, pos.liquidation_ltv, pos.health_factor, pos.liquidation_price);
+        for c in pos.collaterals.iter() {
+          log::info!("{} collateral {}: {} ({} {}), liquidation price: {:?}", target.id(), c.asset, c.amount, c.value, pos.base, c.liquidation_price);
+        }
+        alert::evaluate(&alert_cfg, &target.id(), "health factor low", alert_cfg.severity(&pos), &pos.summary()).await;
+        protect::run(ctx, target, &pos, prev.as_ref(), now).await;
       }
+      Err(err) => log::error!("{} position error: {}", target.id(), err)
     }
-    thread::sleep(time::Duration::from_secs(INTERVAL));
   }
-}
\ No newline at end of file
+}
+
+pub async fn main_loop(ctx: Arc<Context>) -> Result<String, String> {
+  loop {
+    poll(&ctx).await;
+    tokio::time::sleep(time::Duration::from_secs(ctx.interval())).await;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+  use crate::config::MonitorConfig;
+  use crate::engine::exchange::mock::{ MockExchange, Script };
+  use crate::engine::exchange::types::Exchanges;
+  use crate::notify::Severity;
+
+  #[tokio::test]
+  async fn poll_records_positions_and_alerts() {
+    let mock = MockExchange::start(Exchanges::BINANCE).await;
+    let ex = mock.exchange("crv", "usdt");
+    let ctx = Context::new(&MonitorConfig { exchanges: vec![ex], ..Default::default() });
+    let id = ctx.targets()[0].id();
+
+    poll(&ctx).await;
+    assert_eq!(ctx.positions()[&id].position.ltv, 0.4);
+    assert!(ctx.venues()[0].ok);
+    assert!(!alert::active().iter().any(|a| a.target == id));
+
+    // 交易所出错时保留上一次的仓位, 记录连续失败
+    mock.script("/sapi/v1/margin/isolated/account", Script::Error(503, json!({ "code": -1001, "msg": "Internal error; unable to process your request. Please try again." })));
+    poll(&ctx).await;
+    let health = &ctx.venues()[0];
+    assert!(!health.ok);
+    assert_eq!(health.failures, 1);
+    assert!(health.last_error.as_ref().unwrap().contains("-1001"));
+    assert_eq!(ctx.positions()[&id].position.ltv, 0.4);
+
+    // 借款增加后健康因子低于 warning_health_factor
+    mock.set("/sapi/v1/margin/isolated/account", json!({
+      "assets": [{
+        "symbol": "CRVUSDT", "indexPrice": "0.5", "liquidatePrice": "0.45",
+        "baseAsset": { "totalAsset": "1000", "borrowed": "0", "interest": "0" },
+        "quoteAsset": { "totalAsset": "0", "borrowed": "380", "interest": "0" }
+      }]
+    }));
+    poll(&ctx).await;
+    assert_eq!(ctx.venues()[0].failures, 0);
+    let alert = alert::active().into_iter().find(|a| a.target == id).unwrap();
+    assert!(matches!(alert.severity, Severity::WARNING));
+  }
+
+  #[tokio::test]
+  async fn log_market_handles_empty_book() {
+    let mock = MockExchange::start(Exchanges::BINANCE).await;
+    mock.set("/api/v3/depth", json!({ "lastUpdateId": 1, "bids": [], "asks": [] }));
+    log_market(&mock.exchange("crv", "usdt")).await;
+    assert_eq!(mock.requests("/api/v3/depth").len(), 1);
+  }
+}
@@ -0,0 +1,165 @@
+use std::collections::{ HashMap, HashSet };
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use crate::engine::exchange::Exchange;
+use crate::engine::guard;
+use crate::engine::position::LoanPosition;
+use crate::engine::rebalance;
+use crate::engine::strategy::{ self, Decision, Strategy };
+use crate::notify::{ self, Severity };
+use super::context::{ Context, PositionState };
+use super::target::Target;
+
+// 自动保护: 每次读取仓位后按策略决定是否补仓或者还款, 只处理 protect = true 的交易所仓位.
+// 目标交易所余额不足时从其他 protect 交易所调仓, 到账前不再重复操作
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProtectionConfig {
+  pub strategy: Strategy,
+  pub rebalance: bool, // 余额不足时是否跨交易所调仓
+  pub deadline_secs: u64 // 健康因子没有下降趋势时假设的强平时间, 用来选择调仓路线
+}
+
+impl ::std::default::Default for ProtectionConfig {
+  fn default() -> Self {
+    Self {
+      strategy: Strategy::AlertOnly,
+      rebalance: true,
+      deadline_secs: 3600_u64
+    }
+  }
+}
+
+// 正在调仓的仓位
+static RUNNING: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+// 每个仓位已经投入的外部资金, 用于策略的 budget, 进程重启后重新计算
+static SPENT: Mutex<Option<HashMap<String, f64>>> = Mutex::new(None);
+
+fn is_running(id: &str) -> bool {
+  RUNNING.lock().unwrap().as_ref().map(|s| s.contains(id)).unwrap_or(false)
+}
+
+fn set_running(id: &str, running: bool) {
+  let mut guard = RUNNING.lock().unwrap();
+  let set = guard.get_or_insert_with(HashSet::new);
+  if running { set.insert(String::from(id)); } else { set.remove(id); }
+}
+
+fn spent(id: &str) -> f64 {
+  SPENT.lock().unwrap().as_ref().and_then(|m| m.get(id).cloned()).unwrap_or(0_f64)
+}
+
+fn add_spent(id: &str, amount: f64) {
+  let mut guard = SPENT.lock().unwrap();
+  *guard.get_or_insert_with(HashMap::new).entry(String::from(id)).or_insert(0_f64) += amount;
+}
+
+// 按上一次读取以来健康因子下降的速度估算多少秒后降到 1, 没有下降时返回 None
+pub fn time_to_liquidation(prev: Option<&PositionState>, pos: &LoanPosition, now: i64) -> Option<u64> {
+  if pos.health_factor <= 1_f64 {
+    return Some(0);
+  }
+  let prev = prev?;
+  let secs = (now - prev.updated_at) as f64;
+  let drop = prev.position.health_factor - pos.health_factor;
+  if secs <= 0_f64 || drop <= 0_f64 || !drop.is_finite() {
+    return None;
+  }
+  return Some(((pos.health_factor - 1_f64) / (drop / secs)) as u64);
+}
+
+// 可以作为调仓来源的交易所, 每个交易所只取一个配置
+fn sources(ctx: &Context, ex: &Exchange) -> Vec<Exchange> {
+  let mut res: Vec<Exchange> = Vec::new();
+  for target in ctx.targets().into_iter() {
+    if let Target::Exchange(source) = target {
+      if source.protect && source.name != ex.name && !res.iter().any(|s| s.name == source.name) {
+        res.push(source);
+      }
+    }
+  }
+  return res;
+}
+
+async fn top_up(ctx: &Context, target: &Target, ex: &Exchange, amount: f64, deadline_secs: u64) -> Result<String, String> {
+  let id = target.id();
+  let cfg = ctx.protection();
+  let plan = if cfg.rebalance { rebalance::plan(ex, &sources(ctx, ex), amount, deadline_secs).await? } else { None };
+  let plan = match plan {
+    Some(plan) => plan,
+    None => {
+      let res = target.top_up(amount, None).await?;
+      if res != super::target::DRY_RUN {
+        add_spent(&id, amount);
+      }
+      return Ok(res);
+    }
+  };
+  // 调仓要等待到账, 放到后台执行
+  let msg = format!("rebalance {} {} from {} via {}, eta {}s", plan.withdraw_amount, plan.asset, plan.source.name, plan.chain, plan.eta_secs);
+  set_running(&id, true);
+  tokio::spawn(async move {
+    let receive = plan.receive_amount;
+    let wf = rebalance::execute(plan, &guard::current()).await;
+    if wf.is_done() {
+      add_spent(&id, receive);
+    }
+    set_running(&id, false);
+  });
+  return Ok(msg);
+}
+
+// 在监控循环里读取到仓位之后调用, prev 是上一次读取的结果
+pub async fn run(ctx: &Context, target: &Target, pos: &LoanPosition, prev: Option<&PositionState>, now: i64) {
+  let ex = match target {
+    Target::Exchange(ex) if ex.protect => ex,
+    _ => return
+  };
+  let id = target.id();
+  if is_running(&id) {
+    log::info!("{} rebalance in progress", id);
+    return;
+  }
+  let cfg = ctx.protection();
+  let res = match strategy::decide(&cfg.strategy, pos, spent(&id)) {
+    None => return,
+    Some(Decision::TopUp(amount)) => {
+      let deadline_secs = time_to_liquidation(prev, pos, now).unwrap_or(cfg.deadline_secs);
+      log::warn!("{} health factor {:.4}, top up {} {}, about {}s to liquidation", id, pos.health_factor, amount, pos.base, deadline_secs);
+      top_up(ctx, target, ex, amount, deadline_secs).await
+    }
+    Some(Decision::Repay(amount)) => {
+      log::warn!("{} health factor {:.4}, repay {} {}", id, pos.health_factor, amount, pos.base);
+      let res = target.repay(amount, None).await;
+      if res.as_ref().map(|r| r != super::target::DRY_RUN).unwrap_or(false) {
+        add_spent(&id, amount);
+      }
+      res
+    }
+    Some(Decision::Deleverage(_)) => Err(String::from("deleverage is only supported in backtest"))
+  };
+  match res {
+    Ok(msg) => log::info!("{} protection: {}", id, msg),
+    Err(err) => notify::notify(Severity::CRITICAL, &id, "automatic protection failed", &err).await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn state(health_factor: f64, updated_at: i64) -> PositionState {
+    let mut position = LoanPosition::new(String::from("test"), String::from("TEST"), String::from("usdt"), 1000_f64, 500_f64, 0.8);
+    position.health_factor = health_factor;
+    PositionState { position, updated_at }
+  }
+
+  #[test]
+  fn time_to_liquidation_from_trend() {
+    // 60 秒下降 0.1, 剩下 0.3 需要 180 秒
+    assert_eq!(time_to_liquidation(Some(&state(1.4, 1000)), &state(1.3, 1060).position, 1060), Some(180));
+    // 上升或者没有历史时没有估计
+    assert_eq!(time_to_liquidation(Some(&state(1.2, 1000)), &state(1.3, 1060).position, 1060), None);
+    assert_eq!(time_to_liquidation(None, &state(1.3, 1060).position, 1060), None);
+    assert_eq!(time_to_liquidation(None, &state(0.99, 1060).position, 1060), Some(0));
+  }
+}
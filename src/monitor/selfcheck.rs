@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+use crate::engine::exchange::{ Exchange, types::ApiPermissions };
+use crate::notify::{ self, Severity };
+
+// 启动时检查每个交易所 API key 的实际权限, 只监控的 key 不应该能提币,
+// 用于保护操作的 key 需要交易和杠杆权限, 都应该绑定 IP 白名单
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SelfCheckConfig {
+  pub enabled: bool,
+  pub strict: bool, // 有问题(或者无法查询权限)时拒绝启动, false 时只告警
+  pub require_ip_whitelist: bool
+}
+
+impl ::std::default::Default for SelfCheckConfig {
+  fn default() -> Self {
+    Self {
+      enabled: true,
+      strict: true,
+      require_ip_whitelist: true
+    }
+  }
+}
+
+fn problems(ex: &Exchange, perm: &ApiPermissions, cfg: &SelfCheckConfig) -> Vec<String> {
+  let mut problems: Vec<String> = Vec::new();
+  if !ex.protect && perm.withdraw {
+    problems.push(String::from("monitoring-only key has withdraw permission"));
+  }
+  if ex.protect && !perm.trade {
+    problems.push(String::from("protect key lacks trade permission"));
+  }
+  if ex.protect && !perm.margin {
+    problems.push(String::from("protect key lacks margin permission"));
+  }
+  if cfg.require_ip_whitelist && !perm.ip_restricted {
+    problems.push(String::from("ip whitelist is off"));
+  }
+  return problems;
+}
+
+// 返回 Err 时不应该启动
+pub async fn run(exchanges: &[Exchange], cfg: &SelfCheckConfig) -> Result<(), String> {
+  if !cfg.enabled {
+    return Ok(());
+  }
+  let mut failed: Vec<String> = Vec::new();
+  for ex in exchanges.iter() {
+    let found = match ex.api_permissions().await {
+      Ok(perm) => {
+        log::info!("{} api permissions: {:?}", ex.config, perm);
+        problems(ex, &perm, cfg)
+      }
+      Err(err) => vec![format!("cannot read api permissions: {}", err)]
+    };
+    for problem in found.iter() {
+      log::warn!("{}: {}", ex.config, problem);
+      notify::notify(Severity::WARNING, &ex.config, "api key self-check", problem).await;
+    }
+    if !found.is_empty() {
+      failed.push(format!("{}: {}", ex.config, found.join(", ")));
+    }
+  }
+  if cfg.strict && !failed.is_empty() {
+    return Err(format!("api key self-check failed, refuse to start: {}", failed.join("; ")));
+  }
+  return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::engine::exchange::types::Exchanges;
+
+  fn exchange(protect: bool) -> Exchange {
+    Exchange {
+      name: Exchanges::BINANCE,
+      symbol: String::from("crv"),
+      currency: String::from("usdt"),
+      host: String::from("127.0.0.1"),
+      protocol: String::from("http"),
+      config: String::from("selfcheck.test"),
+      protect,
+      subaccount: None
+    }
+  }
+
+  fn perm(trade: bool, margin: bool, withdraw: bool, ip_restricted: bool) -> ApiPermissions {
+    ApiPermissions { read: true, trade, margin, withdraw, ip_restricted }
+  }
+
+  #[test]
+  fn monitoring_key_must_not_withdraw() {
+    let cfg = SelfCheckConfig::default();
+    assert!(problems(&exchange(false), &perm(false, false, false, true), &cfg).is_empty());
+    // 只监控的 key 不需要交易权限, 有也不算问题
+    assert!(problems(&exchange(false), &perm(true, true, false, true), &cfg).is_empty());
+    assert_eq!(problems(&exchange(false), &perm(false, false, true, true), &cfg), vec!["monitoring-only key has withdraw permission"]);
+  }
+
+  #[test]
+  fn protect_key_needs_trade_and_margin() {
+    let cfg = SelfCheckConfig::default();
+    assert!(problems(&exchange(true), &perm(true, true, true, true), &cfg).is_empty());
+    assert_eq!(problems(&exchange(true), &perm(false, false, false, true), &cfg), vec!["protect key lacks trade permission", "protect key lacks margin permission"]);
+    assert_eq!(problems(&exchange(true), &perm(true, false, false, true), &cfg), vec!["protect key lacks margin permission"]);
+  }
+
+  #[test]
+  fn ip_whitelist_is_optional() {
+    let cfg = SelfCheckConfig::default();
+    assert!(cfg.strict);
+    assert_eq!(problems(&exchange(true), &perm(true, true, false, false), &cfg), vec!["ip whitelist is off"]);
+    let relaxed = SelfCheckConfig { require_ip_whitelist: false, ..cfg };
+    assert!(problems(&exchange(true), &perm(true, true, false, false), &relaxed).is_empty());
+    assert_eq!(problems(&exchange(false), &perm(false, false, true, false), &relaxed), vec!["monitoring-only key has withdraw permission"]);
+  }
+}
@@ -0,0 +1,221 @@
+use crate::engine::exchange::Exchange;
+use crate::engine::exchange::types::Exchanges;
+use crate::engine::defi::aave::{ self, AaveConfig };
+use crate::engine::defi::compound::{ self, CompoundConfig };
+use crate::engine::defi::maker::{ self, MakerConfig };
+use crate::engine::defi::chain::ChainConfig;
+use crate::engine::defi::tx::Wallet;
+use crate::engine::defi::actions;
+use crate::engine::guard;
+use crate::engine::journal::ActionJournal;
+use crate::metrics;
+use crate::store;
+use crate::engine::position::LoanPosition;
+use crate::config::MonitorConfig;
+
+pub static DRY_RUN: &str = "dry run";
+
+// 监控对象, 交易所仓位和链上借贷仓位
+#[derive(Debug, Clone)]
+pub enum Target {
+  Exchange(Exchange),
+  Aave(AaveConfig, ChainConfig),
+  Compound(CompoundConfig, ChainConfig),
+  Maker(MakerConfig, ChainConfig)
+}
+
+impl Target {
+  pub fn id(&self) -> String {
+    match self {
+      Target::Exchange(ex) => match &ex.subaccount {
+        // 同一个交易对可能在多个子账户
+        Some(sub) => format!("{}:{}:{}{}", ex.name, sub.id, ex.symbol, ex.currency),
+        None => format!("{}:{}{}", ex.name, ex.symbol, ex.currency)
+      },
+      Target::Aave(cfg, chain) => format!("aave-v{}:{}:{}", cfg.version, chain.name, cfg.id),
+      Target::Compound(cfg, chain) => format!("compound-v{}:{}:{}", cfg.version, chain.name, cfg.id),
+      Target::Maker(cfg, chain) => format!("maker:{}:{}", chain.name, cfg.id)
+    }
+  }
+
+  pub async fn position(&self) -> Result<LoanPosition, String> {
+    match self {
+      Target::Exchange(ex) => ex.position().await,
+      Target::Aave(cfg, chain) => aave::position(cfg, chain).await,
+      Target::Compound(cfg, chain) => compound::position(cfg, chain).await,
+      Target::Maker(cfg, chain) => maker::position(cfg, chain).await
+    }
+  }
+}
+
+fn required<'a>(value: &'a str, name: &str, id: &str) -> Result<&'a str, String> {
+  if value.is_empty() {
+    return Err(format!("{}: {} is not configured", id, name));
+  }
+  return Ok(value);
+}
+
+fn tx_result(res: Option<String>) -> String {
+  res.unwrap_or(String::from(DRY_RUN))
+}
+
+fn record_action(target: &Target, action: &str, amount: f64, res: &Result<String, String>) {
+  let (result, response) = match res {
+    Ok(msg) if msg == DRY_RUN => ("dry_run", msg),
+    Ok(msg) => ("ok", msg),
+    Err(err) => ("error", err)
+  };
+  metrics::protection_action(&target.venue(), action, result);
+  store::action(&target.id(), action, &format!("{} {}", action, amount), response, result);
+}
+
+// 补充抵押物和还款, telegram/http 的手动命令和交易所仓位的自动保护都走这里, 都先经过 guard 检查;
+// 跨交易所调仓在 rebalance 里单独检查 guard
+impl Target {
+  pub async fn top_up(&self, amount: f64, wallet: Option<&Wallet>) -> Result<String, String> {
+    let mut journal = ActionJournal::new(&self.id(), "top_up", amount);
+    let res = self.run_top_up(amount, wallet, &mut journal).await;
+    journal.close();
+    record_action(self, "top_up", amount, &res);
+    return res;
+  }
+
+  pub async fn repay(&self, amount: f64, wallet: Option<&Wallet>) -> Result<String, String> {
+    let mut journal = ActionJournal::new(&self.id(), "repay", amount);
+    let res = self.run_repay(amount, wallet, &mut journal).await;
+    journal.close();
+    record_action(self, "repay", amount, &res);
+    return res;
+  }
+
+  // 从母账户给子账户的仓位补充保证金, 只支持配置了 subaccount 的交易所仓位
+  pub async fn refill(&self, amount: f64) -> Result<String, String> {
+    let mut journal = ActionJournal::new(&self.id(), "refill", amount);
+    let res = match self {
+      Target::Exchange(ex) if ex.subaccount.is_some() => {
+        match guard::current().check_exchange(ex, &format!("{} refill from master", self.id()), amount) {
+          Ok(true) => ex.refill(amount, &mut journal).await,
+          Ok(false) => Ok(String::from(DRY_RUN)),
+          Err(err) => Err(err)
+        }
+      }
+      _ => Err(format!("{} is not a subaccount position", self.id()))
+    };
+    journal.close();
+    record_action(self, "refill", amount, &res);
+    return res;
+  }
+
+  async fn run_top_up(&self, amount: f64, wallet: Option<&Wallet>, journal: &mut ActionJournal) -> Result<String, String> {
+    let id = self.id();
+    if let Target::Exchange(ex) = self {
+      if !guard::current().check_exchange(ex, &format!("{} top up", id), amount)? {
+        return Ok(String::from(DRY_RUN));
+      }
+      return ex.top_up(amount, journal).await;
+    }
+    let wallet = wallet.ok_or(format!("{}: no wallet for its chain", id))?;
+    match self {
+      Target::Aave(cfg, chain) => {
+        let pool = cfg.pool(chain)?;
+        let asset = required(&cfg.collateral_asset, "collateral_asset", &id)?;
+        return Ok(tx_result(actions::aave_supply(wallet, cfg.version, &pool, asset, amount, journal).await?));
+      }
+      Target::Compound(cfg, chain) => {
+        let market = cfg.market(chain)?;
+        let asset = required(&cfg.collateral_asset, "collateral_asset", &id)?;
+        if cfg.version == 2 {
+          let underlying = compound::underlying(chain, asset).await?;
+          return Ok(tx_result(actions::compound_v2_supply(wallet, asset, &underlying, amount, journal).await?));
+        }
+        return Ok(tx_result(actions::compound_v3_supply(wallet, &market, asset, amount, journal).await?));
+      }
+      Target::Maker(cfg, chain) => {
+        let gem_join = required(&cfg.gem_join, "gem_join", &id)?;
+        let gem = maker::join_token(chain, gem_join, "gem()").await?;
+        let (urn, _) = maker::urn_and_rate(cfg, chain).await?;
+        return Ok(tx_result(actions::maker_lock(wallet, cfg, gem_join, &gem, &urn, amount, journal).await?));
+      }
+      Target::Exchange(_) => unreachable!()
+    }
+  }
+
+  async fn run_repay(&self, amount: f64, wallet: Option<&Wallet>, journal: &mut ActionJournal) -> Result<String, String> {
+    let id = self.id();
+    if let Target::Exchange(ex) = self {
+      if !guard::current().check_exchange(ex, &format!("{} repay", id), amount)? {
+        return Ok(String::from(DRY_RUN));
+      }
+      return journal.step("repay", ex.repay(amount)).await;
+    }
+    let wallet = wallet.ok_or(format!("{}: no wallet for its chain", id))?;
+    match self {
+      Target::Aave(cfg, chain) => {
+        let pool = cfg.pool(chain)?;
+        let asset = required(&cfg.debt_asset, "debt_asset", &id)?;
+        return Ok(tx_result(actions::aave_repay(wallet, &pool, asset, amount, journal).await?));
+      }
+      Target::Compound(cfg, chain) => {
+        let market = cfg.market(chain)?;
+        if cfg.version == 2 {
+          let ctoken = required(&cfg.debt_asset, "debt_asset", &id)?;
+          let underlying = compound::underlying(chain, ctoken).await?;
+          return Ok(tx_result(actions::compound_v2_repay(wallet, ctoken, &underlying, amount, journal).await?));
+        }
+        let base = compound::base_token(chain, &market).await?;
+        return Ok(tx_result(actions::compound_v3_supply(wallet, &market, &base, amount, journal).await?));
+      }
+      Target::Maker(cfg, chain) => {
+        let dai = maker::join_token(chain, &cfg.dai_join, "dai()").await?;
+        let (urn, rate) = maker::urn_and_rate(cfg, chain).await?;
+        return Ok(tx_result(actions::maker_wipe(wallet, cfg, &dai, &urn, rate, amount, journal).await?));
+      }
+      Target::Exchange(_) => unreachable!()
+    }
+  }
+
+  // okx 的逐仓杠杆没有单独的还款接口, 只能补充保证金
+  pub fn can_repay(&self) -> bool {
+    !matches!(self, Target::Exchange(ex) if ex.name == Exchanges::OKEX)
+  }
+
+  // 交易所名称或者链名称, 用来统计可用状态
+  pub fn venue(&self) -> String {
+    match self {
+      Target::Exchange(ex) => ex.name.to_string(),
+      Target::Aave(_, chain) | Target::Compound(_, chain) | Target::Maker(_, chain) => chain.name.clone()
+    }
+  }
+
+  pub fn chain(&self) -> Option<&ChainConfig> {
+    match self {
+      Target::Exchange(_) => None,
+      Target::Aave(_, chain) | Target::Compound(_, chain) | Target::Maker(_, chain) => Some(chain)
+    }
+  }
+}
+
+pub fn from_config(cfg: &MonitorConfig) -> Vec<Target> {
+  let mut targets: Vec<Target> = Vec::new();
+  targets.extend(cfg.exchanges.iter().cloned().map(Target::Exchange));
+  // 引用了未配置的链的仓位跳过, 不影响其他仓位
+  for aave in &cfg.aave {
+    match cfg.chain(&aave.chain) {
+      Ok(chain) => targets.push(Target::Aave(aave.clone(), chain.clone())),
+      Err(e) => log::error!("aave {}: {}", aave.id, e)
+    }
+  }
+  for compound in &cfg.compound {
+    match cfg.chain(&compound.chain) {
+      Ok(chain) => targets.push(Target::Compound(compound.clone(), chain.clone())),
+      Err(e) => log::error!("compound {}: {}", compound.id, e)
+    }
+  }
+  for maker in &cfg.maker {
+    match cfg.chain(&maker.chain) {
+      Ok(chain) => targets.push(Target::Maker(maker.clone(), chain.clone())),
+      Err(e) => log::error!("maker {}: {}", maker.id, e)
+    }
+  }
+  return targets;
+}
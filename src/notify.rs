@@ -0,0 +1,309 @@
+pub mod telegram;
+pub mod webhook;
+pub mod email;
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+// INFO 普通状态, WARNING 达到追加保证金线, CRITICAL 接近清算, ACTION 已经执行了操作(还款/补仓/重发交易)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+  INFO,
+  WARNING,
+  CRITICAL,
+  ACTION
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Channels {
+  TELEGRAM,
+  WEBHOOK,
+  SLACK,
+  DISCORD,
+  EMAIL
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SmtpConfig {
+  pub host: String,
+  pub port: u16,
+  #[serde(default = "default_true")]
+  pub starttls: bool, // 本地测试用的 SMTP 服务可以关掉
+  #[serde(default)]
+  pub username: String, // 为空时不登录
+  pub from: String,
+  pub to: Vec<String>
+}
+
+fn default_true() -> bool { true }
+
+fn default_severities() -> Vec<Severity> {
+  vec![Severity::WARNING, Severity::CRITICAL, Severity::ACTION]
+}
+
+// 一个通知渠道, targets 为空时接收所有仓位的通知, 否则只接收列出的仓位(Target::id)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NotifierConfig {
+  pub name: String,
+  pub kind: Channels,
+  #[serde(default)]
+  pub url: String, // WEBHOOK/SLACK/DISCORD 的地址, TELEGRAM 的 api 地址(为空时用 https://api.telegram.org)
+  #[serde(default)]
+  pub chat_id: String, // TELEGRAM
+  #[serde(default)]
+  pub secret_env: String, // TELEGRAM 的 bot token 或 SMTP 密码所在的环境变量
+  #[serde(default)]
+  pub smtp: Option<SmtpConfig>,
+  #[serde(default = "default_severities")]
+  pub severities: Vec<Severity>,
+  #[serde(default)]
+  pub targets: Vec<String>,
+  #[serde(default)]
+  pub allowed_chats: Vec<String> // TELEGRAM 允许发命令的 chat id, 为空时只允许 chat_id
+}
+
+impl NotifierConfig {
+  pub fn accepts(&self, n: &Notification) -> bool {
+    self.severities.contains(&n.severity) && (self.targets.is_empty() || self.targets.contains(&n.target))
+  }
+
+  pub fn allows_chat(&self, chat_id: &str) -> bool {
+    chat_id == self.chat_id || self.allowed_chats.iter().any(|c| c == chat_id)
+  }
+
+  pub fn secret(&self) -> Result<String, String> {
+    std::env::var(&self.secret_env).map_err(|_| format!("notifier {}: env {} is not set", self.name, self.secret_env))
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Notification {
+  pub severity: Severity,
+  pub target: String,
+  pub title: String,
+  pub message: String,
+  pub at: i64
+}
+
+impl Notification {
+  pub fn new(severity: Severity, target: &str, title: &str, message: &str) -> Notification {
+    Notification {
+      severity,
+      target: String::from(target),
+      title: String::from(title),
+      message: String::from(message),
+      at: Local::now().timestamp()
+    }
+  }
+
+  pub fn text(&self) -> String {
+    format!("[{:?}] {}: {}\n{}", self.severity, self.target, self.title, self.message)
+  }
+}
+
+static NOTIFIERS: Mutex<Vec<NotifierConfig>> = Mutex::new(Vec::new());
+// 最近发出的通知, 给 http 接口查询
+static RECENT: Mutex<VecDeque<Notification>> = Mutex::new(VecDeque::new());
+static RECENT_SIZE: usize = 200_usize;
+
+pub fn init(notifiers: &[NotifierConfig]) {
+  *NOTIFIERS.lock().unwrap() = notifiers.to_vec();
+}
+
+pub async fn deliver(notifier: &NotifierConfig, n: &Notification) -> Result<(), String> {
+  match notifier.kind {
+    Channels::TELEGRAM => telegram::send(notifier, n).await,
+    Channels::WEBHOOK => webhook::send(notifier, n).await,
+    Channels::SLACK | Channels::DISCORD => webhook::send_chat(notifier, n).await,
+    Channels::EMAIL => email::send(notifier, n).await
+  }
+}
+
+// 先写日志, 再发给所有匹配的渠道, 单个渠道失败只记日志
+pub async fn send(n: Notification) {
+  match n.severity {
+    Severity::INFO | Severity::ACTION => log::info!("{}", n.text()),
+    Severity::WARNING => log::warn!("{}", n.text()),
+    Severity::CRITICAL => log::error!("{}", n.text())
+  }
+  {
+    let mut recent = RECENT.lock().unwrap();
+    if recent.len() >= RECENT_SIZE {
+      recent.pop_front();
+    }
+    recent.push_back(n.clone());
+  }
+  let notifiers: Vec<NotifierConfig> = NOTIFIERS.lock().unwrap().iter().filter(|c| c.accepts(&n)).cloned().collect();
+  for notifier in notifiers.iter() {
+    if let Err(err) = deliver(notifier, &n).await {
+      log::error!("notifier {} error: {}", notifier.name, err);
+    }
+  }
+}
+
+pub fn recent(severity: Option<Severity>) -> Vec<Notification> {
+  RECENT.lock().unwrap().iter().filter(|n| severity.map(|s| n.severity == s).unwrap_or(true)).cloned().collect()
+}
+
+pub async fn notify(severity: Severity, target: &str, title: &str, message: &str) {
+  send(Notification::new(severity, target, title, message)).await
+}
+
+// 给同步代码用, 不等待发送结果
+pub fn spawn(severity: Severity, target: &str, title: &str, message: &str) {
+  let n = Notification::new(severity, target, title, message);
+  tokio::spawn(send(n));
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::convert::Infallible;
+  use std::net::SocketAddr;
+  use std::sync::Arc;
+  use hyper::{ Body, Request, Response, Server };
+  use hyper::service::{ make_service_fn, service_fn };
+  use serde_json::{ json, Value };
+  use tokio::io::{ AsyncBufReadExt, AsyncWriteExt, BufReader };
+  use tokio::net::TcpListener;
+
+  type Captured = Arc<Mutex<Vec<(String, Value)>>>;
+
+  // 本地 http 服务, 记录每个请求的路径和 json body, 按固定状态码和内容返回
+  async fn http_stand_in(status: u16, reply: Value) -> (String, Captured) {
+    let captured: Captured = Arc::new(Mutex::new(Vec::new()));
+    let svc_captured = captured.clone();
+    let make_svc = make_service_fn(move |_| {
+      let captured = svc_captured.clone();
+      let reply = reply.clone();
+      async move {
+        Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+          let captured = captured.clone();
+          let reply = reply.clone();
+          async move {
+            let path = String::from(req.uri().path());
+            let bytes = hyper::body::to_bytes(req.into_body()).await.unwrap_or_default();
+            captured.lock().unwrap().push((path, serde_json::from_slice(&bytes).unwrap_or(Value::Null)));
+            Ok::<_, Infallible>(Response::builder().status(status).body(Body::from(reply.to_string())).unwrap())
+          }
+        }))
+      }
+    });
+    let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+    let url = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+    return (url, captured);
+  }
+
+  // 只处理一封邮件的 SMTP 服务, 返回收到的命令和 DATA 内容
+  async fn smtp_stand_in() -> (u16, tokio::task::JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = tokio::spawn(async move {
+      let (stream, _) = listener.accept().await.unwrap();
+      let (read, mut write) = stream.into_split();
+      let mut lines = BufReader::new(read).lines();
+      let mut received: Vec<String> = Vec::new();
+      write.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+      while let Ok(Some(line)) = lines.next_line().await {
+        received.push(line.clone());
+        let command = line.to_uppercase();
+        let reply: &[u8] = if command.starts_with("EHLO") {
+          b"250 localhost\r\n"
+        } else if command.starts_with("DATA") {
+          // DATA 之后一直读到单独一行 "."
+          write.write_all(b"354 end data with <CR><LF>.<CR><LF>\r\n").await.unwrap();
+          while let Ok(Some(data)) = lines.next_line().await {
+            if data == "." {
+              break;
+            }
+            received.push(data);
+          }
+          b"250 queued\r\n"
+        } else if command.starts_with("QUIT") {
+          write.write_all(b"221 bye\r\n").await.unwrap();
+          break;
+        } else {
+          b"250 ok\r\n"
+        };
+        write.write_all(reply).await.unwrap();
+      }
+      received
+    });
+    return (port, handle);
+  }
+
+  fn notifier(kind: Channels, url: &str) -> NotifierConfig {
+    NotifierConfig {
+      name: String::from("test"),
+      kind,
+      url: String::from(url),
+      chat_id: String::from("42"),
+      secret_env: String::from("NOTIFY_TEST_TOKEN"),
+      smtp: None,
+      severities: default_severities(),
+      targets: vec![],
+      allowed_chats: vec![]
+    }
+  }
+
+  fn notification() -> Notification {
+    Notification::new(Severity::CRITICAL, "binance:crv/usdt", "health factor low", "hf 1.05")
+  }
+
+  #[tokio::test]
+  async fn telegram_send_message() {
+    std::env::set_var("NOTIFY_TEST_TOKEN", "123:abc");
+    let (url, captured) = http_stand_in(200, json!({ "ok": true, "result": {} })).await;
+    deliver(&notifier(Channels::TELEGRAM, &url), &notification()).await.unwrap();
+    let (path, body) = captured.lock().unwrap()[0].clone();
+    assert_eq!(path, "/bot123:abc/sendMessage");
+    assert_eq!(body["chat_id"], "42");
+    assert_eq!(body["text"], "[CRITICAL] binance:crv/usdt: health factor low\nhf 1.05");
+    assert_eq!(telegram::target_of(body["text"].as_str().unwrap()), Some(String::from("binance:crv/usdt")));
+
+    let (url, _) = http_stand_in(200, json!({ "ok": false, "description": "Bad Request: chat not found" })).await;
+    let err = deliver(&notifier(Channels::TELEGRAM, &url), &notification()).await.unwrap_err();
+    assert!(err.contains("chat not found"), "{}", err);
+  }
+
+  #[tokio::test]
+  async fn webhook_slack_and_discord_bodies() {
+    let (url, captured) = http_stand_in(200, json!({})).await;
+    deliver(&notifier(Channels::WEBHOOK, &format!("{}/hook", url)), &notification()).await.unwrap();
+    deliver(&notifier(Channels::SLACK, &format!("{}/slack", url)), &notification()).await.unwrap();
+    deliver(&notifier(Channels::DISCORD, &format!("{}/discord", url)), &notification()).await.unwrap();
+    let captured = captured.lock().unwrap().clone();
+    assert_eq!(captured[0].0, "/hook");
+    assert_eq!(captured[0].1["severity"], "CRITICAL");
+    assert_eq!(captured[0].1["target"], "binance:crv/usdt");
+    assert_eq!(captured[1].1["text"], notification().text());
+    assert_eq!(captured[2].1["content"], notification().text());
+
+    let (url, _) = http_stand_in(500, json!({})).await;
+    let err = deliver(&notifier(Channels::WEBHOOK, &url), &notification()).await.unwrap_err();
+    assert!(err.contains("500"), "{}", err);
+  }
+
+  #[tokio::test]
+  async fn email_over_smtp() {
+    let (port, handle) = smtp_stand_in().await;
+    let mut cfg = notifier(Channels::EMAIL, "");
+    cfg.smtp = Some(SmtpConfig {
+      host: String::from("127.0.0.1"),
+      port,
+      starttls: false,
+      username: String::new(),
+      from: String::from("monitor@example.com"),
+      to: vec![String::from("ops@example.com")]
+    });
+    deliver(&cfg, &notification()).await.unwrap();
+    let received = handle.await.unwrap();
+    assert!(received.iter().any(|l| l == "MAIL FROM:<monitor@example.com>"), "{:?}", received);
+    assert!(received.iter().any(|l| l == "RCPT TO:<ops@example.com>"), "{:?}", received);
+    assert!(received.iter().any(|l| l == "Subject: [CRITICAL] binance:crv/usdt: health factor low"), "{:?}", received);
+    assert!(received.iter().any(|l| l == "hf 1.05"), "{:?}", received);
+  }
+}
@@ -0,0 +1,26 @@
+use lettre::{ AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor };
+use lettre::transport::smtp::authentication::Credentials;
+use super::{ NotifierConfig, Notification };
+
+pub async fn send(notifier: &NotifierConfig, n: &Notification) -> Result<(), String> {
+  let smtp = notifier.smtp.as_ref().ok_or(format!("notifier {} has no smtp config", notifier.name))?;
+  let mut builder = Message::builder()
+    .from(smtp.from.parse().map_err(|e| format!("invalid from {}: {}", smtp.from, e))?)
+    .subject(format!("[{:?}] {}: {}", n.severity, n.target, n.title));
+  for to in smtp.to.iter() {
+    builder = builder.to(to.parse().map_err(|e| format!("invalid to {}: {}", to, e))?);
+  }
+  let message = builder.body(n.message.clone()).map_err(|e| format!("build email error: {}", e))?;
+
+  let mut transport = if smtp.starttls {
+    AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.host).map_err(|e| format!("smtp {} error: {}", smtp.host, e))?
+  } else {
+    AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&smtp.host)
+  };
+  transport = transport.port(smtp.port);
+  if !smtp.username.is_empty() {
+    transport = transport.credentials(Credentials::new(smtp.username.clone(), notifier.secret()?));
+  }
+  transport.build().send(message).await.map_err(|e| format!("smtp send error: {}", e))?;
+  return Ok(());
+}
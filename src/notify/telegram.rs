@@ -0,0 +1,44 @@
+use serde_json::{ json, Value };
+use super::{ NotifierConfig, Notification };
+use crate::util;
+
+static TELEGRAM_API: &str = "https://api.telegram.org";
+// getUpdates 长轮询的等待时间
+static POLL_TIMEOUT: u64 = 30_u64;
+
+pub fn api_base(notifier: &NotifierConfig) -> &str {
+  if notifier.url.is_empty() { TELEGRAM_API } else { notifier.url.trim_end_matches('/') }
+}
+
+async fn call(notifier: &NotifierConfig, method: &str, body: &Value) -> Result<Value, String> {
+  let token = notifier.secret()?;
+  let url = format!("{}/bot{}/{}", api_base(notifier), token, method);
+  let client = reqwest::Client::new();
+  let text = util::handle_body(client.post(url).json(body).send().await).await?;
+  let resp: Value = serde_json::from_str(&text).map_err(|e| format!("telegram response error: {}", e))?;
+  if resp["ok"] != true {
+    return Err(format!("telegram {} error: {}", method, text));
+  }
+  return Ok(resp["result"].clone());
+}
+
+pub async fn send_text(notifier: &NotifierConfig, chat_id: &str, text: &str) -> Result<(), String> {
+  call(notifier, "sendMessage", &json!({ "chat_id": chat_id, "text": text })).await?;
+  return Ok(());
+}
+
+pub async fn send(notifier: &NotifierConfig, n: &Notification) -> Result<(), String> {
+  send_text(notifier, &notifier.chat_id, &n.text()).await
+}
+
+// 返回 offset 之后的消息, 调用方处理完后用最后一个 update_id + 1 作为下一次的 offset
+pub async fn get_updates(notifier: &NotifierConfig, offset: i64) -> Result<Vec<Value>, String> {
+  let result = call(notifier, "getUpdates", &json!({ "offset": offset, "timeout": POLL_TIMEOUT, "allowed_updates": ["message"] })).await?;
+  return Ok(result.as_array().cloned().unwrap_or_default());
+}
+
+// 从告警消息 "[CRITICAL] target: title" 里取出 target
+pub fn target_of(text: &str) -> Option<String> {
+  let rest = text.split_once("] ")?.1;
+  return Some(String::from(rest.split_once(": ")?.0));
+}
@@ -0,0 +1,25 @@
+use serde_json::json;
+use super::{ Channels, NotifierConfig, Notification };
+
+async fn post(url: &str, body: &serde_json::Value) -> Result<(), String> {
+  let client = reqwest::Client::new();
+  let resp = client.post(url).json(body).send().await.map_err(|e| format!("[REQWEST ERROR]: {}", e))?;
+  if !resp.status().is_success() {
+    return Err(format!("webhook {} status {}", url, resp.status()));
+  }
+  return Ok(());
+}
+
+// 通用 webhook, 直接 POST 通知本身
+pub async fn send(notifier: &NotifierConfig, n: &Notification) -> Result<(), String> {
+  post(&notifier.url, &json!(n)).await
+}
+
+// Slack 用 text 字段, Discord 用 content 字段
+pub async fn send_chat(notifier: &NotifierConfig, n: &Notification) -> Result<(), String> {
+  let body = match notifier.kind {
+    Channels::DISCORD => json!({ "content": n.text() }),
+    _ => json!({ "text": n.text() })
+  };
+  post(&notifier.url, &body).await
+}
@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+use std::fs::{ self, File, OpenOptions };
+use std::io::Write;
+use std::path::{ Path, PathBuf };
+use std::sync::Mutex;
+use std::time::{ Duration, SystemTime };
+use chrono::{ DateTime, Utc };
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use crate::engine::exchange::Exchange;
+use crate::engine::exchange::types::{ DepthInfo, TradeInfo };
+
+// 把收到的深度和成交按 replay 的 csv 格式写入 gzip 文件, 事后用 backtest 回放.
+// 每个交易对每个周期一个文件: {venue}-{symbol}{currency}-{YYYYMMDDHH}.csv.gz, 时间是 UTC 的周期开始时间
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecorderConfig {
+  pub dir: String, // 为空时不记录
+  pub symbols: Vec<String>, // 记录的交易对, 如 crv/usdt, 为空时记录所有配置的交易所仓位
+  pub interval: u64, // 单独拉取成交的间隔, 秒, 0 表示只记录监控循环读到的深度
+  pub rotate_hours: i64, // 每个文件覆盖的小时数
+  pub retention_days: i64 // 超过这个天数的文件会被删除, 0 表示一直保留
+}
+
+impl ::std::default::Default for RecorderConfig {
+  fn default() -> Self {
+    Self {
+      dir: String::new(),
+      symbols: vec![],
+      interval: 5_u64,
+      rotate_hours: 1_i64,
+      retention_days: 30_i64
+    }
+  }
+}
+
+struct Writer {
+  period: i64,
+  encoder: GzEncoder<File>
+}
+
+struct Recorder {
+  cfg: RecorderConfig,
+  writers: HashMap<String, Writer>,
+  last_trade: HashMap<String, u64> // 每个交易对已经写入的最大成交 id, 拉取的成交有重叠
+}
+
+static RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+
+pub fn init(cfg: &RecorderConfig) -> Result<(), String> {
+  let mut guard = RECORDER.lock().unwrap();
+  // 重新初始化前把旧文件写完整
+  if let Some(old) = guard.as_mut() {
+    finish_all(old);
+  }
+  if cfg.dir.is_empty() {
+    *guard = None;
+    return Ok(());
+  }
+  fs::create_dir_all(&cfg.dir).map_err(|e| format!("create recorder dir {} error: {}", cfg.dir, e))?;
+  *guard = Some(Recorder { cfg: cfg.clone(), writers: HashMap::new(), last_trade: HashMap::new() });
+  return Ok(());
+}
+
+fn finish_all(recorder: &mut Recorder) {
+  for (key, writer) in recorder.writers.drain() {
+    if let Err(err) = writer.encoder.finish() {
+      log::error!("recorder {} finish error: {}", key, err);
+    }
+  }
+}
+
+fn key(ex: &Exchange) -> String {
+  return format!("{:?}-{}{}", ex.name, ex.symbol.to_lowercase(), ex.currency.to_lowercase());
+}
+
+fn wanted(cfg: &RecorderConfig, ex: &Exchange) -> bool {
+  let pair = format!("{}/{}", ex.symbol, ex.currency);
+  return cfg.symbols.is_empty() || cfg.symbols.iter().any(|s| s.eq_ignore_ascii_case(&pair));
+}
+
+fn now_millis() -> i64 {
+  return Utc::now().timestamp_millis();
+}
+
+// 同一个周期重启后不追加到旧文件, 旧文件结尾可能不完整, 追加的内容会读不到
+fn open(dir: &str, key: &str, period: i64) -> std::io::Result<File> {
+  let hour = DateTime::from_timestamp_millis(period).unwrap_or_default().format("%Y%m%d%H");
+  let mut path = PathBuf::from(dir).join(format!("{}-{}.csv.gz", key, hour));
+  let mut n = 1;
+  while path.exists() {
+    path = PathBuf::from(dir).join(format!("{}-{}.{}.csv.gz", key, hour, n));
+    n += 1;
+  }
+  log::info!("recorder writing {}", path.display());
+  return OpenOptions::new().write(true).create_new(true).open(path);
+}
+
+fn prune(dir: &str, retention_days: i64) {
+  if retention_days <= 0 {
+    return;
+  }
+  let before = SystemTime::now() - Duration::from_secs(retention_days as u64 * 86400);
+  let entries = match fs::read_dir(dir) {
+    Ok(entries) => entries,
+    Err(err) => {
+      log::error!("recorder read {} error: {}", dir, err);
+      return;
+    }
+  };
+  for entry in entries.flatten() {
+    let path = entry.path();
+    let old = entry.metadata().and_then(|m| m.modified()).map(|t| t < before).unwrap_or(false);
+    if old && path.to_string_lossy().ends_with(".csv.gz") {
+      match fs::remove_file(&path) {
+        Ok(_) => log::info!("recorder removed {}", path.display()),
+        Err(err) => log::error!("recorder remove {} error: {}", path.display(), err)
+      }
+    }
+  }
+}
+
+fn write(recorder: &mut Recorder, key: &str, ts: i64, line: &str) -> std::io::Result<()> {
+  let span = recorder.cfg.rotate_hours.max(1) * 3600 * 1000;
+  let period = ts - ts.rem_euclid(span);
+  let rotate = recorder.writers.get(key).map(|w| w.period != period).unwrap_or(true);
+  if rotate {
+    if let Some(old) = recorder.writers.remove(key) {
+      old.encoder.finish()?;
+      prune(&recorder.cfg.dir, recorder.cfg.retention_days);
+    }
+    let file = open(&recorder.cfg.dir, key, period)?;
+    recorder.writers.insert(String::from(key), Writer { period, encoder: GzEncoder::new(file, Compression::default()) });
+  }
+  let writer = recorder.writers.get_mut(key).unwrap();
+  return writer.encoder.write_all(line.as_bytes());
+}
+
+// 写完一批后 flush, 进程被杀掉时已经 flush 的部分仍然可以解压
+fn record(ex: &Exchange, lines: Vec<(i64, String)>) {
+  let mut guard = RECORDER.lock().unwrap();
+  let recorder = match guard.as_mut() {
+    Some(recorder) => recorder,
+    None => return
+  };
+  if lines.is_empty() || !wanted(&recorder.cfg, ex) {
+    return;
+  }
+  let key = key(ex);
+  let mut res: std::io::Result<()> = Ok(());
+  for (ts, line) in lines.iter() {
+    res = write(recorder, &key, *ts, line);
+    if res.is_err() {
+      break;
+    }
+  }
+  if let Some(writer) = recorder.writers.get_mut(&key) {
+    res = res.and_then(|_| writer.encoder.flush());
+  }
+  if let Err(err) = res {
+    log::error!("recorder {} error: {}", key, err);
+    // 下次写入时重新打开文件
+    recorder.writers.remove(&key);
+  }
+}
+
+fn levels(levels: &[[f64;2]]) -> String {
+  return levels.iter().map(|l| format!("{}:{}", l[0], l[1])).collect::<Vec<String>>().join("|");
+}
+
+// 交易所没有返回时间时用收到的时间
+pub fn depth(ex: &Exchange, depth: &DepthInfo) {
+  let ts = if depth.ts > 0 { depth.ts } else { now_millis() };
+  record(ex, vec![(ts, format!("{},depth,{},{}\n", ts, levels(&depth.tick.bids), levels(&depth.tick.asks)))]);
+}
+
+// 成交按 id 去重, 可以重复传入同一批
+pub fn trades(ex: &Exchange, trades: &[TradeInfo]) {
+  let key = key(ex);
+  let lines: Vec<(i64, String)> = {
+    let mut guard = RECORDER.lock().unwrap();
+    let recorder = match guard.as_mut() {
+      Some(recorder) => recorder,
+      None => return
+    };
+    let last = recorder.last_trade.get(&key).cloned().unwrap_or(0);
+    let fresh: Vec<&TradeInfo> = trades.iter().filter(|t| t.id > last).collect();
+    if let Some(max) = fresh.iter().map(|t| t.id).max() {
+      recorder.last_trade.insert(key, max);
+    }
+    fresh.iter().map(|t| {
+      let ts = if t.ts > 0 { t.ts } else { now_millis() };
+      (ts, format!("{},trade,{},{},{}\n", ts, t.price, t.volume, if t.buy { "buy" } else { "sell" }))
+    }).collect()
+  };
+  record(ex, lines);
+}
+
+// 单独拉取配置的交易对的成交, 不依赖监控循环的间隔; 深度只记录监控循环读到的, 不重复请求
+pub async fn run(exchanges: Vec<Exchange>, cfg: RecorderConfig) {
+  if cfg.dir.is_empty() || cfg.interval == 0 {
+    return;
+  }
+  let targets: Vec<Exchange> = exchanges.into_iter().filter(|ex| wanted(&cfg, ex)).collect();
+  loop {
+    for ex in targets.iter() {
+      match ex.trades().await {
+        Ok(list) => trades(ex, &list),
+        Err(err) => log::warn!("recorder {} trades error: {}", key(ex), err)
+      }
+    }
+    tokio::time::sleep(Duration::from_secs(cfg.interval)).await;
+  }
+}
+
+// 目录下某个交易对的所有文件, 按文件名即时间排序, 给 backtest 使用.
+// 进程被杀掉时最后一个文件结尾不完整, replay 读取时跳过
+pub fn files(dir: &Path, pair: &str) -> Result<Vec<PathBuf>, String> {
+  let pair = pair.replace('/', "").to_lowercase();
+  let entries = fs::read_dir(dir).map_err(|e| format!("read {} error: {}", dir.display(), e))?;
+  let mut res: Vec<PathBuf> = entries.flatten().map(|e| e.path()).filter(|p| {
+    let name = p.file_name().map(|n| n.to_string_lossy().to_lowercase()).unwrap_or_default();
+    name.ends_with(".csv.gz") && name.split('-').nth(1) == Some(pair.as_str())
+  }).collect();
+  res.sort();
+  return Ok(res);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::engine::exchange::types::{ Exchanges, Tick };
+  use crate::engine::replay::{ self, MarketEvent };
+  use crate::engine::exchange::mock::MockExchange;
+
+  fn exchange(symbol: &str) -> Exchange {
+    return Exchange {
+      name: Exchanges::BINANCE,
+      symbol: String::from(symbol),
+      currency: String::from("usdt"),
+      host: String::from("127.0.0.1"),
+      protocol: String::from("http"),
+      config: String::from("binance.test"),
+      protect: false,
+      subaccount: None
+    };
+  }
+
+  // 两个测试都会替换全局的 RECORDER, 不能同时运行
+  static LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+  fn trade(id: u64, ts: i64) -> TradeInfo {
+    return TradeInfo { id, price: 0.5, volume: 10_f64, buy: id != 1 && id != 3, ts };
+  }
+
+  #[test]
+  fn record_rotate_and_replay() {
+    let _lock = LOCK.blocking_lock();
+    let dir = std::env::temp_dir().join(format!("recorder-test-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let cfg = RecorderConfig { dir: dir.to_string_lossy().to_string(), symbols: vec![String::from("CRV/USDT")], ..RecorderConfig::default() };
+    init(&cfg).unwrap();
+    let crv = exchange("crv");
+    let hour = 1621382400000_i64;
+    let book = DepthInfo { tick: Tick { bids: vec![[2.19, 10_f64], [2.18, 20_f64]], asks: vec![[2.21, 5_f64]] }, ts: hour + 1000 };
+    depth(&crv, &book);
+    trades(&crv, &[trade(1, hour + 2000), trade(2, hour + 3000)]);
+    // 重叠的一批只写入新的成交, 跨小时换文件
+    trades(&crv, &[trade(2, hour + 3000), trade(3, hour + 3600 * 1000)]);
+    // 没有配置的交易对不记录
+    depth(&exchange("eth"), &book);
+    // 第二个文件不调用 close, 模拟进程被杀掉
+    let files = files(&dir, "crv/usdt").unwrap();
+    assert_eq!(files.len(), 2);
+    assert!(files[0].ends_with("BINANCE-crvusdt-2021051900.csv.gz"));
+    let events = replay::load_all(&files).unwrap();
+    assert_eq!(events.len(), 4);
+    assert_eq!(events[0], MarketEvent::Depth { ts: hour + 1000, bids: book.tick.bids.clone(), asks: book.tick.asks.clone() });
+    assert_eq!(events[3], MarketEvent::Trade { ts: hour + 3600 * 1000, price: 0.5, volume: 10_f64, buy: false });
+    init(&RecorderConfig::default()).unwrap();
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  // 深度由监控循环记录, run 只拉取成交
+  #[tokio::test]
+  async fn run_fetches_trades_only() {
+    let _lock = LOCK.lock().await;
+    let mock = MockExchange::start(Exchanges::BINANCE).await;
+    let dir = std::env::temp_dir().join(format!("recorder-run-test-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let cfg = RecorderConfig { dir: dir.to_string_lossy().to_string(), symbols: vec![String::from("ETH/USDT")], interval: 1, ..RecorderConfig::default() };
+    init(&cfg).unwrap();
+    let task = tokio::spawn(run(vec![mock.exchange("eth", "usdt")], cfg));
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    task.abort();
+    assert_eq!(mock.requests("/api/v3/trades").len(), 1);
+    assert!(mock.requests("/api/v3/depth").is_empty());
+    let events = replay::load_all(&files(&dir, "eth/usdt").unwrap()).unwrap();
+    assert_eq!(events.len(), 2);
+    assert!(events.iter().all(|e| matches!(e, MarketEvent::Trade { .. })));
+    init(&RecorderConfig::default()).unwrap();
+    let _ = fs::remove_dir_all(&dir);
+  }
+}
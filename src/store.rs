@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use chrono::Local;
+use rusqlite::{ params, Connection };
+use serde::{Deserialize, Serialize};
+use crate::engine::exchange::types::Balances;
+use crate::engine::position::LoanPosition;
+
+// 本地 SQLite 历史记录: 仓位和余额快照, 告警状态变化和保护操作, 进程重启后可以追查
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoreConfig {
+  pub path: String, // 为空时不记录
+  pub snapshot_interval: i64, // 同一个仓位(或者交易所账户的余额)两次快照的最小间隔, 秒
+  pub retention_days: i64 // 超过这个天数的记录会被删除, 0 表示一直保留
+}
+
+impl ::std::default::Default for StoreConfig {
+  fn default() -> Self {
+    Self {
+      path: String::from("crypto-loan-monitor.db"),
+      snapshot_interval: 60_i64,
+      retention_days: 90_i64
+    }
+  }
+}
+
+// 按顺序执行, 已经执行到第几个记录在 PRAGMA user_version 里, 只能追加不能修改
+static MIGRATIONS: [&str; 3] = [
+  "CREATE TABLE snapshots (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    at INTEGER NOT NULL,
+    target TEXT NOT NULL,
+    venue TEXT NOT NULL,
+    base TEXT NOT NULL,
+    ltv REAL NOT NULL,
+    liquidation_ltv REAL NOT NULL,
+    health_factor REAL NOT NULL,
+    collateral_value REAL NOT NULL,
+    debt_value REAL NOT NULL,
+    liquidation_price REAL,
+    position TEXT NOT NULL
+  );
+  CREATE INDEX snapshots_target_at ON snapshots (target, at);
+  CREATE TABLE alerts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    at INTEGER NOT NULL,
+    target TEXT NOT NULL,
+    condition TEXT NOT NULL,
+    transition TEXT NOT NULL,
+    severity TEXT NOT NULL,
+    message TEXT NOT NULL
+  );
+  CREATE INDEX alerts_at ON alerts (at);
+  CREATE TABLE actions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    at INTEGER NOT NULL,
+    target TEXT NOT NULL,
+    action TEXT NOT NULL,
+    request TEXT NOT NULL,
+    response TEXT NOT NULL,
+    outcome TEXT NOT NULL
+  );
+  CREATE INDEX actions_at ON actions (at);",
+  "CREATE TABLE journal (
+    id TEXT PRIMARY KEY,
+    kind TEXT NOT NULL,
+    state TEXT NOT NULL,
+    status TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+  );
+  CREATE INDEX journal_status ON journal (status);",
+  // 没有债务时健康因子是无穷大, 抵押物为 0 时 ltv 不是有限值, 都记为 NULL
+  "CREATE TABLE snapshots_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    at INTEGER NOT NULL,
+    target TEXT NOT NULL,
+    venue TEXT NOT NULL,
+    base TEXT NOT NULL,
+    ltv REAL,
+    liquidation_ltv REAL NOT NULL,
+    health_factor REAL,
+    collateral_value REAL NOT NULL,
+    debt_value REAL NOT NULL,
+    liquidation_price REAL,
+    position TEXT NOT NULL
+  );
+  INSERT INTO snapshots_new SELECT * FROM snapshots;
+  DROP TABLE snapshots;
+  ALTER TABLE snapshots_new RENAME TO snapshots;
+  CREATE INDEX snapshots_target_at ON snapshots (target, at);
+  CREATE TABLE balances (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    at INTEGER NOT NULL,
+    account_key TEXT NOT NULL,
+    venue TEXT NOT NULL,
+    account TEXT NOT NULL,
+    asset TEXT NOT NULL,
+    free REAL NOT NULL,
+    locked REAL NOT NULL,
+    borrowed REAL NOT NULL,
+    interest REAL NOT NULL
+  );
+  CREATE INDEX balances_account_key_at ON balances (account_key, at);"
+];
+
+// 每隔多久清理一次过期记录, 秒
+static PRUNE_INTERVAL: i64 = 3600_i64;
+
+struct Store {
+  conn: Connection,
+  cfg: StoreConfig,
+  last_snapshot: HashMap<String, i64>,
+  last_balances: HashMap<String, i64>,
+  last_prune: i64
+}
+
+static STORE: Mutex<Option<Store>> = Mutex::new(None);
+
+fn migrate(conn: &mut Connection) -> Result<(), String> {
+  let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0)).map_err(|e| format!("read schema version error: {}", e))?;
+  for (i, sql) in MIGRATIONS.iter().enumerate().skip(version) {
+    let tx = conn.transaction().map_err(|e| format!("migration {} error: {}", i + 1, e))?;
+    tx.execute_batch(sql).map_err(|e| format!("migration {} error: {}", i + 1, e))?;
+    tx.execute_batch(&format!("PRAGMA user_version = {}", i + 1)).map_err(|e| format!("migration {} error: {}", i + 1, e))?;
+    tx.commit().map_err(|e| format!("migration {} error: {}", i + 1, e))?;
+    log::info!("store migrated to version {}", i + 1);
+  }
+  return Ok(());
+}
+
+fn open(cfg: &StoreConfig) -> Result<Store, String> {
+  let mut conn = Connection::open(&cfg.path).map_err(|e| format!("open {} error: {}", cfg.path, e))?;
+  // 操作日志要在执行每一步之前落盘, 使用 FULL 保证断电时已经提交的记录也不会丢
+  conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = FULL;").map_err(|e| format!("open {} error: {}", cfg.path, e))?;
+  migrate(&mut conn)?;
+  return Ok(Store { conn, cfg: cfg.clone(), last_snapshot: HashMap::new(), last_balances: HashMap::new(), last_prune: 0 });
+}
+
+pub fn init(cfg: &StoreConfig) -> Result<(), String> {
+  if cfg.path.is_empty() {
+    return Ok(());
+  }
+  *STORE.lock().unwrap() = Some(open(cfg)?);
+  return Ok(());
+}
+
+// 没有初始化时什么都不做, 写入失败只记日志, 不影响监控
+fn with_store(f: impl FnOnce(&mut Store) -> rusqlite::Result<()>) {
+  let mut guard = STORE.lock().unwrap();
+  if let Some(store) = guard.as_mut() {
+    if let Err(err) = f(store) {
+      log::error!("store error: {}", err);
+    }
+  }
+}
+
+fn prune(store: &mut Store, now: i64) -> rusqlite::Result<()> {
+  if store.cfg.retention_days <= 0 || now - store.last_prune < PRUNE_INTERVAL {
+    return Ok(());
+  }
+  store.last_prune = now;
+  let before = now - store.cfg.retention_days * 86400;
+  let mut deleted = 0_usize;
+  for table in ["snapshots", "balances", "alerts", "actions"] {
+    deleted += store.conn.execute(&format!("DELETE FROM {} WHERE at < ?1", table), params![before])?;
+  }
+  if deleted > 0 {
+    log::info!("store pruned {} records older than {} days", deleted, store.cfg.retention_days);
+  }
+  return Ok(());
+}
+
+// NaN 和无穷大存为 NULL
+fn finite(v: f64) -> Option<f64> {
+  if v.is_finite() { Some(v) } else { None }
+}
+
+fn insert_snapshot(store: &mut Store, target: &str, pos: &LoanPosition, now: i64) -> rusqlite::Result<()> {
+  let last = store.last_snapshot.get(target).cloned().unwrap_or(0);
+  if now - last < store.cfg.snapshot_interval {
+    return Ok(());
+  }
+  store.last_snapshot.insert(String::from(target), now);
+  let json = serde_json::to_string(pos).unwrap_or_default();
+  store.conn.execute(
+    "INSERT INTO snapshots (at, target, venue, base, ltv, liquidation_ltv, health_factor, collateral_value, debt_value, liquidation_price, position)
+     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+    params![now, target, pos.venue, pos.base, finite(pos.ltv), pos.liquidation_ltv, finite(pos.health_factor), pos.collateral_value, pos.debt_value, pos.liquidation_price.and_then(finite), json]
+  )?;
+  prune(store, now)
+}
+
+pub fn snapshot(target: &str, pos: &LoanPosition) {
+  let now = Local::now().timestamp();
+  with_store(|store| insert_snapshot(store, target, pos, now));
+}
+
+fn balances_due_at(store: &Store, account_key: &str, now: i64) -> bool {
+  now - store.last_balances.get(account_key).cloned().unwrap_or(0) >= store.cfg.snapshot_interval
+}
+
+// 是否需要读取余额做快照, 没有配置 store 时不读取
+pub fn balances_due(account_key: &str) -> bool {
+  let now = Local::now().timestamp();
+  STORE.lock().unwrap().as_ref().map(|store| balances_due_at(store, account_key, now)).unwrap_or(false)
+}
+
+fn insert_balances(store: &mut Store, account_key: &str, balances: &Balances, now: i64) -> rusqlite::Result<()> {
+  if !balances_due_at(store, account_key, now) {
+    return Ok(());
+  }
+  store.last_balances.insert(String::from(account_key), now);
+  let tx = store.conn.transaction()?;
+  for b in balances.items.iter() {
+    tx.execute(
+      "INSERT INTO balances (at, account_key, venue, account, asset, free, locked, borrowed, interest) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+      params![now, account_key, format!("{:?}", balances.venue), format!("{:?}", b.account), b.asset, b.free, b.locked, b.borrowed, b.interest]
+    )?;
+  }
+  tx.commit()?;
+  prune(store, now)
+}
+
+// account_key 区分同一个交易所的不同账户, 同一个账户的多个仓位只记录一次
+pub fn balances(account_key: &str, balances: &Balances) {
+  let now = Local::now().timestamp();
+  with_store(|store| insert_balances(store, account_key, balances, now));
+}
+
+pub fn alert(target: &str, condition: &str, transition: &str, severity: &str, message: &str) {
+  let now = Local::now().timestamp();
+  with_store(|store| {
+    store.conn.execute(
+      "INSERT INTO alerts (at, target, condition, transition, severity, message) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+      params![now, target, condition, transition, severity, message]
+    )?;
+    Ok(())
+  });
+}
+
+pub fn action(target: &str, action: &str, request: &str, response: &str, outcome: &str) {
+  let now = Local::now().timestamp();
+  with_store(|store| {
+    store.conn.execute(
+      "INSERT INTO actions (at, target, action, request, response, outcome) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+      params![now, target, action, request, response, outcome]
+    )?;
+    Ok(())
+  });
+}
+
+pub fn enabled() -> bool {
+  STORE.lock().unwrap().is_some()
+}
+
+// 多步骤操作的日志, 每一步执行前后都写入完整状态, status 为 running 的在启动时恢复.
+// 没有配置 store 时返回错误, 调用方决定是否在没有日志的情况下继续
+pub fn journal_write(id: &str, kind: &str, state: &str, status: &str) -> Result<(), String> {
+  let now = Local::now().timestamp();
+  let mut guard = STORE.lock().unwrap();
+  let store = guard.as_mut().ok_or(format!("journal {} error: store is disabled, set store.path", id))?;
+  store.conn.execute(
+    "INSERT INTO journal (id, kind, state, status, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?5)
+     ON CONFLICT(id) DO UPDATE SET state = ?3, status = ?4, updated_at = ?5",
+    params![id, kind, state, status, now]
+  ).map_err(|e| format!("journal {} error: {}", id, e))?;
+  return Ok(());
+}
+
+// 还没有结束的操作的状态, 按创建时间排序
+pub fn journal_open(kind: &str) -> Vec<String> {
+  let guard = STORE.lock().unwrap();
+  let store = match guard.as_ref() {
+    Some(store) => store,
+    None => return Vec::new()
+  };
+  let rows = store.conn.prepare("SELECT state FROM journal WHERE kind = ?1 AND status = 'running' ORDER BY created_at")
+    .and_then(|mut stmt| stmt.query_map(params![kind], |row| row.get::<_, String>(0))?.collect::<rusqlite::Result<Vec<String>>>());
+  match rows {
+    Ok(rows) => rows,
+    Err(err) => {
+      log::error!("read journal error: {}", err);
+      Vec::new()
+    }
+  }
+}
+
+// 测试用: 全局 store 换成临时目录下的数据库, 返回的锁释放之前其他测试不能替换
+#[cfg(test)]
+static TEST_LOCK: std::sync::OnceLock<tokio::sync::Mutex<()>> = std::sync::OnceLock::new();
+
+#[cfg(test)]
+pub async fn init_temp(name: &str) -> tokio::sync::MutexGuard<'static, ()> {
+  let lock = TEST_LOCK.get_or_init(|| tokio::sync::Mutex::new(())).lock().await;
+  *STORE.lock().unwrap() = Some(open(&tests::temp_config(name)).unwrap());
+  return lock;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::engine::exchange::types::{ AccountType, Balance, Exchanges };
+
+  // 系统临时目录下的数据库文件, 每个测试用不同的名字
+  pub fn temp_config(name: &str) -> StoreConfig {
+    let path = std::env::temp_dir().join(format!("monitor-store-{}-{}.db", std::process::id(), name));
+    for suffix in ["", "-wal", "-shm"] {
+      let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+    StoreConfig { path: path.display().to_string(), ..Default::default() }
+  }
+
+  fn count(store: &Store, table: &str) -> i64 {
+    store.conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get(0)).unwrap()
+  }
+
+  #[test]
+  fn migrates_old_snapshots_and_stores_non_finite_as_null() {
+    let cfg = temp_config("migrate");
+    {
+      let conn = Connection::open(&cfg.path).unwrap();
+      conn.execute_batch(MIGRATIONS[0]).unwrap();
+      conn.execute_batch(MIGRATIONS[1]).unwrap();
+      conn.execute_batch("PRAGMA user_version = 2").unwrap();
+      conn.execute(
+        "INSERT INTO snapshots (at, target, venue, base, ltv, liquidation_ltv, health_factor, collateral_value, debt_value, liquidation_price, position)
+         VALUES (1, 'old', 'BINANCE', 'usdt', 0.5, 0.8, 1.6, 1000, 500, NULL, '{}')", []
+      ).unwrap();
+    }
+    let mut store = open(&cfg).unwrap();
+    let version: usize = store.conn.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap();
+    assert_eq!(version, MIGRATIONS.len());
+    assert_eq!(count(&store, "snapshots"), 1);
+
+    let mut pos = LoanPosition::new(String::from("BINANCE"), String::from("CRV"), String::from("usdt"), 0_f64, 0_f64, 0.8);
+    pos.ltv = f64::NAN;
+    pos.health_factor = f64::INFINITY;
+    insert_snapshot(&mut store, "new", &pos, 100).unwrap();
+    let (ltv, health_factor): (Option<f64>, Option<f64>) = store.conn.query_row(
+      "SELECT ltv, health_factor FROM snapshots WHERE target = 'new'", [], |row| Ok((row.get(0)?, row.get(1)?))
+    ).unwrap();
+    assert_eq!((ltv, health_factor), (None, None));
+    // 间隔内的快照跳过
+    insert_snapshot(&mut store, "new", &pos, 130).unwrap();
+    assert_eq!(count(&store, "snapshots"), 2);
+  }
+
+  #[test]
+  fn balances_are_throttled_per_account() {
+    let cfg = temp_config("balances");
+    let mut store = open(&cfg).unwrap();
+    let balances = Balances {
+      venue: Exchanges::BINANCE,
+      items: vec![
+        Balance { account: AccountType::SPOT, asset: String::from("usdt"), free: 100_f64, locked: 0_f64, borrowed: 0_f64, interest: 0_f64 },
+        Balance { account: AccountType::MARGIN, asset: String::from("crv"), free: 10_f64, locked: 0_f64, borrowed: 2_f64, interest: 0.1 }
+      ]
+    };
+    insert_balances(&mut store, "BINANCE:a", &balances, 1000).unwrap();
+    insert_balances(&mut store, "BINANCE:a", &balances, 1030).unwrap();
+    assert_eq!(count(&store, "balances"), 2);
+    assert!(!balances_due_at(&store, "BINANCE:a", 1030));
+    assert!(balances_due_at(&store, "BINANCE:b", 1030));
+    insert_balances(&mut store, "BINANCE:a", &balances, 1060).unwrap();
+    assert_eq!(count(&store, "balances"), 4);
+  }
+
+  #[test]
+  fn prunes_records_past_retention() {
+    let cfg = StoreConfig { retention_days: 1, ..temp_config("prune") };
+    let mut store = open(&cfg).unwrap();
+    let now = 10 * 86400_i64;
+    for at in [now - 2 * 86400, now - 3600] {
+      store.conn.execute("INSERT INTO alerts (at, target, condition, transition, severity, message) VALUES (?1, 't', 'c', 'open', 'WARNING', 'm')", params![at]).unwrap();
+      store.conn.execute("INSERT INTO actions (at, target, action, request, response, outcome) VALUES (?1, 't', 'top_up', '1', 'ok', 'ok')", params![at]).unwrap();
+      store.conn.execute("INSERT INTO balances (at, account_key, venue, account, asset, free, locked, borrowed, interest) VALUES (?1, 'k', 'BINANCE', 'SPOT', 'usdt', 1, 0, 0, 0)", params![at]).unwrap();
+    }
+    prune(&mut store, now).unwrap();
+    for table in ["alerts", "actions", "balances"] {
+      assert_eq!(count(&store, table), 1, "{}", table);
+    }
+    // 一个小时内不重复清理
+    store.conn.execute("INSERT INTO alerts (at, target, condition, transition, severity, message) VALUES (1, 't', 'c', 'open', 'WARNING', 'm')", []).unwrap();
+    prune(&mut store, now + 60).unwrap();
+    assert_eq!(count(&store, "alerts"), 2);
+    prune(&mut store, now + PRUNE_INTERVAL).unwrap();
+    assert_eq!(count(&store, "alerts"), 1);
+  }
+}
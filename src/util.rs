@@ -10,14 +10,59 @@ pub async fn handle_body (body_resp: Result<reqwest::Response, reqwest::Error> )
   if body_resp.is_err() {
     return Err(format!("[REQWEST ERROR]: {:}", body_resp.err().unwrap()));
   } else {
-    return Ok(body_resp.unwrap().text().await.expect("body_resp as text error"));
+    let resp = body_resp.unwrap();
+    // binance 在响应头里返回当前窗口已经使用的权重
+    if let Some(weight) = resp.headers().get("x-mbx-used-weight-1m").and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<f64>().ok()) {
+      crate::metrics::rate_limit_weight("BINANCE", weight);
+    }
+    return Ok(resp.text().await.expect("body_resp as text error"));
   }
 }
 
-pub fn huobi_withdraw_fee (asset: &String) -> f64 {
- if asset.eq("usdt") {
-   return 1_f64;
- } else {
-   return 0_f64;
- }
-}
\ No newline at end of file
+// 交易所的每个 http 请求都经过这里, 按交易所和调用的函数记录耗时; 网络错误和非 2xx 状态计入错误数,
+// 返回的 body 仍然交给调用方解析
+pub async fn timed_body (venue: &str, op: &str, request: impl std::future::Future<Output = Result<reqwest::Response, reqwest::Error>>) -> Result<String, String> {
+  let started = std::time::Instant::now();
+  let (status, res) = match request.await {
+    Ok(resp) => (Some(resp.status()), handle_body(Ok(resp)).await),
+    Err(e) => (None, handle_body(Err(e)).await)
+  };
+  let secs = started.elapsed().as_secs_f64();
+  match (&status, &res) {
+    (Some(status), Ok(body)) if !status.is_success() => crate::metrics::request(venue, op, secs, Err(&format!("{} {}", status, body))),
+    _ => crate::metrics::request(venue, op, secs, res.as_ref().map(|_| ()).map_err(|e| e.as_str()))
+  }
+  return res;
+}
+
+// 交易所返回的数字有时是字符串有时是数字, 缺失时当作 0
+pub fn value_f64 (v: &serde_json::Value) -> f64 {
+  if let Some(s) = v.as_str() {
+    return s.parse::<f64>().unwrap_or(0_f64);
+  }
+  return v.as_f64().unwrap_or(0_f64);
+}
+
+// 取5个深度, 盘口不足5档时有多少取多少; binance 和 okx 的价格数量是字符串, huobi 是数字
+pub fn depth_levels(levels: &serde_json::Value) -> Result<Vec<[f64;2]>, String> {
+  let levels = levels.as_array().ok_or(format!("invalid depth levels: {}", levels))?;
+  let mut res: Vec<[f64;2]> = Vec::new();
+  for level in levels.iter().take(5) {
+    let parse = |v: &serde_json::Value| v.as_str().and_then(|s| s.parse::<f64>().ok()).or_else(|| v.as_f64());
+    match (parse(&level[0]), parse(&level[1])) {
+      (Some(price), Some(volume)) => res.push([price, volume]),
+      _ => return Err(format!("invalid depth level: {}", level))
+    }
+  }
+  return Ok(res);
+}
+
+// 交易所记录的数量和请求的数量比较, 允许交易所按精度截断后的误差
+pub fn same_amount (a: f64, b: f64) -> bool {
+  (a - b).abs() <= max_f64(1e-8_f64, b.abs() * 1e-6_f64)
+}
+
+// 交易所返回的不是 json 时(网关的错误页, 截断的响应)返回 Err, 不 panic
+pub fn parse_json (body_text: &str) -> Result<serde_json::Value, String> {
+  serde_json::from_str(body_text).map_err(|e| format!("[JSON ERROR] {}: {}", e, body_text))
+}